@@ -0,0 +1,41 @@
+fn main() {
+	#[cfg(feature = "grpc")]
+	grpc();
+}
+
+/// Generate the gRPC service of `src/grpc.rs`
+///
+/// The messages are written by hand in that module, so the
+/// service is described here instead of in a `.proto` file, and
+/// building doesn't need `protoc`.
+#[cfg(feature = "grpc")]
+fn grpc() {
+	use tonic_build::manual::{Builder, Method, Service};
+
+	let codec = "tonic::codec::ProstCodec";
+	let service = Service::builder()
+		.name("Sonnerie")
+		.package("sonnerie")
+		.method(
+			Method::builder()
+				.name("read")
+				.route_name("Read")
+				.input_type("crate::grpc::ReadRequest")
+				.output_type("crate::grpc::Record")
+				.codec_path(codec)
+				.server_streaming()
+				.build(),
+		)
+		.method(
+			Method::builder()
+				.name("write")
+				.route_name("Write")
+				.input_type("crate::grpc::Record")
+				.output_type("crate::grpc::WriteResponse")
+				.codec_path(codec)
+				.client_streaming()
+				.build(),
+		)
+		.build();
+	Builder::new().compile(&[service]);
+}
@@ -9,32 +9,77 @@ pub fn lines(body: Body) -> Lines {
 		body,
 		buffer: VecDeque::new(),
 		done: false,
+		trailing: TrailingLine::Keep,
+		strip_cr: false,
+		unterminated: false,
 	}
 }
 
+/// What to do with the bytes after the last newline of a body
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TrailingLine {
+	/// Return them as the final line
+	#[default]
+	Keep,
+	/// Discard them
+	Drop,
+}
+
 pub struct Lines {
 	body: Body,
 	buffer: VecDeque<u8>,
 	done: bool,
+	trailing: TrailingLine,
+	strip_cr: bool,
+	unterminated: bool,
 }
 
 impl Lines {
+	/// Choose what happens to a final line that isn't followed by a newline.
+	///
+	/// Either way, [`unterminated`](Self::unterminated) tells you that
+	/// there was one. An empty body has no lines at all.
+	pub fn trailing(mut self, trailing: TrailingLine) -> Self {
+		self.trailing = trailing;
+		self
+	}
+
+	/// Remove a `\r` at the end of each line, so that `\r\n` line
+	/// endings are accepted
+	pub fn strip_cr(mut self, strip_cr: bool) -> Self {
+		self.strip_cr = strip_cr;
+		self
+	}
+
+	/// True once the end of the body was reached and it had
+	/// bytes after its last newline
+	pub fn unterminated(&self) -> bool {
+		self.unterminated
+	}
+
+	fn finish_line(&self, mut line: Vec<u8>) -> Vec<u8> {
+		if self.strip_cr && line.last() == Some(&b'\r') {
+			line.pop();
+		}
+		line
+	}
+
 	pub async fn next(&mut self) -> Option<Result<Vec<u8>, Error>> {
-		while !self.done || self.buffer.len() > 0 {
+		while !self.done || !self.buffer.is_empty() {
 			// check if we already have a nl
-			let nlpos = self
-				.buffer
-				.iter()
-				.enumerate()
-				.find(|&(_, &a)| a == b'\n')
-				.map(|(i, _)| i);
+			let nlpos = self.buffer.iter().position(|&a| a == b'\n');
 			if let Some(nlpos) = nlpos {
-				let next_line = self.buffer.drain(0..=nlpos).take(nlpos).collect();
-				return Some(Ok(next_line));
+				let mut next_line: Vec<u8> = self.buffer.drain(0..=nlpos).collect();
+				next_line.pop();
+				return Some(Ok(self.finish_line(next_line)));
 			} else if self.done {
 				// no new line, but we're at the end of the object
-				let next_line = self.buffer.drain(..).collect();
-				return Some(Ok(next_line));
+				self.unterminated = true;
+				let next_line: Vec<u8> = self.buffer.drain(..).collect();
+				match self.trailing {
+					TrailingLine::Keep => return Some(Ok(self.finish_line(next_line))),
+					TrailingLine::Drop => return None,
+				}
 			}
 
 			// get more data
@@ -52,3 +97,77 @@ impl Lines {
 		None
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn collect(chunks: &[&'static str], lines: impl FnOnce(Lines) -> Lines) -> (Vec<String>, bool) {
+		let chunks: Vec<Result<&'static str, std::io::Error>> =
+			chunks.iter().map(|c| Ok(*c)).collect();
+		let mut lines = lines(super::lines(Body::wrap_stream(futures::stream::iter(
+			chunks,
+		))));
+
+		let mut out = vec![];
+		futures::executor::block_on(async {
+			while let Some(line) = lines.next().await {
+				out.push(String::from_utf8(line.unwrap()).unwrap());
+			}
+		});
+		(out, lines.unterminated())
+	}
+
+	#[test]
+	fn terminated() {
+		assert_eq!(
+			collect(&["a\nbc", "\n", "\nd\n"], |l| l),
+			(vec!["a".into(), "bc".into(), "".into(), "d".into()], false)
+		);
+	}
+
+	#[test]
+	fn empty() {
+		assert_eq!(collect(&[], |l| l), (vec![], false));
+		assert_eq!(collect(&["", ""], |l| l), (vec![], false));
+		assert_eq!(
+			collect(&[], |l| l.trailing(TrailingLine::Drop)),
+			(vec![], false)
+		);
+	}
+
+	#[test]
+	fn trailing() {
+		assert_eq!(
+			collect(&["a\nb", "c"], |l| l),
+			(vec!["a".into(), "bc".into()], true)
+		);
+		assert_eq!(
+			collect(&["a\nb", "c"], |l| l.trailing(TrailingLine::Drop)),
+			(vec!["a".into()], true)
+		);
+		assert_eq!(
+			collect(&["a\n"], |l| l.trailing(TrailingLine::Drop)),
+			(vec!["a".into()], false)
+		);
+	}
+
+	#[test]
+	fn crlf() {
+		assert_eq!(
+			collect(&["a\r\nb\r", "\n\r\n", "c\r"], |l| l),
+			(
+				vec!["a\r".into(), "b\r".into(), "\r".into(), "c\r".into()],
+				true
+			)
+		);
+		assert_eq!(
+			collect(&["a\r\nb\r", "\n\r\n", "c\r"], |l| l.strip_cr(true)),
+			(vec!["a".into(), "b".into(), "".into(), "c".into()], true)
+		);
+		assert_eq!(
+			collect(&["a\rb\n"], |l| l.strip_cr(true)),
+			(vec!["a\rb".into()], false)
+		);
+	}
+}
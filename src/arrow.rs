@@ -0,0 +1,242 @@
+//! Read records as Apache Arrow record batches.
+//!
+//! Enabled by the `arrow` feature. [`DatabaseReader::get_range_arrow`]
+//! reads a range of keys as [`RecordBatch`]es, which can be handed to
+//! Polars, pandas (through pyarrow) or anything else that reads Arrow.
+//!
+//! A key can have records of more than one format, so each batch has
+//! the records of a single key with a single format, in time order. A
+//! batch has these columns:
+//!
+//! * `key`: the key, as `Utf8`
+//! * `time`: the timestamp, as `Timestamp(Nanosecond)`. Arrow's timestamps are
+//!   signed, so timestamps after the year 2262 are clamped to the largest one.
+//! * `1`, `2`, …: the record's columns, numbered from 1 as in the CLI's
+//!   `--columns`, with these types:
+//!
+//! | format | Arrow type |
+//! |--------|------------|
+//! | `c`    | `Int8`     |
+//! | `C`    | `UInt8`    |
+//! | `h`    | `Int16`    |
+//! | `H`    | `UInt16`   |
+//! | `i`    | `Int32`    |
+//! | `u`    | `UInt32`   |
+//! | `I`    | `Int64`    |
+//! | `U`    | `UInt64`   |
+//! | `f`    | `Float32`  |
+//! | `F`    | `Float64`  |
+//! | `s`    | `Utf8`     |
+//! | `B`    | `Binary`   |
+//!
+//! The schema's metadata has the key (`key`) and the format (`format`).
+//!
+//! ```no_run
+//! # let db = sonnerie::DatabaseReader::new(std::path::Path::new("")).unwrap();
+//! for batch in db.get_range_arrow("temperature/".."temperature0").max_rows(65536) {
+//!     println!("{} rows of {}", batch.num_rows(), batch.schema().metadata()["key"]);
+//! }
+//! ```
+
+use crate::{ColumnValue, DatabaseReader, DatabaseRecordIterator, DatabaseRecordReader, Record};
+use arrow_array::builder::*;
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema, TimeUnit};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+impl DatabaseReader {
+	/// Read a range of keys as Arrow record batches
+	///
+	/// Each batch has the consecutive records of one key that have the
+	/// same format; see the [module documentation](crate::arrow) for
+	/// the columns. By default, a batch has all of those records; use
+	/// [`ArrowBatches::max_rows`] to split them into smaller batches.
+	pub fn get_range_arrow<'d>(
+		&'d self,
+		range: impl std::ops::RangeBounds<&'d str> + 'd + Clone,
+	) -> ArrowBatches<'d> {
+		self.get_range(range).into_arrow()
+	}
+}
+
+impl<'d> DatabaseRecordReader<'d> {
+	/// Read the records as Arrow record batches
+	///
+	/// Like [`DatabaseReader::get_range_arrow`], but for any reader, for
+	/// example one made by [`get_filter`](DatabaseReader::get_filter).
+	pub fn into_arrow(self) -> ArrowBatches<'d> {
+		ArrowBatches {
+			records: self.into_iter().peekable(),
+			max_rows: usize::MAX,
+		}
+	}
+}
+
+/// Iterates over the batches of a range, see [`DatabaseReader::get_range_arrow`]
+pub struct ArrowBatches<'d> {
+	records: std::iter::Peekable<DatabaseRecordIterator<'d>>,
+	max_rows: usize,
+}
+
+impl ArrowBatches<'_> {
+	/// Put at most `rows` records in a batch
+	///
+	/// A key with more records of one format is split into several
+	/// consecutive batches. Panics if `rows` is 0.
+	pub fn max_rows(mut self, rows: usize) -> Self {
+		assert!(rows > 0, "a batch must be allowed at least one row");
+		self.max_rows = rows;
+		self
+	}
+}
+
+impl Iterator for ArrowBatches<'_> {
+	type Item = RecordBatch;
+
+	fn next(&mut self) -> Option<RecordBatch> {
+		let first = self.records.next()?;
+		let mut batch = BatchBuilder::new(&first);
+		batch.append(&first);
+		while batch.rows < self.max_rows {
+			match self.records.peek() {
+				Some(r) if r.key() == first.key() && r.format() == first.format() => {}
+				_ => break,
+			}
+			let record = self.records.next().unwrap();
+			batch.append(&record);
+		}
+		Some(batch.finish())
+	}
+}
+
+/// Accumulates the column of one format character
+enum ColumnBuilder {
+	I8(Int8Builder),
+	U8(UInt8Builder),
+	I16(Int16Builder),
+	U16(UInt16Builder),
+	I32(Int32Builder),
+	U32(UInt32Builder),
+	I64(Int64Builder),
+	U64(UInt64Builder),
+	F32(Float32Builder),
+	F64(Float64Builder),
+	Str(StringBuilder),
+	Bytes(BinaryBuilder),
+}
+
+impl ColumnBuilder {
+	fn new(format: u8) -> (ColumnBuilder, DataType) {
+		match format {
+			b'c' => (ColumnBuilder::I8(Default::default()), DataType::Int8),
+			b'C' => (ColumnBuilder::U8(Default::default()), DataType::UInt8),
+			b'h' => (ColumnBuilder::I16(Default::default()), DataType::Int16),
+			b'H' => (ColumnBuilder::U16(Default::default()), DataType::UInt16),
+			b'i' => (ColumnBuilder::I32(Default::default()), DataType::Int32),
+			b'u' => (ColumnBuilder::U32(Default::default()), DataType::UInt32),
+			b'I' => (ColumnBuilder::I64(Default::default()), DataType::Int64),
+			b'U' => (ColumnBuilder::U64(Default::default()), DataType::UInt64),
+			b'f' => (ColumnBuilder::F32(Default::default()), DataType::Float32),
+			b'F' => (ColumnBuilder::F64(Default::default()), DataType::Float64),
+			b's' => (ColumnBuilder::Str(Default::default()), DataType::Utf8),
+			b'B' => (ColumnBuilder::Bytes(Default::default()), DataType::Binary),
+			other => panic!("unknown format character '{}'", other as char),
+		}
+	}
+
+	fn append(&mut self, value: ColumnValue<'_>) {
+		match (self, value) {
+			(ColumnBuilder::I8(b), ColumnValue::I8(v)) => b.append_value(v),
+			(ColumnBuilder::U8(b), ColumnValue::U8(v)) => b.append_value(v),
+			(ColumnBuilder::I16(b), ColumnValue::I16(v)) => b.append_value(v),
+			(ColumnBuilder::U16(b), ColumnValue::U16(v)) => b.append_value(v),
+			(ColumnBuilder::I32(b), ColumnValue::I32(v)) => b.append_value(v),
+			(ColumnBuilder::U32(b), ColumnValue::U32(v)) => b.append_value(v),
+			(ColumnBuilder::I64(b), ColumnValue::I64(v)) => b.append_value(v),
+			(ColumnBuilder::U64(b), ColumnValue::U64(v)) => b.append_value(v),
+			(ColumnBuilder::F32(b), ColumnValue::F32(v)) => b.append_value(v),
+			(ColumnBuilder::F64(b), ColumnValue::F64(v)) => b.append_value(v),
+			(ColumnBuilder::Str(b), ColumnValue::Str(v)) => b.append_value(v),
+			(ColumnBuilder::Bytes(b), ColumnValue::Bytes(v)) => b.append_value(v),
+			(_, v) => panic!(
+				"a column of format '{}' in the wrong place",
+				v.format_char()
+			),
+		}
+	}
+
+	fn finish(&mut self) -> ArrayRef {
+		match self {
+			ColumnBuilder::I8(b) => Arc::new(b.finish()),
+			ColumnBuilder::U8(b) => Arc::new(b.finish()),
+			ColumnBuilder::I16(b) => Arc::new(b.finish()),
+			ColumnBuilder::U16(b) => Arc::new(b.finish()),
+			ColumnBuilder::I32(b) => Arc::new(b.finish()),
+			ColumnBuilder::U32(b) => Arc::new(b.finish()),
+			ColumnBuilder::I64(b) => Arc::new(b.finish()),
+			ColumnBuilder::U64(b) => Arc::new(b.finish()),
+			ColumnBuilder::F32(b) => Arc::new(b.finish()),
+			ColumnBuilder::F64(b) => Arc::new(b.finish()),
+			ColumnBuilder::Str(b) => Arc::new(b.finish()),
+			ColumnBuilder::Bytes(b) => Arc::new(b.finish()),
+		}
+	}
+}
+
+/// Accumulates the records of one key and format
+struct BatchBuilder {
+	schema: Arc<Schema>,
+	keys: StringBuilder,
+	times: TimestampNanosecondBuilder,
+	columns: Vec<ColumnBuilder>,
+	rows: usize,
+}
+
+impl BatchBuilder {
+	fn new(first: &Record) -> BatchBuilder {
+		let mut fields = vec![
+			Field::new("key", DataType::Utf8, false),
+			Field::new(
+				"time",
+				DataType::Timestamp(TimeUnit::Nanosecond, None),
+				false,
+			),
+		];
+		let mut columns = vec![];
+		for (idx, &format) in first.format().as_bytes().iter().enumerate() {
+			let (column, data_type) = ColumnBuilder::new(format);
+			fields.push(Field::new((idx + 1).to_string(), data_type, false));
+			columns.push(column);
+		}
+		let metadata = HashMap::from([
+			("key".to_string(), first.key().to_string()),
+			("format".to_string(), first.format().to_string()),
+		]);
+
+		BatchBuilder {
+			schema: Arc::new(Schema::new_with_metadata(fields, metadata)),
+			keys: StringBuilder::new(),
+			times: TimestampNanosecondBuilder::new(),
+			columns,
+			rows: 0,
+		}
+	}
+
+	fn append(&mut self, record: &Record) {
+		self.keys.append_value(record.key());
+		self.times
+			.append_value(i64::try_from(record.timestamp_nanos()).unwrap_or(i64::MAX));
+		for (column, value) in self.columns.iter_mut().zip(record.columns_checked()) {
+			column.append(value.expect("a corrupt record"));
+		}
+		self.rows += 1;
+	}
+
+	fn finish(mut self) -> RecordBatch {
+		let mut arrays: Vec<ArrayRef> =
+			vec![Arc::new(self.keys.finish()), Arc::new(self.times.finish())];
+		arrays.extend(self.columns.iter_mut().map(|c| c.finish()));
+		RecordBatch::try_new(self.schema, arrays).expect("the columns match the schema")
+	}
+}
@@ -0,0 +1,139 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use clap::Parser;
+use hyper::{Body, Server, StatusCode};
+
+use sonnerie::prometheus;
+use sonnerie::{DatabaseReader, WriteFailure};
+
+pub type Response = hyper::Response<Body>;
+pub type Request = hyper::Request<Body>;
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about = "A Prometheus remote storage for sonnerie", long_about = None)]
+struct Opt {
+	/// Store data here in this directory.
+	#[clap(short, long)]
+	dir: PathBuf,
+
+	/// Listen on this address (addr:port)
+	#[clap(short, long)]
+	listen: SocketAddr,
+
+	/// Sort the samples of a write in about this much memory, in MiB,
+	/// before spilling them to temporary files.
+	#[clap(long, default_value = "256")]
+	memory_limit: usize,
+}
+
+fn main() {
+	let opt = Opt::parse();
+
+	let runtime = tokio::runtime::Builder::new_multi_thread()
+		.thread_name("sonnerie")
+		.enable_all()
+		.build()
+		.expect("tokio runtime");
+
+	let srv = Arc::new(Psrv {
+		dir: opt.dir.clone(),
+		memory_limit: opt.memory_limit * 1024 * 1024,
+	});
+
+	let make_service = hyper::service::make_service_fn(move |_conn| {
+		let srv = srv.clone();
+		async move {
+			Ok::<_, std::convert::Infallible>(hyper::service::service_fn(move |req: Request| {
+				let srv = srv.clone();
+				async move {
+					Ok::<_, std::convert::Infallible>(match srv.run(req).await {
+						Ok(response) => response,
+						Err((status, e)) => text(status, format!("{}\n", e)),
+					})
+				}
+			}))
+		}
+	});
+
+	runtime
+		.block_on(async {
+			let serve = Server::bind(&opt.listen).serve(make_service);
+			eprintln!("now running");
+			serve.await
+		})
+		.expect("rt run");
+}
+
+struct Psrv {
+	dir: PathBuf,
+	memory_limit: usize,
+}
+
+/// An error, and the status that tells Prometheus whether to retry it
+type Failure = (StatusCode, String);
+
+impl Psrv {
+	async fn run(&self, req: Request) -> Result<Response, Failure> {
+		match (req.method(), req.uri().path()) {
+			(&hyper::Method::POST, "/api/v1/write") => self.write(req).await,
+			(&hyper::Method::POST, "/api/v1/read") => self.read(req).await,
+			_ => Ok(text(StatusCode::NOT_FOUND, "not found\n".to_string())),
+		}
+	}
+
+	/// Prometheus's remote write
+	///
+	/// Invalid requests are a 400, which Prometheus doesn't retry,
+	/// but it does retry a 500.
+	async fn write(&self, req: Request) -> Result<Response, Failure> {
+		let body = hyper::body::to_bytes(req.into_body())
+			.await
+			.map_err(|e| (StatusCode::BAD_REQUEST, format!("reading request: {}", e)))?;
+		let request = prometheus::decode_write_request(&body)
+			.map_err(|e| (StatusCode::BAD_REQUEST, format!("decoding request: {}", e)))?;
+
+		tokio::task::block_in_place(|| prometheus::write(&self.dir, &request, self.memory_limit))
+			.map_err(|e| match e {
+			WriteFailure::IOError(e) => {
+				(StatusCode::INTERNAL_SERVER_ERROR, format!("writing: {}", e))
+			}
+			e => (StatusCode::BAD_REQUEST, e.to_string()),
+		})?;
+
+		Ok(hyper::Response::builder()
+			.status(StatusCode::NO_CONTENT)
+			.body(Body::empty())
+			.expect("write response"))
+	}
+
+	/// Prometheus's remote read, which answers with samples
+	async fn read(&self, req: Request) -> Result<Response, Failure> {
+		let body = hyper::body::to_bytes(req.into_body())
+			.await
+			.map_err(|e| (StatusCode::BAD_REQUEST, format!("reading request: {}", e)))?;
+		let request = prometheus::decode_read_request(&body)
+			.map_err(|e| (StatusCode::BAD_REQUEST, format!("decoding request: {}", e)))?;
+
+		let response = tokio::task::block_in_place(|| {
+			let db = DatabaseReader::new(&self.dir)
+				.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("opening: {}", e)))?;
+			prometheus::read(&db, &request).map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))
+		})?;
+
+		Ok(hyper::Response::builder()
+			.header(hyper::header::CONTENT_TYPE, "application/x-protobuf")
+			.header(hyper::header::CONTENT_ENCODING, "snappy")
+			.body(prometheus::encode_read_response(&response).into())
+			.expect("read response"))
+	}
+}
+
+fn text(status: StatusCode, body: String) -> Response {
+	hyper::Response::builder()
+		.status(status)
+		.header(hyper::header::CONTENT_TYPE, "text/plain")
+		.body(body.into())
+		.expect("error response")
+}
@@ -15,7 +15,6 @@ pub use hyper::Body;
 pub type Response = hyper::Response<Body>;
 pub type Request = hyper::Request<Body>;
 
-use escape_string::split_one;
 use futures::sink::SinkExt;
 use futures::stream::StreamExt;
 
@@ -123,8 +122,11 @@ impl Tsrv {
 			let mut writer = sorted_file.get_sender();
 
 			let mut lines = lines_from_request::lines(req.into_body());
+			let mut line_number = 0;
+			let mut row_data = vec![];
 
 			while let Some(line) = lines.next().await {
+				line_number += 1;
 				let line = line.map_err(|e| format!("reading one row from network: {}", e))?;
 				let line =
 					String::from_utf8(line).map_err(|e| format!("data must be utf-8: {}", e))?;
@@ -132,20 +134,19 @@ impl Tsrv {
 				if tail.is_empty() {
 					continue;
 				}
-				let (key, tail) = split_one(tail).ok_or_else(|| "reading key".to_string())?;
-				let (timestamp, tail) =
-					split_one(tail).ok_or_else(|| "reading timestamp".to_string())?;
-				let ts: Timestamp = timestamp
-					.parse()
-					.map_err(|e| format!("parsing timestamp {}", e))?;
-				let (format, tail) =
-					split_one(tail).ok_or_else(|| "reading timestamp".to_string())?;
+				// check the row now, so that the error names its line
+				let (key, ts, format) =
+					match formatted::parse_line_with_fmt(tail, line_number, None, &mut row_data) {
+						Ok(parsed) => parsed,
+						Err(e) => return Ok(bad_row(e)),
+					};
 
 				let rec = SortingRecord {
-					key: key.to_string(),
+					key: key.into_owned(),
 					ts,
-					format: format.to_string(),
-					tail: tail.to_string(),
+					format: format.into_owned(),
+					data: std::mem::take(&mut row_data),
+					line: line_number,
 				};
 
 				tokio::task::block_in_place(|| -> Result<(), String> {
@@ -156,7 +157,7 @@ impl Tsrv {
 			}
 		}
 
-		tokio::task::block_in_place(|| -> Result<(), String> {
+		let added = tokio::task::block_in_place(|| -> Result<Result<(), WriteFailure>, String> {
 			sorted_file
 				.finish()
 				.map_err(|e| format!("doing the external sorting {}", e))?;
@@ -164,8 +165,6 @@ impl Tsrv {
 			let reader = shardio::ShardReader::<SortingRecord>::open(tmpfile.path())
 				.map_err(|e| format!("opening sorted: {}", e))?;
 
-			let mut row_data = vec![];
-
 			for record in reader
 				.iter()
 				.map_err(|e| format!("reading from sorted: {}", e))?
@@ -174,15 +173,24 @@ impl Tsrv {
 					key,
 					ts,
 					format,
-					tail,
+					data,
+					line,
 				} = record.map_err(|e| format!("parsing temporary data: {}", e))?;
-				let row_format = sonnerie::row_format::parse_row_format(&format);
-				row_format
-					.to_stored_format(ts, &tail, &mut row_data)
-					.map_err(|e| format!("parsing data according to format: {}", e))?;
-				tx.add_record_raw(&key, &format, &row_data)
-					.map_err(|e| format!("processing record {}[{}]: {:?}", key, ts, e))?;
-				row_data.clear();
+				match tx.add_record_raw(&key, &format, &data) {
+					Ok(()) => {}
+					Err(WriteFailure::IOError(e)) => return Err(format!("writing: {}", e)),
+					Err(e) => {
+						return Ok(Err(WriteFailure::InvalidLine {
+							line,
+							message: format!(
+								"processing record {}[{}]: {}",
+								escape_string::escape(&key),
+								ts,
+								e
+							),
+						}))
+					}
+				}
 			}
 
 			tx.commit().map_err(|e| format!("committing tx: {}", e))?;
@@ -193,8 +201,11 @@ impl Tsrv {
 				*age = None;
 			}
 
-			Ok(())
+			Ok(Ok(()))
 		})?;
+		if let Err(e) = added {
+			return Ok(bad_row(e));
+		}
 
 		hyper::Response::builder()
 			.status(201)
@@ -300,10 +311,28 @@ impl Tsrv {
 	}
 }
 
+/// The response to a PUT with a row that couldn't be added
+///
+/// Nothing is committed. The body is the error, which starts with
+/// the row's line, and the `X-Sonnerie-Line` header is its line number.
+fn bad_row(e: WriteFailure) -> Response {
+	let mut response = hyper::Response::builder()
+		.status(hyper::StatusCode::BAD_REQUEST)
+		.header(hyper::header::CONTENT_TYPE, "text/plain");
+	if let WriteFailure::InvalidLine { line, .. } = &e {
+		response = response.header("X-Sonnerie-Line", *line);
+	}
+	response
+		.body(format!("{}\n", e).into())
+		.expect("error response")
+}
+
 #[derive(Clone, Eq, PartialEq, Serialize, Deserialize, PartialOrd, Ord)]
 struct SortingRecord {
 	key: String,
 	ts: Timestamp,
 	format: String,
-	tail: String,
+	data: Vec<u8>,
+	/// the line of the request that it's on, for errors
+	line: usize,
 }
@@ -1,7 +1,8 @@
 use crate::database_reader::DatabaseReader;
 use crate::key_reader::*;
 use crate::merge::Merge;
-use crate::DeleteMarkerPrecomputed;
+use crate::wildcard::KeyMatcher;
+use crate::Deletions;
 use crate::Record;
 use lending_cell::{BorrowedCell, LendingCell};
 use std::ops::Bound;
@@ -17,7 +18,7 @@ use std::ops::Bound;
 /// on this object to get a Rayon parallel iterator.
 pub struct DatabaseKeyReader<'d> {
 	pub(crate) db: &'d DatabaseReader,
-	pub(crate) matcher: Option<regex::Regex>,
+	pub(crate) matcher: Option<KeyMatcher>,
 	pub(crate) prefix: &'d str,
 	pub(crate) range: crate::CowStringRange<'d>,
 }
@@ -41,8 +42,12 @@ impl<'d> DatabaseKeyReader<'d> {
 			.txes
 			.iter()
 			.map(|tx| {
-				let filter =
-					tx.2.get_filter_range(self.matcher.clone(), self.prefix, self.range.clone());
+				let filter = tx.2.get_filter_range(
+					self.matcher.clone(),
+					self.prefix,
+					self.range.clone(),
+					None,
+				);
 				let b = filter.compressed_bytes();
 				(filter, b)
 			})
@@ -129,23 +134,23 @@ impl<'d> IntoIterator for DatabaseKeyReader<'d> {
 		let mut readers = Vec::with_capacity(self.db.txes.len());
 
 		for (txid, _path, reader) in self.db.txes.iter() {
-			let iter =
-				reader.get_filter_range(self.matcher.clone(), self.prefix, self.range.clone());
+			let iter = reader.get_filter_range(
+				self.matcher.clone(),
+				self.prefix,
+				self.range.clone(),
+				None,
+			);
 
 			readers.push((*txid, iter));
 		}
-		let merge = Merge::new(readers, |a, b| {
-			a.key()
-				.cmp(b.key())
-				.then_with(|| a.timestamp_nanos().cmp(&b.timestamp_nanos()))
-		});
+		let merge = Merge::new(readers, |a, b| a.key_timestamp().cmp(&b.key_timestamp()));
 
-		let filter_out: Vec<_> = self
-			.db
-			.filter_out
-			.iter()
-			.map(|(txid, _path, dm)| (*txid, DeleteMarkerPrecomputed::from_delete_marker(dm)))
-			.collect();
+		let filter_out = Deletions::new(
+			self.db
+				.filter_out
+				.iter()
+				.map(|(txid, _path, dm)| (*txid, dm)),
+		);
 
 		let mut hot_potato = HotPotato {
 			filter_out,
@@ -165,7 +170,7 @@ impl<'d> IntoIterator for DatabaseKeyReader<'d> {
 }
 
 struct HotPotato<'d> {
-	filter_out: Vec<(usize, DeleteMarkerPrecomputed<'d>)>,
+	filter_out: Deletions<'d>,
 	merge: Box<Merge<StringKeyRangeReader<'d, 'd>, Record>>,
 	queued_record: Option<Record>, // record hasn't been outputted yet
 	current_key: String,
@@ -178,39 +183,8 @@ impl<'d> HotPotato<'d> {
 		}
 
 		for (txid, record) in self.merge.by_ref() {
-			let is_filtered_out = self
-				.filter_out
-				.iter()
-				// select only transactions that are indexed lower than the
-				// delete transaction
-				.filter(|(del_txid, _)| txid < *del_txid)
-				// check if the record's timestamp is within filtering out
-				// this assumes that the filter_out is sorted ascending by
-				// first timestamp (which should have been done in
-				// DatabaseReader::new())
-				.filter(|(_, filter)| {
-					let record_time = record.time();
-					(filter.first_timestamp..filter.last_timestamp).contains(&record_time)
-				})
-				.filter(|(_, filter)| record.time() <= filter.last_timestamp)
-				// if any of the filters went here (i.e. any() returns a true),
-				// then that means that filter found one filter that filters out
-				// the current record. that should be discarded
-				.any(|(_, filter)| {
-					let key = record.key();
-
-					if filter.first_key > key {
-						return false;
-					}
-
-					if !filter.last_key.is_empty() && key >= filter.last_key {
-						return false;
-					}
-
-					filter.wildcard_matches(key)
-				});
-
-			if !is_filtered_out {
+			let (key, timestamp) = record.key_timestamp();
+			if !self.filter_out.contains(txid, key, timestamp) {
 				return Some(record);
 			}
 		}
@@ -288,6 +262,15 @@ impl<'d> KeyRecordReader<'d> {
 	pub fn key(&self) -> &str {
 		&self.hot_potato.current_key
 	}
+
+	/// Read the rest of this key's records, returning the latest one
+	///
+	/// This is `None` if there are no more records, which is only
+	/// the case if some were already read, because a key
+	/// is only yielded if it has a record that isn't deleted.
+	pub fn latest(self) -> Option<Record> {
+		self.last()
+	}
 }
 
 impl<'d> Iterator for KeyRecordReader<'d> {
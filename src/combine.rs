@@ -0,0 +1,96 @@
+//! Combine the versions of a record that more than one transaction has.
+
+use crate::{ColumnValue, DatabaseRecordReader, Record, ToRecord};
+use std::sync::Arc;
+
+impl DatabaseRecordReader<'_> {
+	/// Combine the versions of records that have the same key and
+	/// timestamp, column by column
+	///
+	/// Normally, when more than one transaction has a record with the
+	/// same key and timestamp, only the newest version is yielded. With
+	/// this, each of those records is yielded once, with its columns
+	/// combined from every version according to `spec`, which has
+	/// a character for each column:
+	///
+	/// * `+` - the sum of the versions (integers wrap around)
+	/// * `<` - the smallest value
+	/// * `>` - the largest value
+	/// * `=` - the newest version's value
+	///
+	/// Columns past the end of `spec` are `=`, and so are strings and
+	/// bytes that are summed. When the versions have different formats,
+	/// the newest one is yielded as it is. Deleted versions aren't
+	/// combined, and [`filter_values`](Self::filter_values) sees the
+	/// combined record. This replaces [`with_duplicates`](Self::with_duplicates).
+	///
+	/// For example, counters that are added to in each transaction:
+	///
+	/// ```no_run
+	/// # let db = sonnerie::DatabaseReader::new(std::path::Path::new("")).unwrap();
+	/// for record in db.get_range("requests.".."requests/").combine_duplicates("+") {
+	///     println!("{} {}", record.key(), record.get::<u64>(0));
+	/// }
+	/// ```
+	///
+	/// Panics if `spec` has any other character.
+	pub fn combine_duplicates(mut self, spec: &str) -> Self {
+		if let Some(c) = spec.chars().find(|c| !"+<>=".contains(*c)) {
+			panic!("invalid character '{}' in the combination {:?}", c, spec);
+		}
+		self.combine = Some(Arc::new(Combining {
+			spec: spec.as_bytes().to_vec(),
+		}));
+		self
+	}
+}
+
+/// How to combine each column, see [`DatabaseRecordReader::combine_duplicates`]
+pub(crate) struct Combining {
+	spec: Vec<u8>,
+}
+
+impl Combining {
+	/// Combine an older and a newer version of a record
+	pub(crate) fn apply(&self, older: &Record, newer: &Record) -> Record {
+		if older.format() != newer.format() {
+			return newer.clone();
+		}
+		let (Ok(old), Ok(new)) = (
+			older.columns_checked().collect::<std::io::Result<Vec<_>>>(),
+			newer.columns_checked().collect::<std::io::Result<Vec<_>>>(),
+		) else {
+			return newer.clone();
+		};
+
+		let mut data = vec![];
+		for (col, (a, b)) in old.into_iter().zip(new).enumerate() {
+			let value = match self.spec.get(col) {
+				Some(b'+') => sum(a, b),
+				Some(b'<') if a < b => a,
+				Some(b'>') if a > b => a,
+				_ => b,
+			};
+			value.store(&mut data);
+		}
+		Record::from_parts(newer.key(), newer.format(), newer.timestamp_nanos(), &data)
+	}
+}
+
+/// `a + b` if they're numbers of the same type, or else `b`
+fn sum<'a>(a: ColumnValue<'a>, b: ColumnValue<'a>) -> ColumnValue<'a> {
+	use ColumnValue::*;
+	match (a, b) {
+		(I8(a), I8(b)) => I8(a.wrapping_add(b)),
+		(U8(a), U8(b)) => U8(a.wrapping_add(b)),
+		(I16(a), I16(b)) => I16(a.wrapping_add(b)),
+		(U16(a), U16(b)) => U16(a.wrapping_add(b)),
+		(I32(a), I32(b)) => I32(a.wrapping_add(b)),
+		(U32(a), U32(b)) => U32(a.wrapping_add(b)),
+		(I64(a), I64(b)) => I64(a.wrapping_add(b)),
+		(U64(a), U64(b)) => U64(a.wrapping_add(b)),
+		(F32(a), F32(b)) => F32(a + b),
+		(F64(a), F64(b)) => F64(a + b),
+		(_, b) => b,
+	}
+}
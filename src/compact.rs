@@ -0,0 +1,477 @@
+//! Compact a database while transforming its records.
+
+use crate::{CreateTx, DatabaseReader, Record, WriteFailure};
+use std::path::{Path, PathBuf};
+
+/// Empty transactions newer than this are not pruned, because
+/// they may be the placeholder of a transaction that's being committed
+const PRUNE_GRACE: std::time::Duration = std::time::Duration::from_secs(3600);
+
+/// Compact the database, merging a column of the records that
+/// fall into the same time bucket.
+///
+/// This is a major compaction: everything, including `main`, is
+/// written into a new `main`.
+///
+/// For each key, records are grouped into buckets of `bucket_ns`
+/// nanoseconds (a record is in the bucket `timestamp / bucket_ns`).
+/// When a bucket has more than one record, `merge_fn` receives the
+/// values of column `col` (counting from 0) of each of those records,
+/// in chronological order, and the bucket is replaced with a single
+/// record whose column `col` is the value returned by `merge_fn`. That
+/// record has the timestamp and the other columns of the first record
+/// in the bucket.
+///
+/// Only variable-sized columns (strings) can be merged; records whose format
+/// doesn't have such a column at `col`, or whose format changes within a bucket,
+/// are copied unchanged. The merged value of a string column must be valid UTF-8.
+///
+/// This is useful for rolling up sketches (such as t-digests)
+/// that are stored per-minute into coarser ones.
+pub fn compact_with_column_merge(
+	dir: &Path,
+	bucket_ns: u64,
+	col: usize,
+	merge_fn: impl Fn(&[&[u8]]) -> Vec<u8>,
+) -> Result<(), WriteFailure> {
+	use fs2::FileExt;
+
+	assert!(bucket_ns > 0, "bucket_ns must be nonzero");
+
+	let lock = std::fs::File::create(dir.join(".compact"))?;
+	lock.lock_exclusive()?;
+	finish_interrupted(dir)?;
+
+	let db = DatabaseReader::new(dir)?;
+	let mut compacted = CreateTx::new(dir)?;
+
+	let mut bucket: Vec<Record> = vec![];
+	for record in db.get_range(..) {
+		if let Some(first) = bucket.first() {
+			if first.key() != record.key()
+				|| first.format() != record.format()
+				|| first.timestamp_nanos() / bucket_ns != record.timestamp_nanos() / bucket_ns
+			{
+				write_bucket(&mut compacted, &mut bucket, col, &merge_fn)?;
+			}
+		}
+		bucket.push(record);
+	}
+	write_bucket(&mut compacted, &mut bucket, col, &merge_fn)?;
+
+	purge_compacted_files(compacted, dir, &db, true)?;
+	Ok(())
+}
+
+/// Compact the database, keeping only the latest record of the
+/// keys that match a latest-only wildcard.
+///
+/// This is a major compaction, like [`compact_with_column_merge`].
+/// The wildcards are read from the database, see
+/// [`retention::set_latest_only`](crate::retention::set_latest_only).
+///
+/// The records are read after deletions are applied, so a key keeps
+/// its latest record that isn't deleted. Afterwards the older records
+/// are gone, so [`as_of`](crate::DatabaseRecordReader::as_of) an earlier
+/// time no longer finds them.
+pub fn compact_latest_only(dir: &Path) -> Result<(), WriteFailure> {
+	compact_latest_only_with(dir, |_| Ok(CreateTx::new(dir)?))?;
+	Ok(())
+}
+
+/// Like [`compact_latest_only`], but `new_tx` creates the compacted transaction
+///
+/// It receives the database that's being compacted, so it can
+/// choose the compression or copy the metadata of its transactions.
+/// Returns the number of records that were kept.
+pub fn compact_latest_only_with(
+	dir: &Path,
+	new_tx: impl FnOnce(&DatabaseReader) -> Result<CreateTx, WriteFailure>,
+) -> Result<u64, WriteFailure> {
+	use fs2::FileExt;
+
+	let lock = std::fs::File::create(dir.join(".compact"))?;
+	lock.lock_exclusive()?;
+	finish_interrupted(dir)?;
+
+	let db = DatabaseReader::new(dir)?;
+	let wildcards = crate::retention::latest_only(&db);
+	let mut compacted = new_tx(&db)?;
+
+	for record in crate::retention::keep_latest(db.get_range(..), &wildcards) {
+		compacted.add_record_raw(record.key(), record.format(), record.raw())?;
+	}
+	let records = compacted.record_count();
+
+	purge_compacted_files(compacted, dir, &db, true)?;
+	Ok(records)
+}
+
+/// What a compaction would do, see [`compact_dry_run`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompactDryRun {
+	/// The number of transactions that would be compacted,
+	/// including `main` in a major compaction
+	pub transactions: usize,
+	/// The number of transactions of deletions, which
+	/// a major compaction would remove
+	pub deletions: usize,
+	/// The number of records that would be written,
+	/// which doesn't include the deleted ones
+	pub records: u64,
+	/// The size in bytes of the files that would be replaced
+	pub input_bytes: u64,
+	/// The estimated size in bytes of the compacted transaction
+	///
+	/// This is the size of the records before compression,
+	/// so the compacted transaction is usually much smaller.
+	pub estimated_bytes: u64,
+}
+
+/// Find out how much work compacting the database would be,
+/// without compacting it
+///
+/// This reads the same transactions as a compaction (with `major`, all of
+/// them, otherwise all but `main`) and counts the records that would be
+/// written. Nothing is written or removed, and the `.compact` lock isn't
+/// taken, so it doesn't wait for or block a compaction. If one runs
+/// at the same time, this describes the database from before it.
+///
+/// There's nothing to compact if `transactions` is less than 2.
+pub fn compact_dry_run(dir: &Path, major: bool) -> std::io::Result<CompactDryRun> {
+	let db = if major {
+		DatabaseReader::new(dir)?
+	} else {
+		DatabaseReader::without_main_db(dir)?
+	};
+
+	let mut input_bytes = 0;
+	for path in db.transaction_paths() {
+		input_bytes += std::fs::metadata(path)?.len();
+	}
+	if major {
+		for path in db.delete_txes_paths() {
+			input_bytes += std::fs::metadata(path)?.len();
+		}
+	}
+
+	let mut records = 0;
+	let mut estimated_bytes = 0;
+	let mut last_key: Option<String> = None;
+	for record in db.get_range(..) {
+		if last_key.as_deref() != Some(record.key()) {
+			// each key and format is stored once per segment
+			estimated_bytes += (record.key().len() + record.format().len()) as u64;
+			last_key = Some(record.key().to_owned());
+		}
+		// the timestamp and the values
+		estimated_bytes += 8 + record.raw().len() as u64;
+		records += 1;
+	}
+
+	Ok(CompactDryRun {
+		transactions: db.num_txes(),
+		deletions: db.delete_txes_paths().count(),
+		records,
+		input_bytes,
+		estimated_bytes,
+	})
+}
+
+/// write the records of one bucket, merging them if possible,
+/// and clear `bucket`
+fn write_bucket(
+	tx: &mut CreateTx,
+	bucket: &mut Vec<Record>,
+	col: usize,
+	merge_fn: &impl Fn(&[&[u8]]) -> Vec<u8>,
+) -> Result<(), WriteFailure> {
+	let Some(first) = bucket.first() else {
+		return Ok(());
+	};
+
+	let is_variable =
+		first.format().as_bytes().get(col).is_some_and(|c| {
+			crate::row_format::row_format_size(&(*c as char).to_string()).is_none()
+		});
+
+	if bucket.len() == 1 || !is_variable {
+		for record in bucket.drain(..) {
+			tx.add_record_raw(record.key(), record.format(), record.raw())?;
+		}
+		return Ok(());
+	}
+
+	let mut values = Vec::with_capacity(bucket.len());
+	for record in bucket.iter() {
+		let (_, value) = variable_column(record, col)?;
+		values.push(value);
+	}
+	let merged = merge_fn(&values);
+
+	if first.format().as_bytes()[col] == b's' && std::str::from_utf8(&merged).is_err() {
+		return Err(std::io::Error::new(
+			std::io::ErrorKind::InvalidData,
+			format!("merged value for key {:?} is not valid utf-8", first.key()),
+		)
+		.into());
+	}
+
+	let (range, _) = variable_column(first, col)?;
+	let raw = first.raw();
+	let mut row_data = Vec::with_capacity(raw.len() + merged.len());
+	row_data.extend_from_slice(&raw[..range.start]);
+	let mut lenbuf = unsigned_varint::encode::usize_buffer();
+	row_data.extend_from_slice(unsigned_varint::encode::usize(merged.len(), &mut lenbuf));
+	row_data.extend_from_slice(&merged);
+	row_data.extend_from_slice(&raw[range.end..]);
+
+	tx.add_record_raw(first.key(), first.format(), &row_data)?;
+	bucket.clear();
+	Ok(())
+}
+
+/// Find the variable-sized column `col` in `record.raw()`, returning
+/// the range of its entire encoding (including its length) and its contents
+fn variable_column(
+	record: &Record,
+	col: usize,
+) -> std::io::Result<(std::ops::Range<usize>, &[u8])> {
+	let invalid = |e| std::io::Error::new(std::io::ErrorKind::InvalidData, e);
+
+	let raw = record.raw();
+	let mut pos = crate::TIMESTAMP_SIZE;
+	for (idx, c) in record.format().bytes().enumerate() {
+		let size = match crate::row_format::row_format_size(&(c as char).to_string()) {
+			Some(size) => size,
+			None => {
+				let (len, tail) = unsigned_varint::decode::usize(&raw[pos..])
+					.map_err(|e| invalid(format!("{:?}", e)))?;
+				let header = raw.len() - pos - tail.len();
+				if tail.len() < len {
+					return Err(invalid("column extends past the record".to_string()));
+				}
+				if idx == col {
+					return Ok((pos..pos + header + len, &tail[..len]));
+				}
+				header + len
+			}
+		};
+		pos += size;
+	}
+	Err(invalid(format!("column {} is not variable-sized", col)))
+}
+
+/// Commit the compacted transaction and remove the files it replaces
+///
+/// The files are listed in a journal before the commit, so that if
+/// this is interrupted after the commit, [`finish_interrupted`] removes
+/// them. Otherwise they'd remain, and their records would be read twice
+/// (or a delete marker would be applied to the records added after it).
+pub(crate) fn purge_compacted_files(
+	compacted: CreateTx,
+	dir: &Path,
+	db: &DatabaseReader,
+	major: bool,
+) -> std::io::Result<()> {
+	let removed = commit_compacted(compacted, dir, db, major)?;
+	finish_purge(dir, &removed)
+}
+
+/// The file that lists the files a compaction is replacing
+const JOURNAL: &str = ".compact-journal";
+
+/// Write the journal, then commit the compacted transaction,
+/// returning the files that it replaces
+pub(crate) fn commit_compacted(
+	compacted: CreateTx,
+	dir: &Path,
+	db: &DatabaseReader,
+	major: bool,
+) -> std::io::Result<Vec<PathBuf>> {
+	let source_transaction_paths = db.transaction_paths();
+
+	let (target, removed_transaction_paths) = if major {
+		(dir.join("main"), &source_transaction_paths[..])
+	} else {
+		// allow OS to atomically replace `first_path` (and don't delete it afterwards)
+		let keep_path = source_transaction_paths.last().unwrap().clone();
+		(
+			keep_path,
+			&source_transaction_paths[..source_transaction_paths.len() - 1],
+		)
+	};
+
+	let mut removed: Vec<PathBuf> = removed_transaction_paths
+		.iter()
+		.filter(|txfile| txfile.file_name().expect("filename in txfile") != "main")
+		.cloned()
+		.collect();
+	if major {
+		removed.extend(db.delete_txes_paths().map(Path::to_owned));
+	}
+
+	write_journal(dir, &target, &removed)?;
+	compacted.commit_to(&target)?;
+	// the new file must be in place before the ones it replaces are removed
+	std::fs::File::open(dir)?.sync_all()?;
+	Ok(removed)
+}
+
+/// Remove the files that a compaction replaced, and then its journal
+fn finish_purge(dir: &Path, removed: &[PathBuf]) -> std::io::Result<()> {
+	for txfile in removed {
+		match std::fs::remove_file(txfile) {
+			Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+				eprintln!("warning: failed to remove {:?}: {}", txfile, e);
+			}
+			_ => {}
+		}
+	}
+	std::fs::remove_file(dir.join(JOURNAL))
+}
+
+/// Atomically write the journal of a compaction that commits to `target`
+///
+/// Each line is tab-separated. The first is "target", the name of the
+/// file the compaction is committed to and its inode before the commit
+/// (or "-" if it doesn't exist), and the others are "remove" and the
+/// name of a file to remove after the commit.
+pub(crate) fn write_journal(dir: &Path, target: &Path, removed: &[PathBuf]) -> std::io::Result<()> {
+	use std::io::Write as _;
+
+	let name = |p: &Path| {
+		p.file_name()
+			.and_then(|n| n.to_str())
+			.expect("transaction file name")
+			.to_owned()
+	};
+
+	let mut journal = format!("target\t{}\t", name(target));
+	match inode(target)? {
+		Some(ino) => journal += &ino.to_string(),
+		None => journal += "-",
+	}
+	journal += "\n";
+	for txfile in removed {
+		journal += &format!("remove\t{}\n", name(txfile));
+	}
+
+	let tmp_path = dir.join(format!("{}.tmp", JOURNAL));
+	let mut tmp = std::fs::File::create(&tmp_path)?;
+	tmp.write_all(journal.as_bytes())?;
+	tmp.sync_all()?;
+	drop(tmp);
+	std::fs::rename(&tmp_path, dir.join(JOURNAL))?;
+	std::fs::File::open(dir)?.sync_all()
+}
+
+fn inode(path: &Path) -> std::io::Result<Option<u64>> {
+	use std::os::unix::fs::MetadataExt as _;
+
+	match std::fs::metadata(path) {
+		Ok(m) => Ok(Some(m.ino())),
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+		Err(e) => Err(e),
+	}
+}
+
+/// Finish a compaction that was interrupted while it was
+/// removing the files it replaced
+///
+/// If the compaction was interrupted before it committed, the journal is
+/// removed and the files are kept. The new file replaces the target by
+/// renaming, so the target has a different inode only if it was committed.
+/// This must be called with the `.compact` lock held. Returns
+/// true if there was an interrupted compaction.
+pub(crate) fn finish_interrupted(dir: &Path) -> std::io::Result<bool> {
+	let journal = match std::fs::read_to_string(dir.join(JOURNAL)) {
+		Ok(journal) => journal,
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+		Err(e) => return Err(e),
+	};
+	let invalid = || {
+		std::io::Error::new(
+			std::io::ErrorKind::InvalidData,
+			format!("invalid {}", JOURNAL),
+		)
+	};
+
+	let mut lines = journal.lines();
+	let mut target = lines.next().ok_or_else(invalid)?.split('\t');
+	if target.next() != Some("target") {
+		return Err(invalid());
+	}
+	let target_path = dir.join(target.next().ok_or_else(invalid)?);
+	let old_inode = match target.next().ok_or_else(invalid)? {
+		"-" => None,
+		ino => Some(ino.parse::<u64>().map_err(|_| invalid())?),
+	};
+
+	let mut removed = vec![];
+	for line in lines {
+		let name = line.strip_prefix("remove\t").ok_or_else(invalid)?;
+		removed.push(dir.join(name));
+	}
+
+	if inode(&target_path)? == old_inode {
+		// not committed, the files still have their records
+		removed.clear();
+	}
+	finish_purge(dir, &removed)?;
+	Ok(true)
+}
+
+/// Remove the empty transaction files from a database
+///
+/// Failed commits can leave zero-length `tx.*` files behind, which
+/// [`DatabaseReader`] ignores. This removes those files and returns
+/// their paths. It's safe to run while the database is in use,
+/// for example from a cron job.
+///
+/// Only files named like a committed transaction (`tx.` followed by 16
+/// hexadecimal digits) that are zero bytes are removed, and `main` is never removed.
+/// A transaction that is being committed is briefly an empty file, so
+/// files whose name or modification time is less than an hour old are left alone.
+pub fn prune_empty(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+	use fs2::FileExt;
+
+	let lock = std::fs::File::create(dir.join(".compact"))?;
+	lock.lock_exclusive()?;
+
+	let now = std::time::SystemTime::now();
+	let recent = |t: std::time::SystemTime| {
+		now.duration_since(t)
+			.map(|age| age < PRUNE_GRACE)
+			.unwrap_or(true)
+	};
+
+	let mut removed = vec![];
+	for entry in std::fs::read_dir(dir)? {
+		let entry = entry?;
+		let name = entry.file_name();
+		let Some(hex) = name.to_str().and_then(|n| n.strip_prefix("tx.")) else {
+			continue;
+		};
+		if hex.len() != 16 || !hex.bytes().all(|c| c.is_ascii_hexdigit()) {
+			continue;
+		}
+		let Ok(nanos) = u64::from_str_radix(hex, 16) else {
+			continue;
+		};
+		let named_at = std::time::UNIX_EPOCH + std::time::Duration::from_nanos(nanos);
+
+		let metadata = entry.metadata()?;
+		if !metadata.is_file() || metadata.len() != 0 {
+			continue;
+		}
+		if recent(named_at) || recent(metadata.modified()?) {
+			continue;
+		}
+
+		std::fs::remove_file(entry.path())?;
+		removed.push(entry.path());
+	}
+	removed.sort();
+	Ok(removed)
+}
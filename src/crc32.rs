@@ -0,0 +1,78 @@
+//! The CRC-32 (as in zlib and PNG) of segment payloads.
+
+const POLYNOMIAL: u32 = 0xedb8_8320;
+
+/// the table of each byte of 8 at a time ("slicing-by-8")
+static TABLES: [[u32; 256]; 8] = make_tables();
+
+const fn make_tables() -> [[u32; 256]; 8] {
+	let mut tables = [[0u32; 256]; 8];
+	let mut i = 0;
+	while i < 256 {
+		let mut crc = i as u32;
+		let mut bit = 0;
+		while bit < 8 {
+			crc = if crc & 1 != 0 {
+				(crc >> 1) ^ POLYNOMIAL
+			} else {
+				crc >> 1
+			};
+			bit += 1;
+		}
+		tables[0][i] = crc;
+		i += 1;
+	}
+	let mut i = 0;
+	while i < 256 {
+		let mut t = 1;
+		while t < 8 {
+			let prev = tables[t - 1][i];
+			tables[t][i] = (prev >> 8) ^ tables[0][(prev & 0xff) as usize];
+			t += 1;
+		}
+		i += 1;
+	}
+	tables
+}
+
+/// A CRC-32 of data that comes in pieces
+pub(crate) struct Crc32(u32);
+
+impl Crc32 {
+	pub(crate) fn new() -> Crc32 {
+		Crc32(!0)
+	}
+
+	pub(crate) fn update(&mut self, data: &[u8]) {
+		let t = &TABLES;
+		let mut crc = self.0;
+		let mut chunks = data.chunks_exact(8);
+		for c in &mut chunks {
+			let lo = u32::from_le_bytes([c[0], c[1], c[2], c[3]]) ^ crc;
+			let hi = u32::from_le_bytes([c[4], c[5], c[6], c[7]]);
+			crc = t[7][(lo & 0xff) as usize]
+				^ t[6][((lo >> 8) & 0xff) as usize]
+				^ t[5][((lo >> 16) & 0xff) as usize]
+				^ t[4][(lo >> 24) as usize]
+				^ t[3][(hi & 0xff) as usize]
+				^ t[2][((hi >> 8) & 0xff) as usize]
+				^ t[1][((hi >> 16) & 0xff) as usize]
+				^ t[0][(hi >> 24) as usize];
+		}
+		for &b in chunks.remainder() {
+			crc = (crc >> 8) ^ t[0][((crc ^ b as u32) & 0xff) as usize];
+		}
+		self.0 = crc;
+	}
+
+	pub(crate) fn finish(&self) -> u32 {
+		!self.0
+	}
+}
+
+/// The CRC-32 of `data`
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+	let mut crc = Crc32::new();
+	crc.update(data);
+	crc.finish()
+}
@@ -1,6 +1,7 @@
 //! Add data by means of a new transaction.
 
 use crate::write::Writer;
+use std::collections::BTreeMap;
 use std::io::{Seek, Write};
 use std::path::{Path, PathBuf};
 
@@ -11,11 +12,126 @@ use std::path::{Path, PathBuf};
 ///
 /// After adding records, call [`CreateTx::commit`] which ensures
 /// the transaction is on disk. Not calling commit will
-/// rollback the transaction.
+/// rollback the transaction (except for what was made visible
+/// by [`CreateTx::checkpoint`]).
 pub struct CreateTx {
-	writer: Writer<std::fs::File>,
-	tmp: tempfile_fast::PersistableTempFile,
+	writer: Writer<TxFile>,
+	/// the unnamed file, until it's committed or checkpointed
+	tmp: Option<tempfile_fast::PersistableTempFile>,
+	/// after the first checkpoint, the transaction that's being appended to
+	appending: Option<Appending>,
 	dir: PathBuf,
+	metadata: BTreeMap<String, String>,
+	/// the database as of the first call to `add_record_if_latest`, not as of `new`
+	snapshot: Option<crate::DatabaseReader>,
+	/// the record from `upsert_record`, which may still be replaced
+	pending: Option<PendingUpsert>,
+}
+
+/// How [`CreateTx::commit_with`] makes a transaction durable
+///
+/// A committed transaction is visible to new readers as soon as it's
+/// renamed into place, whether or not it was synced. Syncing only decides
+/// whether it survives a crash or power failure of the whole system:
+///
+/// * With both options (the default), the transaction is on disk when
+///   the commit returns.
+/// * Without `sync_dir`, the contents are on disk, but the transaction may
+///   be missing after a crash, as if it had never been committed.
+/// * Without `sync_file`, the transaction may be missing, or be truncated
+///   after a crash. Readers skip such a file with a warning,
+///   see [`DatabaseReader::corrupt_transaction_files`](crate::DatabaseReader::corrupt_transaction_files),
+///   so it should be deleted and its data written again, such as from
+///   a log that's kept elsewhere.
+///
+/// Turning these off makes committing many small transactions faster.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommitOptions {
+	/// Call fsync on the transaction file before renaming it (the default is true)
+	pub sync_file: bool,
+	/// Call fsync on the database directory after renaming the
+	/// transaction file into it (the default is true)
+	pub sync_dir: bool,
+}
+
+impl Default for CommitOptions {
+	fn default() -> Self {
+		CommitOptions {
+			sync_file: true,
+			sync_dir: true,
+		}
+	}
+}
+
+/// A transaction that's visible, but still being added to
+struct Appending {
+	path: PathBuf,
+	/// a shared lock of `.compact`, so that compactions wait for this
+	/// transaction to be committed instead of replacing its file
+	_compact_lock: std::fs::File,
+}
+
+/// The file of a transaction, as written by its [`Writer`]
+///
+/// After the first [`CreateTx::checkpoint`], the file is visible, so the
+/// segments are kept in `staged` until the next one, when they're appended.
+pub(crate) struct TxFile {
+	file: std::fs::File,
+	staged: Option<Vec<u8>>,
+}
+
+impl TxFile {
+	fn new(file: std::fs::File) -> TxFile {
+		TxFile { file, staged: None }
+	}
+
+	/// Append the staged segments to the file
+	///
+	/// If that fails, the file is truncated to the segments that
+	/// were there before, and the staged ones are appended next time.
+	fn append_staged(&mut self, sync: bool) -> std::io::Result<()> {
+		let Some(staged) = self.staged.as_mut() else {
+			return Ok(());
+		};
+		if !staged.is_empty() {
+			let len = self.file.stream_position()?;
+			if let Err(e) = self.file.write_all(staged) {
+				let _ = self.file.set_len(len);
+				let _ = self.file.seek(std::io::SeekFrom::Start(len));
+				return Err(e);
+			}
+			staged.clear();
+		}
+		if sync {
+			self.file.sync_all()?;
+		}
+		Ok(())
+	}
+}
+
+impl Write for TxFile {
+	fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+		match self.staged.as_mut() {
+			Some(staged) => staged.write(buf),
+			None => self.file.write(buf),
+		}
+	}
+
+	fn flush(&mut self) -> std::io::Result<()> {
+		self.file.flush()
+	}
+}
+
+/// The byte after the last key of a delete marker whose filter is a regex
+pub(crate) const DELETE_FILTER_REGEX: u8 = 1;
+/// The byte after the last key of a delete marker whose filter is a list of keys
+pub(crate) const DELETE_FILTER_KEYS: u8 = 2;
+
+struct PendingUpsert {
+	key: String,
+	timestamp: crate::Timestamp,
+	format: String,
+	data: Vec<u8>,
 }
 
 impl CreateTx {
@@ -30,16 +146,154 @@ impl CreateTx {
 		let tmp = tempfile_fast::PersistableTempFile::new_in(dir)?;
 		let f = tmp.try_clone()?;
 
-		let writer = Writer::new(f);
+		Self::with_writer(dir, tmp, Writer::new(TxFile::new(f)))
+	}
+
+	/// Open a transaction whose segments are compressed with `compression`
+	///
+	/// [`new`](Self::new) compresses them with [`Compression::Lz4`](crate::Compression::Lz4).
+	pub fn new_with_compression(
+		dir: &Path,
+		compression: crate::Compression,
+	) -> std::io::Result<CreateTx> {
+		Self::new_with_compression_level(dir, compression, crate::Compression::DEFAULT_LEVEL)
+	}
+
+	/// Open a transaction whose segments are compressed with `compression` at `level`
+	///
+	/// Fails if `level` isn't in [`Compression::levels`](crate::Compression::levels).
+	pub fn new_with_compression_level(
+		dir: &Path,
+		compression: crate::Compression,
+		level: i32,
+	) -> std::io::Result<CreateTx> {
+		let levels = compression.levels();
+		if !levels.contains(&level) {
+			return Err(std::io::Error::new(
+				std::io::ErrorKind::InvalidInput,
+				format!(
+					"{:?} compression level {} isn't from {} to {}",
+					compression,
+					level,
+					levels.start(),
+					levels.end()
+				),
+			));
+		}
+		let tmp = tempfile_fast::PersistableTempFile::new_in(dir)?;
+		let f = tmp.try_clone()?;
+
+		Self::with_writer(
+			dir,
+			tmp,
+			Writer::new_with_compression(TxFile::new(f), compression, level),
+		)
+	}
 
+	fn with_writer(
+		dir: &Path,
+		tmp: tempfile_fast::PersistableTempFile,
+		writer: Writer<TxFile>,
+	) -> std::io::Result<CreateTx> {
 		let tx = CreateTx {
 			writer,
-			tmp,
+			tmp: Some(tmp),
+			appending: None,
 			dir: dir.to_owned(),
+			metadata: BTreeMap::new(),
+			snapshot: None,
+			pending: None,
 		};
 		Ok(tx)
 	}
 
+	/// Open a transaction, unless the database already has more than `max_txes` transactions
+	///
+	/// Every transaction file is read when the database is read, so reading
+	/// gets slower as they accumulate until a compaction merges them into
+	/// `main`. This makes a loader stop when compactions fall behind:
+	/// if there are more than `max_txes` committed transaction files, it
+	/// returns [`WriteFailure::TooManyTransactions`](crate::WriteFailure::TooManyTransactions)
+	/// instead of opening a transaction, and the caller can wait and try again.
+	///
+	/// Counting the transactions only lists the directory; empty transaction
+	/// files aren't counted, because they aren't read. Transactions that are
+	/// concurrently being committed can exceed the limit.
+	pub fn new_with_limit(dir: &Path, max_txes: usize) -> Result<CreateTx, crate::WriteFailure> {
+		let mut count = 0;
+		for entry in std::fs::read_dir(dir)? {
+			let entry = entry?;
+			let is_tx = entry
+				.file_name()
+				.to_str()
+				.is_some_and(|s| s.starts_with("tx.") && !s.ends_with(".tmp"));
+			if is_tx && entry.metadata()?.len() != 0 {
+				count += 1;
+			}
+		}
+		if count > max_txes {
+			return Err(crate::WriteFailure::TooManyTransactions {
+				count,
+				limit: max_txes,
+			});
+		}
+		Ok(Self::new(dir)?)
+	}
+
+	/// Reject the records whose format isn't the one declared for their key
+	///
+	/// The declarations (see [`declared_formats`](crate::declared_formats)) are
+	/// read from the database when this is called; declarations in this
+	/// transaction aren't considered. Afterwards, adding a record of a key
+	/// that has a declared format, but with another format, fails with
+	/// [`WriteFailure::FormatMismatch`](crate::WriteFailure::FormatMismatch)
+	/// and nothing is added. Segments added with [`copy_from`](Self::copy_from)
+	/// aren't checked.
+	pub fn enforce_formats(&mut self) -> std::io::Result<()> {
+		let db = crate::DatabaseReader::new(&self.dir)?;
+		self.writer
+			.set_declared_formats(crate::declared_formats::declared(&db));
+		Ok(())
+	}
+
+	/// Tag this transaction with a piece of metadata
+	///
+	/// Metadata is a set of key-value pairs, like `("author", "ingest-v2")`,
+	/// that is stored in a header of the transaction file. It
+	/// doesn't affect the records, and can be read back with
+	/// [`DatabaseReader::tx_metadata`](crate::DatabaseReader::tx_metadata).
+	///
+	/// This must be called before any records are added. Setting the
+	/// same key again replaces its value. A transaction that has metadata,
+	/// but no records, is still committed.
+	pub fn set_meta(&mut self, key: &str, value: &str) -> std::io::Result<()> {
+		use std::io::{Error, ErrorKind};
+
+		if !self.writer.is_empty() || self.pending.is_some() {
+			return Err(Error::new(
+				ErrorKind::InvalidInput,
+				"metadata must be set before records are added",
+			));
+		}
+		// the invocation may also span a key, a length and a value
+		let mut metadata = self.metadata.clone();
+		metadata.insert(key.to_owned(), value.to_owned());
+		let encoded = crate::segment::encode_metadata(&metadata);
+		if crate::segment::find_segment_invocation(&encoded).is_some() {
+			return Err(Error::new(
+				ErrorKind::InvalidInput,
+				format!(
+					"metadata may not contain {:?}",
+					String::from_utf8_lossy(crate::segment::SEGMENT_INVOCATION)
+				),
+			));
+		}
+
+		self.metadata = metadata;
+		self.writer.set_preamble(encoded);
+		Ok(())
+	}
+
 	/// Add a record with the given key, timestamp, and values.
 	///
 	/// The values can be encoded with the function [`crate::record()`]
@@ -74,6 +328,7 @@ impl CreateTx {
 		timestamp: chrono::NaiveDateTime,
 		values: impl crate::RecordBuilder,
 	) -> std::result::Result<(), crate::WriteFailure> {
+		self.flush_pending()?;
 		self.writer.add_record(
 			key,
 			timestamp
@@ -84,6 +339,153 @@ impl CreateTx {
 		)
 	}
 
+	/// Add a record whose columns were built at runtime with a [`RecordVec`](crate::RecordVec)
+	///
+	/// This is the same as [`add_record`](Self::add_record), except that
+	/// it fails if `values` has no columns.
+	pub fn add_record_dynamic(
+		&mut self,
+		key: &str,
+		timestamp: chrono::NaiveDateTime,
+		values: &crate::RecordVec,
+	) -> std::result::Result<(), crate::WriteFailure> {
+		if values.is_empty() {
+			return Err(std::io::Error::new(
+				std::io::ErrorKind::InvalidInput,
+				format!("the record for key {:?} has no columns", key),
+			)
+			.into());
+		}
+		self.add_record(key, timestamp, values)
+	}
+
+	/// Add many records of the same key
+	///
+	/// This is like calling [`add_record`](Self::add_record) for
+	/// each of `records`, but faster, because the timestamps are
+	/// already in nanoseconds since the epoch and the buffers are
+	/// reused. The timestamps must be ascending (and after the key's
+	/// previous record), or else
+	/// [`WriteFailure::TimeOrderingViolation`](crate::WriteFailure::TimeOrderingViolation)
+	/// is returned. The records before the one that failed are still added.
+	/// ```no_run
+	/// # let mut transaction = sonnerie::CreateTx::new(std::path::Path::new("")).unwrap();
+	/// let readings: &[(u64, f64)] = &[(1_000_000_000, 20.5), (2_000_000_000, 21.0)];
+	/// transaction.add_record_many(
+	///    "temperature",
+	///    readings.iter().map(|&(t, v)| (t, sonnerie::record(v))),
+	///  ).unwrap();
+	/// ```
+	pub fn add_record_many<R: crate::RecordBuilder>(
+		&mut self,
+		key: &str,
+		records: impl IntoIterator<Item = (crate::Timestamp, R)>,
+	) -> std::result::Result<(), crate::WriteFailure> {
+		self.flush_pending()?;
+		self.writer.add_records(key, records)
+	}
+
+	/// Add a record, but only if the key's latest record is at `expected_latest`
+	///
+	/// This is for optimistic concurrency: read the latest record of a key,
+	/// then add a new one only if nobody else added one in the meantime.
+	/// `expected_latest` is `None` if the key is expected to have no records.
+	/// If the latest record is somewhere else, [`WriteFailure::StaleLatest`](crate::WriteFailure::StaleLatest)
+	/// is returned and nothing is added.
+	///
+	/// The check is made against a snapshot of the database that's opened
+	/// the first time this function is called in this transaction, not
+	/// when the transaction is created: it has the transactions that were
+	/// committed before that first call (including those committed since
+	/// [`new`](Self::new)), and none that are committed after it, even for
+	/// later calls. Records added to this transaction aren't considered either.
+	/// The guarantee is only that no record is silently clobbered relative to that
+	/// snapshot: two transactions that are in progress at the same time can both
+	/// pass the check, and both be committed.
+	///
+	/// The latest record is found by reading the key from the newest
+	/// record (see [`DatabaseReader::get_reverse`](crate::DatabaseReader::get_reverse)),
+	/// so the key's older records aren't read.
+	pub fn add_record_if_latest(
+		&mut self,
+		key: &str,
+		expected_latest: Option<chrono::NaiveDateTime>,
+		timestamp: chrono::NaiveDateTime,
+		values: impl crate::RecordBuilder,
+	) -> std::result::Result<(), crate::WriteFailure> {
+		if self.snapshot.is_none() {
+			self.snapshot = Some(crate::DatabaseReader::new(&self.dir)?);
+		}
+		let snapshot = self.snapshot.as_ref().unwrap();
+
+		let actual = snapshot.get_reverse(key).next().map(|r| r.time());
+		if actual != expected_latest {
+			return Err(crate::WriteFailure::StaleLatest {
+				key: key.to_owned(),
+				expected: expected_latest,
+				actual,
+			});
+		}
+
+		self.add_record(key, timestamp, values)
+	}
+
+	/// Add a record, replacing the one that was just added at the same key and timestamp
+	///
+	/// This is like [`add_record`](Self::add_record), except that calling it
+	/// again with the same key and timestamp replaces the value instead of
+	/// failing with [`WriteFailure::TimeOrderingViolation`](crate::WriteFailure::TimeOrderingViolation),
+	/// which makes retrying idempotent. Only the last value is written to the transaction.
+	///
+	/// Across transactions, the newest one wins: when a record at the same key and
+	/// timestamp is committed in a later transaction, readers only see the later one, and
+	/// compacting discards the earlier one. Which transaction is newer is decided by the
+	/// order in which they're committed (their file names), so the records don't need to be
+	/// marked as upserts.
+	pub fn upsert_record(
+		&mut self,
+		key: &str,
+		timestamp: chrono::NaiveDateTime,
+		values: impl crate::RecordBuilder,
+	) -> std::result::Result<(), crate::WriteFailure> {
+		let timestamp = timestamp
+			.and_utc()
+			.timestamp_nanos_opt()
+			.ok_or(crate::WriteFailure::UnableToParseTimestamp)? as crate::Timestamp;
+
+		let mut format = compact_str::CompactString::default();
+		values.format_str(&mut format);
+		let mut data = Vec::with_capacity(crate::TIMESTAMP_SIZE + values.size());
+		data.extend_from_slice(&timestamp.to_be_bytes());
+		values.store(&mut data);
+
+		if let Some(pending) = self.pending.as_mut() {
+			if pending.key == key && pending.timestamp == timestamp {
+				pending.format.replace_range(.., &format);
+				pending.data = data;
+				return Ok(());
+			}
+		}
+
+		self.flush_pending()?;
+		self.writer.check_order(key, timestamp)?;
+		self.pending = Some(PendingUpsert {
+			key: key.to_owned(),
+			timestamp,
+			format: format.to_string(),
+			data,
+		});
+		Ok(())
+	}
+
+	/// write the record from `upsert_record`, now that it can't be replaced anymore
+	fn flush_pending(&mut self) -> std::result::Result<(), crate::WriteFailure> {
+		if let Some(p) = self.pending.take() {
+			self.writer.add_record_raw(&p.key, &p.format, &p.data)?;
+		}
+		Ok(())
+	}
+
 	/// Add a record with the given key, format, and payload.
 	///
 	/// The data must match the format (otherwise you can corrupt
@@ -96,15 +498,86 @@ impl CreateTx {
 	///
 	/// This function is made available for tools that need more versaility
 	/// in how they process databases. It's generally preferable to use [`CreateTx::add_record()`]
+	///
+	/// Keys may contain any character, including tabs and newlines. The
+	/// text formats escape them (see [`print_record`](crate::formatted::print_record)).
 	pub fn add_record_raw(
 		&mut self,
 		key: &str,
 		format: &str,
 		data: &[u8],
 	) -> std::result::Result<(), crate::write::WriteFailure> {
+		self.flush_pending()?;
 		self.writer.add_record_raw(key, format, data)
 	}
 
+	/// Add a record that was read from a database
+	///
+	/// This is the same as calling [`CreateTx::add_record_raw`] with
+	/// the key, format and [`raw`](crate::Record::raw) data of `record`,
+	/// but it's faster because the data is already known to match the
+	/// format and is copied verbatim.
+	///
+	/// Each successive call to this function must have greater
+	/// or equal values for key and timestamp.
+	pub fn add_record_from(
+		&mut self,
+		record: &crate::Record,
+	) -> std::result::Result<(), crate::write::WriteFailure> {
+		self.flush_pending()?;
+		self.writer
+			.add_record_verbatim(record.key(), record.format(), record.raw())
+	}
+
+	/// Copy segments from another transaction file without decompressing them
+	///
+	/// This is much faster than copying each record, and is useful for
+	/// making a subset of a database. The segments must be from the
+	/// same file and in order (as from [`Reader::raw_segments`](crate::Reader::raw_segments),
+	/// possibly with some of them skipped). Their keys must come after those of the records that
+	/// were already added, and records added afterwards must have keys that
+	/// come after the last segment's last key.
+	///
+	/// Returns the number of segments that were copied.
+	pub fn copy_from<'a>(
+		&mut self,
+		segments: impl IntoIterator<Item = crate::RawSegment<'a>>,
+	) -> std::result::Result<usize, crate::write::WriteFailure> {
+		self.flush_pending()?;
+		let mut n = 0;
+		for s in segments {
+			let version = s.segment.segment_version;
+			if !(0x0100..=crate::SEGMENT_VERSION).contains(&version) {
+				return Err(std::io::Error::new(
+					std::io::ErrorKind::InvalidData,
+					format!(
+						"segment version {:#06x} can't be copied",
+						s.segment.segment_version
+					),
+				)
+				.into());
+			}
+			if let Some(checksum) = s.segment.checksum {
+				if checksum != crate::segment::payload_checksum(s.stored) {
+					return Err(std::io::Error::new(
+						std::io::ErrorKind::InvalidData,
+						"segment has the wrong checksum",
+					)
+					.into());
+				}
+			}
+			self.writer.add_raw_segment(
+				s.segment.first_key,
+				s.segment.last_key,
+				s.stored,
+				s.segment.payload.len(),
+				s.segment.compression,
+			)?;
+			n += 1;
+		}
+		Ok(n)
+	}
+
 	/// Delete a range of records
 	///
 	/// You can use an empty string to indicate "unbounded" and `u64::MIN` and `u64::MAX`
@@ -119,23 +592,110 @@ impl CreateTx {
 		after_time: u64,
 		before_time: u64,
 		filter: &str,
+	) -> std::result::Result<(), crate::write::WriteFailure> {
+		self.add_delete_marker(first_key, last_key, after_time, before_time, filter, None)
+	}
+
+	/// Delete a range of records of the keys that match a regular expression
+	///
+	/// This is the same as [`delete`](Self::delete), but `regex` is a
+	/// regular expression (as in the [`regex`](https://docs.rs/regex) crate)
+	/// instead of a `%`-wildcard. It matches a key if it matches any part of
+	/// it, so anchor it with `^` and `$` to match the whole key,
+	/// like `^sensor-\d+-temp$`. It fails if `regex` isn't valid.
+	///
+	/// Older versions of sonnerie don't recognize the regex,
+	/// and don't delete any records with this marker.
+	pub fn delete_regex(
+		&mut self,
+		first_key: &str,
+		last_key: &str,
+		after_time: u64,
+		before_time: u64,
+		regex: &str,
+	) -> std::result::Result<(), crate::write::WriteFailure> {
+		if let Err(e) = regex::Regex::new(regex) {
+			return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, e).into());
+		}
+		self.add_delete_marker(
+			first_key,
+			last_key,
+			after_time,
+			before_time,
+			regex,
+			Some(DELETE_FILTER_REGEX),
+		)
+	}
+
+	/// Delete a range of records of exactly these keys
+	///
+	/// This is the same as [`delete`](Self::delete) of every one of `keys`,
+	/// but in a single delete marker, which readers check with a lookup
+	/// instead of one comparison for each key. It fails if `keys` is empty
+	/// or if one of them has a newline.
+	///
+	/// Older versions of sonnerie read the list as a single `%`-wildcard,
+	/// so they only delete the records of a list of one key.
+	pub fn delete_keys(
+		&mut self,
+		keys: &[&str],
+		after_time: u64,
+		before_time: u64,
+	) -> std::result::Result<(), crate::write::WriteFailure> {
+		if keys.is_empty() {
+			return Err(
+				std::io::Error::new(std::io::ErrorKind::InvalidInput, "no keys to delete").into(),
+			);
+		}
+		if let Some(key) = keys.iter().find(|k| k.contains('\n')) {
+			return Err(std::io::Error::new(
+				std::io::ErrorKind::InvalidInput,
+				format!("can't delete a key with a newline: {:?}", key),
+			)
+			.into());
+		}
+		let mut keys = keys.to_vec();
+		keys.sort_unstable();
+		keys.dedup();
+		self.add_delete_marker(
+			keys[0],
+			"",
+			after_time,
+			before_time,
+			&keys.join("\n"),
+			Some(DELETE_FILTER_KEYS),
+		)
+	}
+
+	/// `kind` follows the last key, if the filter isn't a `%`-wildcard
+	pub(crate) fn add_delete_marker(
+		&mut self,
+		first_key: &str,
+		last_key: &str,
+		after_time: u64,
+		before_time: u64,
+		filter: &str,
+		kind: Option<u8>,
 	) -> std::result::Result<(), crate::write::WriteFailure> {
 		use core::ops::IndexMut as _;
 
 		use crate::row_format::{Element as _, ElementString};
 		use byteorder::{BigEndian, ByteOrder as _};
 
+		self.flush_pending()?;
+
 		// write row format
 		let key = first_key;
 		let format = "\u{007f}";
 
 		let mut row_data = Vec::with_capacity(
-			first_key.as_bytes().len()
-				+ filter.as_bytes().len()
-				+ last_key.as_bytes().len()
+			first_key.len()
+				+ filter.len()
+				+ last_key.len()
 				+ 16 // length of two u64's
 				+ 27 // practical maximum length of three varints
-				+ 1, // the format
+				+ 1 // the format
+				+ 1, // the kind of filter
 		);
 
 		// bypass RowFormat entirely, we're going to be building row_data here
@@ -160,26 +720,98 @@ impl CreateTx {
 			before_time,
 		);
 
-		// write key wildcard
-		ElementString
-			.to_stored_format(filter, &mut row_data)
-			.unwrap();
+		// write key wildcard; a regex or a list of keys is written
+		// as is, because unescaping would lose its backslashes
+		if kind.is_some() {
+			let mut buf = unsigned_varint::encode::u64_buffer();
+			row_data.extend_from_slice(unsigned_varint::encode::u64(filter.len() as u64, &mut buf));
+			row_data.extend_from_slice(filter.as_bytes());
+		} else {
+			ElementString
+				.to_stored_format(filter, &mut row_data)
+				.unwrap();
+		}
 
 		// write last key
 		ElementString
 			.to_stored_format(last_key, &mut row_data)
 			.unwrap();
 
+		// the filter is a LIKE pattern unless this says otherwise
+		if let Some(kind) = kind {
+			row_data.push(kind);
+		}
+
 		self.writer.add_record_raw(key, format, &row_data)
 	}
 
+	/// Delete the records of every key that are before `time` (in nanoseconds)
+	///
+	/// This is for retention policies, such as dropping everything older
+	/// than 90 days. It's the same as `delete("", "", 0, time, "%")`, which
+	/// readers recognize and apply by only comparing each record's timestamp
+	/// to `time`, so it doesn't slow down reading like other deletions
+	/// do until the next major compaction.
+	///
+	/// Like [`delete`](Self::delete), this function must be called as
+	/// the one and only action in a transaction and then committed.
+	pub fn expire_before(
+		&mut self,
+		time: u64,
+	) -> std::result::Result<(), crate::write::WriteFailure> {
+		self.delete("", "", 0, time, "%")
+	}
+
+	/// The number of records added so far
+	///
+	/// Records in segments from [`copy_from`](Self::copy_from) aren't counted.
+	pub fn record_count(&self) -> u64 {
+		self.writer.record_count() + self.pending.is_some() as u64
+	}
+
+	/// The size of the records added so far, before they're compressed
+	///
+	/// This includes each key's header in the file. Segments from
+	/// [`copy_from`](Self::copy_from) aren't counted.
+	pub fn uncompressed_bytes(&self) -> u64 {
+		self.writer.uncompressed_bytes() + self.pending.as_ref().map_or(0, |p| p.data.len() as u64)
+	}
+
+	/// The number of bytes written to the transaction file so far
+	///
+	/// Records are compressed a segment (about 1 MiB) at a time, so
+	/// the last of them are only written when the transaction is
+	/// committed. This waits for the segments that are being
+	/// compressed to be written.
+	pub fn compressed_bytes(&self) -> u64 {
+		self.writer.compressed_bytes()
+	}
+
 	/// Commit the transaction, but give it a specific name.
 	///
 	/// This function is necessary for compacting, normally
 	/// you would just call the basic [`CreateTx::commit`].
+	/// An existing file named `final_name` is replaced atomically.
+	///
+	/// As with `commit`, the data is on disk when this returns (the
+	/// file and then its directory are synced).
 	pub fn commit_to(self, final_name: &Path) -> std::io::Result<()> {
+		self.commit_to_with(final_name, CommitOptions::default())
+	}
+
+	fn commit_to_with(mut self, final_name: &Path, options: CommitOptions) -> std::io::Result<()> {
+		if let Some(appending) = &self.appending {
+			return Err(std::io::Error::new(
+				std::io::ErrorKind::InvalidInput,
+				format!(
+					"the transaction is already committed as {:?}",
+					appending.path
+				),
+			));
+		}
+		self.flush_pending().map_err(into_io_error)?;
 		let writer = self.writer;
-		let mut file = writer.finish()?;
+		let mut file = writer.finish()?.file;
 		file.flush()?;
 		let len = file.seek(std::io::SeekFrom::End(0))? as usize;
 		if len == 0 {
@@ -190,24 +822,109 @@ impl CreateTx {
 			}
 			return Ok(());
 		}
-		file.sync_all()?;
+		if options.sync_file {
+			file.sync_all()?;
+		}
 		drop(file);
 		self.tmp
+			.take()
+			.expect("the transaction has a file")
 			.persist_by_rename(final_name)
 			.map_err(|e| e.error)?;
-		if let Some(umask) = get_umask() {
-			use std::os::unix::fs::PermissionsExt;
-			let p = std::fs::Permissions::from_mode((0o444 & !umask) as _);
-			let _ = std::fs::set_permissions(final_name, p);
+		make_read_only(final_name);
+		if options.sync_dir {
+			sync_parent(final_name)?;
 		}
 		Ok(())
 	}
 
+	/// Make the records added so far visible, and keep adding to this transaction
+	///
+	/// The first checkpoint commits the transaction as a new file, like
+	/// [`commit`](Self::commit), and each one after that appends the
+	/// records that were added since the previous one to the same file. An
+	/// ingest process that commits often can therefore make fewer files.
+	/// The records must still be added in order, across checkpoints too,
+	/// but the last key may be continued after a checkpoint.
+	///
+	/// Readers that are opened before a checkpoint don't see its records,
+	/// and readers that are opened while one is written see some of its
+	/// segments, but never a segment that isn't entirely written. The
+	/// records since the last checkpoint are kept in memory until the next.
+	///
+	/// Compactions (and committing another transaction as `main`) wait
+	/// until this transaction is committed with [`commit`](Self::commit).
+	/// If it's dropped instead, the records up to the last checkpoint remain.
+	/// A checkpoint is synced as [`commit`](Self::commit) is.
+	pub fn checkpoint(&mut self) -> std::io::Result<()> {
+		self.checkpoint_with(CommitOptions::default())
+	}
+
+	/// Make the records added so far visible, choosing whether to wait for them to be on disk
+	///
+	/// See [`checkpoint`](Self::checkpoint) and [`CommitOptions`].
+	pub fn checkpoint_with(&mut self, options: CommitOptions) -> std::io::Result<()> {
+		self.flush_pending().map_err(into_io_error)?;
+		self.writer.flush_segments()?;
+
+		if self.appending.is_some() {
+			return self
+				.writer
+				.with_inner(|w| w.append_staged(options.sync_file));
+		}
+		if self.writer.is_empty() {
+			return Ok(());
+		}
+
+		let compact_lock = std::fs::File::create(self.dir.join(".compact"))?;
+		fs2::FileExt::lock_shared(&compact_lock)?;
+		let final_name = self.reserve_tx_name()?;
+		self.writer.with_inner(|w| {
+			// which tells readers that it's being appended to
+			fs2::FileExt::lock_exclusive(&w.file)?;
+			if options.sync_file {
+				w.file.sync_all()?;
+			}
+			Ok::<_, std::io::Error>(())
+		})?;
+		let tmp = self.tmp.take().expect("the transaction has a file");
+		if let Err(e) = tmp.persist_by_rename(&final_name) {
+			self.tmp = Some(e.file);
+			return Err(e.error);
+		}
+		// nothing is being written now, the worker threads are done
+		self.writer.with_inner(|w| w.staged = Some(vec![]));
+		if options.sync_dir {
+			sync_parent(&final_name)?;
+		}
+		self.appending = Some(Appending {
+			path: final_name,
+			_compact_lock: compact_lock,
+		});
+		Ok(())
+	}
+
 	/// Commit the transaction.
 	///
-	/// On successful completion, the data is on disk (fsync is called)
+	/// On successful completion, the data is on disk (the file
+	/// and then the database directory are synced)
 	/// and the filename is renamed to lose its ".tmp" suffix.
 	pub fn commit(self) -> std::io::Result<()> {
+		self.commit_with(CommitOptions::default())
+	}
+
+	/// Commit the transaction, choosing whether to wait for it to be on disk
+	///
+	/// See [`CommitOptions`] for what's safe after a crash without syncing.
+	pub fn commit_with(mut self, options: CommitOptions) -> std::io::Result<()> {
+		if let Some(appending) = self.appending.take() {
+			self.flush_pending().map_err(into_io_error)?;
+			let mut file = self.writer.finish()?;
+			file.append_staged(options.sync_file)?;
+			make_read_only(&appending.path);
+			return Ok(());
+		}
+
 		{
 			// maybe we can just replace `main`
 			let mainpath = self.dir.join("main");
@@ -221,18 +938,29 @@ impl CreateTx {
 				if maininfo.len() == 0 {
 					// now, with a lock, `main` is still 0 bytes, so
 					// we can safely replace it
-					return self.commit_to(&mainpath);
+					return self.commit_to_with(&mainpath, options);
 				}
 			}
 		}
 
+		let final_name = self.reserve_tx_name()?;
+		if let Err(e) = self.commit_to_with(&final_name, options) {
+			eprintln!("failure committing {:?}", final_name);
+			return Err(e);
+		}
+		Ok(())
+	}
+
+	/// Create an empty file with a new transaction name, which
+	/// is replaced when the transaction is committed
+	fn reserve_tx_name(&self) -> std::io::Result<PathBuf> {
 		for attempt in 0.. {
 			let timestamp: i64 = std::time::SystemTime::now()
 				.duration_since(std::time::SystemTime::UNIX_EPOCH)
 				.expect("duration_since epoch")
 				.as_nanos()
 				.try_into()
-				.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+				.map_err(std::io::Error::other)?;
 
 			let n = format!("tx.{:016x}", timestamp);
 			let final_name = self.dir.join(n);
@@ -242,14 +970,7 @@ impl CreateTx {
 				.create_new(true)
 				.open(&final_name);
 			match f {
-				Ok(_) => {
-					if let Err(e) = self.commit_to(&final_name) {
-						eprintln!("failure committing {:?}", final_name);
-						return Err(e);
-					} else {
-						return Ok(());
-					}
-				}
+				Ok(_) => return Ok(final_name),
 				Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
 					if attempt == 1000 {
 						return Err(e);
@@ -264,6 +985,31 @@ impl CreateTx {
 	}
 }
 
+fn into_io_error(e: crate::WriteFailure) -> std::io::Error {
+	match e {
+		crate::WriteFailure::IOError(e) => e,
+		e => std::io::Error::other(e),
+	}
+}
+
+/// Committed transactions are read-only
+fn make_read_only(path: &Path) {
+	if let Some(umask) = get_umask() {
+		use std::os::unix::fs::PermissionsExt;
+		let p = std::fs::Permissions::from_mode((0o444 & !umask) as _);
+		let _ = std::fs::set_permissions(path, p);
+	}
+}
+
+/// Sync the directory that `path` is in, so that its name is on disk
+fn sync_parent(path: &Path) -> std::io::Result<()> {
+	let dir = match path.parent() {
+		Some(p) if !p.as_os_str().is_empty() => p,
+		_ => Path::new("."),
+	};
+	std::fs::File::open(dir)?.sync_all()
+}
+
 fn get_umask() -> Option<libc::mode_t> {
 	let s = std::fs::read_to_string("/proc/self/status").ok()?;
 	for line in s.split('\n') {
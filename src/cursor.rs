@@ -0,0 +1,258 @@
+//! Read records a page at a time, continuing later from where a page ended.
+
+use crate::database_reader::DuplicateMode;
+use crate::wildcard::KeyMatcher;
+use crate::{DatabaseReader, DatabaseRecordIterator, DatabaseRecordReader, Record, Timestamp};
+use escape_string::{escape, split_one};
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::ops::Bound;
+use std::sync::Arc;
+
+impl<'d> DatabaseRecordReader<'d> {
+	/// Iterate over the records, remembering where the iteration is
+	///
+	/// After reading some records (a page), get a [`CursorToken`] with
+	/// [`Cursor::token`] and keep it, for example by sending it to a
+	/// client. [`DatabaseReader::resume`] continues from the token with
+	/// the next record.
+	///
+	/// ```no_run
+	/// # let db = sonnerie::DatabaseReader::new(std::path::Path::new("")).unwrap();
+	/// let mut cursor = db.get_range("a".."m").cursor();
+	/// let page: Vec<_> = cursor.by_ref().take(1000).collect();
+	/// let token = cursor.token().to_string();
+	///
+	/// // later, with another reader of the same database
+	/// let token = token.parse().unwrap();
+	/// let next_page: Vec<_> = db.resume(&token).into_iter().take(1000).collect();
+	/// ```
+	pub fn cursor(self) -> Cursor<'d> {
+		let token = CursorToken {
+			begin: crate::bound_deep_copy(self.range.begin.as_ref().map(|b| &b[..])),
+			after: None,
+			end: crate::bound_deep_copy(self.range.end.as_ref().map(|b| &b[..])),
+			prefix: self.prefix.to_owned(),
+			matcher: self.matcher.as_ref().map(|m| m.as_str().to_owned()),
+			keys: self.keys.as_ref().map(|k| k.iter().cloned().collect()),
+		};
+		Cursor {
+			records: self.into_iter(),
+			token,
+		}
+	}
+}
+
+/// Iterates over records like [`DatabaseRecordIterator`], see [`DatabaseRecordReader::cursor`]
+pub struct Cursor<'d> {
+	records: DatabaseRecordIterator<'d>,
+	token: CursorToken,
+}
+
+impl Cursor<'_> {
+	/// Where this cursor is, to continue after the records it has yielded so far
+	pub fn token(&self) -> CursorToken {
+		let mut token = self.token.clone();
+		if let (Bound::Included(key), Some(keys)) = (&token.begin, &mut token.keys) {
+			keys.retain(|k| k >= key);
+		}
+		token
+	}
+}
+
+impl Iterator for Cursor<'_> {
+	type Item = Record;
+
+	fn next(&mut self) -> Option<Record> {
+		let record = self.records.next()?;
+		match &mut self.token.begin {
+			Bound::Included(key) if key == record.key() => {}
+			begin => *begin = Bound::Included(record.key().to_owned()),
+		}
+		self.token.after = Some(record.timestamp_nanos());
+		Some(record)
+	}
+}
+
+/// The position of a [`Cursor`], with what it was reading
+///
+/// The position is the key and timestamp of the last record that was
+/// read, not a place in the files, so a token can be resumed with any
+/// reader of the database, even after a compaction; records that were
+/// added since then after that position are read too. Resuming seeks
+/// to the first segment of that key and skips its records up to that
+/// timestamp without making records of them or applying any filter, but
+/// its segments up to there are still decompressed.
+///
+/// The token has the range of keys, the wildcard or keys that were
+/// read, but not the other options of the [`DatabaseRecordReader`], such
+/// as [`filter_values`](DatabaseRecordReader::filter_values) or
+/// [`as_of`](DatabaseRecordReader::as_of); apply them again to the reader
+/// that [`DatabaseReader::resume`] returns. The keys excluded by
+/// [`get_filter_except`](DatabaseReader::get_filter_except) are not kept either.
+///
+/// A token is converted to a string with `to_string` and back with `parse`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CursorToken {
+	begin: Bound<String>,
+	/// the records of the key in `begin` that are at or before this were read
+	after: Option<Timestamp>,
+	end: Bound<String>,
+	prefix: String,
+	matcher: Option<String>,
+	keys: Option<Vec<String>>,
+}
+
+impl DatabaseReader {
+	/// Continue reading after the last record of a [`Cursor`], see [`DatabaseRecordReader::cursor`]
+	///
+	/// With [`DuplicateMode::All`], the other versions of the last
+	/// record (with the same key and timestamp) are not read again.
+	pub fn resume<'d>(&'d self, token: &'d CursorToken) -> DatabaseRecordReader<'d> {
+		DatabaseRecordReader {
+			db: self,
+			matcher: token.matcher.as_ref().map(|m| {
+				KeyMatcher::from_regex(
+					regex::Regex::new(m).expect("the regex of the token was checked"),
+				)
+			}),
+			prefix: &token.prefix,
+			range: crate::CowStringRange {
+				begin: token.begin.as_ref().map(|b| Cow::Borrowed(&b[..])),
+				end: token.end.as_ref().map(|b| Cow::Borrowed(&b[..])),
+			},
+			keys: token
+				.keys
+				.as_ref()
+				.map(|k| Arc::new(k.iter().cloned().collect::<BTreeSet<_>>())),
+			value_filters: vec![],
+			duplicates: DuplicateMode::Newest,
+			memory_budget: None,
+			read_ahead: crate::ReadAhead::Auto,
+			decompress_ahead: 0,
+			as_of: None,
+			exclude: None,
+			combine: None,
+			latest: false,
+			resume_after: token.after,
+		}
+	}
+}
+
+/// Each field is a tag character followed by its escaped contents,
+/// so that no field is empty
+impl std::fmt::Display for CursorToken {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		fn bound(f: &mut std::fmt::Formatter<'_>, b: &Bound<String>) -> std::fmt::Result {
+			match b {
+				Bound::Included(k) => write!(f, " i{}", escape(k)),
+				Bound::Excluded(k) => write!(f, " e{}", escape(k)),
+				Bound::Unbounded => write!(f, " u"),
+			}
+		}
+
+		write!(f, "1")?;
+		bound(f, &self.begin)?;
+		match self.after {
+			Some(after) => write!(f, " t{}", after)?,
+			None => write!(f, " -")?,
+		}
+		bound(f, &self.end)?;
+		write!(f, " p{}", escape(&self.prefix))?;
+		match &self.matcher {
+			Some(m) => write!(f, " r{}", escape(m))?,
+			None => write!(f, " -")?,
+		}
+		match &self.keys {
+			Some(keys) => {
+				write!(f, " k")?;
+				for key in keys {
+					write!(f, " ={}", escape(key))?;
+				}
+			}
+			None => write!(f, " -")?,
+		}
+		Ok(())
+	}
+}
+
+impl std::str::FromStr for CursorToken {
+	type Err = std::io::Error;
+
+	fn from_str(s: &str) -> std::io::Result<CursorToken> {
+		let invalid =
+			|| std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid cursor token");
+
+		let mut fields = vec![];
+		let mut tail = s;
+		while let Some((field, rest)) = split_one(tail).filter(|(f, _)| !f.is_empty()) {
+			fields.push(field);
+			tail = rest;
+		}
+		let mut fields = fields.into_iter();
+		let mut next = || fields.next().ok_or_else(invalid);
+		let bound = |field: &str| match split_tag(field) {
+			("i", k) => Ok(Bound::Included(k.to_owned())),
+			("e", k) => Ok(Bound::Excluded(k.to_owned())),
+			("u", "") => Ok(Bound::Unbounded),
+			_ => Err(invalid()),
+		};
+
+		if next()? != "1" {
+			return Err(invalid());
+		}
+		let begin = bound(&next()?)?;
+		let after = match split_tag(&next()?) {
+			("t", t) => Some(t.parse().map_err(|_| invalid())?),
+			("-", "") => None,
+			_ => return Err(invalid()),
+		};
+		let end = bound(&next()?)?;
+		let prefix = match split_tag(&next()?) {
+			("p", p) => p.to_owned(),
+			_ => return Err(invalid()),
+		};
+		let matcher = match split_tag(&next()?) {
+			("r", m) => {
+				regex::Regex::new(m).map_err(|_| invalid())?;
+				Some(m.to_owned())
+			}
+			("-", "") => None,
+			_ => return Err(invalid()),
+		};
+		let keys = match &*next()? {
+			"k" => {
+				let mut keys = vec![];
+				for key in fields.by_ref() {
+					match split_tag(&key) {
+						("=", k) => keys.push(k.to_owned()),
+						_ => return Err(invalid()),
+					}
+				}
+				Some(keys)
+			}
+			"-" => None,
+			_ => return Err(invalid()),
+		};
+		if fields.next().is_some() {
+			return Err(invalid());
+		}
+		if after.is_some() && !matches!(begin, Bound::Included(_)) {
+			return Err(invalid());
+		}
+
+		Ok(CursorToken {
+			begin,
+			after,
+			end,
+			prefix,
+			matcher,
+			keys,
+		})
+	}
+}
+
+/// split the first character of `field` from the rest
+fn split_tag(field: &str) -> (&str, &str) {
+	field.split_at(field.chars().next().map_or(0, char::len_utf8))
+}
@@ -1,5 +1,6 @@
 //! Read a database.
 
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs::File;
 use std::io::Seek;
 use std::path::{Path, PathBuf};
@@ -7,9 +8,11 @@ use std::path::{Path, PathBuf};
 use crate::key_reader::*;
 use crate::merge::Merge;
 use crate::segment_reader::DeleteMarker;
+use crate::wildcard::{Exclusions, KeyMatcher};
 use crate::Record;
 use crate::Wildcard;
 use std::ops::Bound;
+use std::sync::Arc;
 
 use chrono::NaiveDateTime;
 use either::Either;
@@ -23,15 +26,52 @@ use crate::bykey::DatabaseKeyReader;
 /// very large number of transaction files.
 const MAX_FILES_TO_COMPACT: usize = 1000;
 
+/// How [`DatabaseReader::new_opts`] treats the `main` file
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum MainFile {
+	/// don't open it
+	Skip,
+	/// open it if it exists
+	Optional,
+	/// it must exist
+	Required,
+}
+
+/// Open a transaction file, unless `previous` already has
+/// it open, which is when it has the same path and is the same file
+fn open_file(
+	previous: Option<&DatabaseReader>,
+	path: &Path,
+	file: File,
+) -> std::io::Result<Either<Arc<Reader>, DeleteMarker>> {
+	if let Some(previous) = previous {
+		let identity = crate::segment_cache::FileIdentity::of(&file)?;
+		if let Some((_, _, reader)) = previous
+			.txes
+			.iter()
+			.find(|(_, p, r)| p == path && r.segments.identity == identity)
+		{
+			return Ok(Either::Left(reader.clone()));
+		}
+	}
+	Ok(Reader::new(file)?.map_left(Arc::new))
+}
+
 /// Read a database in key-timestamp sorted format.
 ///
 /// Open a database with [`new`](#method.new) and then [`get`](#method.get),
 /// [`get_filter`](#method.get_filter) or [`get_range`](#method.get_range) to select which keys to read.
 pub struct DatabaseReader {
 	_dir: PathBuf,
-	pub(crate) txes: Vec<(usize, PathBuf, Reader)>,
+	/// how the directory was opened, `None` if this isn't a directory
+	main_file: Option<MainFile>,
+	/// shared with the readers made by [`reopen`](Self::reopen)
+	pub(crate) txes: Vec<(usize, PathBuf, Arc<Reader>)>,
 	pub(crate) filter_out: Vec<(usize, PathBuf, DeleteMarker)>,
 	empty_files: Vec<PathBuf>,
+	corrupt_files: Vec<PathBuf>,
+	pub(crate) segment_cache: Option<Arc<crate::SegmentCache>>,
+	on_checksum_mismatch: ChecksumMismatch,
 }
 
 impl DatabaseReader {
@@ -40,24 +80,192 @@ impl DatabaseReader {
 	/// All of the committed transactions are opened.
 	///
 	/// Any transactions that appear after `new` is called
-	/// are not opened (create a new `DatabaseReader`).
+	/// are not opened (create a new `DatabaseReader`, or
+	/// [`reopen`](Self::reopen) this one).
+	///
+	/// A missing `main` file is the same as an empty one, so a
+	/// directory of only transaction files can be read. Use
+	/// [`new_strict`](Self::new_strict) to require `main`.
 	pub fn new(dir: &Path) -> std::io::Result<DatabaseReader> {
-		Self::new_opts(dir, true)
+		Self::new_opts(dir, MainFile::Optional, None)
+	}
+
+	/// Open a database at the given path, failing if it has no `main` file
+	///
+	/// This is like [`new`](Self::new), except that it catches a `dir` that
+	/// isn't a database (`main` may still be empty).
+	pub fn new_strict(dir: &Path) -> std::io::Result<DatabaseReader> {
+		Self::new_opts(dir, MainFile::Required, None)
 	}
 
 	/// Open a database at the given path, but not the `main` file.
 	///
 	/// This is only useful for doing a minor compaction.
 	pub fn without_main_db(dir: &Path) -> std::io::Result<DatabaseReader> {
-		Self::new_opts(dir, false)
+		Self::new_opts(dir, MainFile::Skip, None)
+	}
+
+	/// Open a database from transaction files that aren't on the filesystem
+	///
+	/// This is useful when the files are fetched from object storage: `main`
+	/// is the contents of the `main` file, if there is one, and `txes`
+	/// are the contents of the transaction files, each with an id. When two
+	/// transactions have a record with the same key and timestamp, the one
+	/// with the greater id wins, so the ids should be in the same order as the
+	/// transaction files' names. Ids must be nonzero and unique (0 is `main`'s).
+	///
+	/// Every file is read into memory. Since there are no real paths,
+	/// [`transaction_paths`](Self::transaction_paths) and similar functions
+	/// return the names `main` and `tx.` followed by the id as 16 hexadecimal digits.
+	pub fn from_readers<R: std::io::Read>(
+		main: Option<R>,
+		txes: Vec<(usize, R)>,
+	) -> std::io::Result<DatabaseReader> {
+		use Either::*;
+
+		let invalid = |e: String| std::io::Error::new(std::io::ErrorKind::InvalidInput, e);
+
+		let mut files = Vec::with_capacity(txes.len() + 1);
+		if let Some(main) = main {
+			files.push((0, PathBuf::from("main"), main));
+		}
+		for (txid, r) in txes {
+			if txid == 0 {
+				return Err(invalid("transaction id 0 is reserved for main".to_string()));
+			}
+			files.push((txid, PathBuf::from(format!("tx.{:016x}", txid)), r));
+		}
+		files.sort_by_key(|(txid, _, _)| *txid);
+		if let Some(w) = files.windows(2).find(|w| w[0].0 == w[1].0) {
+			return Err(invalid(format!("duplicate transaction id {}", w[0].0)));
+		}
+
+		let mut txes = Vec::with_capacity(files.len());
+		let mut filter_out = vec![];
+		let mut empty_files = vec![];
+		for (txid, path, mut r) in files {
+			let mut bytes = vec![];
+			r.read_to_end(&mut bytes)?;
+			if bytes.is_empty() {
+				empty_files.push(path);
+				continue;
+			}
+			match Reader::from_bytes(bytes)? {
+				Left(r) => txes.push((txid, path, Arc::new(r))),
+				Right(_) if txid == 0 => {
+					return Err(invalid("main cannot be a delete marker".to_string()))
+				}
+				Right(d) => filter_out.push((txid, path, d)),
+			}
+		}
+
+		Ok(DatabaseReader {
+			txes,
+			filter_out,
+			_dir: PathBuf::new(),
+			main_file: None,
+			empty_files,
+			corrupt_files: vec![],
+			segment_cache: None,
+			on_checksum_mismatch: ChecksumMismatch::default(),
+		})
+	}
+
+	/// Open a database that was packed into a single file
+	///
+	/// See [`crate::pack`]. The file is mapped into memory, like the
+	/// files of a database directory. Its sections are given the same
+	/// names as with [`from_readers`](Self::from_readers).
+	pub fn open_packed(path: &Path) -> std::io::Result<DatabaseReader> {
+		use Either::*;
+
+		let file = File::open(path)?;
+		let map = Arc::new(unsafe { memmap::Mmap::map(&file)? });
+		let sections = crate::pack::read_footer(&map)?;
+
+		let mut txes = vec![];
+		let mut filter_out = vec![];
+		let mut empty_files = vec![];
+		for (txid, range) in sections.into_iter().enumerate() {
+			let path = if txid == 0 {
+				PathBuf::from("main")
+			} else {
+				PathBuf::from(format!("tx.{:016x}", txid))
+			};
+			if range.is_empty() {
+				empty_files.push(path);
+				continue;
+			}
+			match crate::segment_reader::SegmentReader::from_section(map.clone(), range)? {
+				Left(segments) => {
+					txes.push((txid, path, Arc::new(Reader::from_segments(segments))))
+				}
+				Right(_) if txid == 0 => {
+					return Err(std::io::Error::new(
+						std::io::ErrorKind::InvalidData,
+						"main cannot be a delete marker",
+					))
+				}
+				Right(d) => filter_out.push((txid, path, d)),
+			}
+		}
+
+		Ok(DatabaseReader {
+			txes,
+			filter_out,
+			_dir: path.to_owned(),
+			main_file: None,
+			empty_files,
+			corrupt_files: vec![],
+			segment_cache: None,
+			on_checksum_mismatch: ChecksumMismatch::default(),
+		})
+	}
+
+	/// Open the database again, with the transactions that were committed since
+	///
+	/// The transaction files that this reader already has open are shared
+	/// with the new one instead of being opened again, so this is cheaper
+	/// than [`new`](Self::new) for a database that's read repeatedly. The
+	/// files that have been replaced, such as `main` by a compaction, are
+	/// opened again. This reader is unchanged, so the iterators that are reading
+	/// from it still see the database as it was. The segment cache and
+	/// [`on_checksum_mismatch`](Self::on_checksum_mismatch) are kept.
+	///
+	/// A database from [`from_readers`](Self::from_readers) or
+	/// [`open_packed`](Self::open_packed) can't change, so it's reopened
+	/// with the same transactions.
+	pub fn reopen(&self) -> std::io::Result<DatabaseReader> {
+		let reopened = match self.main_file {
+			Some(main_file) => Self::new_opts(&self._dir, main_file, Some(self))?,
+			None => DatabaseReader {
+				_dir: self._dir.clone(),
+				main_file: None,
+				txes: self.txes.clone(),
+				filter_out: self.filter_out.clone(),
+				empty_files: self.empty_files.clone(),
+				corrupt_files: self.corrupt_files.clone(),
+				segment_cache: None,
+				on_checksum_mismatch: self.on_checksum_mismatch,
+			},
+		};
+		Ok(DatabaseReader {
+			segment_cache: self.segment_cache.clone(),
+			..reopened
+		}
+		.on_checksum_mismatch(self.on_checksum_mismatch))
 	}
 
 	/// Open a database at the given path.
 	///
-	/// The `include_main_db` option, if set to false indicates that
-	/// the main database should not be opened. This is useful for
-	/// minor compaction.
-	fn new_opts(dir: &Path, include_main_db: bool) -> std::io::Result<DatabaseReader> {
+	/// The `main_file` option says whether the main database is opened
+	/// (not opening it is useful for minor compaction), and whether
+	/// it may be missing. The files that `previous` has open are reused.
+	fn new_opts(
+		dir: &Path,
+		main_file: MainFile,
+		previous: Option<&DatabaseReader>,
+	) -> std::io::Result<DatabaseReader> {
 		use Either::*;
 		'compaction_in_progress: loop {
 			let mut paths = vec![];
@@ -75,32 +283,47 @@ impl DatabaseReader {
 			}
 
 			paths.sort();
-			let mut txes: Vec<(usize, PathBuf, Reader)> = Vec::with_capacity(paths.len());
+			let mut txes: Vec<(usize, PathBuf, Arc<Reader>)> = Vec::with_capacity(paths.len());
 
-			if include_main_db {
+			if main_file != MainFile::Skip {
 				let main_db_name = dir.join("main");
-				let mut f = File::open(&main_db_name)?; // No need to jump to compaction_in_progress
-				let len = f.seek(std::io::SeekFrom::End(0))? as usize;
-				if len == 0 {
-					empty_files.push(main_db_name);
-				} else {
-					match Reader::new(f)? {
-						Left(main_db) => txes.push((0, main_db_name, main_db)),
-						// the main database cannot be a delete marker
-						Right(_) => unreachable!(),
+				// No need to jump to compaction_in_progress
+				match File::open(&main_db_name) {
+					Err(e)
+						if e.kind() == std::io::ErrorKind::NotFound
+							&& main_file == MainFile::Optional => {}
+					f => {
+						let mut f = f?;
+						let len = f.seek(std::io::SeekFrom::End(0))? as usize;
+						if len == 0 {
+							empty_files.push(main_db_name);
+						} else {
+							// without main, a compaction would lose its records
+							let invalid = |e: &dyn std::fmt::Display| {
+								std::io::Error::new(
+									std::io::ErrorKind::InvalidData,
+									format!("{:?}: {}", main_db_name, e),
+								)
+							};
+							match open_file(previous, &main_db_name, f).map_err(|e| invalid(&e))? {
+								Left(main_db) => txes.push((0, main_db_name, main_db)),
+								Right(_) => return Err(invalid(&"main cannot be a delete marker")),
+							}
+						}
 					}
 				}
 			}
 
 			let mut filter_out = vec![];
+			let mut corrupt_files = vec![];
 
 			let iter = paths
 				.into_iter()
 				.enumerate()
 				// we add 1 because we'd reserve the 0 for the main database,
-				// regardless of whether `include_main_db` or not
+				// regardless of whether it's opened or not
 				.map(|(txid, p)| (txid + 1, p));
-			for (txid, p) in iter.take(if include_main_db {
+			for (txid, p) in iter.take(if main_file != MainFile::Skip {
 				usize::MAX
 			} else {
 				MAX_FILES_TO_COMPACT
@@ -113,7 +336,14 @@ impl DatabaseReader {
 					empty_files.push(p);
 					continue;
 				}
-				let r = Reader::new(f)?;
+				let r = match open_file(previous, &p, f) {
+					Ok(r) => r,
+					Err(e) => {
+						eprintln!("warning: disregarding {:?}, it is corrupt: {}", p, e);
+						corrupt_files.push(p);
+						continue;
+					}
+				};
 
 				// match the reader if it is indeed a reader or a delete marker
 				match r {
@@ -126,11 +356,52 @@ impl DatabaseReader {
 				txes,
 				filter_out,
 				_dir: dir.to_owned(),
+				main_file: Some(main_file),
 				empty_files,
+				corrupt_files,
+				segment_cache: None,
+				on_checksum_mismatch: ChecksumMismatch::default(),
 			});
 		}
 	}
 
+	/// Keep the segments that are decompressed while reading in `cache`
+	///
+	/// Reading the same segments again, with this or another `DatabaseReader`
+	/// that has the same cache, then doesn't decompress them again. The cache
+	/// can be shared between threads and is used by the parallel iterators
+	/// too. Records that are read with a
+	/// [`memory_budget`](DatabaseRecordReader::memory_budget) don't use the cache.
+	///
+	/// ```no_run
+	/// # let dir = std::path::Path::new("");
+	/// let cache = std::sync::Arc::new(sonnerie::SegmentCache::new(256 << 20));
+	/// // each time the database is reopened
+	/// let db = sonnerie::DatabaseReader::new(dir)
+	///     .unwrap()
+	///     .with_segment_cache(cache.clone());
+	/// ```
+	pub fn with_segment_cache(mut self, cache: Arc<crate::SegmentCache>) -> Self {
+		self.segment_cache = Some(cache);
+		self
+	}
+
+	/// Set what to do with segments whose checksum is wrong
+	///
+	/// The default, [`ChecksumMismatch::Error`], panics while reading
+	/// such a segment. With [`ChecksumMismatch::Skip`], a warning is printed
+	/// and the segment's records are left out, so a server can keep
+	/// answering the rest of the database.
+	pub fn on_checksum_mismatch(mut self, on_mismatch: ChecksumMismatch) -> Self {
+		self.on_checksum_mismatch = on_mismatch;
+		for (_, _, reader) in &mut self.txes {
+			if reader.on_checksum_mismatch != on_mismatch {
+				*reader = Arc::new(reader.with_checksum_mismatch(on_mismatch));
+			}
+		}
+		self
+	}
+
 	/// Number of tx files found in this iteration
 	/// This reduces the likelihood of file ulimit errors
 	///
@@ -161,6 +432,82 @@ impl DatabaseReader {
 		self.filter_out.iter().map(|(_, path, _)| &**path)
 	}
 
+	/// Get the delete markers, with the paths of their transactions
+	///
+	/// These are the deletions that apply to reads, in the order of their
+	/// transactions, until a major compaction removes their records
+	/// and the markers. See [`CreateTx::delete`](crate::CreateTx::delete).
+	pub fn delete_markers(&self) -> impl Iterator<Item = (&Path, &DeleteMarker)> {
+		self.filter_out
+			.iter()
+			.map(|(_, path, marker)| (&**path, marker))
+	}
+
+	/// Get the id of one of the transaction files
+	///
+	/// `path` is one of the files from [`transaction_paths`](Self::transaction_paths)
+	/// or [`delete_txes_paths`](Self::delete_txes_paths). Ids increase
+	/// with each transaction, `main`'s is 0. Returns `None` if the file
+	/// is not part of this database.
+	///
+	/// This is used with [`DatabaseRecordReader::as_of`].
+	pub fn txid(&self, path: &Path) -> Option<usize> {
+		self.txes
+			.iter()
+			.map(|(txid, p, _)| (txid, p))
+			.chain(self.filter_out.iter().map(|(txid, p, _)| (txid, p)))
+			.find(|(_, p)| *p == path)
+			.map(|(txid, _)| *txid)
+	}
+
+	/// Get the metadata of one of the transaction files
+	///
+	/// `path` is one of the files from [`transaction_paths`](Self::transaction_paths)
+	/// or [`delete_txes_paths`](Self::delete_txes_paths). Returns `None` if
+	/// the file is not part of this database; transactions without
+	/// metadata return an empty map.
+	///
+	/// Metadata is set with [`CreateTx::set_meta`](crate::CreateTx::set_meta).
+	pub fn tx_metadata(&self, path: &Path) -> Option<&BTreeMap<String, String>> {
+		if let Some((_, _, r)) = self.txes.iter().find(|(_, p, _)| p == path) {
+			return Some(r.metadata());
+		}
+		self.filter_out
+			.iter()
+			.find(|(_, p, _)| p == path)
+			.map(|(_, _, d)| &d.metadata)
+	}
+
+	/// Combine the metadata of all of the transactions
+	///
+	/// For each metadata key, the distinct values are joined with `", "`
+	/// in the order of the transactions. This is used for summarizing the metadata
+	/// of transactions that are compacted together.
+	pub fn merged_tx_metadata(&self) -> BTreeMap<String, String> {
+		let mut merged: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+		let all = self
+			.txes
+			.iter()
+			.map(|(txid, _, r)| (*txid, r.metadata()))
+			.chain(
+				self.filter_out
+					.iter()
+					.map(|(txid, _, d)| (*txid, &d.metadata)),
+			);
+		let mut all: Vec<_> = all.collect();
+		all.sort_by_key(|(txid, _)| *txid);
+
+		for (_, meta) in all {
+			for (k, v) in meta {
+				let values = merged.entry(k.clone()).or_default();
+				if !values.contains(&v.as_str()) {
+					values.push(v);
+				}
+			}
+		}
+		merged.into_iter().map(|(k, v)| (k, v.join(", "))).collect()
+	}
+
 	/// Get a reader for only a single key
 	///
 	/// Returns an object that will read all of the
@@ -171,9 +518,43 @@ impl DatabaseReader {
 			matcher: None,
 			prefix: "",
 			range: crate::disassemble_range_bound(key..=key).into(),
+			keys: None,
+			value_filters: vec![],
+			duplicates: DuplicateMode::Newest,
+			memory_budget: None,
+			read_ahead: ReadAhead::Auto,
+			decompress_ahead: 0,
+			as_of: None,
+			exclude: None,
+			combine: None,
+			latest: false,
+			resume_after: None,
 		}
 	}
 
+	/// Check if `key` has any record after (but not at) the time `after`
+	///
+	/// This stops at the first such record that isn't deleted, so it's
+	/// much faster than reading the key with [`get`](Self::get). In each
+	/// transaction, the key's segments are read from its newest records
+	/// backwards, so usually only one segment per transaction
+	/// is decompressed.
+	pub fn any(&self, key: &str, after: NaiveDateTime) -> bool {
+		let Some(after) = after.and_utc().timestamp_nanos_opt() else {
+			return false;
+		};
+		// every record is after a time before the epoch
+		let after = u64::try_from(after).ok();
+		let filter_out =
+			Deletions::new(self.filter_out.iter().map(|(txid, _path, dm)| (*txid, dm)));
+
+		self.txes.iter().rev().any(|(txid, _path, reader)| {
+			reader.any_after(key, after, self.segment_cache.as_deref(), |timestamp| {
+				filter_out.contains(*txid, key, timestamp)
+			})
+		})
+	}
+
 	/// Get a reader for a lexicographic range of keys
 	///
 	/// Use inclusive or exclusive range syntax to select a range.
@@ -191,6 +572,116 @@ impl DatabaseReader {
 			matcher: None,
 			prefix: "",
 			range: crate::disassemble_range_bound(range).into(),
+			keys: None,
+			value_filters: vec![],
+			duplicates: DuplicateMode::Newest,
+			memory_budget: None,
+			read_ahead: ReadAhead::Auto,
+			decompress_ahead: 0,
+			as_of: None,
+			exclude: None,
+			combine: None,
+			latest: false,
+			resume_after: None,
+		}
+	}
+
+	/// Get a reader for a lexicographic range of keys, and only
+	/// the records in a range of times
+	///
+	/// The same as [`get_range`](Self::get_range) followed by
+	/// [`time_range`](DatabaseRecordReader::time_range).
+	///
+	/// ```no_run
+	/// # let db = sonnerie::DatabaseReader::new(std::path::Path::new("")).unwrap();
+	/// let since: chrono::NaiveDateTime = "2024-01-01T00:00:00".parse().unwrap();
+	/// let recent = db.get_range_time("sensor.".."sensor/", since..);
+	/// ```
+	pub fn get_range_time<'d>(
+		&'d self,
+		range: impl std::ops::RangeBounds<&'d str> + 'd + Clone,
+		time: impl std::ops::RangeBounds<NaiveDateTime>,
+	) -> DatabaseRecordReader<'d> {
+		self.get_range(range).time_range(time)
+	}
+
+	/// Get a reader for a set of keys
+	///
+	/// Returns the records for each of `keys` that exists, in key
+	/// order, as if [`get`](Self::get) were called for each key and the
+	/// results concatenated. The database is only read once, and the
+	/// segments that lie between the requested keys are skipped
+	/// without being decompressed, so this is much faster than
+	/// calling `get` repeatedly.
+	///
+	/// Duplicate keys are ignored.
+	pub fn get_keys(&self, keys: &[&str]) -> DatabaseRecordReader<'_> {
+		let keys: BTreeSet<String> = keys.iter().map(|k| k.to_string()).collect();
+
+		let range = match (keys.first(), keys.last()) {
+			(Some(first), Some(last)) => (
+				Bound::Included(first.clone()),
+				Bound::Included(last.clone()),
+			)
+				.into(),
+			_ => (Bound::<String>::Unbounded, Bound::<String>::Unbounded).into(),
+		};
+
+		DatabaseRecordReader {
+			db: self,
+			matcher: None,
+			prefix: "",
+			range,
+			keys: Some(Arc::new(keys)),
+			value_filters: vec![],
+			duplicates: DuplicateMode::Newest,
+			memory_budget: None,
+			read_ahead: ReadAhead::Auto,
+			decompress_ahead: 0,
+			as_of: None,
+			exclude: None,
+			combine: None,
+			latest: false,
+			resume_after: None,
+		}
+	}
+
+	/// Read only the keys and timestamps of a lexicographic range of keys
+	///
+	/// This is a faster alternative to [`get_range`](Self::get_range)
+	/// for when the values aren't needed: no [`Record`] is created for each
+	/// row, the keys are borrowed from the decompressed data.
+	///
+	/// ```no_run
+	/// # let db = sonnerie::DatabaseReader::new(std::path::Path::new("")).unwrap();
+	/// let mut scanner = db.scan_headers(..);
+	/// while let Some(header) = scanner.next() {
+	///     println!("{} {}", header.key, header.timestamp);
+	/// }
+	/// ```
+	pub fn scan_headers<'d>(
+		&'d self,
+		range: impl std::ops::RangeBounds<&'d str> + 'd + Clone,
+	) -> HeaderScanner<'d> {
+		let range: crate::CowStringRange<'d> = crate::disassemble_range_bound(range).into();
+
+		let sources = self
+			.txes
+			.iter()
+			.map(|(txid, _path, reader)| HeaderSource {
+				txid: *txid,
+				reader: reader.get_filter_range(None, "", range.clone(), None),
+				timestamp: None,
+				consumed: true,
+			})
+			.collect();
+
+		let filter_out =
+			Deletions::new(self.filter_out.iter().map(|(txid, _path, dm)| (*txid, dm)));
+
+		HeaderScanner {
+			sources,
+			filter_out,
 		}
 	}
 
@@ -205,6 +696,15 @@ impl DatabaseReader {
 		&self.empty_files
 	}
 
+	/// Return a list of the transaction files that can't be read
+	///
+	/// They are left out of the database (with a warning), so that
+	/// the others can still be read and compacted, and aren't
+	/// removed by a compaction. A corrupt `main` is an error instead.
+	pub fn corrupt_transaction_files(&self) -> &[PathBuf] {
+		&self.corrupt_files
+	}
+
 	/// Get a key reader for a lexicographic range of keys **`feature=by-key`**
 	///
 	/// Each iterator represents a given key, you may iterate over each of those
@@ -231,26 +731,83 @@ impl DatabaseReader {
 
 	/// Get a reader that filters on SQL's "LIKE"-like syntax.
 	///
-	/// A wildcard filter that has a fixed prefix, such as
-	/// `"chimp%"` is always efficient.
+	/// Only the keys that start with the wildcard's [prefix](Wildcard::prefix)
+	/// are read, so a wildcard filter that has a fixed prefix, such as
+	/// `"chimp%"`, is always efficient. Without a prefix, such as `"%.tmp"`,
+	/// every key in the database is scanned, though the records of the keys
+	/// that don't match aren't decoded. Each key is first compared to the
+	/// [suffix](Wildcard::suffix), which is all that's needed for a wildcard
+	/// with one `%`; with more, the keys that have the suffix are also
+	/// matched with a regex.
 	pub fn get_filter<'d>(&'d self, wildcard: &'d Wildcard) -> DatabaseRecordReader<'d> {
 		if wildcard.is_exact() {
 			DatabaseRecordReader {
 				db: self,
-				matcher: wildcard.as_regex(),
+				matcher: wildcard.matcher(),
 				prefix: wildcard.prefix(),
 				range: crate::disassemble_range_bound(wildcard.prefix()..=wildcard.prefix()).into(),
+				keys: None,
+				value_filters: vec![],
+				duplicates: DuplicateMode::Newest,
+				memory_budget: None,
+				read_ahead: ReadAhead::Auto,
+				decompress_ahead: 0,
+				as_of: None,
+				exclude: None,
+				combine: None,
+				latest: false,
+				resume_after: None,
 			}
 		} else {
 			DatabaseRecordReader {
 				db: self,
-				matcher: wildcard.as_regex(),
+				matcher: wildcard.matcher(),
 				prefix: wildcard.prefix(),
 				range: crate::disassemble_range_bound(wildcard.prefix()..).into(),
+				keys: None,
+				value_filters: vec![],
+				duplicates: DuplicateMode::Newest,
+				memory_budget: None,
+				read_ahead: ReadAhead::Auto,
+				decompress_ahead: 0,
+				as_of: None,
+				exclude: None,
+				combine: None,
+				latest: false,
+				resume_after: None,
 			}
 		}
 	}
 
+	/// Get a reader like [`get_filter`](Self::get_filter), but without
+	/// the keys that match any of `exclude`
+	///
+	/// For example, `include` of `host.%` and `exclude` of `host.admin.%`.
+	/// The excluded keys are skipped without reading their records, and when
+	/// an exclusion is only a prefix (like `host.admin.%`), the segments that
+	/// only have such keys are skipped without being decompressed.
+	pub fn get_filter_except<'d>(
+		&'d self,
+		include: &'d Wildcard,
+		exclude: &[&Wildcard],
+	) -> DatabaseRecordReader<'d> {
+		let mut reader = self.get_filter(include);
+		if !exclude.is_empty() {
+			reader.exclude = Some(Arc::new(Exclusions::new(exclude)));
+		}
+		reader
+	}
+
+	/// Get the latest record of each key that matches `wildcard`
+	///
+	/// This is the current value of each series, the same as
+	/// [`get_filter`](Self::get_filter) with
+	/// [`latest`](DatabaseRecordReader::latest). Use `latest` on
+	/// the other readers for a range or a list of keys.
+	pub fn latest<'d>(&'d self, wildcard: &'d Wildcard) -> DatabaseRecordReader<'d> {
+		self.get_filter(wildcard).latest()
+	}
+
 	/// Get a key reader that filters on SQL's "LIKE"-like syntax. **`feature=by-key`**
 	///
 	/// Each iterator represents a given key, you may iterate over each of those
@@ -263,14 +820,14 @@ impl DatabaseReader {
 		if wildcard.is_exact() {
 			DatabaseKeyReader {
 				db: self,
-				matcher: wildcard.as_regex(),
+				matcher: wildcard.matcher(),
 				prefix: wildcard.prefix(),
 				range: crate::disassemble_range_bound(wildcard.prefix()..=wildcard.prefix()).into(),
 			}
 		} else {
 			DatabaseKeyReader {
 				db: self,
-				matcher: wildcard.as_regex(),
+				matcher: wildcard.matcher(),
 				prefix: wildcard.prefix(),
 				range: crate::disassemble_range_bound(wildcard.prefix()..).into(),
 			}
@@ -286,13 +843,266 @@ impl DatabaseReader {
 /// Note that only one thread will get any specific key; keys are never
 /// divided between multiple workers.
 pub struct DatabaseRecordReader<'d> {
-	db: &'d DatabaseReader,
-	matcher: Option<regex::Regex>,
-	prefix: &'d str,
-	range: crate::CowStringRange<'d>,
+	pub(crate) db: &'d DatabaseReader,
+	pub(crate) matcher: Option<KeyMatcher>,
+	pub(crate) prefix: &'d str,
+	pub(crate) range: crate::CowStringRange<'d>,
+	pub(crate) keys: Option<Arc<BTreeSet<String>>>,
+	pub(crate) value_filters: Vec<ValueFilter<'d>>,
+	pub(crate) duplicates: DuplicateMode,
+	pub(crate) memory_budget: Option<usize>,
+	pub(crate) read_ahead: ReadAhead,
+	pub(crate) decompress_ahead: usize,
+	pub(crate) as_of: Option<usize>,
+	pub(crate) exclude: Option<Arc<Exclusions>>,
+	pub(crate) combine: Option<Arc<crate::combine::Combining>>,
+	pub(crate) latest: bool,
+	/// when the range begins with an included key, skip that key's
+	/// records at or before this time (see [`DatabaseReader::resume`])
+	pub(crate) resume_after: Option<crate::Timestamp>,
+}
+
+/// What to do when more than one transaction has a record with
+/// the same key and timestamp, see [`DatabaseRecordReader::with_duplicates`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum DuplicateMode {
+	/// Only yield the record from the newest transaction
+	#[default]
+	Newest,
+	/// Yield every version of the record, oldest transaction first
+	All,
+}
+
+/// A filter on the values of records, see [`DatabaseRecordReader::filter_values`]
+#[derive(Clone)]
+pub(crate) enum ValueFilter<'d> {
+	Predicate(Arc<dyn Fn(&Record) -> bool + Send + Sync + 'd>),
+	ColumnGt {
+		col: usize,
+		value: f64,
+	},
+	/// the timestamps, in nanoseconds since the epoch
+	Time {
+		begin: Bound<i128>,
+		end: Bound<i128>,
+	},
 }
 
 impl<'d> DatabaseRecordReader<'d> {
+	/// Only yield the records for which `pred` returns true
+	///
+	/// The predicate is evaluated after deleted records and older
+	/// versions of records are removed. Calling this more than once
+	/// yields only the records that match every predicate.
+	///
+	/// ```no_run
+	/// # let db = sonnerie::DatabaseReader::new(std::path::Path::new("")).unwrap();
+	/// let hot = db
+	///     .get_range(..)
+	///     .filter_values(|r| r.get_checked::<f64>(1).is_ok_and(|v| v > 100.0));
+	/// ```
+	pub fn filter_values(mut self, pred: impl Fn(&Record) -> bool + Send + Sync + 'd) -> Self {
+		self.value_filters
+			.push(ValueFilter::Predicate(Arc::new(pred)));
+		self
+	}
+
+	/// Only yield the records whose column `col` (counting from 0) is greater than `value`
+	///
+	/// This is a faster version of filtering with [`filter_values`](Self::filter_values),
+	/// because the column is read directly from the record's data. Integer columns
+	/// are converted to `f64` for the comparison. Records that don't have
+	/// a numeric column `col` don't match.
+	pub fn filter_column_gt(mut self, col: usize, value: f64) -> Self {
+		self.value_filters
+			.push(ValueFilter::ColumnGt { col, value });
+		self
+	}
+
+	/// Only yield the records whose time is in `time`
+	///
+	/// The times are compared as the records are read, so callers
+	/// don't need to compare each record's timestamp themselves.
+	/// Calling this more than once yields only the records that are
+	/// in every range.
+	pub fn time_range(mut self, time: impl std::ops::RangeBounds<NaiveDateTime>) -> Self {
+		let nanos = |t: &NaiveDateTime| {
+			let t = t.and_utc();
+			t.timestamp() as i128 * 1_000_000_000 + t.timestamp_subsec_nanos() as i128
+		};
+		self.value_filters.push(ValueFilter::Time {
+			begin: time.start_bound().map(nanos),
+			end: time.end_bound().map(nanos),
+		});
+		self
+	}
+
+	/// Choose what to do with records that have the same key and timestamp
+	///
+	/// Normally, when more than one transaction has a record with the same
+	/// key and timestamp, only the one from the newest transaction is yielded
+	/// ([`DuplicateMode::Newest`]). With [`DuplicateMode::All`], every version
+	/// is yielded, from the oldest transaction to the newest. This shows
+	/// what a compaction would discard.
+	///
+	/// Deleted records are not yielded in either mode.
+	pub fn with_duplicates(mut self, mode: DuplicateMode) -> Self {
+		self.duplicates = mode;
+		self
+	}
+
+	/// Only yield the latest record of each key
+	///
+	/// That's the record with the greatest timestamp, of those that
+	/// aren't deleted and that pass the other filters (so, with
+	/// [`time_range`](Self::time_range), the latest record in that range).
+	/// Keys whose records are all deleted aren't yielded at all.
+	/// Every record is still read, because records are stored
+	/// in chronological order.
+	///
+	/// ```no_run
+	/// # let db = sonnerie::DatabaseReader::new(std::path::Path::new("")).unwrap();
+	/// for record in db.get_range("temperature.".."temperature/").latest() {
+	///     println!("{} is now {}", record.key(), record.get::<f64>(0));
+	/// }
+	/// ```
+	pub fn latest(mut self) -> Self {
+		self.latest = true;
+		self
+	}
+
+	/// Limit how much of each segment is decompressed at a time
+	///
+	/// Normally, a segment is decompressed entirely when reading reaches it.
+	/// Segments are usually around a megabyte, but can be much larger, for
+	/// example when a single record is large. With a budget, a segment that
+	/// decompresses to more than `bytes` is decompressed `bytes` at a time as
+	/// the records are read (or more, if a single record is larger than that);
+	/// smaller segments are read as they are without a budget. This applies
+	/// to each transaction file, and under `into_par_iter`, to each worker.
+	/// The range is divided among the workers at the boundaries of segments,
+	/// so a single large segment is read by one worker.
+	///
+	/// Records share the buffer they were decompressed into, so holding
+	/// on to a record keeps that buffer alive.
+	pub fn memory_budget(mut self, bytes: usize) -> Self {
+		self.memory_budget = Some(bytes);
+		self
+	}
+
+	/// Hint to the OS how the files are read
+	///
+	/// With [`ReadAhead::Sequential`], while a segment is decompressed, the
+	/// OS reads the ones after it from the disk, which speeds up a scan of a
+	/// large range whose files aren't in the page cache. [`ReadAhead::Random`]
+	/// is for reading a few keys from a large file, so that the OS doesn't read
+	/// more of it than is needed. The default is [`ReadAhead::Auto`].
+	///
+	/// A scan that's limited by decompressing rather than by the disk, as it
+	/// usually is on a local SSD, isn't any faster with `Sequential`; it's
+	/// for slower storage, such as network disks.
+	pub fn read_ahead(mut self, read_ahead: ReadAhead) -> Self {
+		self.read_ahead = read_ahead;
+		self
+	}
+
+	/// Decompress up to `segments` segments ahead of reading, on other threads
+	///
+	/// Normally a segment is decompressed when reading reaches it. This
+	/// starts a thread for each transaction file that decompresses the
+	/// segments of the range in order, while the records of the previous ones
+	/// are read, which speeds up reading a large range when the records are
+	/// read quickly. It uses up to `segments` more decompressed segments of
+	/// memory for each transaction file. The records are the same, in the same order.
+	///
+	/// It's not worth starting threads for a small range. It has no effect
+	/// with a [`memory_budget`](Self::memory_budget) or when reading a set
+	/// of keys, and 0 turns it off.
+	pub fn decompress_ahead(mut self, segments: usize) -> Self {
+		self.decompress_ahead = segments;
+		self
+	}
+
+	/// Read the database as it was when transaction `txid` was the newest
+	///
+	/// Only the transactions (and deletions) whose id is at most `txid`
+	/// are read; get a transaction's id with [`DatabaseReader::txid`]. `main`
+	/// is always read, since its id is 0.
+	///
+	/// Ids are the order of the transactions in this `DatabaseReader`, so
+	/// they are only meaningful until the next compaction.
+	pub fn as_of(mut self, txid: usize) -> Self {
+		self.as_of = Some(txid);
+		self
+	}
+
+	/// Yield the records in vectors of `n` records
+	///
+	/// Every chunk has exactly `n` records, except the last one, which
+	/// may have fewer. Each chunk is allocated once, with room for `n` records.
+	///
+	/// Like this object, the returned object can be iterated over
+	/// or made into a Rayon parallel iterator; in the latter case each
+	/// worker makes chunks from its own portion of the keys, so more than
+	/// one chunk may have fewer than `n` records.
+	///
+	/// Panics if `n` is zero.
+	pub fn chunks(self, n: usize) -> RecordChunks<'d> {
+		assert!(n > 0, "chunk size must be nonzero");
+		RecordChunks { reader: self, n }
+	}
+
+	/// Yield the records sorted by key with `cmp`, for presentation
+	///
+	/// The database always stores and yields keys in byte order; this
+	/// reorders the keys (for example, to sort `host2` before `host10`)
+	/// while the records of each key stay in order of timestamp. Keys
+	/// that `cmp` says are equal stay in byte order.
+	///
+	/// The range is read once to find its keys, which are kept in
+	/// memory and sorted, and then the records of each key are read
+	/// as they're yielded, so only one key is read at a time.
+	pub fn collate(
+		self,
+		cmp: impl Fn(&str, &str) -> std::cmp::Ordering,
+	) -> impl Iterator<Item = Record> + 'd {
+		let mut keys: Vec<String> = vec![];
+		for record in self.with_range(self.range.clone()) {
+			if keys.last().map(|k| k.as_str()) != Some(record.key()) {
+				keys.push(record.key().to_owned());
+			}
+		}
+		keys.sort_by(|a, b| cmp(a, b));
+		keys.into_iter().flat_map(move |key| {
+			let mut reader =
+				self.with_range((Bound::Included(key.clone()), Bound::Included(key)).into());
+			// like a set of keys, a single key isn't worth decompressing ahead
+			reader.decompress_ahead = 0;
+			reader
+		})
+	}
+
+	/// The same reader, but of `range`
+	fn with_range(&self, range: crate::CowStringRange<'d>) -> DatabaseRecordReader<'d> {
+		DatabaseRecordReader {
+			db: self.db,
+			matcher: self.matcher.clone(),
+			prefix: self.prefix,
+			range,
+			keys: self.keys.clone(),
+			value_filters: self.value_filters.clone(),
+			duplicates: self.duplicates,
+			memory_budget: self.memory_budget,
+			read_ahead: self.read_ahead,
+			decompress_ahead: self.decompress_ahead,
+			as_of: self.as_of,
+			exclude: self.exclude.clone(),
+			combine: self.combine.clone(),
+			latest: self.latest,
+			resume_after: None,
+		}
+	}
+
 	pub(crate) fn check(&self) {
 		match (self.range.start_bound(), self.range.end_bound()) {
 			(Bound::Unbounded, _) => {}
@@ -304,6 +1114,10 @@ impl<'d> DatabaseRecordReader<'d> {
 		}
 	}
 
+	/// Divide the range of keys in two, for Rayon
+	///
+	/// Everything but the range is the same in both halves, including
+	/// the value filters, so a [`time_range`](Self::time_range) applies to each.
 	pub(crate) fn split(&self) -> Option<(DatabaseRecordReader<'d>, DatabaseRecordReader<'d>)> {
 		// look into the readers and see which Reader was biggest
 		let (biggest_reader, biggest_portion_size) = self
@@ -311,8 +1125,12 @@ impl<'d> DatabaseRecordReader<'d> {
 			.txes
 			.iter()
 			.map(|tx| {
-				let filter =
-					tx.2.get_filter_range(self.matcher.clone(), self.prefix, self.range.clone());
+				let filter = tx.2.get_filter_range(
+					self.matcher.clone(),
+					self.prefix,
+					self.range.clone(),
+					self.keys.clone(),
+				);
 				let b = filter.compressed_bytes();
 				(filter, b)
 			})
@@ -345,6 +1163,17 @@ impl<'d> DatabaseRecordReader<'d> {
 			db: self.db,
 			matcher: self.matcher.clone(),
 			prefix: self.prefix,
+			keys: self.keys.clone(),
+			value_filters: self.value_filters.clone(),
+			duplicates: self.duplicates,
+			memory_budget: self.memory_budget,
+			read_ahead: self.read_ahead,
+			decompress_ahead: self.decompress_ahead,
+			as_of: self.as_of,
+			exclude: self.exclude.clone(),
+			combine: self.combine.clone(),
+			latest: self.latest,
+			resume_after: self.resume_after,
 			range: (
 				crate::bound_deep_copy(self.range.start_bound()),
 				Bound::Included(middle_start_key.to_owned()),
@@ -369,6 +1198,17 @@ impl<'d> DatabaseRecordReader<'d> {
 			db: self.db,
 			matcher: self.matcher.clone(),
 			prefix: self.prefix,
+			keys: self.keys.clone(),
+			value_filters: self.value_filters.clone(),
+			duplicates: self.duplicates,
+			memory_budget: self.memory_budget,
+			read_ahead: self.read_ahead,
+			decompress_ahead: self.decompress_ahead,
+			as_of: self.as_of,
+			exclude: self.exclude.clone(),
+			combine: self.combine.clone(),
+			latest: self.latest,
+			resume_after: None,
 			range: (
 				Bound::Excluded(middle_start_key.to_owned()),
 				crate::bound_deep_copy(self.range.end_bound()),
@@ -397,28 +1237,106 @@ impl<'d> IntoIterator for DatabaseRecordReader<'d> {
 
 		let mut readers = Vec::with_capacity(self.db.txes.len());
 
+		let as_of = self.as_of.unwrap_or(usize::MAX);
+
 		for (txid, _path, reader) in self.db.txes.iter() {
-			let iter =
-				reader.get_filter_range(self.matcher.clone(), self.prefix, self.range.clone());
+			if *txid > as_of {
+				continue;
+			}
+			let mut iter = reader
+				.get_filter_range_with_budget(
+					self.matcher.clone(),
+					self.prefix,
+					self.range.clone(),
+					self.keys.clone(),
+					self.memory_budget,
+					self.db.segment_cache.as_deref(),
+				)
+				.excluding(self.exclude.clone())
+				.read_ahead(self.read_ahead)
+				.decompress_ahead(
+					reader.clone(),
+					self.db.segment_cache.clone(),
+					self.decompress_ahead,
+				);
+			if let (Bound::Included(key), Some(after)) =
+				(self.range.start_bound(), self.resume_after)
+			{
+				iter.skip_through(key, after);
+			}
 
 			readers.push((*txid, iter));
 		}
-		let merge = Merge::new(readers, |a, b| {
-			a.key()
-				.cmp(b.key())
-				.then_with(|| a.timestamp_nanos().cmp(&b.timestamp_nanos()))
-		});
+		let compare = |a: &Record, b: &Record| a.key_timestamp().cmp(&b.key_timestamp());
+		let merge = match self.duplicates {
+			DuplicateMode::Newest if self.combine.is_none() => Merge::new(readers, compare),
+			_ => Merge::new_keep_duplicates(readers, compare),
+		};
+		// a reader that has gone past the end is dropped instead of
+		// being asked for more
+		let merge = match crate::bound_deep_copy(self.range.end_bound()) {
+			Bound::Included(end) => merge.until(move |r: &Record| r.key() > end.as_str()),
+			Bound::Excluded(end) => merge.until(move |r: &Record| r.key() >= end.as_str()),
+			Bound::Unbounded => merge,
+		};
 
-		let filter_out: Vec<_> = self
-			.db
-			.filter_out
-			.iter()
-			.map(|(txid, _path, dm)| (*txid, DeleteMarkerPrecomputed::from_delete_marker(dm)))
-			.collect();
+		let filter_out = Deletions::new(
+			self.db
+				.filter_out
+				.iter()
+				.filter(|(txid, _path, _)| *txid <= as_of)
+				.map(|(txid, _path, dm)| (*txid, dm)),
+		);
 
 		DatabaseRecordIterator {
 			filter_out,
 			merge: Box::new(merge),
+			value_filters: self.value_filters,
+			fixed_columns: vec![],
+			combine: self.combine,
+			pending: None,
+			latest: self.latest,
+			next_key: None,
+		}
+	}
+}
+
+/// Groups the records of a [`DatabaseRecordReader`] into vectors, see [`DatabaseRecordReader::chunks`]
+///
+/// Implements `IntoIterator` and Rayon's `ParallelIterator`.
+pub struct RecordChunks<'d> {
+	pub(crate) reader: DatabaseRecordReader<'d>,
+	pub(crate) n: usize,
+}
+
+impl<'d> IntoIterator for RecordChunks<'d> {
+	type Item = Vec<Record>;
+	type IntoIter = RecordChunksIterator<'d>;
+
+	fn into_iter(self) -> Self::IntoIter {
+		RecordChunksIterator {
+			records: self.reader.into_iter(),
+			n: self.n,
+		}
+	}
+}
+
+/// An iterator over vectors of records, see [`DatabaseRecordReader::chunks`]
+pub struct RecordChunksIterator<'d> {
+	records: DatabaseRecordIterator<'d>,
+	n: usize,
+}
+
+impl<'d> Iterator for RecordChunksIterator<'d> {
+	type Item = Vec<Record>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let mut chunk = Vec::with_capacity(self.n);
+		chunk.extend(self.records.by_ref().take(self.n));
+		if chunk.is_empty() {
+			None
+		} else {
+			Some(chunk)
 		}
 	}
 }
@@ -428,8 +1346,19 @@ impl<'d> IntoIterator for DatabaseRecordReader<'d> {
 /// Yields an [`Record`](record/struct.Record.html)
 /// for each row in the database, sorted by key and timestamp.
 pub struct DatabaseRecordIterator<'d> {
-	filter_out: Vec<(usize, DeleteMarkerPrecomputed<'d>)>,
+	filter_out: Deletions<'d>,
 	merge: Box<Merge<StringKeyRangeReader<'d, 'd>, Record>>,
+	value_filters: Vec<ValueFilter<'d>>,
+	/// for each `ValueFilter::ColumnGt`, the last format seen and where in
+	/// `raw()` that column is, if it has a fixed position in that format
+	fixed_columns: Vec<(String, Option<(usize, u8)>)>,
+	combine: Option<Arc<crate::combine::Combining>>,
+	/// the record after the versions that were combined
+	pending: Option<Record>,
+	/// only yield the last record of each key
+	latest: bool,
+	/// with `latest`, the first record of the key after the one being read
+	next_key: Option<Record>,
 }
 
 pub(crate) struct DeleteMarkerPrecomputed<'a> {
@@ -437,18 +1366,30 @@ pub(crate) struct DeleteMarkerPrecomputed<'a> {
 	pub last_key: &'a str,
 	pub first_timestamp: NaiveDateTime,
 	pub last_timestamp: NaiveDateTime,
-	pub wildcard: Either<Regex, &'a str>,
+	pub wildcard: KeyFilter<'a>,
+}
+
+/// What a delete marker's keys must match
+pub(crate) enum KeyFilter<'a> {
+	Regex(Regex),
+	Prefix(&'a str),
+	Keys(std::collections::HashSet<&'a str>),
 }
 
 impl<'a> DeleteMarkerPrecomputed<'a> {
 	pub(crate) fn from_delete_marker(marker: &'a DeleteMarker) -> DeleteMarkerPrecomputed<'a> {
-		use Either::*;
-
-		let wildcard = match Wildcard::new(&marker.wildcard).as_regex() {
-			Some(re) => Left(re),
-			None => {
-				let starts_with = marker.wildcard.split('%').next().unwrap();
-				Right(starts_with)
+		let wildcard = if marker.regex {
+			// validated when the marker was read
+			KeyFilter::Regex(Regex::new(&marker.wildcard).unwrap())
+		} else if marker.keys {
+			KeyFilter::Keys(marker.wildcard.split('\n').collect())
+		} else {
+			match Wildcard::new(&marker.wildcard).as_regex() {
+				Some(re) => KeyFilter::Regex(re),
+				None => {
+					let starts_with = marker.wildcard.split('%').next().unwrap();
+					KeyFilter::Prefix(starts_with)
+				}
 			}
 		};
 
@@ -462,57 +1403,329 @@ impl<'a> DeleteMarkerPrecomputed<'a> {
 	}
 
 	pub(crate) fn wildcard_matches(&self, key: &str) -> bool {
-		use Either::*;
-
 		match &self.wildcard {
-			Left(re) => re.is_match(key),
-			Right(start) => key.starts_with(start),
+			KeyFilter::Regex(re) => re.is_match(key),
+			KeyFilter::Prefix(start) => key.starts_with(start),
+			KeyFilter::Keys(keys) => keys.contains(key),
+		}
+	}
+}
+
+/// The delete markers that apply to a read
+pub(crate) struct Deletions<'a> {
+	/// markers that aren't expiries
+	markers: Vec<(usize, DeleteMarkerPrecomputed<'a>)>,
+	/// the txids of the expiries (see [`DeleteMarker::expires_before`]),
+	/// ascending, each with the time before which the records of earlier
+	/// transactions are deleted by it or by the expiries after it
+	expiries: Vec<(usize, crate::Timestamp)>,
+}
+
+impl<'a> Deletions<'a> {
+	pub(crate) fn new(markers: impl Iterator<Item = (usize, &'a DeleteMarker)>) -> Deletions<'a> {
+		let mut general = vec![];
+		let mut expiries: Vec<(usize, crate::Timestamp)> = vec![];
+		for (txid, marker) in markers {
+			if let Some(time) = marker.expires_before() {
+				expiries.push((txid, time));
+			} else {
+				general.push((txid, DeleteMarkerPrecomputed::from_delete_marker(marker)));
+			}
+		}
+		// txids can be sparse (see `DatabaseReader::from_readers`), so
+		// this has an entry for each expiry rather than for each txid
+		expiries.sort_unstable();
+		for i in (0..expiries.len().saturating_sub(1)).rev() {
+			expiries[i].1 = expiries[i].1.max(expiries[i + 1].1);
 		}
+
+		Deletions {
+			markers: general,
+			expiries,
+		}
+	}
+
+	/// true if a record of transaction `txid` is deleted
+	pub(crate) fn contains(&self, txid: usize, key: &str, timestamp: crate::Timestamp) -> bool {
+		// the first expiry in a later transaction applies with all those after it
+		let later = self.expiries.partition_point(|&(t, _)| t <= txid);
+		if self
+			.expiries
+			.get(later)
+			.is_some_and(|&(_, t)| timestamp < t)
+		{
+			return true;
+		}
+		if self.markers.is_empty() {
+			return false;
+		}
+		let time = chrono::DateTime::from_timestamp(
+			(timestamp / 1_000_000_000) as i64,
+			(timestamp % 1_000_000_000) as u32,
+		)
+		.unwrap()
+		.naive_utc();
+		is_filtered_out(&self.markers, txid, key, time)
 	}
 }
 
+/// true if a record of transaction `txid` is deleted by one of the markers
+fn is_filtered_out(
+	filter_out: &[(usize, DeleteMarkerPrecomputed<'_>)],
+	txid: usize,
+	key: &str,
+	record_time: NaiveDateTime,
+) -> bool {
+	filter_out
+		.iter()
+		// select only transactions that are indexed lower than the
+		// delete transaction
+		.filter(|(del_txid, _)| txid < *del_txid)
+		// check if the record's timestamp is within filtering out
+		// this assumes that the filter_out is sorted ascending by
+		// first timestamp (which should have been done in
+		// DatabaseReader::new())
+		.filter(|(_, filter)| {
+			(filter.first_timestamp..filter.last_timestamp).contains(&record_time)
+		})
+		.filter(|(_, filter)| record_time <= filter.last_timestamp)
+		// if any of the filters went here (i.e. any() returns a true),
+		// then that means that filter found one filter that filters out
+		// the current record. that should be discarded
+		.any(|(_, filter)| {
+			if filter.first_key > key {
+				return false;
+			}
+
+			if !filter.last_key.is_empty() && key >= filter.last_key {
+				return false;
+			}
+
+			filter.wildcard_matches(key)
+		})
+}
+
 impl<'d> Iterator for DatabaseRecordIterator<'d> {
 	type Item = Record;
 
 	fn next(&mut self) -> Option<Self::Item> {
-		for (txid, record) in self.merge.by_ref() {
-			let is_filtered_out = self
-				.filter_out
-				.iter()
-				// select only transactions that are indexed lower than the
-				// delete transaction
-				.filter(|(del_txid, _)| txid < *del_txid)
-				// check if the record's timestamp is within filtering out
-				// this assumes that the filter_out is sorted ascending by
-				// first timestamp (which should have been done in
-				// DatabaseReader::new())
-				.filter(|(_, filter)| {
-					let record_time = record.time();
-					(filter.first_timestamp..filter.last_timestamp).contains(&record_time)
-				})
-				.filter(|(_, filter)| record.time() <= filter.last_timestamp)
-				// if any of the filters went here (i.e. any() returns a true),
-				// then that means that filter found one filter that filters out
-				// the current record. that should be discarded
-				.any(|(_, filter)| {
-					let key = record.key();
-
-					if filter.first_key > key {
-						return false;
-					}
+		if !self.latest {
+			return self.next_matching();
+		}
+		// the records of a key are consecutive, the latest last
+		let mut record = match self.next_key.take() {
+			Some(record) => record,
+			None => self.next_matching()?,
+		};
+		while let Some(next) = self.next_matching() {
+			if next.key() != record.key() {
+				self.next_key = Some(next);
+				break;
+			}
+			record = next;
+		}
+		Some(record)
+	}
+}
 
-					if !filter.last_key.is_empty() && key >= filter.last_key {
-						return false;
+impl DatabaseRecordIterator<'_> {
+	/// the next record that isn't deleted and matches the value filters
+	fn next_matching(&mut self) -> Option<Record> {
+		loop {
+			let mut record = match self.pending.take() {
+				Some(record) => record,
+				None => self.next_undeleted()?,
+			};
+			if let Some(combine) = self.combine.clone() {
+				// the versions of a record are consecutive, the oldest first
+				while let Some(next) = self.next_undeleted() {
+					if next.key_timestamp() != record.key_timestamp() {
+						self.pending = Some(next);
+						break;
 					}
+					record = combine.apply(&record, &next);
+				}
+			}
+			if matches_value_filters(&self.value_filters, &mut self.fixed_columns, &record) {
+				return Some(record);
+			}
+		}
+	}
+
+	fn next_undeleted(&mut self) -> Option<Record> {
+		let filter_out = &self.filter_out;
+		self.merge
+			.by_ref()
+			.find(|(txid, record)| {
+				let (key, timestamp) = record.key_timestamp();
+				!filter_out.contains(*txid, key, timestamp)
+			})
+			.map(|(_, record)| record)
+	}
+}
+
+fn matches_value_filters(
+	value_filters: &[ValueFilter<'_>],
+	fixed_columns: &mut Vec<(String, Option<(usize, u8)>)>,
+	record: &Record,
+) -> bool {
+	let mut column_filter_idx = 0;
+	for filter in value_filters {
+		match filter {
+			ValueFilter::Predicate(pred) => {
+				if !pred(record) {
+					return false;
+				}
+			}
+			ValueFilter::Time { begin, end } => {
+				if !std::ops::RangeBounds::contains(
+					&(*begin, *end),
+					&(record.timestamp_nanos() as i128),
+				) {
+					return false;
+				}
+			}
+			ValueFilter::ColumnGt { col, value } => {
+				if fixed_columns.len() == column_filter_idx {
+					fixed_columns.push((String::new(), None));
+				}
+				let (format, position) = &mut fixed_columns[column_filter_idx];
+				column_filter_idx += 1;
 
-					filter.wildcard_matches(key)
-				});
+				if format != record.format() {
+					format.replace_range(.., record.format());
+					*position = fixed_column_position(format, *col);
+				}
 
-			if !is_filtered_out {
-				return Some(record);
+				let column = match *position {
+					Some((offset, fmt_char)) => Some((fmt_char, &record.raw()[offset..])),
+					None => record.column_data(*col).ok(),
+				};
+				match column.and_then(|(fmt_char, data)| numeric_value(fmt_char, data)) {
+					Some(v) if v > *value => {}
+					_ => return false,
+				}
 			}
 		}
+	}
+	true
+}
+
+/// Where column `col` is in the data of records with `format`,
+/// and its format character, if no variable-sized columns precede it
+fn fixed_column_position(format: &str, col: usize) -> Option<(usize, u8)> {
+	let fmt_char = *format.as_bytes().get(col)?;
+	let offset = crate::row_format::row_format_size(&format[..col])?;
+	Some((crate::TIMESTAMP_SIZE + offset, fmt_char))
+}
+
+pub(crate) fn numeric_value(fmt_char: u8, data: &[u8]) -> Option<f64> {
+	use byteorder::{BigEndian, ByteOrder};
+	let v = match fmt_char {
+		b'c' => data[0] as i8 as f64,
+		b'C' => data[0] as f64,
+		b'h' => BigEndian::read_i16(data) as f64,
+		b'H' => BigEndian::read_u16(data) as f64,
+		b'i' => BigEndian::read_i32(data) as f64,
+		b'I' => BigEndian::read_i64(data) as f64,
+		b'u' => BigEndian::read_u32(data) as f64,
+		b'U' => BigEndian::read_u64(data) as f64,
+		b'f' => BigEndian::read_f32(data) as f64,
+		b'F' => BigEndian::read_f64(data),
+		_ => return None,
+	};
+	Some(v)
+}
+
+/// The key and timestamp of a record, see [`DatabaseReader::scan_headers`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordHeader<'a> {
+	pub key: &'a str,
+	pub timestamp: crate::Timestamp,
+}
+
+struct HeaderSource<'d> {
+	txid: usize,
+	reader: StringKeyRangeReader<'d, 'd>,
+	/// the timestamp of the record `reader` is at, None if it's finished
+	timestamp: Option<crate::Timestamp>,
+	/// `reader` needs to go to its next record
+	consumed: bool,
+}
+
+/// Reads the keys and timestamps of records, created
+/// by [`DatabaseReader::scan_headers`].
+///
+/// This doesn't implement `Iterator`, because each [`RecordHeader`]
+/// borrows from the decompressed data, which is replaced as reading
+/// continues. Call [`next`](Self::next) in a `while let` loop instead.
+pub struct HeaderScanner<'d> {
+	sources: Vec<HeaderSource<'d>>,
+	filter_out: Deletions<'d>,
+}
+
+impl<'d> HeaderScanner<'d> {
+	/// Get the next record's key and timestamp
+	///
+	/// The result is only valid until the next call.
+	#[allow(clippy::should_implement_trait)]
+	pub fn next(&mut self) -> Option<RecordHeader<'_>> {
+		let best = loop {
+			for source in self.sources.iter_mut().filter(|s| s.consumed) {
+				source.timestamp = source.reader.next_timestamp();
+				source.consumed = false;
+			}
+			self.sources.retain(|s| s.timestamp.is_some());
+
+			// the lowest key and timestamp, ties go to the newest transaction
+			let mut best: Option<usize> = None;
+			for (idx, source) in self.sources.iter().enumerate() {
+				let better = match best {
+					None => true,
+					Some(b) => {
+						let b = &self.sources[b];
+						(source.reader.current_key(), source.timestamp)
+							.cmp(&(b.reader.current_key(), b.timestamp))
+							.then(source.txid.cmp(&b.txid).reverse())
+							.is_lt()
+					}
+				};
+				if better {
+					best = Some(idx);
+				}
+			}
+			let best = best?;
+
+			// all the records with the same key and timestamp are
+			// replaced by this one
+			let (key, timestamp) = (
+				self.sources[best].reader.current_key(),
+				self.sources[best].timestamp,
+			);
+			let duplicates: smallvec::SmallVec<[usize; 8]> = self
+				.sources
+				.iter()
+				.enumerate()
+				.filter(|(_, s)| s.timestamp == timestamp && s.reader.current_key() == key)
+				.map(|(idx, _)| idx)
+				.collect();
+
+			let deleted =
+				self.filter_out
+					.contains(self.sources[best].txid, key, timestamp.unwrap());
+
+			for idx in duplicates {
+				self.sources[idx].consumed = true;
+			}
+			if !deleted {
+				break best;
+			}
+		};
 
-		None
+		let source = &self.sources[best];
+		Some(RecordHeader {
+			key: source.reader.current_key(),
+			timestamp: source.timestamp.unwrap(),
+		})
 	}
 }
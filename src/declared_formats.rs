@@ -0,0 +1,65 @@
+//! Formats that are declared for keys, and stored in the database itself.
+//!
+//! A key's format is normally whatever its records have, and can
+//! change from one record to the next. Declaring a format for a key
+//! lets a transaction reject the records of that key that have another
+//! format, see [`CreateTx::enforce_formats`].
+//!
+//! The declaration for a key is stored in the key [`FORMAT_PREFIX`]
+//! followed by the key, as a record of the format (the format `s`).
+//! The record with the latest timestamp applies, and an empty
+//! format removes the declaration.
+
+use crate::{record, CreateTx, DatabaseReader, Wildcard, WriteFailure};
+use chrono::NaiveDateTime;
+use std::collections::BTreeMap;
+
+/// The declarations are stored in the keys that start with this
+pub const FORMAT_PREFIX: &str = "@sonnerie.format/";
+
+/// Declare that the records of `key` have the format `format`, as of `now`
+///
+/// The declaration is a record in the database, so it's only in
+/// effect after `tx` is committed. Fails if `format` is empty or isn't a valid format.
+pub fn declare(
+	tx: &mut CreateTx,
+	now: NaiveDateTime,
+	key: &str,
+	format: &str,
+) -> Result<(), WriteFailure> {
+	if let Some(c) = format.chars().find(|c| !"cChHiuIUfFsB".contains(*c)) {
+		return Err(std::io::Error::new(
+			std::io::ErrorKind::InvalidInput,
+			format!("invalid format character '{}' in {:?}", c, format),
+		)
+		.into());
+	}
+	if format.is_empty() {
+		return Err(std::io::Error::new(
+			std::io::ErrorKind::InvalidInput,
+			"the declared format may not be empty",
+		)
+		.into());
+	}
+	tx.add_record(&format!("{}{}", FORMAT_PREFIX, key), now, record(format))
+}
+
+/// Remove the declared format of `key`, as of `now`
+pub fn undeclare(tx: &mut CreateTx, now: NaiveDateTime, key: &str) -> Result<(), WriteFailure> {
+	tx.add_record(&format!("{}{}", FORMAT_PREFIX, key), now, record(""))
+}
+
+/// Read the declared format of each key
+pub fn declared(db: &DatabaseReader) -> BTreeMap<String, String> {
+	let mut formats = BTreeMap::new();
+	let filter = Wildcard::new(&format!("{}%", FORMAT_PREFIX));
+	// the records are in order of time, so later declarations replace earlier ones
+	for r in db.get_filter(&filter) {
+		let Ok(format) = r.get_checked::<String>(0) else {
+			continue;
+		};
+		formats.insert(r.key()[FORMAT_PREFIX.len()..].to_owned(), format);
+	}
+	formats.retain(|_, format| !format.is_empty());
+	formats
+}
@@ -0,0 +1,86 @@
+//! Compare the database at two transactions.
+
+use crate::{DatabaseReader, DatabaseRecordIterator, Record};
+use std::iter::Peekable;
+
+/// A difference between two snapshots, see [`DatabaseReader::diff`]
+#[derive(Clone)]
+pub enum Diff {
+	/// The record is only in the second snapshot
+	Added(Record),
+	/// The record is only in the first snapshot
+	Removed(Record),
+	/// Both snapshots have a record with this key and timestamp,
+	/// but their formats or values differ
+	Changed { before: Record, after: Record },
+}
+
+impl DatabaseReader {
+	/// Compare the records in a range of keys as of two transactions
+	///
+	/// Reads `range` [`as_of`](crate::DatabaseRecordReader::as_of) `as_of_a` and
+	/// as of `as_of_b`, and yields the records that differ, ordered by key and timestamp.
+	/// Records are matched by their key and timestamp. With `as_of_a` lower
+	/// than `as_of_b`, this is what the transactions after `as_of_a` changed.
+	///
+	/// ```no_run
+	/// # let db = sonnerie::DatabaseReader::new(std::path::Path::new("")).unwrap();
+	/// # let (before, after) = (1, 2);
+	/// for diff in db.diff(.., before, after) {
+	///     if let sonnerie::Diff::Added(r) = diff {
+	///         println!("added {} at {}", r.key(), r.time());
+	///     }
+	/// }
+	/// ```
+	pub fn diff<'d>(
+		&'d self,
+		range: impl std::ops::RangeBounds<&'d str> + 'd + Clone,
+		as_of_a: usize,
+		as_of_b: usize,
+	) -> Differences<'d> {
+		Differences {
+			a: self
+				.get_range(range.clone())
+				.as_of(as_of_a)
+				.into_iter()
+				.peekable(),
+			b: self.get_range(range).as_of(as_of_b).into_iter().peekable(),
+		}
+	}
+}
+
+/// The differences between two snapshots, see [`DatabaseReader::diff`]
+pub struct Differences<'d> {
+	a: Peekable<DatabaseRecordIterator<'d>>,
+	b: Peekable<DatabaseRecordIterator<'d>>,
+}
+
+impl Iterator for Differences<'_> {
+	type Item = Diff;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		loop {
+			let order = match (self.a.peek(), self.b.peek()) {
+				(None, None) => return None,
+				(Some(_), None) => std::cmp::Ordering::Less,
+				(None, Some(_)) => std::cmp::Ordering::Greater,
+				(Some(a), Some(b)) => a
+					.key()
+					.cmp(b.key())
+					.then(a.timestamp_nanos().cmp(&b.timestamp_nanos())),
+			};
+
+			match order {
+				std::cmp::Ordering::Less => return self.a.next().map(Diff::Removed),
+				std::cmp::Ordering::Greater => return self.b.next().map(Diff::Added),
+				std::cmp::Ordering::Equal => {
+					let before = self.a.next().unwrap();
+					let after = self.b.next().unwrap();
+					if before.format() != after.format() || before.raw() != after.raw() {
+						return Some(Diff::Changed { before, after });
+					}
+				}
+			}
+		}
+	}
+}
@@ -0,0 +1,309 @@
+//! Downsample a key into time buckets, and remember the results.
+
+use crate::database_reader::numeric_value;
+use crate::segment_cache::FileIdentity;
+use crate::{DatabaseReader, DatabaseRecordIterator, Timestamp};
+use antidote::Mutex;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::{Bound, RangeBounds};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// How the values in a bucket are combined, see [`DatabaseReader::downsample`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Aggregate {
+	/// The number of values
+	Count,
+	Sum,
+	Mean,
+	Min,
+	Max,
+	/// The earliest value
+	First,
+	/// The latest value
+	Last,
+}
+
+/// The combined values of the records in a span of time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bucket {
+	/// The start of the bucket, in nanoseconds since the epoch
+	pub start: Timestamp,
+	/// The number of records in the bucket
+	pub count: u64,
+	/// The [`Aggregate`] of their values
+	pub value: f64,
+}
+
+impl DatabaseReader {
+	/// Combine the first column of `key`'s records into buckets of `bucket_ns` nanoseconds
+	///
+	/// A record is in the bucket that starts at its timestamp rounded
+	/// down to a multiple of `bucket_ns`. Only the records in `time_range`
+	/// (in nanoseconds since the epoch) are read. Integer and floating
+	/// point columns are converted to `f64`; records whose first column
+	/// isn't a number are skipped. Buckets without records aren't yielded.
+	///
+	/// To avoid computing the same buckets again, use a [`DownsampleCache`].
+	///
+	/// ```no_run
+	/// # let db = sonnerie::DatabaseReader::new(std::path::Path::new("")).unwrap();
+	/// let five_minutes = 5 * 60 * 1_000_000_000;
+	/// for bucket in db.downsample("temperature", five_minutes, sonnerie::Aggregate::Mean, ..) {
+	///     println!("{} {}", bucket.start, bucket.value);
+	/// }
+	/// ```
+	pub fn downsample<'d>(
+		&'d self,
+		key: &'d str,
+		bucket_ns: u64,
+		aggregate: Aggregate,
+		time_range: impl RangeBounds<Timestamp>,
+	) -> Downsample<'d> {
+		assert!(bucket_ns > 0, "bucket_ns must be nonzero");
+		let time_range = (
+			time_range.start_bound().cloned(),
+			time_range.end_bound().cloned(),
+		);
+		Downsample {
+			records: self
+				.get(key)
+				.filter_values(move |r| time_range.contains(&r.timestamp_nanos()))
+				.into_iter(),
+			bucket_ns,
+			aggregate,
+			next: None,
+		}
+	}
+}
+
+/// The buckets of a key, created by [`DatabaseReader::downsample`]
+pub struct Downsample<'d> {
+	records: DatabaseRecordIterator<'d>,
+	bucket_ns: u64,
+	aggregate: Aggregate,
+	/// a value that was read, but is in the next bucket
+	next: Option<(Timestamp, f64)>,
+}
+
+impl Downsample<'_> {
+	/// the timestamp and value of the next record with a numeric first column
+	fn next_value(&mut self) -> Option<(Timestamp, f64)> {
+		if let Some(next) = self.next.take() {
+			return Some(next);
+		}
+		for record in self.records.by_ref() {
+			let value = record
+				.column_data(0)
+				.ok()
+				.and_then(|(fmt_char, data)| numeric_value(fmt_char, data));
+			if let Some(value) = value {
+				return Some((record.timestamp_nanos(), value));
+			}
+		}
+		None
+	}
+}
+
+impl Iterator for Downsample<'_> {
+	type Item = Bucket;
+
+	fn next(&mut self) -> Option<Bucket> {
+		let (timestamp, first) = self.next_value()?;
+		let start = timestamp - timestamp % self.bucket_ns;
+		let (mut count, mut sum, mut min, mut max, mut last) = (1, first, first, first, first);
+		while let Some((timestamp, value)) = self.next_value() {
+			if timestamp - timestamp % self.bucket_ns != start {
+				self.next = Some((timestamp, value));
+				break;
+			}
+			count += 1;
+			sum += value;
+			min = min.min(value);
+			max = max.max(value);
+			last = value;
+		}
+
+		let value = match self.aggregate {
+			Aggregate::Count => count as f64,
+			Aggregate::Sum => sum,
+			Aggregate::Mean => sum / count as f64,
+			Aggregate::Min => min,
+			Aggregate::Max => max,
+			Aggregate::First => first,
+			Aggregate::Last => last,
+		};
+		Some(Bucket {
+			start,
+			count,
+			value,
+		})
+	}
+}
+
+/// The arguments of a downsample: key, `bucket_ns`, aggregate and time range
+type Query = (String, u64, Aggregate, (Bound<Timestamp>, Bound<Timestamp>));
+
+/// Identifies the records a key has in a database
+///
+/// The latest timestamp of a key isn't enough, because a record can
+/// be added before it (a backfill). Instead, this is each segment that
+/// may have records of the key, as the file that it's in and its position.
+/// Files never change, so the key has the same records as long as
+/// the same segments have it. A new transaction that has the key adds
+/// segments, and a compaction replaces the files. Deletions aren't
+/// specific to a key, so any new deletion changes every key's version.
+#[derive(PartialEq, Eq)]
+struct KeyVersion {
+	segments: Vec<(FileIdentity, usize)>,
+	deletions: Vec<PathBuf>,
+}
+
+impl KeyVersion {
+	fn of(db: &DatabaseReader, key: &str) -> KeyVersion {
+		let mut segments = vec![];
+		for (_txid, _path, reader) in &db.txes {
+			for segment in reader.key_segments(key) {
+				segments.push((reader.segments.identity, segment.segment_offset));
+			}
+		}
+		KeyVersion {
+			segments,
+			deletions: db
+				.filter_out
+				.iter()
+				.map(|(_, path, _)| path.clone())
+				.collect(),
+		}
+	}
+}
+
+/// A cache of downsampled keys, shared between readers
+///
+/// Requesting the same downsample again (the same key, `bucket_ns`,
+/// aggregate and time range) returns the buckets that were already
+/// computed, as long as the key has the same records. When the key
+/// changes, such as when records are added to it (even before its
+/// latest record) or deleted, its buckets are computed again.
+/// Checking reads the segment headers of the key, but doesn't decompress them.
+///
+/// The same cache can be used with each new `DatabaseReader` as the
+/// database changes. When the buckets exceed the cache's size, the
+/// least recently used downsamples are dropped.
+pub struct DownsampleCache {
+	budget: usize,
+	state: Mutex<DownsampleState>,
+	hits: AtomicU64,
+	misses: AtomicU64,
+}
+
+#[derive(Default)]
+struct DownsampleState {
+	entries: HashMap<Query, DownsampleEntry>,
+	/// the keys of `entries` by when they were last used
+	recency: BTreeMap<u64, Query>,
+	clock: u64,
+	buckets: usize,
+}
+
+struct DownsampleEntry {
+	version: KeyVersion,
+	buckets: Arc<[Bucket]>,
+	last_used: u64,
+}
+
+impl DownsampleCache {
+	/// Create a cache that holds up to `buckets` buckets
+	pub fn new(buckets: usize) -> DownsampleCache {
+		DownsampleCache {
+			budget: buckets,
+			state: Mutex::new(DownsampleState::default()),
+			hits: AtomicU64::new(0),
+			misses: AtomicU64::new(0),
+		}
+	}
+
+	/// How many times a downsample was found in the cache
+	pub fn hits(&self) -> u64 {
+		self.hits.load(Ordering::Relaxed)
+	}
+
+	/// How many times a downsample had to be computed
+	pub fn misses(&self) -> u64 {
+		self.misses.load(Ordering::Relaxed)
+	}
+
+	/// The number of buckets currently in the cache
+	pub fn buckets(&self) -> usize {
+		self.state.lock().buckets
+	}
+
+	/// Get the buckets of [`DatabaseReader::downsample`], computing them if
+	/// they aren't in the cache or if `key` has changed since
+	///
+	/// The buckets are computed without the lock held, so two
+	/// threads may compute the same downsample at the same time.
+	pub fn downsample(
+		&self,
+		db: &DatabaseReader,
+		key: &str,
+		bucket_ns: u64,
+		aggregate: Aggregate,
+		time_range: impl RangeBounds<Timestamp>,
+	) -> Arc<[Bucket]> {
+		let time_range = (
+			time_range.start_bound().cloned(),
+			time_range.end_bound().cloned(),
+		);
+		let query: Query = (key.to_owned(), bucket_ns, aggregate, time_range);
+		let version = KeyVersion::of(db, key);
+		{
+			let mut state = self.state.lock();
+			let state = &mut *state;
+			state.clock += 1;
+			if let Some(entry) = state.entries.get_mut(&query) {
+				if entry.version == version {
+					state.recency.remove(&entry.last_used);
+					entry.last_used = state.clock;
+					state.recency.insert(state.clock, query);
+					self.hits.fetch_add(1, Ordering::Relaxed);
+					return entry.buckets.clone();
+				}
+			}
+		}
+
+		self.misses.fetch_add(1, Ordering::Relaxed);
+		let buckets: Arc<[Bucket]> = db
+			.downsample(key, bucket_ns, aggregate, time_range)
+			.collect();
+		if buckets.len() > self.budget {
+			return buckets;
+		}
+
+		let mut state = self.state.lock();
+		let state = &mut *state;
+		state.clock += 1;
+		let entry = DownsampleEntry {
+			version,
+			buckets: buckets.clone(),
+			last_used: state.clock,
+		};
+		if let Some(old) = state.entries.insert(query.clone(), entry) {
+			state.recency.remove(&old.last_used);
+			state.buckets -= old.buckets.len();
+		}
+		state.recency.insert(state.clock, query);
+		state.buckets += buckets.len();
+
+		while state.buckets > self.budget {
+			let (_, oldest) = state.recency.pop_first().expect("cache is not empty");
+			let removed = state
+				.entries
+				.remove(&oldest)
+				.expect("recency matches entries");
+			state.buckets -= removed.buckets.len();
+		}
+		buckets
+	}
+}
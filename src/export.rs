@@ -0,0 +1,408 @@
+//! Export a database to a single file, and import it again.
+//!
+//! The export is a lossless copy of every record, in a self-describing
+//! container that's independent of how the database was split into
+//! transactions:
+//!
+//! * the header: the 16 bytes `SONNERIE_EXPORT\0` and a big-endian `u16` version (1)
+//! * any number of blocks: the byte `1`, a big-endian `u32` length and that many
+//!   bytes of LZ4 (frame format). Decompressed, a block is the key (a varint length and the
+//!   key) followed by its records, each as a varint length and the format,
+//!   then a varint length and the record's data (its timestamp followed by its columns).
+//!   Each block has the records of a single key; a key with many records has several blocks.
+//! * the footer: the byte `0`, then big-endian `u64`s of the number of blocks, the
+//!   number of records, and the FNV-1a hash of everything before the footer.
+//!
+//! A truncated or corrupted export is detected by the footer, and [`import`]
+//! writes nothing in that case.
+//!
+//! [`export_parallel`] writes a large database faster, as several exports
+//! that [`concatenate`] joins into one.
+
+use crate::{CreateTx, DatabaseReader, Record, WriteFailure};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 16] = b"SONNERIE_EXPORT\0";
+const VERSION: u16 = 1;
+const BLOCK: u8 = 1;
+const FOOTER: u8 = 0;
+/// the decompressed size at which a block is finished, even if the key has more records
+const BLOCK_SIZE_GOAL: usize = 1024 * 1024;
+
+/// FNV-1a, which is enough to detect a damaged file
+struct Checksum(u64);
+
+impl Checksum {
+	fn new() -> Self {
+		Checksum(0xcbf2_9ce4_8422_2325)
+	}
+	fn update(&mut self, bytes: &[u8]) {
+		for b in bytes {
+			self.0 ^= *b as u64;
+			self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+		}
+	}
+}
+
+/// Writes to `out` and updates the checksum
+struct Hashed<W: Write> {
+	out: W,
+	checksum: Checksum,
+}
+
+impl<W: Write> Hashed<W> {
+	fn write_all(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+		self.checksum.update(bytes);
+		self.out.write_all(bytes)
+	}
+}
+
+/// Reads from `input` and updates the checksum
+struct HashedReader<'r, R: Read> {
+	input: &'r mut R,
+	checksum: Checksum,
+}
+
+impl<R: Read> HashedReader<'_, R> {
+	fn read_exact(&mut self, buf: &mut [u8]) -> std::io::Result<()> {
+		read_exact_or_truncated(self.input, buf)?;
+		self.checksum.update(buf);
+		Ok(())
+	}
+}
+
+fn invalid(e: &str) -> std::io::Error {
+	std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string())
+}
+
+fn read_exact_or_truncated<R: Read>(input: &mut R, buf: &mut [u8]) -> std::io::Result<()> {
+	input.read_exact(buf).map_err(|e| {
+		if e.kind() == std::io::ErrorKind::UnexpectedEof {
+			invalid("the export is truncated")
+		} else {
+			e
+		}
+	})
+}
+
+/// remove a varint length and that many bytes from the beginning of `rest`
+fn take_varint_bytes<'a>(rest: &mut &'a [u8]) -> std::io::Result<&'a [u8]> {
+	let (len, tail) = unsigned_varint::decode::usize(rest)
+		.map_err(|_| invalid("the export is damaged (bad length)"))?;
+	if tail.len() < len {
+		return Err(invalid("the export is damaged (bad length)"));
+	}
+	let (item, tail) = tail.split_at(len);
+	*rest = tail;
+	Ok(item)
+}
+
+fn push_varint_bytes(dest: &mut Vec<u8>, bytes: &[u8]) {
+	let mut lenbuf = unsigned_varint::encode::usize_buffer();
+	dest.extend_from_slice(unsigned_varint::encode::usize(bytes.len(), &mut lenbuf));
+	dest.extend_from_slice(bytes);
+}
+
+/// Writes the blocks of an export as records are pushed to it
+struct Exporter<W: Write> {
+	out: Hashed<W>,
+	blocks: u64,
+	records: u64,
+	block: Vec<u8>,
+	/// the key of `block`, None before the first record
+	block_key: Option<String>,
+}
+
+impl<W: Write> Exporter<W> {
+	fn new(out: W) -> std::io::Result<Self> {
+		let mut out = Hashed {
+			out,
+			checksum: Checksum::new(),
+		};
+		out.write_all(MAGIC)?;
+		out.write_all(&VERSION.to_be_bytes())?;
+		Ok(Exporter {
+			out,
+			blocks: 0,
+			records: 0,
+			block: vec![],
+			block_key: None,
+		})
+	}
+
+	fn push(&mut self, record: &Record) -> std::io::Result<()> {
+		if self.block_key.as_deref() != Some(record.key()) || self.block.len() >= BLOCK_SIZE_GOAL {
+			self.finish_block()?;
+			match &mut self.block_key {
+				Some(key) => key.replace_range(.., record.key()),
+				None => self.block_key = Some(record.key().to_string()),
+			}
+			push_varint_bytes(&mut self.block, record.key().as_bytes());
+		}
+		push_varint_bytes(&mut self.block, record.format().as_bytes());
+		push_varint_bytes(&mut self.block, record.raw());
+		self.records += 1;
+		Ok(())
+	}
+
+	fn finish_block(&mut self) -> std::io::Result<()> {
+		if self.block.is_empty() {
+			return Ok(());
+		}
+		let mut encoder = lz4::EncoderBuilder::new().build(vec![])?;
+		encoder.write_all(&self.block)?;
+		let (compressed, e) = encoder.finish();
+		e?;
+		self.write_block(&compressed)?;
+		self.block.clear();
+		Ok(())
+	}
+
+	/// write an already compressed block
+	fn write_block(&mut self, compressed: &[u8]) -> std::io::Result<()> {
+		let len = u32::try_from(compressed.len())
+			.map_err(|_| std::io::Error::other("export block is too large"))?;
+
+		self.out.write_all(&[BLOCK])?;
+		self.out.write_all(&len.to_be_bytes())?;
+		self.out.write_all(compressed)?;
+		self.blocks += 1;
+		Ok(())
+	}
+
+	/// write the footer, returning the output and the number of records
+	fn finish(mut self) -> std::io::Result<(W, u64)> {
+		self.finish_block()?;
+		let checksum = self.out.checksum.0;
+		self.out.write_all(&[FOOTER])?;
+		self.out.write_all(&self.blocks.to_be_bytes())?;
+		self.out.write_all(&self.records.to_be_bytes())?;
+		self.out.write_all(&checksum.to_be_bytes())?;
+		self.out.out.flush()?;
+		Ok((self.out.out, self.records))
+	}
+}
+
+/// Write every record of `db` to `out`
+///
+/// Returns the number of records written. Deleted records and
+/// older versions of records are not exported.
+pub fn export<W: Write>(db: &DatabaseReader, out: &mut W) -> std::io::Result<u64> {
+	let mut exporter = Exporter::new(out)?;
+	for record in db.get_range(..) {
+		exporter.push(&record)?;
+	}
+	let (_, records) = exporter.finish()?;
+	Ok(records)
+}
+
+/// Export every record of `db` into several files in `dir`, in parallel
+///
+/// The database is split by key in the same way as its parallel
+/// iterators, and each part is written by its own thread to
+/// `dir/shard.00000`, `dir/shard.00001` and so on. Each shard is a
+/// complete export of a contiguous range of keys, so the shards
+/// in order have the same records in the same order as [`export`].
+/// [`concatenate`] joins them into a single export. The shards of an
+/// earlier export to `dir` that aren't replaced are removed, so that
+/// the shards in `dir` are only those of this export.
+///
+/// Returns the shards' paths, in order.
+pub fn export_parallel(db: &DatabaseReader, dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+	use rayon::iter::ParallelIterator;
+
+	std::fs::create_dir_all(dir)?;
+	let new_shard = || {
+		let file = tempfile::NamedTempFile::new_in(dir)?;
+		Exporter::new(std::io::BufWriter::new(file))
+	};
+
+	// `fold` gives one exporter per contiguous part of the database, and
+	// `collect` keeps those in the order of the keys
+	let shards: Vec<std::io::Result<(tempfile::NamedTempFile, u64)>> = db
+		.get_range(..)
+		.fold(new_shard, |exporter, record| {
+			let mut exporter = exporter?;
+			exporter.push(&record)?;
+			Ok(exporter)
+		})
+		.map(|exporter| {
+			let (w, records) = exporter?.finish()?;
+			let file = w.into_inner().map_err(|e| e.into_error())?;
+			file.as_file().sync_all()?;
+			Ok((file, records))
+		})
+		.collect();
+
+	let mut paths = vec![];
+	for shard in shards {
+		let (file, records) = shard?;
+		if records == 0 && !paths.is_empty() {
+			continue;
+		}
+		let path = dir.join(format!("shard.{:05}", paths.len()));
+		file.persist(&path)?;
+		paths.push(path);
+	}
+
+	for entry in std::fs::read_dir(dir)? {
+		let path = entry?.path();
+		let is_shard = path
+			.file_name()
+			.and_then(|n| n.to_str())
+			.and_then(|n| n.strip_prefix("shard."))
+			.is_some_and(|n| !n.is_empty() && n.bytes().all(|c| c.is_ascii_digit()));
+		if is_shard && !paths.contains(&path) {
+			std::fs::remove_file(&path)?;
+		}
+	}
+	Ok(paths)
+}
+
+/// Join exports, such as the shards made by [`export_parallel`], into one
+///
+/// The exports must be given in key order and must not have
+/// any keys in common. Each is checked as it is read, and the
+/// blocks are copied without being decompressed.
+/// Returns the number of records written.
+pub fn concatenate<R: Read, W: Write>(inputs: &mut [R], out: &mut W) -> std::io::Result<u64> {
+	let mut exporter = Exporter::new(out)?;
+	let mut compressed = vec![];
+	let mut block = vec![];
+	for input in inputs {
+		let mut input = HashedReader {
+			input,
+			checksum: Checksum::new(),
+		};
+		read_header(&mut input)?;
+		let mut blocks = 0u64;
+		let mut records = 0u64;
+		while next_block(&mut input, &mut compressed, blocks, records)? {
+			decompress(&compressed, &mut block)?;
+			records += for_each_record(&block, |_, _, _| Ok::<_, std::io::Error>(()))?;
+			blocks += 1;
+			exporter.write_block(&compressed)?;
+		}
+		exporter.records += records;
+	}
+	let (_, records) = exporter.finish()?;
+	Ok(records)
+}
+
+/// Read an export made by [`export`] into the `main` of the database at `dir`
+///
+/// `dir` must not have a database in it yet (it may be an empty directory,
+/// or not exist). The records are only committed after the
+/// whole export is read and its footer is checked, so if this fails,
+/// `dir` is left as it was. An interrupted import doesn't resume where
+/// it stopped; it has to be run again from the beginning.
+/// Returns the number of records imported.
+pub fn import<R: Read>(input: &mut R, dir: &Path) -> Result<u64, WriteFailure> {
+	std::fs::create_dir_all(dir)?;
+	let main = dir.join("main");
+	if std::fs::metadata(&main).is_ok_and(|m| m.len() != 0) {
+		return Err(invalid("the directory already has a database").into());
+	}
+
+	let mut input = HashedReader {
+		input,
+		checksum: Checksum::new(),
+	};
+	read_header(&mut input)?;
+
+	let mut tx = CreateTx::new(dir)?;
+	let mut blocks = 0u64;
+	let mut records = 0u64;
+	let mut compressed = vec![];
+	let mut block = vec![];
+
+	while next_block(&mut input, &mut compressed, blocks, records)? {
+		decompress(&compressed, &mut block)?;
+		records += for_each_record(&block, |key, format, data| {
+			tx.add_record_raw(key, format, data)
+		})?;
+		blocks += 1;
+	}
+
+	tx.commit_to(&main)?;
+	Ok(records)
+}
+
+fn read_header<R: Read>(input: &mut HashedReader<R>) -> std::io::Result<()> {
+	let mut header = [0u8; 18];
+	input.read_exact(&mut header)?;
+	if &header[..16] != MAGIC {
+		return Err(invalid("not a sonnerie export"));
+	}
+	if u16::from_be_bytes([header[16], header[17]]) != VERSION {
+		return Err(invalid("unsupported export version"));
+	}
+	Ok(())
+}
+
+/// Read the next compressed block into `compressed`
+///
+/// Returns false at the footer, after checking it against the
+/// number of `blocks` and `records` that came before it.
+fn next_block<R: Read>(
+	input: &mut HashedReader<R>,
+	compressed: &mut Vec<u8>,
+	blocks: u64,
+	records: u64,
+) -> std::io::Result<bool> {
+	let checksum_before = input.checksum.0;
+	let mut tag = [0u8];
+	input.read_exact(&mut tag)?;
+	match tag[0] {
+		BLOCK => {}
+		FOOTER => {
+			let mut footer = [0u8; 24];
+			read_exact_or_truncated(input.input, &mut footer)?;
+			let number = |i: usize| u64::from_be_bytes(footer[i..i + 8].try_into().unwrap());
+			if number(0) != blocks || number(8) != records || number(16) != checksum_before {
+				return Err(invalid("the export is damaged (its footer doesn't match)"));
+			}
+			if input.input.read(&mut [0u8])? != 0 {
+				return Err(invalid("unexpected data after the end of the export"));
+			}
+			return Ok(false);
+		}
+		_ => return Err(invalid("the export is damaged (unknown block type)")),
+	}
+
+	let mut len = [0u8; 4];
+	input.read_exact(&mut len)?;
+	compressed.resize(u32::from_be_bytes(len) as usize, 0);
+	input.read_exact(compressed)?;
+	Ok(true)
+}
+
+fn decompress(compressed: &[u8], block: &mut Vec<u8>) -> std::io::Result<()> {
+	block.clear();
+	lz4::Decoder::new(compressed)?
+		.read_to_end(block)
+		.map_err(|_| invalid("the export is damaged (bad compressed block)"))?;
+	Ok(())
+}
+
+/// Call `f` with the key, format and data of each record in a decompressed block
+///
+/// Returns the number of records.
+fn for_each_record<E: From<std::io::Error>>(
+	block: &[u8],
+	mut f: impl FnMut(&str, &str, &[u8]) -> Result<(), E>,
+) -> Result<u64, E> {
+	let mut rest = block;
+	let utf8 = |b| std::str::from_utf8(b).map_err(|_| invalid("the export is damaged (bad utf-8)"));
+
+	let key = utf8(take_varint_bytes(&mut rest)?)?;
+	let mut records = 0;
+	while !rest.is_empty() {
+		let format = utf8(take_varint_bytes(&mut rest)?)?;
+		let data = take_varint_bytes(&mut rest)?;
+		f(key, format, data)?;
+		records += 1;
+	}
+	Ok(records)
+}
@@ -1,9 +1,252 @@
-//! Read or write formatted data to a text stream.
+//! Read or write formatted data to a text stream, or as binary frames.
 
 use crate::row_format::*;
 use crate::Timestamp;
 use byteorder::ByteOrder;
 use escape_string::split_one;
+use std::borrow::Cow;
+
+/// How [`add_from_stream_opts`] and [`add_from_stream_with_fmt_opts`] read lines
+#[derive(Debug, Clone)]
+pub struct TextOptions<'a> {
+	/// Lines that start with this, possibly after some whitespace, are ignored.
+	/// Keys that start with it therefore can't be added. Often `#`.
+	pub comment_prefix: Option<&'a str>,
+	/// Ignore lines that are empty or only whitespace, instead
+	/// of failing (the default is true)
+	pub skip_blank_lines: bool,
+	/// Remove whitespace from the end of each line before parsing it
+	/// (the default is true). Turn this off to keep an escaped space
+	/// (`\ `) at the end of the last value.
+	pub trim_trailing_whitespace: bool,
+	/// The unit of the timestamps, when they're integers because no
+	/// `timestamp_format` is given (the default is nanoseconds)
+	pub timestamp_unit: TimestampUnit,
+	/// The time zone of the timestamps that are parsed with a
+	/// `timestamp_format` (the default is UTC)
+	pub time_zone: TimeZone,
+}
+
+impl Default for TextOptions<'_> {
+	fn default() -> Self {
+		TextOptions {
+			comment_prefix: None,
+			skip_blank_lines: true,
+			trim_trailing_whitespace: true,
+			timestamp_unit: TimestampUnit::Nanos,
+			time_zone: TimeZone::Utc,
+		}
+	}
+}
+
+/// The unit of timestamps that are integers since the unix epoch, see [`TextOptions`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum TimestampUnit {
+	/// Nanoseconds
+	#[default]
+	Nanos,
+	/// Microseconds
+	Micros,
+	/// Milliseconds
+	Millis,
+	/// Seconds
+	Seconds,
+}
+
+impl TimestampUnit {
+	/// The number of nanoseconds in one of this unit
+	pub fn nanos(self) -> u64 {
+		match self {
+			TimestampUnit::Nanos => 1,
+			TimestampUnit::Micros => 1_000,
+			TimestampUnit::Millis => 1_000_000,
+			TimestampUnit::Seconds => 1_000_000_000,
+		}
+	}
+
+	/// Convert `t` of this unit to nanoseconds, or `None` if it's too large
+	pub fn to_timestamp(self, t: u64) -> Option<Timestamp> {
+		t.checked_mul(self.nanos())
+	}
+}
+
+/// A time zone for timestamps that are printed or parsed as text
+///
+/// Timestamps are always stored in UTC, so this only changes how they're
+/// read and written. Named zones need the `timezones` feature.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum TimeZone {
+	/// UTC
+	#[default]
+	Utc,
+	/// A fixed offset from UTC
+	Fixed(chrono::FixedOffset),
+	/// A zone of the IANA database, with its daylight saving time
+	#[cfg(feature = "timezones")]
+	Named(chrono_tz::Tz),
+}
+
+impl TimeZone {
+	/// The time in this zone at `utc`
+	pub fn from_utc(&self, utc: chrono::NaiveDateTime) -> chrono::NaiveDateTime {
+		match self {
+			TimeZone::Utc => utc,
+			TimeZone::Fixed(offset) => utc.and_utc().with_timezone(offset).naive_local(),
+			#[cfg(feature = "timezones")]
+			TimeZone::Named(tz) => utc.and_utc().with_timezone(tz).naive_local(),
+		}
+	}
+
+	/// The time in UTC of `local` in this zone
+	///
+	/// When the clocks go back, a local time happens twice and the
+	/// earlier one is returned. When they go forward, some local times
+	/// don't happen at all, and then this returns `None`.
+	pub fn to_utc(&self, local: chrono::NaiveDateTime) -> Option<chrono::NaiveDateTime> {
+		use chrono::TimeZone as _;
+		match self {
+			TimeZone::Utc => Some(local),
+			TimeZone::Fixed(offset) => offset
+				.from_local_datetime(&local)
+				.earliest()
+				.map(|t| t.naive_utc()),
+			#[cfg(feature = "timezones")]
+			TimeZone::Named(tz) => tz
+				.from_local_datetime(&local)
+				.earliest()
+				.map(|t| t.naive_utc()),
+		}
+	}
+
+	/// Write `utc` in this zone with the `strftime` format `strf`, in which `%z` is this zone's offset
+	fn write_formatted<W: std::io::Write>(
+		&self,
+		out: &mut W,
+		utc: chrono::NaiveDateTime,
+		strf: &str,
+	) -> std::io::Result<()> {
+		match self {
+			TimeZone::Utc => write!(out, "{}", utc.and_utc().format(strf)),
+			TimeZone::Fixed(offset) => {
+				write!(out, "{}", utc.and_utc().with_timezone(offset).format(strf))
+			}
+			#[cfg(feature = "timezones")]
+			TimeZone::Named(tz) => write!(out, "{}", utc.and_utc().with_timezone(tz).format(strf)),
+		}
+	}
+}
+
+/// Parses `UTC`, an offset like `+05:30` or `-0800`, or (with the
+/// `timezones` feature) the name of a zone, like `Europe/Paris`
+impl std::str::FromStr for TimeZone {
+	type Err = String;
+	fn from_str(s: &str) -> Result<TimeZone, String> {
+		if s.eq_ignore_ascii_case("utc") || s == "Z" {
+			return Ok(TimeZone::Utc);
+		}
+		if let Some(offset) = parse_offset(s) {
+			return Ok(TimeZone::Fixed(offset));
+		}
+		#[cfg(feature = "timezones")]
+		if let Ok(tz) = s.parse::<chrono_tz::Tz>() {
+			return Ok(TimeZone::Named(tz));
+		}
+		if cfg!(feature = "timezones") {
+			Err(format!("unknown time zone {:?}", s))
+		} else {
+			Err(format!(
+				"unknown time zone {:?} (expected UTC or an offset like +05:30; \
+				named zones need the \"timezones\" feature)",
+				s
+			))
+		}
+	}
+}
+
+/// parse `+hh:mm`, `+hhmm` or `+hh` (or with `-`)
+fn parse_offset(s: &str) -> Option<chrono::FixedOffset> {
+	let (sign, digits) = match s.as_bytes().first()? {
+		b'+' => (1, &s[1..]),
+		b'-' => (-1, &s[1..]),
+		_ => return None,
+	};
+	let digits = digits.replacen(':', "", 1);
+	if !digits.bytes().all(|b| b.is_ascii_digit()) {
+		return None;
+	}
+	let (hours, minutes) = match digits.len() {
+		2 => (digits.parse::<i32>().ok()?, 0),
+		4 => (
+			digits[..2].parse::<i32>().ok()?,
+			digits[2..].parse::<i32>().ok()?,
+		),
+		_ => return None,
+	};
+	if minutes >= 60 {
+		return None;
+	}
+	chrono::FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+/// Parses `nanos`, `micros`, `millis` or `seconds`
+impl std::str::FromStr for TimestampUnit {
+	type Err = String;
+	fn from_str(s: &str) -> Result<TimestampUnit, String> {
+		match s {
+			"nanos" => Ok(TimestampUnit::Nanos),
+			"micros" => Ok(TimestampUnit::Micros),
+			"millis" => Ok(TimestampUnit::Millis),
+			"seconds" => Ok(TimestampUnit::Seconds),
+			s => Err(format!(
+				"unknown timestamp unit {:?} (expected nanos, micros, millis or seconds)",
+				s
+			)),
+		}
+	}
+}
+
+/// Read the next line that has a record in it into `line`, returning
+/// its contents, or None at the end of `input`
+fn next_record_line<'l, R: std::io::BufRead>(
+	input: &mut R,
+	line: &'l mut String,
+	line_number: &mut usize,
+	options: &TextOptions<'_>,
+) -> Result<Option<&'l str>, crate::WriteFailure> {
+	loop {
+		line.clear();
+		if input.read_line(line)? == 0 {
+			return Ok(None);
+		}
+		*line_number += 1;
+
+		let text = line.strip_suffix('\n').unwrap_or(line);
+		let text = text.strip_suffix('\r').unwrap_or(text);
+		if text.trim().is_empty() {
+			if options.skip_blank_lines {
+				continue;
+			}
+			return Err(crate::WriteFailure::InvalidLine {
+				line: *line_number,
+				message: "blank line".to_string(),
+			});
+		}
+		if let Some(prefix) = options.comment_prefix {
+			if text.trim_start().starts_with(prefix) {
+				continue;
+			}
+		}
+		break;
+	}
+
+	let text = line.strip_suffix('\n').unwrap_or(line);
+	let text = text.strip_suffix('\r').unwrap_or(text);
+	if options.trim_trailing_whitespace {
+		Ok(Some(text.trim_end()))
+	} else {
+		Ok(Some(text))
+	}
+}
 
 /// Read keys from a text stream and insert it into a transaction
 ///
@@ -11,40 +254,55 @@ use escape_string::split_one;
 /// * `tx` - a transaction to write into
 /// * `db` - the database that is type-checked against
 /// * `format` - the format of each row. If each row
-/// contains its own format, you can instead use [`add_from_stream_with_fmt`].
+///   contains its own format, you can instead use [`add_from_stream_with_fmt`].
 /// * `input` - a text stream to read from, the keys are formatted as
-/// `label timestamp value [value ...]`. Whitespace is escaped with a backslash.
+///   `label timestamp value [value ...]`. Whitespace is escaped with a backslash.
 /// * `timestamp` - the strftime-like format to parse timestamps as. If `None`, use
-/// epoch nanos.
+///   epoch nanos.
 /// * `nocheck` - turns off slow type checking (with `db`).
 pub fn add_from_stream<R: std::io::BufRead>(
 	tx: &mut crate::CreateTx,
 	format: &str,
 	input: &mut R,
 	timestamp_format: Option<&str>,
+) -> Result<(), crate::WriteFailure> {
+	add_from_stream_opts(tx, format, input, timestamp_format, &TextOptions::default())
+}
+
+/// Like [`add_from_stream`], with options for skipping comments
+/// and blank lines, see [`TextOptions`]
+pub fn add_from_stream_opts<R: std::io::BufRead>(
+	tx: &mut crate::CreateTx,
+	format: &str,
+	input: &mut R,
+	timestamp_format: Option<&str>,
+	options: &TextOptions<'_>,
 ) -> Result<(), crate::WriteFailure> {
 	let row_format = parse_row_format(format);
 
 	let mut line = String::new();
+	let mut line_number = 0;
 	let mut row_data = vec![]; // this is single allocation only
 
-	while 0 != input.read_line(&mut line).unwrap() {
-		let tail = line.trim_end();
-		if tail.is_empty() {
-			continue;
-		}
+	while let Some(tail) = next_record_line(input, &mut line, &mut line_number, options)? {
 		let (key, tail) = split_one(tail).unwrap();
 		let (timestamp, tail) = split_one(tail).unwrap();
 		let ts: Timestamp;
 		if let Some(f) = timestamp_format.as_ref() {
 			let n = chrono::NaiveDateTime::parse_from_str(&timestamp, f)
 				.expect("parsing timestamp according to format");
-			ts = n
+			ts = options
+				.time_zone
+				.to_utc(n)
+				.ok_or(crate::WriteFailure::UnableToParseTimestamp)?
 				.and_utc()
 				.timestamp_nanos_opt()
 				.ok_or(crate::WriteFailure::UnableToParseTimestamp)? as Timestamp;
 		} else {
-			ts = timestamp.parse().expect("parsing timestamp");
+			ts = options
+				.timestamp_unit
+				.to_timestamp(timestamp.parse().expect("parsing timestamp"))
+				.ok_or(crate::WriteFailure::UnableToParseTimestamp)?;
 		}
 
 		row_format
@@ -53,7 +311,6 @@ pub fn add_from_stream<R: std::io::BufRead>(
 
 		tx.add_record_raw(&key, format, &row_data)?;
 		row_data.clear();
-		line.clear();
 	}
 
 	Ok(())
@@ -62,60 +319,571 @@ pub fn add_from_stream<R: std::io::BufRead>(
 /// Reads from text, each record reports its own format.
 ///
 /// Like [`add_from_stream`] except the format string
-/// comes after the timestamp
+/// comes after the timestamp. A line with only a key and a
+/// timestamp is a record with an empty format.
 pub fn add_from_stream_with_fmt<R: std::io::BufRead>(
 	tx: &mut crate::CreateTx,
 	input: &mut R,
 	timestamp_format: Option<&str>,
+) -> Result<(), crate::WriteFailure> {
+	add_from_stream_with_fmt_opts(tx, input, timestamp_format, &TextOptions::default())
+}
+
+/// Like [`add_from_stream_with_fmt`], with options for skipping
+/// comments and blank lines, see [`TextOptions`]
+pub fn add_from_stream_with_fmt_opts<R: std::io::BufRead>(
+	tx: &mut crate::CreateTx,
+	input: &mut R,
+	timestamp_format: Option<&str>,
+	options: &TextOptions<'_>,
 ) -> Result<(), crate::WriteFailure> {
 	let mut line = String::new();
+	let mut line_number = 0;
 	let mut row_data = vec![];
 
-	while 0 != input.read_line(&mut line).unwrap() {
-		let tail = line.trim_end();
-		if tail.is_empty() {
+	while let Some(tail) = next_record_line(input, &mut line, &mut line_number, options)? {
+		let (key, _, format) =
+			parse_line_with_fmt_opts(tail, line_number, timestamp_format, options, &mut row_data)?;
+		tx.add_record_raw(&key, &format, &row_data)?;
+		row_data.clear();
+	}
+
+	Ok(())
+}
+
+/// Parse one line of the input of [`add_from_stream_with_fmt`]
+///
+/// The line is `key timestamp format [value ...]`, and the values
+/// are written to `row_data` in the stored format. Returns the
+/// key, the timestamp and the format.
+///
+/// When the line can't be parsed, the error is a
+/// [`WriteFailure::InvalidLine`](crate::WriteFailure::InvalidLine) with
+/// `line_number`, and its message has the part of the line that's invalid.
+pub fn parse_line_with_fmt<'l>(
+	line: &'l str,
+	line_number: usize,
+	timestamp_format: Option<&str>,
+	row_data: &mut Vec<u8>,
+) -> Result<(Cow<'l, str>, Timestamp, Cow<'l, str>), crate::WriteFailure> {
+	parse_line_with_fmt_opts(
+		line,
+		line_number,
+		timestamp_format,
+		&TextOptions::default(),
+		row_data,
+	)
+}
+
+/// [`parse_line_with_fmt`] with the timestamp unit and time zone of `options`
+fn parse_line_with_fmt_opts<'l>(
+	line: &'l str,
+	line_number: usize,
+	timestamp_format: Option<&str>,
+	options: &TextOptions<'_>,
+	row_data: &mut Vec<u8>,
+) -> Result<(Cow<'l, str>, Timestamp, Cow<'l, str>), crate::WriteFailure> {
+	let invalid = |message: String| crate::WriteFailure::InvalidLine {
+		line: line_number,
+		message,
+	};
+
+	let (key, tail) = split_one(line)
+		.filter(|(key, _)| !key.is_empty())
+		.ok_or_else(|| invalid("no key".to_string()))?;
+	let (timestamp, tail) = split_one(tail)
+		.filter(|(timestamp, _)| !timestamp.is_empty())
+		.ok_or_else(|| invalid("no timestamp".to_string()))?;
+	let ts: Timestamp = if let Some(f) = timestamp_format {
+		chrono::NaiveDateTime::parse_from_str(&timestamp, f)
+			.ok()
+			.and_then(|n| options.time_zone.to_utc(n))
+			.and_then(|n| n.and_utc().timestamp_nanos_opt())
+			.ok_or_else(|| invalid(format!("invalid timestamp \"{}\"", timestamp)))? as Timestamp
+	} else {
+		let t = timestamp
+			.parse()
+			.map_err(|e| invalid(format!("invalid timestamp \"{}\": {}", timestamp, e)))?;
+		options
+			.timestamp_unit
+			.to_timestamp(t)
+			.ok_or_else(|| invalid(format!("the timestamp {} is out of range", timestamp)))?
+	};
+
+	let (format, values) = split_one(tail).unwrap_or(("".into(), ""));
+	if let Some(c) = format.chars().find(|c| !"cChHiuIUfFsB".contains(*c)) {
+		return Err(invalid(format!(
+			"invalid format character '{}' in \"{}\"",
+			c, format
+		)));
+	}
+	row_data.clear();
+	parse_row_format(&format)
+		.to_stored_format(ts, values, row_data)
+		.map_err(|e| invalid(format!("at {}, invalid values \"{}\": {}", ts, values, e)))?;
+
+	Ok((key, ts, format))
+}
+
+/// Read newline-delimited JSON and insert it into a transaction
+///
+/// Each line is an object like `{"key":"a","t":"2010-01-01T00:00:01","v":[1.5,"x"]}`:
+/// * `key` - the key, a string
+/// * `t` (or `timestamp`) - the timestamp, either a string in ISO-8601 format (with or without
+///   a timezone offset, a missing offset means UTC) or an integer of nanoseconds
+///   since the unix epoch
+/// * `v` (or `values`) - the values of the columns, an array
+///
+/// If `format` is given, every record has that format and the values are
+/// converted to it. Otherwise, each key's format is inferred from the types of its
+/// first record: a nonnegative integer is `U`, any other number is `F` and
+/// a string is `s`. There's no boolean column type, so booleans are stored
+/// as `u` (0 or 1). All the records of a key must have the same inferred
+/// format; write integers as `1.0` to have them stored as floats.
+/// Byte strings (`B`) are never inferred; with `format`, their values are
+/// strings of hexadecimal digits, as printed by [`print_record_json`].
+///
+/// The input doesn't need to be sorted, all of it is read into memory
+/// and sorted by key and timestamp before it's written to `tx`. Empty lines are ignored.
+///
+/// Errors in the input are reported as [`WriteFailure::InvalidLine`](crate::WriteFailure::InvalidLine),
+/// and nothing is written to `tx` in that case.
+pub fn add_from_ndjson<R: std::io::BufRead>(
+	tx: &mut crate::CreateTx,
+	input: &mut R,
+	format: Option<&str>,
+) -> Result<(), crate::WriteFailure> {
+	if let Some(format) = format {
+		// validate the format
+		parse_row_format(format);
+	}
+
+	struct Row {
+		key: String,
+		ts: Timestamp,
+		format: String,
+		data: Vec<u8>,
+	}
+
+	let mut rows = vec![];
+	// the inferred format of each key, and the line where it was inferred
+	let mut formats = std::collections::HashMap::<String, (String, usize)>::new();
+
+	let mut line = String::new();
+	let mut line_number = 0;
+	loop {
+		line.clear();
+		if input.read_line(&mut line)? == 0 {
+			break;
+		}
+		line_number += 1;
+		if line.trim().is_empty() {
 			continue;
 		}
-		let (key, tail) = split_one(tail).unwrap();
-		let (timestamp, tail) = split_one(tail).unwrap();
-		let ts: Timestamp;
-		if let Some(f) = timestamp_format.as_ref() {
-			let n = chrono::NaiveDateTime::parse_from_str(&timestamp, f)
-				.expect("parsing timestamp according to format");
-			ts = n
-				.and_utc()
-				.timestamp_nanos_opt()
-				.ok_or(crate::WriteFailure::UnableToParseTimestamp)? as Timestamp;
-		} else {
-			ts = timestamp.parse().expect("parsing timestamp");
+		let invalid = |message: String| crate::WriteFailure::InvalidLine {
+			line: line_number,
+			message,
+		};
+
+		let object: serde_json::Value =
+			serde_json::from_str(&line).map_err(|e| invalid(e.to_string()))?;
+		let key = object
+			.get("key")
+			.and_then(|k| k.as_str())
+			.ok_or_else(|| invalid("\"key\" is missing or not a string".to_string()))?;
+		let ts = object
+			.get("t")
+			.or_else(|| object.get("timestamp"))
+			.ok_or_else(|| invalid("\"t\" is missing".to_string()))
+			.and_then(|t| json_timestamp(t).map_err(invalid))?;
+		let values = object
+			.get("v")
+			.or_else(|| object.get("values"))
+			.and_then(|v| v.as_array())
+			.ok_or_else(|| invalid("\"v\" is missing or not an array".to_string()))?;
+
+		let row_format = match format {
+			Some(format) => format.to_owned(),
+			None => {
+				let inferred = values
+					.iter()
+					.map(|v| json_column_type(v).map(char::from))
+					.collect::<Result<String, _>>()
+					.map_err(invalid)?;
+				if let Some((first, first_line)) = formats.get(key) {
+					if *first != inferred {
+						return Err(invalid(format!(
+							"the values of key {:?} have the format {:?}, but had {:?} on line {}",
+							key, inferred, first, first_line
+						)));
+					}
+				} else {
+					formats.insert(key.to_owned(), (inferred.clone(), line_number));
+				}
+				inferred
+			}
+		};
+
+		if values.len() != row_format.len() {
+			return Err(invalid(format!(
+				"{} values for the format {:?}",
+				values.len(),
+				row_format
+			)));
 		}
 
-		let (format, values) = split_one(tail).unwrap();
-		let row_format = parse_row_format(&format);
+		let mut data = Vec::with_capacity(8 + row_format.len() * 8);
+		data.extend_from_slice(&ts.to_be_bytes());
+		for (col, (c, value)) in row_format.bytes().zip(values).enumerate() {
+			encode_json_value(c, value, &mut data)
+				.map_err(|e| invalid(format!("column {}: {}", col, e)))?;
+		}
 
-		row_format
-			.to_stored_format(ts, values, &mut row_data)
-			.unwrap();
+		rows.push(Row {
+			key: key.to_owned(),
+			ts,
+			format: row_format,
+			data,
+		});
+	}
 
-		tx.add_record_raw(&key, &format, &row_data)?;
-		row_data.clear();
-		line.clear();
+	rows.sort_by(|a, b| a.key.cmp(&b.key).then(a.ts.cmp(&b.ts)));
+	for row in rows {
+		tx.add_record_raw(&row.key, &row.format, &row.data)?;
+	}
+
+	Ok(())
+}
+
+/// parse a JSON timestamp (an ISO-8601 string or epoch nanos)
+fn json_timestamp(t: &serde_json::Value) -> Result<Timestamp, String> {
+	let naive = match t {
+		serde_json::Value::Number(n) => {
+			return n
+				.as_u64()
+				.ok_or_else(|| format!("the timestamp {} is not an integer of nanoseconds", n));
+		}
+		serde_json::Value::String(s) => {
+			if let Ok(dt) = chrono::DateTime::parse_from_rfc3339(s) {
+				dt.naive_utc()
+			} else {
+				chrono::NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f")
+					.or_else(|_| chrono::NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f"))
+					.map_err(|_| format!("the timestamp {:?} is not in ISO-8601 format", s))?
+			}
+		}
+		_ => return Err("\"t\" is not a string or a number".to_string()),
+	};
+	naive
+		.and_utc()
+		.timestamp_nanos_opt()
+		.and_then(|n| Timestamp::try_from(n).ok())
+		.ok_or_else(|| format!("the timestamp {} is out of range", t))
+}
+
+/// the format character for a JSON value
+fn json_column_type(v: &serde_json::Value) -> Result<u8, String> {
+	match v {
+		serde_json::Value::Number(n) if n.is_u64() => Ok(b'U'),
+		serde_json::Value::Number(_) => Ok(b'F'),
+		serde_json::Value::String(_) => Ok(b's'),
+		serde_json::Value::Bool(_) => Ok(b'u'),
+		other => Err(format!("{} can't be stored in a column", other)),
+	}
+}
+
+/// append `v` to `dest`, encoded as the format character `c`
+fn encode_json_value(c: u8, v: &serde_json::Value, dest: &mut Vec<u8>) -> Result<(), String> {
+	let mismatch = || format!("{} can't be stored as '{}'", v, c as char);
+	let integer = |v: &serde_json::Value| match v {
+		serde_json::Value::Bool(b) => Some(*b as i128),
+		serde_json::Value::Number(n) => n
+			.as_u64()
+			.map(i128::from)
+			.or_else(|| n.as_i64().map(i128::from)),
+		_ => None,
+	};
+
+	match c {
+		b'c' => {
+			let n = integer(v).and_then(|n| i8::try_from(n).ok());
+			dest.extend_from_slice(&n.ok_or_else(mismatch)?.to_be_bytes());
+		}
+		b'C' => {
+			let n = integer(v).and_then(|n| u8::try_from(n).ok());
+			dest.extend_from_slice(&n.ok_or_else(mismatch)?.to_be_bytes());
+		}
+		b'h' => {
+			let n = integer(v).and_then(|n| i16::try_from(n).ok());
+			dest.extend_from_slice(&n.ok_or_else(mismatch)?.to_be_bytes());
+		}
+		b'H' => {
+			let n = integer(v).and_then(|n| u16::try_from(n).ok());
+			dest.extend_from_slice(&n.ok_or_else(mismatch)?.to_be_bytes());
+		}
+		b'i' => {
+			let n = integer(v).and_then(|n| i32::try_from(n).ok());
+			dest.extend_from_slice(&n.ok_or_else(mismatch)?.to_be_bytes());
+		}
+		b'u' => {
+			let n = integer(v).and_then(|n| u32::try_from(n).ok());
+			dest.extend_from_slice(&n.ok_or_else(mismatch)?.to_be_bytes());
+		}
+		b'I' => {
+			let n = integer(v).and_then(|n| i64::try_from(n).ok());
+			dest.extend_from_slice(&n.ok_or_else(mismatch)?.to_be_bytes());
+		}
+		b'U' => {
+			let n = integer(v).and_then(|n| u64::try_from(n).ok());
+			dest.extend_from_slice(&n.ok_or_else(mismatch)?.to_be_bytes());
+		}
+		b'f' => {
+			let n = v.as_f64().ok_or_else(mismatch)? as f32;
+			dest.extend_from_slice(&n.to_be_bytes());
+		}
+		b'F' => {
+			let n = v.as_f64().ok_or_else(mismatch)?;
+			dest.extend_from_slice(&n.to_be_bytes());
+		}
+		b's' => {
+			let s = v.as_str().ok_or_else(mismatch)?;
+			let mut lenbuf = unsigned_varint::encode::usize_buffer();
+			dest.extend_from_slice(unsigned_varint::encode::usize(s.len(), &mut lenbuf));
+			dest.extend_from_slice(s.as_bytes());
+		}
+		b'B' => {
+			let s = v.as_str().ok_or_else(mismatch)?;
+			if s.len() % 2 != 0 || !s.bytes().all(|c| c.is_ascii_hexdigit()) {
+				return Err(mismatch());
+			}
+			let mut lenbuf = unsigned_varint::encode::usize_buffer();
+			dest.extend_from_slice(unsigned_varint::encode::usize(s.len() / 2, &mut lenbuf));
+			for pair in s.as_bytes().chunks(2) {
+				let pair = std::str::from_utf8(pair).unwrap();
+				dest.push(u8::from_str_radix(pair, 16).unwrap());
+			}
+		}
+		_ => return Err(mismatch()),
+	}
+	Ok(())
+}
+
+/// Read InfluxDB line protocol and insert it into a transaction
+///
+/// Each line is `measurement[,tag=value...] field=value[,field=value...] [timestamp]`,
+/// with commas, spaces and `=` escaped with a backslash, as InfluxDB
+/// writes it. Each field is a key of its own, `measurement:tags:field`, where
+/// `tags` are the tags sorted by name and separated by commas (with their
+/// escapes kept, so the key can't be ambiguous), such as
+/// `cpu:host=a,region=eu:usage`; it's empty for a line without tags, as in
+/// `cpu::usage`. The record of a field has a single column, whose format
+/// is from the type of the value:
+/// * a float (`1.5`, `1`, `-2e3`) is `F`
+/// * an integer (`3i`) is `I` and an unsigned integer (`3u`) is `U`
+/// * a string (`"x"`, with `\"` and `\\` escaped) is `s`
+/// * a boolean (`t`, `true`, `f`, `false`, in any of InfluxDB's cases) is `u`, 0 or 1
+///
+/// The timestamp is in nanoseconds since the unix epoch. A line without
+/// one is at the time that this function was called, as InfluxDB uses the
+/// time that it received the line.
+///
+/// Like [`add_from_ndjson`], the input doesn't need to be sorted: all of it
+/// is read into memory and sorted by key and timestamp before it's written to
+/// `tx`. Of the records with the same key and timestamp, the last one in
+/// the input is kept. Blank lines and lines that start with `#` are ignored.
+///
+/// Errors in the input are reported as [`WriteFailure::InvalidLine`](crate::WriteFailure::InvalidLine),
+/// and nothing is written to `tx` in that case.
+pub fn add_from_influx_stream<R: std::io::BufRead>(
+	tx: &mut crate::CreateTx,
+	input: &mut R,
+) -> Result<(), crate::WriteFailure> {
+	let now = std::time::SystemTime::now()
+		.duration_since(std::time::UNIX_EPOCH)
+		.map_err(|_| crate::WriteFailure::UnableToParseTimestamp)?
+		.as_nanos() as Timestamp;
+
+	struct Row {
+		key: String,
+		format: u8,
+		data: Vec<u8>,
+	}
+
+	let options = TextOptions {
+		comment_prefix: Some("#"),
+		..TextOptions::default()
+	};
+	let mut rows = vec![];
+	let mut line = String::new();
+	let mut line_number = 0;
+	while let Some(text) = next_record_line(input, &mut line, &mut line_number, &options)? {
+		let invalid = |message: String| crate::WriteFailure::InvalidLine {
+			line: line_number,
+			message,
+		};
+
+		let sections = split_influx(text.trim_start(), ' ');
+		let (series, fields, timestamp) = match sections[..] {
+			[series, fields] => (series, fields, None),
+			[series, fields, timestamp] => (series, fields, Some(timestamp)),
+			_ => {
+				return Err(invalid(
+					"expected a measurement, fields and a timestamp".to_string(),
+				))
+			}
+		};
+		let ts = match timestamp {
+			Some(t) => t
+				.parse::<Timestamp>()
+				.map_err(|e| invalid(format!("invalid timestamp \"{}\": {}", t, e)))?,
+			None => now,
+		};
+
+		let mut tags = split_influx(series, ',');
+		let measurement = tags.remove(0);
+		if measurement.is_empty() {
+			return Err(invalid("no measurement".to_string()));
+		}
+		for tag in &tags {
+			if split_influx(tag, '=').len() != 2 {
+				return Err(invalid(format!("invalid tag \"{}\"", tag)));
+			}
+		}
+		tags.sort_by_key(|tag| split_influx(tag, '=')[0]);
+		let prefix = format!("{}:{}:", measurement, tags.join(","));
+
+		for field in split_influx(fields, ',') {
+			let (name, value) = match split_influx(field, '=')[..] {
+				[name, value] if !name.is_empty() => (name, value),
+				_ => return Err(invalid(format!("invalid field \"{}\"", field))),
+			};
+			let mut data = Vec::with_capacity(16);
+			data.extend_from_slice(&ts.to_be_bytes());
+			let format = encode_influx_value(value, &mut data)
+				.map_err(|e| invalid(format!("field \"{}\": {}", name, e)))?;
+			rows.push(Row {
+				key: format!("{}{}", prefix, name),
+				format,
+				data,
+			});
+		}
+	}
+
+	// stable, so that the last of the records at the same time is last
+	rows.sort_by(|a, b| a.key.cmp(&b.key).then(a.data[..8].cmp(&b.data[..8])));
+	let mut rows = rows.into_iter().peekable();
+	while let Some(row) = rows.next() {
+		if let Some(next) = rows.peek() {
+			if next.key == row.key && next.data[..8] == row.data[..8] {
+				continue;
+			}
+		}
+		let format = [row.format];
+		tx.add_record_raw(&row.key, std::str::from_utf8(&format).unwrap(), &row.data)?;
 	}
 
 	Ok(())
 }
 
+/// split at each `sep` that isn't escaped with a backslash or in a quoted string
+fn split_influx(text: &str, sep: char) -> Vec<&str> {
+	let mut parts = vec![];
+	let mut start = 0;
+	let mut escaped = false;
+	let mut quoted = false;
+	for (i, c) in text.char_indices() {
+		if escaped {
+			escaped = false;
+		} else if c == '\\' {
+			escaped = true;
+		} else if c == '"' {
+			quoted = !quoted;
+		} else if c == sep && !quoted {
+			parts.push(&text[start..i]);
+			start = i + 1;
+		}
+	}
+	parts.push(&text[start..]);
+	parts
+}
+
+/// append the value of an InfluxDB field to `dest`, returning its format character
+fn encode_influx_value(value: &str, dest: &mut Vec<u8>) -> Result<u8, String> {
+	if let Some(quoted) = value.strip_prefix('"') {
+		let quoted = quoted
+			.strip_suffix('"')
+			.ok_or_else(|| format!("unterminated string {}", value))?;
+		let mut s = String::with_capacity(quoted.len());
+		let mut chars = quoted.chars();
+		while let Some(c) = chars.next() {
+			match c {
+				'\\' => match chars.next() {
+					Some(e @ ('"' | '\\')) => s.push(e),
+					Some(e) => {
+						s.push('\\');
+						s.push(e);
+					}
+					None => s.push('\\'),
+				},
+				c => s.push(c),
+			}
+		}
+		let mut lenbuf = unsigned_varint::encode::usize_buffer();
+		dest.extend_from_slice(unsigned_varint::encode::usize(s.len(), &mut lenbuf));
+		dest.extend_from_slice(s.as_bytes());
+		return Ok(b's');
+	}
+
+	match value {
+		"t" | "T" | "true" | "True" | "TRUE" => {
+			dest.extend_from_slice(&1u32.to_be_bytes());
+			return Ok(b'u');
+		}
+		"f" | "F" | "false" | "False" | "FALSE" => {
+			dest.extend_from_slice(&0u32.to_be_bytes());
+			return Ok(b'u');
+		}
+		_ => {}
+	}
+
+	let invalid = |e: &dyn std::fmt::Display| format!("invalid value {}: {}", value, e);
+	if let Some(n) = value.strip_suffix('i') {
+		let n: i64 = n.parse().map_err(|e| invalid(&e))?;
+		dest.extend_from_slice(&n.to_be_bytes());
+		Ok(b'I')
+	} else if let Some(n) = value.strip_suffix('u') {
+		let n: u64 = n.parse().map_err(|e| invalid(&e))?;
+		dest.extend_from_slice(&n.to_be_bytes());
+		Ok(b'U')
+	} else {
+		let n: f64 = value.parse().map_err(|e| invalid(&e))?;
+		dest.extend_from_slice(&n.to_be_bytes());
+		Ok(b'F')
+	}
+}
+
 /// Print the record format (`uUfF`) right after the timestamp
 #[derive(Debug, Copy, Clone)]
-pub enum PrintRecordFormat {
+#[non_exhaustive]
+pub enum PrintRecordFormat<'a> {
 	/// Do
 	Yes,
 	/// Or do not
 	No,
+	/// Don't print it in each row, instead [`print_record`] prints a line
+	/// with [`print_format_header`] before the first record of each key
+	/// (and when the format changes). The [`FormatHeader`] keeps track of
+	/// the previous record, so use one for each stream.
+	OncePerKey(&'a FormatHeader),
 }
 
+/// The key and format of the previous record printed
+/// with [`PrintRecordFormat::OncePerKey`]
+#[derive(Debug, Default)]
+pub struct FormatHeader(std::sync::Mutex<Option<(String, String)>>);
+
 /// Print record formats by default
-impl std::default::Default for PrintRecordFormat {
+impl std::default::Default for PrintRecordFormat<'static> {
 	fn default() -> Self {
 		PrintRecordFormat::Yes
 	}
@@ -123,14 +891,22 @@ impl std::default::Default for PrintRecordFormat {
 
 /// Specify how to print the timestamp
 #[derive(Debug, Copy, Clone)]
+#[non_exhaustive]
 pub enum PrintTimestamp<'a> {
 	/// Print the timestamp as nanoseconds since the unix epoch
 	Nanos,
+	/// Print the timestamp as microseconds since the unix epoch (rounded down)
+	Micros,
+	/// Print the timestamp as milliseconds since the unix epoch (rounded down)
+	Millis,
 	/// Print the timestamp as seconds since the unix epoch
 	Seconds,
 	/// Print the timestamp according to this `strftime` format.
 	/// Refer to [`chrono`](https://docs.rs/chrono/*/chrono/format/strftime/)
 	FormatString(&'a str),
+	/// Print the timestamp in a time zone according to this `strftime`
+	/// format, in which `%z` is the zone's offset
+	ZonedFormatString(&'a str, TimeZone),
 }
 
 /// Format as `%FT%T` (ISO-8601)
@@ -140,18 +916,75 @@ impl std::default::Default for PrintTimestamp<'static> {
 	}
 }
 
+/// Write a line that names the key and format of the records that follow
+///
+/// [`print_record`] calls this for [`PrintRecordFormat::OncePerKey`]. The line is `#`, a space, the
+/// key (escaped as in [`print_record`]), a tab and the format.
+/// It's only printed when the key or the format is different from
+/// the previous record's, which `previous` keeps track of (start with `None`).
+pub fn print_format_header<W: std::io::Write>(
+	record: &crate::Record,
+	out: &mut W,
+	previous: &mut Option<(String, String)>,
+) -> std::io::Result<()> {
+	if let Some((key, format)) = previous {
+		if key == record.key() && format == record.format() {
+			return Ok(());
+		}
+	}
+	writeln!(
+		out,
+		"# {}\t{}",
+		escape_string::escape(record.key()),
+		record.format()
+	)?;
+	*previous = Some((record.key().to_owned(), record.format().to_owned()));
+	Ok(())
+}
+
 /// Write a formatted record to a stream
 ///
 /// Each row is written in the same format that [`add_from_stream`]
 /// or [`add_from_stream_with_fmt`] accept, depending
 /// on the options for the parameters `print_timestamp`
 /// or `print_record_format`.
+///
+/// A record with an empty format is printed as only its key and timestamp.
+///
+/// Whitespace (including newlines and tabs) in the key and in string
+/// columns is escaped with a backslash, so each record is on a single line
+/// no matter what its key is.
+///
+/// Floating point values are printed with the shortest representation
+/// that reads back as the same value, see [`print_record_with_precision`].
 pub fn print_record<W: std::io::Write>(
 	record: &crate::Record,
 	out: &mut W,
 	print_timestamp: PrintTimestamp<'_>,
-	print_record_format: PrintRecordFormat,
+	print_record_format: PrintRecordFormat<'_>,
 	column_selection: &choice_string::Selection,
+) -> std::io::Result<()> {
+	print_record_with_precision(
+		record,
+		out,
+		print_timestamp,
+		print_record_format,
+		column_selection,
+		FloatPrecision::Shortest,
+	)
+}
+
+/// Like [`print_record`], but choose how floating point values are printed.
+///
+/// [`FloatPrecision::Digits`] rounds the values, so they might not
+/// read back in as exactly the same value.
+pub fn print_record_with_precision<W: std::io::Write>(
+	record: &crate::Record,
+	out: &mut W,
+	print_timestamp: PrintTimestamp<'_>,
+	print_record_format: PrintRecordFormat<'_>,
+	column_selection: &choice_string::Selection,
+	float_precision: FloatPrecision,
 ) -> std::io::Result<()> {
 	let fmt_string = record.format();
 	let fmt = parse_row_format(fmt_string);
@@ -160,25 +993,21 @@ pub fn print_record<W: std::io::Write>(
 	let value = &record.raw()[8..];
 	let ts: u64 = byteorder::BigEndian::read_u64(ts);
 
+	if let PrintRecordFormat::OncePerKey(header) = print_record_format {
+		print_format_header(record, out, &mut header.0.lock().unwrap())?;
+	}
 	write!(out, "{}\t", escape_string::escape(key))?;
+	write_timestamp(out, ts, print_timestamp)?;
 
-	match print_timestamp {
-		PrintTimestamp::Nanos => write!(out, "{}", ts)?,
-		PrintTimestamp::Seconds => write!(out, "{}", ts / 1_000_000_000)?,
-		PrintTimestamp::FormatString(strf) => {
-			let ts = chrono::DateTime::from_timestamp(
-				(ts / 1_000_000_000) as i64,
-				(ts % 1_000_000_000) as u32,
-			)
-			.unwrap();
-			write!(out, "{}", ts.format(strf))?;
-		}
+	// a record with no columns is just the key and timestamp
+	if fmt_string.is_empty() {
+		return Ok(());
 	}
 
 	write!(out, "\t")?;
 	match print_record_format {
 		PrintRecordFormat::Yes => write!(out, "{}\t", fmt_string)?,
-		PrintRecordFormat::No => {}
+		PrintRecordFormat::No | PrintRecordFormat::OncePerKey(_) => {}
 	}
 
 	let mut value = value;
@@ -189,10 +1018,407 @@ pub fn print_record<W: std::io::Write>(
 				write!(out, " ")?;
 			}
 			first = false;
-			value = e.to_protocol_format(value, out)?;
+			value = e.to_protocol_format_with_precision(value, out, float_precision)?;
 		} else {
 			value = e.to_protocol_format(value, &mut std::io::sink())?;
 		}
 	}
 	Ok(())
 }
+
+fn write_timestamp<W: std::io::Write>(
+	out: &mut W,
+	ts: Timestamp,
+	print_timestamp: PrintTimestamp<'_>,
+) -> std::io::Result<()> {
+	match print_timestamp {
+		PrintTimestamp::Nanos => write!(out, "{}", ts),
+		PrintTimestamp::Micros => write!(out, "{}", ts / 1_000),
+		PrintTimestamp::Millis => write!(out, "{}", ts / 1_000_000),
+		PrintTimestamp::Seconds => write!(out, "{}", ts / 1_000_000_000),
+		PrintTimestamp::FormatString(strf) => match timestamp_to_time(ts) {
+			Some(t) => write!(out, "{}", t.format(strf)),
+			// print what's there instead of failing the whole read
+			None => write!(out, "{}", ts),
+		},
+		PrintTimestamp::ZonedFormatString(strf, tz) => match timestamp_to_time(ts) {
+			Some(t) => tz.write_formatted(out, t, strf),
+			None => write!(out, "{}", ts),
+		},
+	}
+}
+
+/// Convert nanoseconds since the unix epoch to a time, or
+/// `None` if it can't be represented
+pub(crate) fn timestamp_to_time(ts: Timestamp) -> Option<chrono::NaiveDateTime> {
+	chrono::DateTime::from_timestamp((ts / 1_000_000_000) as i64, (ts % 1_000_000_000) as u32)
+		.map(|t| t.naive_utc())
+}
+
+/// Write a record to a stream as a line of CSV ([RFC 4180](https://www.rfc-editor.org/rfc/rfc4180)),
+/// without the line ending
+///
+/// The fields are the key, the timestamp, the format (only with
+/// [`PrintRecordFormat::Yes`]) and the columns in `column_selection`.
+/// The key, the timestamp and string columns are quoted if they have
+/// a comma, a double quote or a line break, and their double quotes
+/// are doubled; unlike [`print_record`], nothing is escaped with a backslash.
+/// Bytes are written in hexadecimal, and floating point values with
+/// `float_precision`, as in [`print_record_with_precision`].
+pub fn print_record_csv<W: std::io::Write>(
+	record: &crate::Record,
+	out: &mut W,
+	print_timestamp: PrintTimestamp<'_>,
+	print_record_format: PrintRecordFormat<'_>,
+	column_selection: &choice_string::Selection,
+	float_precision: FloatPrecision,
+) -> std::io::Result<()> {
+	use crate::ColumnValue;
+
+	write_csv_field(out, record.key())?;
+	let mut ts = vec![];
+	write_timestamp(&mut ts, record.timestamp_nanos(), print_timestamp)?;
+	write!(out, ",")?;
+	write_csv_field(out, &String::from_utf8_lossy(&ts))?;
+	if let PrintRecordFormat::Yes = print_record_format {
+		write!(out, ",{}", record.format())?;
+	}
+
+	for (idx, column) in record.columns_checked().enumerate() {
+		let column = column?;
+		if !column_selection.contains_item(idx + 1) {
+			continue;
+		}
+		write!(out, ",")?;
+		match (column, float_precision) {
+			(ColumnValue::I8(v), _) => write!(out, "{}", v)?,
+			(ColumnValue::U8(v), _) => write!(out, "{}", v)?,
+			(ColumnValue::I16(v), _) => write!(out, "{}", v)?,
+			(ColumnValue::U16(v), _) => write!(out, "{}", v)?,
+			(ColumnValue::I32(v), _) => write!(out, "{}", v)?,
+			(ColumnValue::U32(v), _) => write!(out, "{}", v)?,
+			(ColumnValue::I64(v), _) => write!(out, "{}", v)?,
+			(ColumnValue::U64(v), _) => write!(out, "{}", v)?,
+			(ColumnValue::F32(v), FloatPrecision::Shortest) => write!(out, "{}", v)?,
+			(ColumnValue::F32(v), FloatPrecision::Digits(d)) => write!(out, "{:.*}", d, v)?,
+			(ColumnValue::F64(v), FloatPrecision::Shortest) => write!(out, "{}", v)?,
+			(ColumnValue::F64(v), FloatPrecision::Digits(d)) => write!(out, "{:.*}", d, v)?,
+			(ColumnValue::Str(v), _) => write_csv_field(out, v)?,
+			(ColumnValue::Bytes(v), _) => {
+				for b in v {
+					write!(out, "{:02x}", b)?;
+				}
+			}
+		}
+	}
+	Ok(())
+}
+
+/// Write a record to a stream as a JSON object, without the line ending
+///
+/// The object is `{"key":"…","timestamp":…,"values":[…]}`, with
+/// `"format":"…"` after the timestamp with [`PrintRecordFormat::Yes`].
+/// The timestamp is a number with [`PrintTimestamp::Nanos`], [`PrintTimestamp::Micros`],
+/// [`PrintTimestamp::Millis`] or [`PrintTimestamp::Seconds`], and otherwise a string. The values are the
+/// columns in `column_selection`: numbers are JSON numbers (floating point
+/// values are written with `float_precision`, and those that aren't finite
+/// are `null`), strings are JSON strings and bytes are strings of hexadecimal.
+///
+/// A line of this can be read back with [`add_from_ndjson`], if the
+/// timestamp is in nanoseconds or in ISO-8601 format.
+pub fn print_record_json<W: std::io::Write>(
+	record: &crate::Record,
+	out: &mut W,
+	print_timestamp: PrintTimestamp<'_>,
+	print_record_format: PrintRecordFormat<'_>,
+	column_selection: &choice_string::Selection,
+	float_precision: FloatPrecision,
+) -> std::io::Result<()> {
+	use crate::ColumnValue;
+	use serde_json::to_string as json;
+
+	fn float<W: std::io::Write>(
+		out: &mut W,
+		v: f64,
+		shortest: std::fmt::Arguments,
+		precision: FloatPrecision,
+	) -> std::io::Result<()> {
+		match precision {
+			_ if !v.is_finite() => write!(out, "null"),
+			FloatPrecision::Shortest => out.write_fmt(shortest),
+			FloatPrecision::Digits(d) => write!(out, "{:.*}", d, v),
+		}
+	}
+
+	write!(out, "{{\"key\":{},\"timestamp\":", json(record.key())?)?;
+	let ts = record.timestamp_nanos();
+	match print_timestamp {
+		PrintTimestamp::Nanos
+		| PrintTimestamp::Micros
+		| PrintTimestamp::Millis
+		| PrintTimestamp::Seconds => write_timestamp(out, ts, print_timestamp)?,
+		PrintTimestamp::FormatString(_) | PrintTimestamp::ZonedFormatString(..) => {
+			let mut formatted = vec![];
+			write_timestamp(&mut formatted, ts, print_timestamp)?;
+			write!(out, "{}", json(&*String::from_utf8_lossy(&formatted))?)?;
+		}
+	}
+	if let PrintRecordFormat::Yes = print_record_format {
+		write!(out, ",\"format\":{}", json(record.format())?)?;
+	}
+
+	write!(out, ",\"values\":[")?;
+	let mut first = true;
+	for (idx, column) in record.columns_checked().enumerate() {
+		let column = column?;
+		if !column_selection.contains_item(idx + 1) {
+			continue;
+		}
+		if !first {
+			write!(out, ",")?;
+		}
+		first = false;
+		match column {
+			ColumnValue::I8(v) => write!(out, "{}", v)?,
+			ColumnValue::U8(v) => write!(out, "{}", v)?,
+			ColumnValue::I16(v) => write!(out, "{}", v)?,
+			ColumnValue::U16(v) => write!(out, "{}", v)?,
+			ColumnValue::I32(v) => write!(out, "{}", v)?,
+			ColumnValue::U32(v) => write!(out, "{}", v)?,
+			ColumnValue::I64(v) => write!(out, "{}", v)?,
+			ColumnValue::U64(v) => write!(out, "{}", v)?,
+			ColumnValue::F32(v) => float(out, v as f64, format_args!("{}", v), float_precision)?,
+			ColumnValue::F64(v) => float(out, v, format_args!("{}", v), float_precision)?,
+			ColumnValue::Str(v) => write!(out, "{}", json(v)?)?,
+			ColumnValue::Bytes(v) => {
+				write!(out, "\"")?;
+				for b in v {
+					write!(out, "{:02x}", b)?;
+				}
+				write!(out, "\"")?;
+			}
+		}
+	}
+	write!(out, "]}}")
+}
+
+/// write `field`, in double quotes if it needs them
+fn write_csv_field<W: std::io::Write>(out: &mut W, field: &str) -> std::io::Result<()> {
+	if field.contains([',', '"', '\r', '\n']) {
+		write!(out, "\"{}\"", field.replace('"', "\"\""))
+	} else {
+		write!(out, "{}", field)
+	}
+}
+
+/// A file name for the records of `key`, see [`SplitPerKey`]
+///
+/// The characters that can't be in a file name, or that have a
+/// special meaning in one, are replaced by `%` and their hexadecimal
+/// value: `/` is `%2F`, `:` is `%3A`, `\` is `%5C`, a nul is `%00` and
+/// `%` itself is `%25`. A `.` at the start is `%2E`, so that
+/// no key is a hidden file, `.` or `..`. The empty key is `%`
+/// alone, which no other key is, so that its file isn't named only
+/// by its extension. [`key_from_file_name`] reverses this.
+pub fn key_file_name(key: &str) -> String {
+	if key.is_empty() {
+		return "%".to_owned();
+	}
+	let mut name = String::with_capacity(key.len());
+	for (i, c) in key.char_indices() {
+		match c {
+			'/' | ':' | '\\' | '\0' | '%' => name.push_str(&format!("%{:02X}", c as u8)),
+			'.' if i == 0 => name.push_str("%2E"),
+			c => name.push(c),
+		}
+	}
+	name
+}
+
+/// The key whose records are in the file named `name` by [`key_file_name`]
+///
+/// Returns `None` if `name` has a `%` that isn't followed by
+/// two hexadecimal digits, other than the `%` of the empty key.
+pub fn key_from_file_name(name: &str) -> Option<String> {
+	if name == "%" {
+		return Some(String::new());
+	}
+	let mut key = Vec::with_capacity(name.len());
+	let mut bytes = name.bytes();
+	while let Some(b) = bytes.next() {
+		if b == b'%' {
+			let hex = [bytes.next()?, bytes.next()?];
+			let hex = std::str::from_utf8(&hex).ok()?;
+			key.push(u8::from_str_radix(hex, 16).ok()?);
+		} else {
+			key.push(b);
+		}
+	}
+	String::from_utf8(key).ok()
+}
+
+/// Write the records of each key to its own file
+///
+/// Each key's file is `dir/` [`key_file_name`] `.extension`. The records
+/// must be sorted by key, as they're read from a database, so only one
+/// file is open at a time: a key's file is created when its first record
+/// is written, and closed when a record of another key is.
+pub struct SplitPerKey {
+	dir: std::path::PathBuf,
+	extension: String,
+	current: Option<(String, std::io::BufWriter<std::fs::File>)>,
+	paths: Vec<std::path::PathBuf>,
+}
+
+impl SplitPerKey {
+	/// Write files into `dir`, which is created if necessary
+	pub fn new(dir: &std::path::Path, extension: &str) -> std::io::Result<SplitPerKey> {
+		std::fs::create_dir_all(dir)?;
+		Ok(SplitPerKey {
+			dir: dir.to_owned(),
+			extension: extension.to_owned(),
+			current: None,
+			paths: vec![],
+		})
+	}
+
+	/// The file to write the records of `key` into
+	pub fn writer(&mut self, key: &str) -> std::io::Result<&mut impl std::io::Write> {
+		if self.current.as_ref().is_none_or(|(k, _)| k != key) {
+			self.close()?;
+			let path = self
+				.dir
+				.join(format!("{}.{}", key_file_name(key), self.extension));
+			let file = std::fs::File::create(&path)?;
+			self.paths.push(path);
+			self.current = Some((key.to_owned(), std::io::BufWriter::new(file)));
+		}
+		Ok(&mut self.current.as_mut().unwrap().1)
+	}
+
+	fn close(&mut self) -> std::io::Result<()> {
+		if let Some((_, mut out)) = self.current.take() {
+			std::io::Write::flush(&mut out)?;
+		}
+		Ok(())
+	}
+
+	/// Close the last file, and return the paths of all of the files, in key order
+	pub fn finish(mut self) -> std::io::Result<Vec<std::path::PathBuf>> {
+		self.close()?;
+		Ok(self.paths)
+	}
+}
+
+/// Write records as length-prefixed binary frames
+///
+/// Each record is written as its key, its format and its
+/// [`raw`](crate::Record::raw) data, each preceded by its length
+/// as a varint. Unlike the text formats, this is lossless, so it's
+/// suitable for piping records between processes. Read the frames
+/// with [`read_record_frames`].
+///
+/// Returns the number of records written.
+pub fn write_record_frames<W: std::io::Write>(
+	records: impl IntoIterator<Item = crate::Record>,
+	out: &mut W,
+) -> std::io::Result<u64> {
+	let mut n = 0;
+	for record in records {
+		write_frame(out, record.key(), record.format(), record.raw())?;
+		n += 1;
+	}
+	Ok(n)
+}
+
+/// Write one record's frame, see [`write_record_frames`]
+pub(crate) fn write_frame<W: std::io::Write>(
+	out: &mut W,
+	key: &str,
+	format: &str,
+	data: &[u8],
+) -> std::io::Result<()> {
+	let mut lenbuf = unsigned_varint::encode::usize_buffer();
+	for part in [key.as_bytes(), format.as_bytes(), data] {
+		out.write_all(unsigned_varint::encode::usize(part.len(), &mut lenbuf))?;
+		out.write_all(part)?;
+	}
+	Ok(())
+}
+
+/// Read the frames written by [`write_record_frames`]
+///
+/// Yields the key, format and data of each record, which can
+/// be passed to [`CreateTx::add_record_raw`](crate::CreateTx::add_record_raw).
+/// The input may end only between frames.
+pub fn read_record_frames<R: std::io::Read>(input: R) -> RecordFrames<R> {
+	RecordFrames { input }
+}
+
+/// An iterator over records in binary frames, see [`read_record_frames`]
+pub struct RecordFrames<R: std::io::Read> {
+	input: R,
+}
+
+impl<R: std::io::Read> RecordFrames<R> {
+	/// read a varint length, or None at the end of the input
+	fn read_len(&mut self) -> std::io::Result<Option<usize>> {
+		let mut buf = [0u8; 10];
+		for i in 0..buf.len() {
+			match self.input.read_exact(&mut buf[i..=i]) {
+				Err(e) if i == 0 && e.kind() == std::io::ErrorKind::UnexpectedEof => {
+					return Ok(None)
+				}
+				r => r?,
+			}
+			if unsigned_varint::decode::is_last(buf[i]) {
+				let (len, _) = unsigned_varint::decode::usize(&buf[..=i]).map_err(|e| {
+					std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string())
+				})?;
+				return Ok(Some(len));
+			}
+		}
+		Err(std::io::Error::new(
+			std::io::ErrorKind::InvalidData,
+			"frame length is too long",
+		))
+	}
+
+	/// read a length and that many bytes
+	fn read_part(&mut self, len: Option<usize>) -> std::io::Result<Vec<u8>> {
+		use std::io::Read;
+
+		let len = len.ok_or(std::io::ErrorKind::UnexpectedEof)?;
+		let mut part = vec![];
+		(&mut self.input).take(len as u64).read_to_end(&mut part)?;
+		if part.len() != len {
+			return Err(std::io::ErrorKind::UnexpectedEof.into());
+		}
+		Ok(part)
+	}
+
+	fn read_frame(&mut self) -> std::io::Result<Option<(String, String, Vec<u8>)>> {
+		let utf8 = |b| {
+			String::from_utf8(b)
+				.map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+		};
+
+		let Some(len) = self.read_len()? else {
+			return Ok(None);
+		};
+		let key = utf8(self.read_part(Some(len))?)?;
+		let len = self.read_len()?;
+		let format = utf8(self.read_part(len)?)?;
+		let len = self.read_len()?;
+		let data = self.read_part(len)?;
+		Ok(Some((key, format, data)))
+	}
+}
+
+impl<R: std::io::Read> Iterator for RecordFrames<R> {
+	type Item = std::io::Result<(String, String, Vec<u8>)>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.read_frame().transpose()
+	}
+}
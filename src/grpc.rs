@@ -0,0 +1,333 @@
+//! Read and write a database over gRPC.
+//!
+//! Enabled by the `grpc` feature. [`SonnerieService`] is a
+//! [tonic](https://docs.rs/tonic) service for a database directory with
+//! two methods, in the protobuf package `sonnerie`:
+//!
+//! ```text
+//! service Sonnerie {
+//!   rpc Read(ReadRequest) returns (stream Record);
+//!   rpc Write(stream Record) returns (WriteResponse);
+//! }
+//! ```
+//!
+//! The messages are [`ReadRequest`], [`Record`] and [`WriteResponse`].
+//! `Read` streams the records as they're read from the database, so
+//! a large read isn't held in the server's memory. `Write` accepts the
+//! records in any order: they're sorted with a [`SortingCreateTx`]
+//! and committed as one transaction when the client finishes its stream.
+//! If any record is invalid, nothing is committed.
+//!
+//! ```no_run
+//! # async fn serve() -> Result<(), Box<dyn std::error::Error>> {
+//! let service = sonnerie::grpc::SonnerieService::new(std::path::Path::new("db"));
+//! tonic::transport::Server::builder()
+//!     .add_service(service.into_server())
+//!     .serve("127.0.0.1:5599".parse()?)
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{DatabaseReader, RecordVec, SortingCreateTx, WriteFailure};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use tonic::{Request, Response, Status, Streaming};
+
+include!(concat!(env!("OUT_DIR"), "/sonnerie.Sonnerie.rs"));
+
+pub use sonnerie_client::SonnerieClient;
+pub use sonnerie_server::{Sonnerie, SonnerieServer};
+
+/// How many records `Read` reads ahead of the client
+const READ_AHEAD: usize = 256;
+
+/// Select the records to read
+///
+/// The keys and times are both ranges; an empty request reads
+/// the whole database.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ReadRequest {
+	/// The first key (inclusive)
+	#[prost(string, tag = "1")]
+	pub first_key: String,
+	/// The last key (inclusive), or no limit
+	#[prost(string, optional, tag = "2")]
+	pub last_key: Option<String>,
+	/// Only records at or after this time, in nanoseconds since the epoch
+	#[prost(uint64, optional, tag = "3")]
+	pub from_time: Option<u64>,
+	/// Only records before this time, in nanoseconds since the epoch
+	#[prost(uint64, optional, tag = "4")]
+	pub to_time: Option<u64>,
+}
+
+/// A record as it's sent over gRPC
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Record {
+	#[prost(string, tag = "1")]
+	pub key: String,
+	/// Nanoseconds since the epoch
+	#[prost(uint64, tag = "2")]
+	pub timestamp: u64,
+	/// The columns, whose types are the record's format
+	#[prost(message, repeated, tag = "3")]
+	pub values: Vec<Value>,
+}
+
+/// A column of a [`Record`]
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Value {
+	#[prost(oneof = "value::Kind", tags = "1, 2, 3, 4, 5, 6, 7, 8")]
+	pub kind: Option<value::Kind>,
+}
+
+/// The types of a [`Value`]
+pub mod value {
+	/// A column, by its format character
+	#[derive(Clone, PartialEq, prost::Oneof)]
+	pub enum Kind {
+		/// `i`, and also `c` and `h` when they're read
+		#[prost(int32, tag = "1")]
+		I32(i32),
+		/// `u`, and also `C` and `H` when they're read
+		#[prost(uint32, tag = "2")]
+		U32(u32),
+		/// `I`
+		#[prost(int64, tag = "3")]
+		I64(i64),
+		/// `U`
+		#[prost(uint64, tag = "4")]
+		U64(u64),
+		/// `f`
+		#[prost(float, tag = "5")]
+		F32(f32),
+		/// `F`
+		#[prost(double, tag = "6")]
+		F64(f64),
+		/// `s`
+		#[prost(string, tag = "7")]
+		String(String),
+		/// `B`
+		#[prost(bytes = "vec", tag = "8")]
+		Bytes(Vec<u8>),
+	}
+}
+
+/// The result of a `Write`
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct WriteResponse {
+	/// How many records were committed
+	#[prost(uint64, tag = "1")]
+	pub records: u64,
+}
+
+/// Fails if a column doesn't match the record's format, which is a damaged database
+impl TryFrom<&crate::Record> for Record {
+	type Error = Status;
+
+	fn try_from(r: &crate::Record) -> Result<Record, Status> {
+		use value::Kind;
+		let values = r
+			.format()
+			.bytes()
+			.enumerate()
+			.map(|(col, format)| {
+				let kind = match format {
+					b'c' | b'h' | b'i' => Kind::I32(r.get_checked(col)?),
+					b'C' | b'H' | b'u' => Kind::U32(r.get_checked(col)?),
+					b'I' => Kind::I64(r.get_checked(col)?),
+					b'U' => Kind::U64(r.get_checked(col)?),
+					b'f' => Kind::F32(r.get_checked(col)?),
+					b'F' => Kind::F64(r.get_checked(col)?),
+					b's' => Kind::String(r.get_checked::<&str>(col)?.to_owned()),
+					b'B' => Kind::Bytes(r.get_checked(col)?),
+					a => {
+						return Err(std::io::Error::new(
+							std::io::ErrorKind::InvalidData,
+							format!("invalid format character '{}'", a as char),
+						))
+					}
+				};
+				Ok(Value { kind: Some(kind) })
+			})
+			.collect::<std::io::Result<_>>()
+			.map_err(|e| Status::internal(format!("reading key {:?}: {}", r.key(), e)))?;
+		Ok(Record {
+			key: r.key().to_owned(),
+			timestamp: r.timestamp_nanos(),
+			values,
+		})
+	}
+}
+
+impl Record {
+	/// The columns of this record, fails if a value has no type
+	pub fn to_record_vec(&self) -> std::io::Result<RecordVec> {
+		use value::Kind;
+		let mut columns = RecordVec::new();
+		for (col, value) in self.values.iter().enumerate() {
+			columns = match &value.kind {
+				Some(Kind::I32(v)) => columns.push_i32(*v),
+				Some(Kind::U32(v)) => columns.push_u32(*v),
+				Some(Kind::I64(v)) => columns.push_i64(*v),
+				Some(Kind::U64(v)) => columns.push_u64(*v),
+				Some(Kind::F32(v)) => columns.push_f32(*v),
+				Some(Kind::F64(v)) => columns.push_f64(*v),
+				Some(Kind::String(v)) => columns.push_str(v),
+				Some(Kind::Bytes(v)) => columns.push_bytes(v),
+				None => {
+					return Err(std::io::Error::new(
+						std::io::ErrorKind::InvalidInput,
+						format!("column {} of key {:?} has no value", col, self.key),
+					))
+				}
+			};
+		}
+		Ok(columns)
+	}
+}
+
+/// Errors in the written records are `INVALID_ARGUMENT`
+impl From<WriteFailure> for Status {
+	fn from(e: WriteFailure) -> Status {
+		match e {
+			WriteFailure::KeyOrderingViolation { .. }
+			| WriteFailure::TimeOrderingViolation { .. }
+			| WriteFailure::IncorrectLength(_)
+			| WriteFailure::UnableToParseTimestamp
+			| WriteFailure::InvalidLine { .. }
+			| WriteFailure::FormatMismatch { .. } => Status::invalid_argument(e.to_string()),
+			WriteFailure::StaleLatest { .. } => Status::failed_precondition(e.to_string()),
+			WriteFailure::TooManyTransactions { .. } => Status::resource_exhausted(e.to_string()),
+			WriteFailure::IOError(e) => match e.kind() {
+				std::io::ErrorKind::InvalidInput | std::io::ErrorKind::InvalidData => {
+					Status::invalid_argument(e.to_string())
+				}
+				_ => Status::internal(e.to_string()),
+			},
+		}
+	}
+}
+
+/// Serve the database in a directory
+///
+/// Each `Read` opens the database again, so it sees the
+/// transactions that were committed before it started.
+#[derive(Debug, Clone)]
+pub struct SonnerieService {
+	dir: PathBuf,
+	memory_limit: usize,
+}
+
+impl SonnerieService {
+	/// Serve the database at `dir`
+	pub fn new(dir: &Path) -> SonnerieService {
+		SonnerieService {
+			dir: dir.to_owned(),
+			memory_limit: 64 * 1024 * 1024,
+		}
+	}
+
+	/// Buffer up to about `bytes` of each `Write` in memory, see [`SortingCreateTx::new`]
+	///
+	/// The default is 64MiB.
+	pub fn memory_limit(mut self, bytes: usize) -> Self {
+		self.memory_limit = bytes;
+		self
+	}
+
+	/// The tonic service, to add to a [`tonic::transport::Server`]
+	pub fn into_server(self) -> SonnerieServer<SonnerieService> {
+		SonnerieServer::new(self)
+	}
+}
+
+fn join_error(e: tokio::task::JoinError) -> Status {
+	Status::internal(e.to_string())
+}
+
+#[tonic::async_trait]
+impl Sonnerie for SonnerieService {
+	type ReadStream = futures::stream::BoxStream<'static, Result<Record, Status>>;
+
+	async fn read(
+		&self,
+		request: Request<ReadRequest>,
+	) -> Result<Response<Self::ReadStream>, Status> {
+		let request = request.into_inner();
+		let dir = self.dir.clone();
+		let db = tokio::task::spawn_blocking(move || DatabaseReader::new(&dir))
+			.await
+			.map_err(join_error)?
+			.map_err(|e| Status::internal(e.to_string()))?;
+
+		// the records are read on a blocking thread, which waits
+		// while the client is more than READ_AHEAD records behind
+		let (sender, receiver) = tokio::sync::mpsc::channel(READ_AHEAD);
+		tokio::task::spawn_blocking(move || {
+			let last_key = match &request.last_key {
+				Some(last_key) => Bound::Included(last_key.as_str()),
+				None => Bound::Unbounded,
+			};
+			let (from_time, to_time) = (request.from_time, request.to_time);
+			// a damaged database panics, which must not look like the end
+			// of the records to the client
+			let read = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+				let records = db
+					.get_range((Bound::Included(request.first_key.as_str()), last_key))
+					.filter_values(move |r| {
+						let t = r.timestamp_nanos();
+						from_time.is_none_or(|from| t >= from) && to_time.is_none_or(|to| t < to)
+					});
+				for record in records {
+					let record = Record::try_from(&record);
+					let failed = record.is_err();
+					if sender.blocking_send(record).is_err() || failed {
+						// the client went away, or it was sent the error
+						break;
+					}
+				}
+			}));
+			if let Err(panic) = read {
+				let message = panic
+					.downcast_ref::<&str>()
+					.map(|m| m.to_string())
+					.or_else(|| panic.downcast_ref::<String>().cloned())
+					.unwrap_or_default();
+				let _ = sender.blocking_send(Err(Status::internal(format!(
+					"reading failed: {}",
+					message
+				))));
+			}
+		});
+
+		let stream = futures::stream::unfold(receiver, |mut receiver| async move {
+			receiver.recv().await.map(|record| (record, receiver))
+		});
+		Ok(Response::new(Box::pin(stream)))
+	}
+
+	async fn write(
+		&self,
+		request: Request<Streaming<Record>>,
+	) -> Result<Response<WriteResponse>, Status> {
+		let mut stream = request.into_inner();
+		let mut tx =
+			SortingCreateTx::new(&self.dir, self.memory_limit).map_err(WriteFailure::from)?;
+		let mut records = 0u64;
+		while let Some(record) = stream.message().await? {
+			let columns = record.to_record_vec().map_err(WriteFailure::from)?;
+			tx.add_record(
+				&record.key,
+				crate::write::nanos_to_datetime(record.timestamp),
+				&columns,
+			)?;
+			records += 1;
+		}
+		tokio::task::spawn_blocking(move || tx.commit())
+			.await
+			.map_err(join_error)??;
+		Ok(Response::new(WriteResponse { records }))
+	}
+}
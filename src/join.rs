@@ -0,0 +1,101 @@
+//! Join the records of two keys by timestamp.
+
+use crate::{DatabaseReader, DatabaseRecordIterator, Record, Timestamp};
+use std::iter::Peekable;
+
+/// How [`DatabaseReader::join`] matches the records of its two keys
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum JoinKind {
+	/// Only the timestamps that both keys have a record at
+	Inner,
+	/// Every timestamp of the first key, with the second key's
+	/// record at that timestamp, if there is one
+	Left,
+	/// Every timestamp of either key
+	Outer,
+	/// Every timestamp of the first key, with the second key's
+	/// latest record at or before that timestamp, if there is one
+	AsOf,
+}
+
+impl DatabaseReader {
+	/// Join the records of `key_a` and `key_b` by timestamp
+	///
+	/// Yields `(timestamp, a, b)` in chronological order, where `a` and `b`
+	/// are the records of `key_a` and `key_b` that match at that timestamp,
+	/// as chosen by `kind`. For example, with [`JoinKind::AsOf`],
+	/// `b` is the last record of `key_b` at or before the timestamp, and
+	/// it's `None` until `key_b` has its first record.
+	///
+	/// ```no_run
+	/// # let db = sonnerie::DatabaseReader::new(std::path::Path::new("")).unwrap();
+	/// for (ts, errors, requests) in db.join("errors", "requests", sonnerie::JoinKind::AsOf) {
+	///     if let (Some(errors), Some(requests)) = (errors, requests) {
+	///         println!("{} {}", ts, errors.get::<f64>(0) / requests.get::<f64>(0));
+	///     }
+	/// }
+	/// ```
+	pub fn join<'d>(&'d self, key_a: &'d str, key_b: &'d str, kind: JoinKind) -> Join<'d> {
+		Join {
+			a: self.get(key_a).into_iter().peekable(),
+			b: self.get(key_b).into_iter().peekable(),
+			kind,
+			latest_b: None,
+		}
+	}
+}
+
+/// The records of two keys joined by timestamp, see [`DatabaseReader::join`]
+pub struct Join<'d> {
+	a: Peekable<DatabaseRecordIterator<'d>>,
+	b: Peekable<DatabaseRecordIterator<'d>>,
+	kind: JoinKind,
+	/// for `AsOf`, the last record of `b` that's not after the current `a`
+	latest_b: Option<Record>,
+}
+
+impl Iterator for Join<'_> {
+	type Item = (Timestamp, Option<Record>, Option<Record>);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		loop {
+			let a_ts = self.a.peek().map(|r| r.timestamp_nanos());
+			let b_ts = self.b.peek().map(|r| r.timestamp_nanos());
+
+			match self.kind {
+				JoinKind::AsOf => {
+					let a = self.a.next()?;
+					let ts = a.timestamp_nanos();
+					while let Some(b) = self.b.next_if(|b| b.timestamp_nanos() <= ts) {
+						self.latest_b = Some(b);
+					}
+					return Some((ts, Some(a), self.latest_b.clone()));
+				}
+				JoinKind::Inner => match (a_ts?, b_ts?) {
+					(a, b) if a < b => {
+						self.a.next();
+					}
+					(a, b) if a > b => {
+						self.b.next();
+					}
+					(ts, _) => return Some((ts, self.a.next(), self.b.next())),
+				},
+				JoinKind::Left => {
+					let ts = a_ts?;
+					while self.b.next_if(|b| b.timestamp_nanos() < ts).is_some() {}
+					let b = self.b.next_if(|b| b.timestamp_nanos() == ts);
+					return Some((ts, self.a.next(), b));
+				}
+				JoinKind::Outer => {
+					return match (a_ts, b_ts) {
+						(None, None) => None,
+						(Some(a), Some(b)) if a == b => Some((a, self.a.next(), self.b.next())),
+						(Some(a), Some(b)) if a > b => Some((b, None, self.b.next())),
+						(Some(a), _) => Some((a, self.a.next(), None)),
+						(None, Some(b)) => Some((b, None, self.b.next())),
+					};
+				}
+			}
+		}
+	}
+}
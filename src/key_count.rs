@@ -0,0 +1,319 @@
+//! Count the keys that match a wildcard, mostly from the segment headers.
+
+use crate::database_reader::DeleteMarkerPrecomputed;
+use crate::key_reader::Reader;
+use crate::segment::Segment;
+use crate::{DatabaseReader, Wildcard};
+use std::collections::VecDeque;
+use std::ops::{Bound, RangeBounds};
+
+impl DatabaseReader {
+	/// The distinct keys in `range`, in order, without reading their records
+	///
+	/// As with [`estimate_key_count`](Self::estimate_key_count), the
+	/// segments whose first and last key are the same aren't decompressed,
+	/// nor are those outside of `range`; other segments are decompressed
+	/// to find the keys between their first and last. A key isn't included
+	/// if a delete marker removes all of its records, regardless of their
+	/// timestamps; other deletions aren't considered.
+	///
+	/// The keys are found lazily, so taking only the first few of
+	/// a large range is quick, such as to complete a key:
+	///
+	/// ```no_run
+	/// # let db = sonnerie::DatabaseReader::new(std::path::Path::new("")).unwrap();
+	/// let completions: Vec<String> = db.keys("sensor."..).take(10).collect();
+	/// ```
+	pub fn keys<'d>(&'d self, range: impl RangeBounds<&'d str>) -> Keys<'d> {
+		let start = range.start_bound().cloned();
+		let end = range.end_bound().cloned();
+		let whole = (
+			crate::write::nanos_to_datetime(0),
+			crate::write::nanos_to_datetime(u64::MAX),
+		);
+		let deletions = self
+			.filter_out
+			.iter()
+			.filter(|(_, _, d)| d.first_timestamp <= whole.0 && d.last_timestamp >= whole.1)
+			.map(|(txid, _, d)| (*txid, DeleteMarkerPrecomputed::from_delete_marker(d)))
+			.collect();
+
+		let txes = self
+			.txes
+			.iter()
+			.map(|(txid, _, reader)| {
+				let segment = match start {
+					Bound::Included(k) | Bound::Excluded(k) => reader.segments.find(k),
+					Bound::Unbounded => reader.segments.first(),
+				};
+				let mut keys = TxKeys {
+					txid: *txid,
+					reader,
+					cache: self.segment_cache.as_deref(),
+					start,
+					end,
+					segment,
+					pending: VecDeque::new(),
+					head: None,
+				};
+				keys.head = keys.next_key();
+				keys
+			})
+			.collect();
+		Keys { txes, deletions }
+	}
+
+	/// The number of distinct keys in `range`
+	///
+	/// This counts the keys of [`keys`](Self::keys), so a key whose
+	/// records are all deleted isn't counted.
+	pub fn key_count<'d>(&'d self, range: impl RangeBounds<&'d str>) -> u64 {
+		self.keys(range).count() as u64
+	}
+
+	/// Count the distinct keys that match `wildcard`
+	///
+	/// Each segment's header has its first and last key, so a
+	/// segment whose first and last key are the same is counted without
+	/// decompressing it; so is every segment outside of the wildcard's
+	/// prefix, which is found by a binary search. Only the segments that
+	/// have more than one key, and may have a matching key between
+	/// those two, are decompressed. Deletions aren't considered,
+	/// so a key whose records are all deleted is still counted.
+	///
+	/// For a quicker estimate of a large database, see
+	/// [`estimate_key_count_sampled`](Self::estimate_key_count_sampled).
+	///
+	/// ```no_run
+	/// # let db = sonnerie::DatabaseReader::new(std::path::Path::new("")).unwrap();
+	/// let keys = db.estimate_key_count(&sonnerie::Wildcard::new("sensor.%"));
+	/// ```
+	pub fn estimate_key_count(&self, wildcard: &Wildcard) -> u64 {
+		self.count_matching_keys(wildcard, None)
+	}
+
+	/// Estimate the number of distinct keys that match `wildcard`,
+	/// decompressing at most `max_segments` segments
+	///
+	/// Like [`estimate_key_count`](Self::estimate_key_count), but when
+	/// more than `max_segments` segments would need to be decompressed,
+	/// that many evenly spaced ones are, and the number of matching keys
+	/// between the first and last key of each of the others is assumed to be
+	/// the average of those. Those keys can't be told apart between
+	/// transaction files, so a key in several files may be counted more than
+	/// once; the estimate is best right after a major compaction.
+	///
+	/// With a `max_segments` of zero, only the keys in the headers are counted.
+	pub fn estimate_key_count_sampled(&self, wildcard: &Wildcard, max_segments: usize) -> u64 {
+		self.count_matching_keys(wildcard, Some(max_segments))
+	}
+
+	fn count_matching_keys(&self, wildcard: &Wildcard, max_segments: Option<usize>) -> u64 {
+		let matcher = wildcard.matcher();
+		let prefix = wildcard.prefix();
+		let matches = |key: &str| match &matcher {
+			_ if wildcard.is_exact() => key == prefix,
+			Some(m) => m.is_match(key),
+			None => key.starts_with(prefix),
+		};
+
+		let mut keys: Vec<String> = vec![];
+		// the segments that weren't read, and the keys of the sampled segments
+		let (mut unsampled, mut sampled, mut sampled_keys) = (0usize, 0usize, 0usize);
+		for (_txid, _path, reader) in &self.txes {
+			let counted = self.count_in(reader, prefix, &matches, max_segments, &mut keys);
+			unsampled += counted.unsampled;
+			sampled += counted.sampled;
+			sampled_keys += counted.sampled_keys;
+		}
+
+		keys.sort_unstable();
+		keys.dedup();
+		let mut count = keys.len() as u64;
+		if sampled > 0 {
+			count += (unsampled as f64 * sampled_keys as f64 / sampled as f64).round() as u64;
+		}
+		count
+	}
+
+	/// add the matching keys of `reader` to `keys`, decompressing up to `max_segments`
+	fn count_in(
+		&self,
+		reader: &Reader,
+		prefix: &str,
+		matches: &impl Fn(&str) -> bool,
+		max_segments: Option<usize>,
+		keys: &mut Vec<String>,
+	) -> Counted {
+		let mut segment = if prefix.is_empty() {
+			reader.segments.first()
+		} else {
+			reader.segments.find(prefix)
+		};
+
+		// the segments that may have matching keys between their first and last
+		let mut interiors = vec![];
+		while let Some(seg) = segment {
+			if seg.first_key > prefix && !seg.first_key.starts_with(prefix) {
+				break;
+			}
+			segment = reader.segments.segment_after(&seg);
+			for key in [seg.first_key, seg.last_key] {
+				if matches(key) && keys.last().map(|k| k.as_str()) != Some(key) {
+					keys.push(key.to_owned());
+				}
+			}
+			if seg.first_key != seg.last_key && seg.last_key > prefix {
+				interiors.push(seg);
+			}
+		}
+
+		let sample = match max_segments {
+			Some(max) if max < interiors.len() => max,
+			_ => interiors.len(),
+		};
+		let extrapolate = sample < interiors.len();
+		let mut counted = Counted {
+			unsampled: interiors.len() - sample,
+			sampled: if extrapolate { sample } else { 0 },
+			sampled_keys: 0,
+		};
+		if sample == 0 {
+			return counted;
+		}
+		let every = interiors.len() as f64 / sample as f64;
+		let mut next = 0.0;
+		for (index, seg) in interiors.into_iter().enumerate() {
+			if (index as f64) < next {
+				continue;
+			}
+			next += every;
+			let (first, last) = (seg.first_key, seg.last_key);
+			let inside = reader
+				.segment_keys(seg, self.segment_cache.as_deref())
+				.into_iter()
+				.filter(|k| k.as_str() != first && k.as_str() != last && matches(k));
+			if extrapolate {
+				counted.sampled_keys += inside.count();
+			} else {
+				keys.extend(inside);
+			}
+		}
+		counted
+	}
+}
+
+struct Counted {
+	/// the segments that weren't decompressed
+	unsampled: usize,
+	/// the segments that were
+	sampled: usize,
+	/// the matching keys in `sampled`, besides their first and last
+	sampled_keys: usize,
+}
+
+/// An iterator of the distinct keys of a database, see [`DatabaseReader::keys`]
+pub struct Keys<'d> {
+	txes: Vec<TxKeys<'d>>,
+	/// the delete markers that remove every record of the keys they match
+	deletions: Vec<(usize, DeleteMarkerPrecomputed<'d>)>,
+}
+
+impl Iterator for Keys<'_> {
+	type Item = String;
+
+	fn next(&mut self) -> Option<String> {
+		loop {
+			let key = self
+				.txes
+				.iter()
+				.filter_map(|t| t.head.as_deref())
+				.min()?
+				.to_owned();
+
+			let deleted = |txid: usize| {
+				self.deletions.iter().any(|(del_txid, d)| {
+					txid < *del_txid
+						&& d.first_key <= key.as_str()
+						&& (d.last_key.is_empty() || key.as_str() < d.last_key)
+						&& d.wildcard_matches(&key)
+				})
+			};
+			let mut present = false;
+			for tx in &mut self.txes {
+				if tx.head.as_deref() == Some(key.as_str()) {
+					present |= !deleted(tx.txid);
+					tx.head = tx.next_key();
+				}
+			}
+			if present {
+				return Some(key);
+			}
+		}
+	}
+}
+
+/// The distinct keys of one transaction, in order
+struct TxKeys<'d> {
+	txid: usize,
+	reader: &'d Reader,
+	cache: Option<&'d crate::SegmentCache>,
+	start: Bound<&'d str>,
+	end: Bound<&'d str>,
+	/// the next segment to read keys from
+	segment: Option<Segment<'d>>,
+	/// the keys of the last segment that was read
+	pending: VecDeque<String>,
+	/// the key that `next_key` returned last
+	head: Option<String>,
+}
+
+impl TxKeys<'_> {
+	fn after_start(&self, key: &str) -> bool {
+		match self.start {
+			Bound::Included(s) => key >= s,
+			Bound::Excluded(s) => key > s,
+			Bound::Unbounded => true,
+		}
+	}
+
+	fn before_end(&self, key: &str) -> bool {
+		match self.end {
+			Bound::Included(e) => key <= e,
+			Bound::Excluded(e) => key < e,
+			Bound::Unbounded => true,
+		}
+	}
+
+	/// The key after `head`
+	fn next_key(&mut self) -> Option<String> {
+		loop {
+			while let Some(key) = self.pending.pop_front() {
+				if !self.after_start(&key) || self.head.as_deref() >= Some(key.as_str()) {
+					continue;
+				}
+				if !self.before_end(&key) {
+					self.pending.clear();
+					self.segment = None;
+					return None;
+				}
+				return Some(key);
+			}
+
+			let segment = self.segment.take()?;
+			if !self.before_end(segment.first_key) {
+				return None;
+			}
+			self.segment = self.reader.segments.segment_after(&segment);
+			if !self.after_start(segment.last_key) {
+				continue;
+			}
+			if segment.first_key == segment.last_key {
+				self.pending.push_back(segment.first_key.to_owned());
+			} else {
+				self.pending
+					.extend(self.reader.segment_keys(segment, self.cache));
+			}
+		}
+	}
+}
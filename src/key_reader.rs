@@ -3,9 +3,12 @@
 use crate::records::*;
 use crate::segment::*;
 use crate::segment_reader::*;
+use crate::wildcard::{Exclusions, KeyMatcher};
 use crate::Wildcard;
 use byteorder::{BigEndian, ByteOrder};
 use either::Either;
+use std::collections::BTreeSet;
+use std::io::Read;
 use std::ops::Bound;
 use std::ops::Bound::*;
 use std::ops::RangeBounds;
@@ -13,12 +16,52 @@ use std::sync::Arc as Rc;
 
 /// Read and filter keys from a single transaction file
 pub struct Reader {
-	pub(crate) segments: SegmentReader,
+	/// shared by the copies that only differ in `on_checksum_mismatch`
+	pub(crate) segments: Rc<SegmentReader>,
+	pub(crate) on_checksum_mismatch: ChecksumMismatch,
 }
 
+/// What a [`Reader`] does with a segment whose checksum is wrong
+///
+/// Segments since version 0x0102 have a CRC-32 of their compressed
+/// payload, which is checked before they are decompressed. Older
+/// segments don't have one and are always read. A segment that's in
+/// the [`SegmentCache`](crate::SegmentCache) isn't checked again.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ChecksumMismatch {
+	/// Panic when reading, and fail in [`Reader::verify`]
+	#[default]
+	Error,
+	/// Print a warning and skip the segment's records
+	Skip,
+}
+
+/// How a read tells the OS that it accesses the files, see
+/// [`DatabaseRecordReader::read_ahead`](crate::DatabaseRecordReader::read_ahead)
+///
+/// These are hints to the OS, which reads the files into its page cache.
+/// They make no difference to files that are already there.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReadAhead {
+	/// Sequential for a range of keys, and no hint for a single key
+	#[default]
+	Auto,
+	/// Read the range from beginning to end, and while a segment
+	/// is decompressed, have the OS fetch the ones after it
+	Sequential,
+	/// Only a few segments are read, so the OS shouldn't read ahead
+	Random,
+}
+
+/// With [`ReadAhead::Sequential`], how much of the file after a segment is fetched
+const PREFETCH_BYTES: usize = 4 * crate::write::SEGMENT_SIZE_GOAL;
+
 impl Reader {
 	/// Open a single transaction file
 	///
+	/// The file is mapped into memory, or read into memory if
+	/// it's on a filesystem that can't be mapped.
+	///
 	/// If instead you want to read from an entire database,
 	/// use [`DatabaseReader`](struct.DatabaseReader.html)
 	/// which provides a similar API.
@@ -26,11 +69,76 @@ impl Reader {
 		use Either::*;
 
 		match SegmentReader::open(&mut r)? {
-			Left(segments) => Ok(Left(Reader { segments })),
+			Left(segments) => Ok(Left(Reader::from_segments(segments))),
 			Right(delete) => Ok(Right(delete)),
 		}
 	}
 
+	/// Read a single transaction file that's already in memory
+	///
+	/// The same as [`new`](Self::new), but for a file that isn't on the filesystem.
+	pub fn from_bytes(bytes: Vec<u8>) -> std::io::Result<Either<Reader, DeleteMarker>> {
+		use Either::*;
+
+		match SegmentReader::from_bytes(bytes)? {
+			Left(segments) => Ok(Left(Reader::from_segments(segments))),
+			Right(delete) => Ok(Right(delete)),
+		}
+	}
+
+	pub(crate) fn from_segments(segments: SegmentReader) -> Reader {
+		Reader {
+			segments: Rc::new(segments),
+			on_checksum_mismatch: ChecksumMismatch::default(),
+		}
+	}
+
+	/// The same file, without opening it again, with another [`ChecksumMismatch`]
+	pub(crate) fn with_checksum_mismatch(&self, on_mismatch: ChecksumMismatch) -> Reader {
+		Reader {
+			segments: self.segments.clone(),
+			on_checksum_mismatch: on_mismatch,
+		}
+	}
+
+	/// Set what to do with segments whose checksum is wrong
+	///
+	/// The default is [`ChecksumMismatch::Error`].
+	pub fn on_checksum_mismatch(mut self, on_mismatch: ChecksumMismatch) -> Self {
+		self.on_checksum_mismatch = on_mismatch;
+		self
+	}
+
+	/// Whether to read `segment`, according to its checksum
+	///
+	/// Panics if the checksum is wrong and mismatches are errors.
+	fn check_checksum(&self, segment: &Segment) -> bool {
+		if self.segments.checksum_matches(segment) {
+			return true;
+		}
+		match self.on_checksum_mismatch {
+			ChecksumMismatch::Error => panic!(
+				"segment at byte {} has the wrong checksum",
+				segment.segment_offset
+			),
+			ChecksumMismatch::Skip => {
+				eprintln!(
+					"warning: skipping segment at byte {}, it has the wrong checksum",
+					segment.segment_offset
+				);
+				false
+			}
+		}
+	}
+
+	/// The metadata that was stored with this transaction
+	///
+	/// See [`CreateTx::set_meta`](crate::CreateTx::set_meta). Transactions
+	/// without metadata return an empty map.
+	pub fn metadata(&self) -> &std::collections::BTreeMap<String, String> {
+		self.segments.metadata()
+	}
+
 	/// Get a reader for only a single key
 	///
 	/// Returns an object that will read all of the
@@ -51,7 +159,7 @@ impl Reader {
 		&'rdr self,
 		range: impl RangeBounds<&'k str> + 'k + Clone,
 	) -> StringKeyRangeReader<'rdr, 'k> {
-		self.get_filter_range(None, "", crate::disassemble_range_bound(range).into())
+		self.get_filter_range(None, "", crate::disassemble_range_bound(range).into(), None)
 	}
 
 	/// Get a reader that filters on SQL's "LIKE"-like syntax.
@@ -67,20 +175,35 @@ impl Reader {
 		} else {
 			let mut filter = self.get_range(wildcard.prefix()..);
 			filter.prefix = wildcard.prefix();
-			filter.matcher = wildcard.as_regex();
+			filter.matcher = wildcard.matcher();
 			filter
 		}
 	}
 
 	pub(crate) fn get_filter_range<'rdr, 'k>(
 		&'rdr self,
-		matcher: Option<regex::Regex>,
+		matcher: Option<KeyMatcher>,
 		prefix: &'k str,
 		range: crate::CowStringRange<'k>,
+		keys: Option<Rc<BTreeSet<String>>>,
 	) -> StringKeyRangeReader<'rdr, 'k> {
-		let mut data = vec![];
+		self.get_filter_range_with_budget(matcher, prefix, range, keys, None, None)
+	}
 
+	/// Like `get_filter_range`, but with a limit on how much of
+	/// each segment is decompressed at a time, see [`StringKeyRangeReader::ensure`],
+	/// or with a cache for the decompressed segments. A budget bypasses the cache.
+	pub(crate) fn get_filter_range_with_budget<'rdr, 'k>(
+		&'rdr self,
+		matcher: Option<KeyMatcher>,
+		prefix: &'k str,
+		range: crate::CowStringRange<'k>,
+		keys: Option<Rc<BTreeSet<String>>>,
+		memory_budget: Option<usize>,
+		cache: Option<&'rdr crate::SegmentCache>,
+	) -> StringKeyRangeReader<'rdr, 'k> {
 		let segment = match range.start_bound() {
+			_ if keys.as_ref().is_some_and(|k| k.is_empty()) => None,
 			Included(v) | Excluded(v) => self.segments.find(v),
 			Unbounded => self.segments.first(),
 		};
@@ -96,16 +219,21 @@ impl Reader {
 					self.segments.advise(d);
 				}
 			}
-
-			crate::segment_reader::decode_into_with_unescaping(&mut data, d.payload);
 		}
 
-		StringKeyRangeReader {
+		let mut reader = StringKeyRangeReader {
 			reader: self,
 			range,
-			decoded: Rc::new(data),
+			decoded: Rc::new(vec![]),
 			pos: 0,
-			segment,
+			stream: None,
+			memory_budget,
+			cache,
+			segment: None,
+			single_segment: false,
+			exclude: None,
+			prefetch: false,
+			ahead: None,
 			current_key_text_len: 0,
 			current_key_text_pos: 0,
 			current_fmt_text_len: 0,
@@ -116,8 +244,350 @@ impl Reader {
 			_phantom: std::marker::PhantomData,
 			prefix,
 			matcher,
+			keys,
+		};
+		reader.load_segment(segment);
+		reader
+	}
+
+	/// true if `key` has a record after the timestamp `after`
+	/// (any record if `None`) that isn't `deleted`
+	///
+	/// The segments that have `key` are read from the last one, which has
+	/// its newest records, until one whose records of `key` begin at or before
+	/// `after`, so usually only one segment is decompressed.
+	pub(crate) fn any_after(
+		&self,
+		key: &str,
+		after: Option<crate::Timestamp>,
+		cache: Option<&crate::SegmentCache>,
+		deleted: impl Fn(crate::Timestamp) -> bool,
+	) -> bool {
+		for segment in self.key_segments(key).into_iter().rev() {
+			let mut reader = self.get_in_segment(key, segment, cache);
+
+			let mut older = false;
+			let mut first = true;
+			while let Some(timestamp) = reader.next_timestamp() {
+				if first && Some(timestamp) <= after {
+					// the segments before this one are older still
+					older = true;
+				}
+				first = false;
+				if Some(timestamp) > after && !deleted(timestamp) {
+					return true;
+				}
+			}
+			if older {
+				break;
+			}
+		}
+		false
+	}
+	/// Read the records of `key` that are in `segment`, one of its [`key_segments`](Self::key_segments)
+	pub(crate) fn get_in_segment<'rdr>(
+		&'rdr self,
+		key: &'rdr str,
+		segment: crate::segment::Segment<'rdr>,
+		cache: Option<&'rdr crate::SegmentCache>,
+	) -> StringKeyRangeReader<'rdr, 'rdr> {
+		// no keys, so that no segment is loaded until this one
+		let mut reader = self.get_filter_range_with_budget(
+			None,
+			"",
+			crate::disassemble_range_bound(key..=key).into(),
+			Some(Rc::new(BTreeSet::new())),
+			None,
+			cache,
+		);
+		reader.keys = None;
+		reader.single_segment = true;
+		reader.load_segment(Some(segment));
+		reader
+	}
+
+	/// The segments that may have records of `key`, found from their
+	/// headers without decompressing them
+	pub(crate) fn key_segments(&self, key: &str) -> Vec<crate::segment::Segment<'_>> {
+		let mut segments = vec![];
+		let mut s = self.segments.find(key);
+		while let Some(seg) = s {
+			if seg.first_key > key {
+				break;
+			}
+			s = self.segments.segment_after(&seg);
+			if seg.last_key >= key {
+				segments.push(seg);
+			}
+		}
+		segments
+	}
+
+	/// The distinct keys of `segment`, in order, which decompresses it
+	pub(crate) fn segment_keys<'s>(
+		&'s self,
+		segment: crate::segment::Segment<'s>,
+		cache: Option<&'s crate::SegmentCache>,
+	) -> Vec<String> {
+		// as in any_after, load only this segment
+		let mut reader = self.get_filter_range_with_budget(
+			None,
+			"",
+			crate::disassemble_range_bound(segment.first_key..).into(),
+			Some(Rc::new(BTreeSet::new())),
+			None,
+			cache,
+		);
+		reader.keys = None;
+		reader.single_segment = true;
+		reader.load_segment(Some(segment));
+
+		let mut keys: Vec<String> = vec![];
+		while reader.next_timestamp().is_some() {
+			let key = reader.current_key();
+			if keys.last().map(|k| k.as_str()) != Some(key) {
+				keys.push(key.to_owned());
+			}
+		}
+		keys
+	}
+
+	/// The number of records of each key of `segment`, in order, which
+	/// decompresses it, but only reads the headers of its keys (and
+	/// the lengths of records whose format doesn't have a fixed size)
+	pub(crate) fn segment_record_counts(
+		&self,
+		segment: &crate::segment::Segment<'_>,
+		cache: Option<&crate::SegmentCache>,
+	) -> Vec<(String, u64)> {
+		let decode = || {
+			self.check_checksum(segment).then(|| {
+				let mut data = vec![];
+				crate::segment_reader::decode_into_with_unescaping(&mut data, segment);
+				data
+			})
+		};
+		let data = match cache {
+			Some(cache) => {
+				cache.get_or_decode(self.segments.identity, segment.segment_offset, decode)
+			}
+			None => decode().map(Rc::new),
+		};
+		let Some(data) = data else {
+			return vec![];
+		};
+
+		let header_len = if segment.segment_version == 0x0000 {
+			16
+		} else {
+			12
+		};
+		let mut counts: Vec<(String, u64)> = vec![];
+		let mut pos = 0;
+		while pos + header_len <= data.len() {
+			let klen = BigEndian::read_u32(&data[pos..pos + 4]) as usize;
+			let flen = BigEndian::read_u32(&data[pos + 4..pos + 8]) as usize;
+			let dlen = BigEndian::read_u32(&data[pos + header_len - 4..pos + header_len]) as usize;
+			let key_at = pos + header_len;
+			let key =
+				std::str::from_utf8(&data[key_at..key_at + klen]).expect("input data is not utf8");
+			let fmt = std::str::from_utf8(&data[key_at + klen..key_at + klen + flen])
+				.expect("input data is not utf8");
+			let data_at = key_at + klen + flen;
+			let records = &data[data_at..data_at + dlen];
+
+			let n = match crate::row_format::row_format_size(fmt) {
+				Some(size) => (dlen / (size + crate::TIMESTAMP_SIZE)) as u64,
+				None => {
+					let mut n = 0;
+					let mut rest = records;
+					while !rest.is_empty() {
+						let (len, tail) =
+							unsigned_varint::decode::usize(rest).expect("record length");
+						rest = &tail[crate::TIMESTAMP_SIZE + len..];
+						n += 1;
+					}
+					n
+				}
+			};
+			match counts.last_mut() {
+				// the same key with another format
+				Some((last, count)) if last == key => *count += n,
+				_ => counts.push((key.to_owned(), n)),
+			}
+			pos = data_at + dlen;
+		}
+		counts
+	}
+
+	/// Iterate over the still-compressed segments of this file
+	///
+	/// Copy them into a new transaction with [`CreateTx::copy_from`](crate::CreateTx::copy_from),
+	/// which doesn't need to decompress them.
+	pub fn raw_segments(&self) -> impl Iterator<Item = RawSegment<'_>> {
+		std::iter::successors(self.segments.first(), move |s| {
+			self.segments.segment_after(s)
+		})
+		.map(move |segment| RawSegment {
+			stored: self.segments.stored_payload(&segment),
+			segment,
+		})
+	}
+
+	/// The lowest and highest [`version`](RawSegment::version) of this file's segments
+	///
+	/// Returns `None` if the file has no segments.
+	pub fn segment_versions(&self) -> Option<std::ops::RangeInclusive<u16>> {
+		self.raw_segments()
+			.map(|s| s.version())
+			.fold(None, |range, v| match range {
+				None => Some(v..=v),
+				Some(r) => Some(*r.start().min(&v)..=*r.end().max(&v)),
+			})
+	}
+
+	/// Check that the records of this file are in order
+	///
+	/// This is what the writer checks as records are added: the keys must
+	/// not decrease (across segments too), and the timestamps of each
+	/// key must increase. Returns the first record that's out of order as
+	/// [`KeyOrderingViolation`](crate::WriteFailure::KeyOrderingViolation) or
+	/// [`TimeOrderingViolation`](crate::WriteFailure::TimeOrderingViolation),
+	/// otherwise the number of records.
+	pub fn check_ordering(&self) -> Result<u64, crate::WriteFailure> {
+		use crate::write::nanos_to_datetime;
+
+		let mut records = 0u64;
+		let mut reader = self.get_range(..);
+		let mut previous: Option<(String, crate::Timestamp)> = None;
+		while let Some(timestamp) = reader.next_timestamp() {
+			let key = reader.current_key();
+			match previous.as_mut() {
+				Some((prev_key, _)) if key < prev_key.as_str() => {
+					return Err(crate::WriteFailure::KeyOrderingViolation {
+						first: prev_key.clone(),
+						second: key.to_owned(),
+					});
+				}
+				Some((prev_key, prev)) if key == prev_key.as_str() => {
+					if timestamp <= *prev {
+						return Err(crate::WriteFailure::TimeOrderingViolation {
+							first: nanos_to_datetime(*prev),
+							second: nanos_to_datetime(timestamp),
+							key: key.to_owned(),
+						});
+					}
+					*prev = timestamp;
+				}
+				_ => previous = Some((key.to_owned(), timestamp)),
+			}
+			records += 1;
+		}
+		Ok(records)
+	}
+
+	/// Check this file for corruption
+	///
+	/// Every segment is decompressed, and its keys, formats and records are
+	/// checked to be consistent with each other and with the segment's header.
+	/// The keys must be in order and each key's timestamps must increase,
+	/// as with [`check_ordering`](Self::check_ordering), and the segments must
+	/// follow each other without a gap until the end of the file.
+	///
+	/// Returns the number of records, or an
+	/// [`InvalidData`](std::io::ErrorKind::InvalidData) error that
+	/// says what's wrong and at which byte of the file its segment is.
+	/// Unlike reading a corrupt file, this doesn't panic.
+	pub fn verify(&self) -> std::io::Result<u64> {
+		let invalid = |at: usize, e: String| {
+			std::io::Error::new(
+				std::io::ErrorKind::InvalidData,
+				format!("segment at byte {}: {}", at, e),
+			)
+		};
+
+		let mut records = 0u64;
+		let mut previous: Option<(String, crate::Timestamp)> = None;
+		let mut expected_offset = None;
+		let mut end = None;
+		let mut segment = self.segments.first();
+		while let Some(s) = segment {
+			if let Some(expected) = expected_offset.filter(|&e| e != s.segment_offset) {
+				return Err(invalid(
+					s.segment_offset,
+					format!("the previous segment ends at byte {}", expected),
+				));
+			}
+			if !self.segments.checksum_matches(&s) {
+				match self.on_checksum_mismatch {
+					ChecksumMismatch::Error => {
+						return Err(invalid(s.segment_offset, "wrong checksum".to_string()));
+					}
+					ChecksumMismatch::Skip => {
+						eprintln!(
+							"warning: segment at byte {} has the wrong checksum",
+							s.segment_offset
+						);
+						expected_offset = Some(s.segment_offset + s.stride);
+						end = expected_offset;
+						segment = self.segments.segment_after(&s);
+						continue;
+					}
+				}
+			}
+			let mut decoded = vec![];
+			decoder_with_unescaping(&s)
+				.read_to_end(&mut decoded)
+				.map_err(|e| invalid(s.segment_offset, format!("can't be decompressed: {}", e)))?;
+			records += verify_segment(&s, &decoded, &mut previous)
+				.map_err(|e| invalid(s.segment_offset, e))?;
+
+			expected_offset = Some(s.segment_offset + s.stride);
+			end = expected_offset;
+			segment = self.segments.segment_after(&s);
 		}
+
+		let len = self.segments.number_of_bytes();
+		if let Some(end) = end.filter(|&end| end != len) {
+			return Err(std::io::Error::new(
+				std::io::ErrorKind::InvalidData,
+				format!(
+					"the bytes from {} to the end of the file ({}) aren't a segment",
+					end, len
+				),
+			));
+		}
+		Ok(records)
 	}
+
+	/// Summarize each key in this file
+	///
+	/// Reads every record, but only looks at their keys, formats and
+	/// timestamps. The keys are in order.
+	pub fn key_summaries(&self) -> Vec<KeySummary> {
+		let mut summaries: Vec<KeySummary> = vec![];
+		for record in self.get_range(..) {
+			match summaries.last_mut() {
+				Some(last) if last.key == record.key() => {
+					if !last.formats.iter().any(|f| f == record.format()) {
+						last.formats.push(record.format().to_owned());
+					}
+					last.records += 1;
+					last.first_timestamp = last.first_timestamp.min(record.time());
+					last.last_timestamp = last.last_timestamp.max(record.time());
+				}
+				_ => summaries.push(KeySummary {
+					key: record.key().to_owned(),
+					formats: vec![record.format().to_owned()],
+					records: 1,
+					first_timestamp: record.time(),
+					last_timestamp: record.time(),
+				}),
+			}
+		}
+		summaries
+	}
+
 	/// Print diagnostic information about this transaction file.
 	///
 	/// This function is for debugging only.
@@ -126,6 +596,176 @@ impl Reader {
 	}
 }
 
+/// Check the decompressed payload of a segment, see [`Reader::verify`],
+/// returning its number of records
+///
+/// `previous` is the key and timestamp of the record before it.
+fn verify_segment(
+	segment: &Segment<'_>,
+	decoded: &[u8],
+	previous: &mut Option<(String, crate::Timestamp)>,
+) -> Result<u64, String> {
+	let read_u32 = |at: usize| BigEndian::read_u32(&decoded[at..at + 4]) as usize;
+	let header_len = if segment.segment_version == 0x0000 {
+		16
+	} else {
+		12
+	};
+
+	let mut records = 0;
+	let mut pos = 0;
+	while pos < decoded.len() {
+		if decoded.len() - pos < header_len {
+			return Err(format!("the key at byte {} is truncated", pos));
+		}
+		let klen = read_u32(pos);
+		let flen = read_u32(pos + 4);
+		let dlen = read_u32(pos + header_len - 4);
+		let key_at = pos + header_len;
+		if decoded.len() - key_at < klen + flen + dlen {
+			return Err(format!("the key at byte {} is truncated", pos));
+		}
+		let key = std::str::from_utf8(&decoded[key_at..key_at + klen])
+			.map_err(|e| format!("the key at byte {} isn't utf-8: {}", pos, e))?;
+		let format = &decoded[key_at + klen..key_at + klen + flen];
+		if key < segment.first_key || key > segment.last_key {
+			return Err(format!(
+				"the key {:?} isn't between the segment's first and last keys, {:?} and {:?}",
+				key, segment.first_key, segment.last_key
+			));
+		}
+		if let Some(c) = format.iter().find(|c| !b"cChHiuIUfFsB".contains(c)) {
+			return Err(format!(
+				"the key {:?} has an invalid format character {:?}",
+				key, *c as char
+			));
+		}
+		let fixed_size = crate::row_format::row_format_size(std::str::from_utf8(format).unwrap());
+
+		let mut data = &decoded[key_at + klen + flen..key_at + klen + flen + dlen];
+		while !data.is_empty() {
+			let len = match fixed_size {
+				Some(len) => len,
+				None => {
+					let (len, tail) = unsigned_varint::decode::usize(data)
+						.map_err(|e| format!("a record of {:?}: {}", key, e))?;
+					data = tail;
+					len
+				}
+			};
+			let Some(len) = len
+				.checked_add(crate::TIMESTAMP_SIZE)
+				.filter(|&l| l <= data.len())
+			else {
+				return Err(format!("a record of {:?} extends past its key's data", key));
+			};
+			let (record, tail) = data.split_at(len);
+			data = tail;
+
+			let timestamp = BigEndian::read_u64(&record[..crate::TIMESTAMP_SIZE]);
+			verify_columns(format, &record[crate::TIMESTAMP_SIZE..])
+				.map_err(|e| format!("a record of {:?} at {}: {}", key, timestamp, e))?;
+
+			match previous.as_mut() {
+				Some((prev_key, _)) if key < prev_key.as_str() => {
+					return Err(format!("the key {:?} is after {:?}", key, prev_key));
+				}
+				Some((prev_key, prev)) if key == prev_key.as_str() => {
+					if timestamp <= *prev {
+						return Err(format!(
+							"the key {:?} has a record at {} after one at {}",
+							key, timestamp, prev
+						));
+					}
+					*prev = timestamp;
+				}
+				_ => *previous = Some((key.to_owned(), timestamp)),
+			}
+			records += 1;
+		}
+		pos = key_at + klen + flen + dlen;
+	}
+	Ok(records)
+}
+
+/// Check that a record's columns (after its timestamp) are `format`
+fn verify_columns(format: &[u8], mut data: &[u8]) -> Result<(), String> {
+	for (col, &c) in format.iter().enumerate() {
+		let len = match c {
+			b's' | b'B' => {
+				let (len, tail) = unsigned_varint::decode::usize(data)
+					.map_err(|e| format!("column {}: {}", col, e))?;
+				data = tail;
+				len
+			}
+			c => crate::row_format::row_format_size(std::str::from_utf8(&[c]).unwrap()).unwrap(),
+		};
+		if len > data.len() {
+			return Err(format!("column {} is truncated", col));
+		}
+		let (value, tail) = data.split_at(len);
+		if c == b's' {
+			std::str::from_utf8(value).map_err(|e| format!("column {}: {}", col, e))?;
+		}
+		data = tail;
+	}
+	if !data.is_empty() {
+		return Err(format!("{} bytes after the last column", data.len()));
+	}
+	Ok(())
+}
+
+/// The records that a transaction file has for one key, see [`Reader::key_summaries`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeySummary {
+	pub key: String,
+	/// Each distinct format, in the order they first appear
+	pub formats: Vec<String>,
+	/// The number of records
+	pub records: u64,
+	pub first_timestamp: chrono::NaiveDateTime,
+	pub last_timestamp: chrono::NaiveDateTime,
+}
+
+/// A segment of a transaction file, as it is stored
+///
+/// Created by [`Reader::raw_segments`].
+pub struct RawSegment<'a> {
+	pub(crate) segment: Segment<'a>,
+	/// the payload, escaped as it is in the file
+	pub(crate) stored: &'a [u8],
+}
+
+impl<'a> RawSegment<'a> {
+	/// The first key that this segment has records for
+	pub fn first_key(&self) -> &'a str {
+		self.segment.first_key
+	}
+	/// The last key that this segment has records for
+	pub fn last_key(&self) -> &'a str {
+		self.segment.last_key
+	}
+	/// The size of this segment's compressed data
+	pub fn compressed_len(&self) -> usize {
+		self.stored.len()
+	}
+	/// Where this segment starts in its file
+	pub fn offset(&self) -> usize {
+		self.segment.segment_offset
+	}
+	/// The version of the format this segment is stored in
+	///
+	/// Segments are written in [`SEGMENT_VERSION`](crate::SEGMENT_VERSION),
+	/// and older versions are upgraded by a major compaction.
+	pub fn version(&self) -> u16 {
+		self.segment.segment_version
+	}
+	/// What this segment is compressed with
+	pub fn compression(&self) -> crate::Compression {
+		self.segment.compression
+	}
+}
+
 /// An iterator over a range of keys
 ///
 /// This struct implements `Iterator` and yields items of [`Record`].
@@ -134,6 +774,11 @@ pub struct StringKeyRangeReader<'rdr, 'k> {
 	pub(crate) range: crate::CowStringRange<'k>,
 	decoded: Rc<Vec<u8>>,
 	pos: usize,
+	/// with a memory budget, the rest of the current segment that
+	/// isn't in `decoded` yet (`None` if it's all in `decoded`)
+	stream: Option<Box<dyn std::io::Read + Send + 'rdr>>,
+	memory_budget: Option<usize>,
+	cache: Option<&'rdr crate::SegmentCache>,
 	current_key_text_pos: usize,
 	current_key_text_len: usize,
 	current_fmt_text_pos: usize,
@@ -144,11 +789,59 @@ pub struct StringKeyRangeReader<'rdr, 'k> {
 	current_record_len: Option<usize>,
 	current_key_data_end: usize, // where the next key begins
 	pub(crate) segment: Option<Segment<'rdr>>,
-	pub(crate) matcher: Option<regex::Regex>,
+	/// stop at the end of the current segment
+	single_segment: bool,
+	/// skip the keys that match these
+	exclude: Option<Rc<Exclusions>>,
+	/// fetch the segments after each one that's loaded
+	prefetch: bool,
+	/// the segments that another thread decompressed
+	ahead: Option<DecompressAhead>,
+	pub(crate) matcher: Option<KeyMatcher>,
 	pub(crate) prefix: &'k str,
+	/// only yield these keys, skipping over the segments between them
+	pub(crate) keys: Option<Rc<BTreeSet<String>>>,
 	_phantom: std::marker::PhantomData<&'k str>,
 }
 
+/// Is `key` after the `end` of a range, or if it's unbounded,
+/// after the keys that begin with `prefix`?
+fn past_end(end: Bound<&str>, prefix: &str, key: &str) -> bool {
+	match end {
+		Bound::Included(end) => key > end,
+		Bound::Excluded(end) => key >= end,
+		Bound::Unbounded => !key.starts_with(prefix) && key > prefix,
+	}
+}
+
+/// The segments decompressed by the thread of [`StringKeyRangeReader::decompress_ahead`],
+/// by their offset, or `None` if the checksum is wrong
+struct DecompressAhead {
+	decoded: crossbeam::channel::Receiver<(usize, Option<Rc<Vec<u8>>>)>,
+	/// received, but not needed yet
+	next: Option<(usize, Option<Rc<Vec<u8>>>)>,
+}
+
+impl DecompressAhead {
+	/// The segment at `offset`, skipping those before it, or `None`
+	/// if the thread didn't decompress it
+	fn take(&mut self, offset: usize) -> Option<Rc<Vec<u8>>> {
+		loop {
+			let (at, decoded) = match self.next.take() {
+				Some(next) => next,
+				None => self.decoded.recv().ok()?,
+			};
+			if at == offset {
+				return decoded;
+			}
+			if at > offset {
+				self.next = Some((at, decoded));
+				return None;
+			}
+		}
+	}
+}
+
 impl<'rdr, 'k> StringKeyRangeReader<'rdr, 'k> {
 	/// Determines the on-disk size of this range of data,
 	/// which is useful for estimating progress and size.
@@ -201,15 +894,215 @@ impl<'rdr, 'k> StringKeyRangeReader<'rdr, 'k> {
 	}
 
 	fn next_segment(&mut self) {
-		self.pos = 0;
-
+		if self.single_segment {
+			self.segment = None;
+			return;
+		}
 		let s = self
 			.reader
 			.segments
-			.segment_after(&self.segment.take().unwrap());
-		self.segment = s;
+			.segment_after(&self.segment.take().unwrap())
+			.filter(|s| !self.past_end(s.first_key));
+		let s = self.skip_excluded(s);
+		self.load_segment(s);
+	}
+
+	/// Is `key` after the end of the range, so that a segment
+	/// that begins with it needn't be decompressed?
+	fn past_end(&self, key: &str) -> bool {
+		past_end(self.range.end_bound(), self.prefix, key)
+	}
+
+	/// Decompress up to `segments` of the segments after the current one on another thread
+	///
+	/// `reader` is the same as `self.reader`, which the thread keeps. The
+	/// segments are decompressed in order until the end of the range, and
+	/// [`load_segment`](Self::load_segment) takes them as they're needed.
+	pub(crate) fn decompress_ahead(
+		mut self,
+		reader: Rc<Reader>,
+		cache: Option<Rc<crate::SegmentCache>>,
+		segments: usize,
+	) -> Self {
+		assert!(std::ptr::eq(&*reader, self.reader));
+		let Some(current) = self.segment.as_ref() else {
+			return self;
+		};
+		// a set of keys seeks past most segments
+		if segments == 0 || self.memory_budget.is_some() || self.keys.is_some() {
+			return self;
+		}
+
+		let (send, recv) = crossbeam::channel::bounded(segments);
+		let mut offset = current.segment_offset;
+		let end = crate::bound_deep_copy(self.range.end_bound());
+		let prefix = self.prefix.to_owned();
+		let spawned = std::thread::Builder::new()
+			.name("sonnerie-decompress".to_string())
+			.spawn(move || {
+				let segments = &reader.segments;
+				let end = end.as_ref().map(|e| e.as_str());
+				while let Some(s) = segments
+					.scan_from(offset)
+					.and_then(|s| segments.segment_after(&s))
+					.filter(|s| !past_end(end, &prefix, s.first_key))
+				{
+					offset = s.segment_offset;
+					// on a mismatch, the reader checks it again, to warn
+					// or panic on its own thread
+					let decode = || {
+						segments.checksum_matches(&s).then(|| {
+							let mut data = vec![];
+							crate::segment_reader::decode_into_with_unescaping(&mut data, &s);
+							data
+						})
+					};
+					let decoded = match &cache {
+						Some(cache) => cache.get_or_decode(segments.identity, offset, decode),
+						None => decode().map(Rc::new),
+					};
+					if send.send((offset, decoded)).is_err() {
+						// the reader was dropped
+						return;
+					}
+				}
+			});
+		if spawned.is_ok() {
+			self.ahead = Some(DecompressAhead {
+				decoded: recv,
+				next: None,
+			});
+		}
+		self
+	}
+
+	/// Hint how the file is accessed; the default, [`ReadAhead::Auto`],
+	/// was already applied when this was made
+	pub(crate) fn read_ahead(mut self, read_ahead: ReadAhead) -> Self {
+		match (read_ahead, self.segment.as_ref()) {
+			(ReadAhead::Sequential, Some(s)) => {
+				self.reader.segments.advise(s);
+				self.reader.segments.prefetch_after(s, PREFETCH_BYTES);
+				self.prefetch = true;
+			}
+			(ReadAhead::Random, Some(s)) => self.reader.segments.advise_random(s),
+			_ => {}
+		}
+		self
+	}
+
+	/// Skip the keys that match `exclude`
+	pub(crate) fn excluding(mut self, exclude: Option<Rc<Exclusions>>) -> Self {
+		let skip = match (self.segment.as_ref(), exclude.as_ref()) {
+			(Some(s), Some(e)) => e.contains_all(s.first_key, s.last_key),
+			_ => false,
+		};
+		self.exclude = exclude;
+		if skip {
+			self.next_segment();
+		}
+		self
+	}
+
+	/// The first of `segment` and the segments after it
+	/// that has a key that isn't excluded
+	fn skip_excluded(&self, mut segment: Option<Segment<'rdr>>) -> Option<Segment<'rdr>> {
+		let Some(exclude) = self.exclude.as_ref() else {
+			return segment;
+		};
+		while let Some(s) = segment.as_ref() {
+			if !exclude.contains_all(s.first_key, s.last_key) {
+				break;
+			}
+			segment = self.reader.segments.segment_after(s);
+		}
+		segment
+	}
+
+	/// Go to the segment that may contain `key`, without decompressing
+	/// any of the segments in between
+	fn seek_segment(&mut self, key: &str) {
+		let current = self.segment.take().unwrap();
+		let mut s = self
+			.reader
+			.segments
+			.find(key)
+			.filter(|s| s.segment_offset > current.segment_offset)
+			.or_else(|| self.reader.segments.segment_after(&current));
+
+		while let Some(seg) = s.as_ref() {
+			if seg.last_key >= key {
+				break;
+			}
+			s = self.reader.segments.segment_after(seg);
+		}
+		self.load_segment(s);
+	}
+
+	fn load_segment(&mut self, segment: Option<Segment<'rdr>>) {
+		self.pos = 0;
+		self.segment = segment;
+		self.stream = None;
+
+		if let (Some(s), true) = (self.segment.as_ref(), self.prefetch) {
+			self.reader.segments.prefetch_after(s, PREFETCH_BYTES);
+		}
+
+		if let (Some(s), Some(ahead)) = (self.segment.as_ref(), self.ahead.as_mut()) {
+			if let Some(decoded) = ahead.take(s.segment_offset) {
+				self.decoded = decoded;
+				return;
+			}
+		}
+
+		if let (Some(s), None, Some(cache)) =
+			(self.segment.as_ref(), self.memory_budget, self.cache)
+		{
+			// the checksum was checked when the segment went into the cache
+			let reader = self.reader;
+			let decoded = cache.get_or_decode(reader.segments.identity, s.segment_offset, || {
+				reader.check_checksum(s).then(|| {
+					let mut data = vec![];
+					crate::segment_reader::decode_into_with_unescaping(&mut data, s);
+					data
+				})
+			});
+			match decoded {
+				Some(decoded) => self.decoded = decoded,
+				None => {
+					// as if the segment were empty
+					self.decoded = Rc::new(vec![]);
+					self.current_key_data_end = 0;
+				}
+			}
+			return;
+		}
 
 		if let Some(s) = self.segment.as_ref() {
+			if !self.reader.check_checksum(s) {
+				// as if the segment were empty
+				self.decoded = Rc::new(vec![]);
+				self.current_key_data_end = 0;
+				return;
+			}
+		}
+
+		if let (Some(s), Some(budget)) = (self.segment.as_ref(), self.memory_budget) {
+			// a segment that fits in the budget is decoded entirely, as
+			// without one; only a larger one continues to be streamed
+			let mut stream = crate::segment_reader::decoder_with_unescaping(s);
+			let mut data = vec![];
+			stream
+				.by_ref()
+				.take(budget as u64 + 1)
+				.read_to_end(&mut data)
+				.expect("segment decoding");
+			if data.len() > budget {
+				self.stream = Some(stream);
+			}
+			self.decoded = Rc::new(data);
+			self.current_key_data_end = 0;
+		} else if let Some(s) = self.segment.as_ref() {
 			let reuse_vec = std::mem::replace(&mut self.decoded, Rc::new(vec![]));
 			let mut old_vec;
 			if let Ok(maybe_old_vec) = Rc::try_unwrap(reuse_vec) {
@@ -218,19 +1111,84 @@ impl<'rdr, 'k> StringKeyRangeReader<'rdr, 'k> {
 				old_vec = vec![];
 			}
 			old_vec.clear();
-			crate::segment_reader::decode_into_with_unescaping(&mut old_vec, s.payload);
+			crate::segment_reader::decode_into_with_unescaping(&mut old_vec, s);
 			self.decoded = Rc::new(old_vec);
 		}
 	}
 
+	/// With a memory budget, make sure that the `n` bytes after `pos` are
+	/// in `decoded`, unless the segment ends first.
+	///
+	/// When they aren't, `decoded` is replaced with a new buffer that starts at
+	/// `pos` (preceded by the current key and format if `keep_key`) and has
+	/// the next `memory_budget` bytes, or more if a single record is larger.
+	/// Records already yielded keep the old buffer.
+	fn ensure(&mut self, n: usize, keep_key: bool) {
+		let Some(stream) = self.stream.as_mut() else {
+			return;
+		};
+		if self.pos + n <= self.decoded.len() {
+			return;
+		}
+
+		let budget = self.memory_budget.unwrap_or(0);
+		let mut data = Vec::with_capacity(budget.max(n) + 64);
+		if keep_key {
+			let key =
+				self.current_key_text_pos..self.current_key_text_pos + self.current_key_text_len;
+			let fmt =
+				self.current_fmt_text_pos..self.current_fmt_text_pos + self.current_fmt_text_len;
+			data.extend_from_slice(&self.decoded[key]);
+			data.extend_from_slice(&self.decoded[fmt]);
+			self.current_key_text_pos = 0;
+			self.current_fmt_text_pos = self.current_key_text_len;
+		}
+		let start = data.len();
+
+		if self.pos < self.decoded.len() {
+			data.extend_from_slice(&self.decoded[self.pos..]);
+		} else {
+			// skipping over data that we didn't decode yet
+			let skip = (self.pos - self.decoded.len()) as u64;
+			let skipped = std::io::copy(&mut stream.by_ref().take(skip), &mut std::io::sink())
+				.expect("segment decoding");
+			assert_eq!(skipped, skip, "segment is truncated");
+		}
+		self.current_key_data_end = (self.current_key_data_end + start).saturating_sub(self.pos);
+		self.pos = start;
+
+		let want = budget.max(start + n);
+		if data.len() < want {
+			let missing = (want - data.len()) as u64;
+			let read = stream
+				.by_ref()
+				.take(missing)
+				.read_to_end(&mut data)
+				.expect("segment decoding");
+			if (read as u64) < missing {
+				self.stream = None;
+			}
+		}
+		self.decoded = Rc::new(data);
+	}
+
 	fn next_key(&mut self) -> bool {
 		while let Some(segment) = self.segment.as_ref() {
-			while self.pos != self.decoded.len() {
-				let data = &self.decoded;
-				let klen = BigEndian::read_u32(&data[self.pos..self.pos + 4]) as usize;
-				let flen = BigEndian::read_u32(&data[self.pos + 4..self.pos + 8]) as usize;
+			let segment_version = segment.segment_version;
+			let segment_last_key = segment.last_key;
+			let mut seek_to = None;
+			loop {
+				let header_len = if segment_version == 0x0000 { 16 } else { 12 };
+				self.ensure(header_len, false);
+				if self.pos == self.decoded.len() {
+					break;
+				}
+				let klen = BigEndian::read_u32(&self.decoded[self.pos..self.pos + 4]) as usize;
+				let flen = BigEndian::read_u32(&self.decoded[self.pos + 4..self.pos + 8]) as usize;
+				self.ensure(header_len + klen + flen, false);
 
-				let pos = if segment.segment_version == 0x0000 {
+				let data = &self.decoded;
+				let pos = if segment_version == 0x0000 {
 					self.pos + 12
 				} else {
 					self.pos + 8
@@ -303,55 +1261,157 @@ impl<'rdr, 'k> StringKeyRangeReader<'rdr, 'k> {
 					}
 				}
 
-				if let Some(regex) = self.matcher.as_ref() {
-					if !regex.is_match(key) {
+				if let Some(matcher) = self.matcher.as_ref() {
+					if !matcher.is_match(key) {
 						self.pos = self.current_key_data_end;
 						continue;
 					}
 				}
 
+				if self.exclude.as_ref().is_some_and(|e| e.contains(key)) {
+					self.pos = self.current_key_data_end;
+					continue;
+				}
+
+				if let Some(keys) = self.keys.as_ref() {
+					if !keys.contains(key) {
+						match keys.range::<str, _>((Excluded(key), Unbounded)).next() {
+							None => {
+								self.pos = data.len();
+								self.segment = None;
+								return false;
+							}
+							Some(next) if next.as_str() > segment_last_key => {
+								seek_to = Some(next.clone());
+								break;
+							}
+							Some(_) => {
+								self.pos = self.current_key_data_end;
+								continue;
+							}
+						}
+					}
+				}
+
 				return true;
 			}
 
-			self.next_segment();
+			if let Some(key) = seek_to {
+				self.seek_segment(&key);
+			} else {
+				self.next_segment();
+			}
 		}
 		false
 	}
 }
 
-impl<'rdr, 'k> Iterator for StringKeyRangeReader<'rdr, 'k> {
-	type Item = Record;
-	fn next(&mut self) -> Option<Self::Item> {
+impl<'rdr, 'k> StringKeyRangeReader<'rdr, 'k> {
+	/// go to the next record, returning the position and
+	/// length of its value (timestamp included) in `decoded`
+	fn advance(&mut self) -> Option<(usize, usize)> {
 		self.segment.as_ref()?;
 
 		if self.pos == self.current_key_data_end && !self.next_key() {
 			return None;
 		}
 
-		let data = self.decoded.clone();
-
 		let current_record_len;
 		if let Some(len) = self.current_record_len {
 			current_record_len = len;
 		} else {
-			let data = &data[self.pos..];
+			self.ensure(10, true);
+			let data = &self.decoded[self.pos..];
 			let (len, tail) = unsigned_varint::decode::u64(data).unwrap();
 			let varint_len = data.len() - tail.len();
 			self.pos += varint_len;
 			current_record_len = len as usize;
 		}
 
-		let r = Record {
+		let value_len = current_record_len + crate::TIMESTAMP_SIZE;
+		self.ensure(value_len, true);
+		let value_pos = self.pos;
+		self.pos += value_len;
+		Some((value_pos, value_len))
+	}
+
+	/// If the next record is of `key`, skip it and the records of `key`
+	/// after it that are at or before `after`, without making a `Record`
+	/// of them
+	pub(crate) fn skip_through(&mut self, key: &str, after: crate::Timestamp) {
+		while self.segment.is_some() {
+			if self.pos == self.current_key_data_end && !self.next_key() {
+				return;
+			}
+			if self.current_key() != key {
+				return;
+			}
+
+			let (varint_len, record_len) = match self.current_record_len {
+				Some(len) => (0, len),
+				None => {
+					self.ensure(10, true);
+					let data = &self.decoded[self.pos..];
+					let (len, tail) = unsigned_varint::decode::u64(data).unwrap();
+					(data.len() - tail.len(), len as usize)
+				}
+			};
+			let len = varint_len + record_len + crate::TIMESTAMP_SIZE;
+			self.ensure(len, true);
+			let timestamp_pos = self.pos + varint_len;
+			let timestamp = BigEndian::read_u64(
+				&self.decoded[timestamp_pos..timestamp_pos + crate::TIMESTAMP_SIZE],
+			);
+			if timestamp > after {
+				return;
+			}
+			self.pos += len;
+		}
+	}
+
+	/// Go to the next record without making a `Record` of it,
+	/// returning only its timestamp. Its key is [`Self::current_key`]
+	pub(crate) fn next_timestamp(&mut self) -> Option<crate::Timestamp> {
+		let (value_pos, _) = self.advance()?;
+		Some(BigEndian::read_u64(
+			&self.decoded[value_pos..value_pos + crate::TIMESTAMP_SIZE],
+		))
+	}
+
+	/// Go to the next record without making a `Record` of it,
+	/// returning its format and its data (which begins with the timestamp)
+	pub(crate) fn next_raw(&mut self) -> Option<(&[u8], &[u8])> {
+		let (value_pos, value_len) = self.advance()?;
+		let format =
+			self.current_fmt_text_pos..self.current_fmt_text_pos + self.current_fmt_text_len;
+		Some((
+			&self.decoded[format],
+			&self.decoded[value_pos..value_pos + value_len],
+		))
+	}
+
+	/// The key of the record most recently read
+	pub(crate) fn current_key(&self) -> &str {
+		let d = &self.decoded
+			[self.current_key_text_pos..self.current_key_text_pos + self.current_key_text_len];
+		// this string was checked for utf-8 validity by next_key
+		unsafe { std::str::from_utf8_unchecked(d) }
+	}
+}
+
+impl<'rdr, 'k> Iterator for StringKeyRangeReader<'rdr, 'k> {
+	type Item = Record;
+	fn next(&mut self) -> Option<Self::Item> {
+		let (value_pos, value_len) = self.advance()?;
+
+		Some(Record {
 			key_pos: self.current_key_text_pos,
 			key_len: self.current_key_text_len,
 			fmt_pos: self.current_fmt_text_pos,
 			fmt_len: self.current_fmt_text_len,
-			value_pos: self.pos,
-			value_len: current_record_len + crate::TIMESTAMP_SIZE,
-			data,
-		};
-
-		self.pos += current_record_len + crate::TIMESTAMP_SIZE;
-		Some(r)
+			value_pos,
+			value_len,
+			data: self.decoded.clone(),
+		})
 	}
 }
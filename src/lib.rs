@@ -1,27 +1,66 @@
+#[cfg(feature = "arrow")]
+pub mod arrow;
 #[cfg(feature = "by-key")]
 mod bykey;
+mod combine;
+pub mod compact;
+mod crc32;
 mod create_tx;
+mod cursor;
 pub(crate) mod database_reader;
+pub mod declared_formats;
+mod diff;
+mod downsample;
+pub mod export;
 pub mod formatted;
+pub mod generate;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+mod join;
+mod key_count;
 mod key_reader;
 pub(crate) mod merge;
+pub mod pack;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+#[cfg(feature = "prometheus")]
+pub mod prometheus;
+mod provenance;
 pub(crate) mod rayon;
+mod record_count;
 mod records;
+pub mod retention;
+mod reverse;
 pub mod row_format;
 pub(crate) mod segment;
+mod segment_cache;
 pub(crate) mod segment_reader;
+mod series;
+mod sorting_tx;
 mod wildcard;
 pub(crate) mod write;
 
-pub use write::WriteFailure;
+pub use compact::{compact_dry_run, prune_empty, CompactDryRun};
+pub use cursor::{Cursor, CursorToken};
+pub use key_count::Keys;
+pub use reverse::ReverseRecordIterator;
+pub use write::{Compression, WriteFailure};
 
 #[cfg(feature = "by-key")]
 pub use bykey::*;
 pub use create_tx::*;
 pub use database_reader::*;
+pub use diff::*;
+pub use downsample::{Aggregate, Bucket, Downsample, DownsampleCache};
+pub use join::*;
 pub use key_reader::*;
+pub use provenance::{SegmentId, WithSegments};
 pub use records::*;
 pub(crate) use segment::*;
+pub use segment_cache::SegmentCache;
+pub use segment_reader::DeleteMarker;
+pub use series::{Series, SeriesValue};
+pub use sorting_tx::SortingCreateTx;
 pub use wildcard::*;
 #[cfg(test)]
 mod tests;
@@ -29,6 +68,9 @@ mod tests;
 /// Nanoseconds since the unix epoch
 pub type Timestamp = u64;
 
+/// The version of the segments that are written, see [`RawSegment::version`]
+pub const SEGMENT_VERSION: u16 = 0x0102;
+
 use std::ops::{Bound, RangeBounds};
 
 pub(crate) fn disassemble_range_bound<'k, T: Copy>(
@@ -176,35 +218,12 @@ pub fn _purge_compacted_files(
 	db: &DatabaseReader,
 	major: bool,
 ) -> std::io::Result<()> {
-	let source_transaction_paths = db.transaction_paths();
-
-	let removed_transaction_paths = if major {
-		compacted.commit_to(&dir.join("main"))?;
-		&source_transaction_paths[..]
-	} else {
-		// allow OS to atomically replace `first_path` (and don't delete it afterwards)
-		let keep_path = &source_transaction_paths.last().unwrap();
-
-		compacted.commit_to(keep_path)?;
-		&source_transaction_paths[..source_transaction_paths.len() - 1]
-	};
-
-	for txfile in removed_transaction_paths {
-		if txfile.file_name().expect("filename in txfile") == "main" {
-			continue;
-		}
-		if let Err(e) = std::fs::remove_file(txfile) {
-			eprintln!("warning: failed to remove {:?}: {}", txfile, e);
-		}
-	}
-
-	if major {
-		for txfile in db.delete_txes_paths() {
-			if let Err(e) = std::fs::remove_file(txfile) {
-				eprintln!("warning: failed to remove {:?}: {}", txfile, e);
-			}
-		}
-	}
+	compact::purge_compacted_files(compacted, dir, db, major)
+}
 
-	Ok(())
+// not part of public api
+#[doc(hidden)]
+#[cfg(feature = "bin")]
+pub fn _finish_interrupted_compaction(dir: &std::path::Path) -> std::io::Result<bool> {
+	compact::finish_interrupted(dir)
 }
@@ -1,10 +1,11 @@
 use ::rayon::prelude::*;
 use chrono::{NaiveDate, NaiveDateTime};
 use clap::{Parser, Subcommand};
-use sonnerie::{formatted, *};
+use sonnerie::{declared_formats, formatted, generate, retention, *};
 use std::ffi::OsString;
 use std::fs::File;
 use std::io::Write;
+use std::ops::Bound;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
@@ -14,6 +15,8 @@ pub(crate) const EXPECT_TIMESTAMP_CORRECTNESS: &str = "The timestamp is guarante
 #[clap(author, version, about, long_about = None)]
 struct Opt {
 	/// Store data here in this directory. Create a "main" file here first.
+	///
+	/// "read" also accepts a file made by "pack".
 	#[clap(short, long)]
 	dir: PathBuf,
 
@@ -25,12 +28,36 @@ struct Opt {
 enum Command {
 	/// Adds records.
 	Add {
-		#[clap(short, long)]
-		format: String,
+		#[clap(short, long, required_unless_present = "ndjson")]
+		format: Option<String>,
 
 		/// Instead of nanoseconds since the epoch, use this strftime format.
-		#[clap(long)]
+		#[clap(long, conflicts_with = "ndjson")]
 		timestamp_format: Option<String>,
+
+		/// The unit of the timestamps since the epoch: "nanos", "micros",
+		/// "millis" or "seconds".
+		#[clap(long, default_value = "nanos", conflicts_with_all = ["timestamp_format", "ndjson"])]
+		timestamp_unit: formatted::TimestampUnit,
+
+		/// With --timestamp-format, the times are in this time zone: "UTC",
+		/// an offset like "+05:30", or (if built with the "timezones"
+		/// feature) a name like "Europe/Paris".
+		#[clap(long, requires = "timestamp_format", allow_hyphen_values = true)]
+		timezone: Option<formatted::TimeZone>,
+
+		/// Ignore lines that start with this, such as "#".
+		#[clap(long, conflicts_with = "ndjson")]
+		comment: Option<String>,
+
+		/// Read newline-delimited JSON objects like `{"key":..., "t":..., "v":[...]}`.
+		/// If no format is given, it's inferred from the values.
+		#[clap(long)]
+		ndjson: bool,
+
+		/// Fail if a record's format isn't the one declared for its key by "declare-format".
+		#[clap(long)]
+		strict_formats: bool,
 	},
 	/// Deletes records.
 	Delete {
@@ -56,8 +83,15 @@ enum Command {
 		before_time: Option<EasyNaiveDateTime>,
 
 		/// Delete values at exactly this time (in ISO-9601 format, date, seconds, or nanosecond precision).
-		#[clap(long, conflicts_with = "time")]
+		#[clap(long)]
 		time: Option<EasyNaiveDateTime>,
+
+		/// The filter is a regular expression instead of a "%"-wildcard.
+		///
+		/// It selects the keys it matches any part of, use "^" and "$"
+		/// to match the whole key.
+		#[clap(long, requires = "filter")]
+		regex: bool,
 	},
 	/// Merges transactions.
 	///
@@ -83,6 +117,37 @@ enum Command {
 		/// With --gegnum, instead of nanoseconds since the epoch, use this strftime format.
 		#[clap(long, requires = "gegnum")]
 		timestamp_format: Option<String>,
+
+		/// Keep only the latest record of the keys made latest-only by "set-latest-only".
+		#[clap(long, requires = "major", conflicts_with = "gegnum")]
+		latest_only: bool,
+
+		/// Keep the metadata of the compacted transactions, combining
+		/// the values of each metadata key.
+		#[clap(long)]
+		merge_metadata: bool,
+
+		/// Compress the compacted data with "lz4" or "zstd". zstd is
+		/// smaller but slower, and only this and later versions can read it.
+		#[clap(long, default_value = "lz4")]
+		compression: sonnerie::Compression,
+
+		/// The LZ4 compression level, from 0 to 12 (default 9).
+		/// Higher levels are slower but smaller.
+		#[clap(long, value_parser = clap::value_parser!(i32).range(0..=12), conflicts_with = "zstd_level")]
+		lz4_level: Option<i32>,
+
+		/// With "--compression zstd", the compression level, from 1 to 22 (default 9).
+		/// Higher levels are slower but smaller.
+		#[clap(long, value_parser = clap::value_parser!(i32).range(1..=22))]
+		zstd_level: Option<i32>,
+
+		/// Don't compact, print how many transactions and records would be
+		/// compacted and an estimate of the size before compression.
+		///
+		/// This doesn't wait for a compaction that's running.
+		#[clap(long, conflicts_with_all = ["gegnum", "latest_only"])]
+		dry_run: bool,
 	},
 	/// Reads records.
 	Read {
@@ -94,6 +159,11 @@ enum Command {
 		#[clap(long)]
 		print_format: bool,
 
+		/// Output the line format only in a "# key<tab>format" line
+		/// before the first record of each key.
+		#[clap(long, conflicts_with = "print_format")]
+		format_once: bool,
+
 		/// Instead of "%F %T", output in this strftime format.
 		#[clap(long, default_value = "%F %T")]
 		timestamp_format: String,
@@ -110,6 +180,30 @@ enum Command {
 		)]
 		timestamp_seconds: bool,
 
+		/// Print timestamps as milliseconds since the unix epoch (rounded down if necessary).
+		#[clap(
+			long,
+			conflicts_with_all = ["timestamp_format", "timestamp_nanos", "timestamp_seconds"]
+		)]
+		timestamp_millis: bool,
+
+		/// Print timestamps as microseconds since the unix epoch (rounded down if necessary).
+		#[clap(
+			long,
+			conflicts_with_all = ["timestamp_format", "timestamp_nanos", "timestamp_seconds", "timestamp_millis"]
+		)]
+		timestamp_micros: bool,
+
+		/// Print times, and read --before-time and --after-time, in this
+		/// time zone: "UTC", an offset like "+05:30", or (if built with the
+		/// "timezones" feature) a name like "Europe/Paris".
+		#[clap(
+			long,
+			conflicts_with_all = ["timestamp_nanos", "timestamp_seconds", "timestamp_millis", "timestamp_micros"],
+			allow_hyphen_values = true
+		)]
+		timezone: Option<formatted::TimeZone>,
+
 		/// Read values before (but not including) this key.
 		#[clap(long, conflicts_with = "filter")]
 		before_key: Option<String>,
@@ -137,6 +231,186 @@ enum Command {
 		/// is '1'. Example: 1,3,5-7
 		#[clap(long, short = 'C')]
 		columns: Option<String>,
+
+		/// Print floating point values with this many digits after the decimal point.
+		/// By default, the shortest representation that reads back as the same value
+		/// is printed.
+		#[clap(long)]
+		float_precision: Option<usize>,
+
+		/// Instead of printing the records, write each key's records to its
+		/// own file, "<key>.txt", in this directory. In the file names, "/", ":",
+		/// "\\" and "%" are escaped as "%" and their hexadecimal value.
+		#[clap(long, conflicts_with = "parallel")]
+		split_per_key: Option<PathBuf>,
+
+		/// Print each record as a line of CSV: the key, the timestamp and
+		/// each column are separate fields, and fields with commas, quotes or
+		/// line breaks are quoted. With --split-per-key, the files are "<key>.csv".
+		#[clap(long, conflicts_with = "format_once")]
+		csv: bool,
+
+		/// Print each record as a JSON object on its own line:
+		/// {"key":…,"timestamp":…,"values":[…]}. The timestamp is a number
+		/// with --timestamp-nanos or --timestamp-seconds, otherwise a string.
+		/// With --split-per-key, the files are "<key>.jsonl".
+		#[clap(long, conflicts_with_all = ["format_once", "csv"])]
+		json: bool,
+	},
+	/// Generates deterministic synthetic records.
+	///
+	/// The same parameters and seed always produce the same database,
+	/// which is useful for load-testing and reproducible bug reports.
+	Generate {
+		/// How many distinct keys to create.
+		#[clap(long)]
+		keys: usize,
+
+		/// How many records to create for each key.
+		#[clap(long)]
+		points_per_key: usize,
+
+		/// The random seed.
+		#[clap(long, default_value = "0")]
+		seed: u64,
+
+		/// The format of each record.
+		#[clap(short, long, default_value = "F")]
+		format: String,
+	},
+	/// Exports every record into a single file.
+	///
+	/// The file is written completely or not at all.
+	Export {
+		/// The file to create.
+		file: PathBuf,
+
+		/// Export several parts of the database at once, and
+		/// then join them into the file.
+		#[clap(long)]
+		parallel: bool,
+
+		/// Export in parallel to several files in the directory "file",
+		/// without joining them. Each is an export of a range of keys,
+		/// and their names sort in the order of the keys. The shards
+		/// of an earlier export to "file" are removed.
+		#[clap(long, conflicts_with = "parallel")]
+		shards: bool,
+
+		/// Write Parquet instead, with one file for each format.
+		///
+		/// If the records have more than one format, "file" is numbered:
+		/// "out.parquet" becomes "out.1.parquet", "out.2.parquet"... The
+		/// path and the format of each file are printed.
+		#[cfg(feature = "parquet")]
+		#[clap(long, conflicts_with_all = ["parallel", "shards"])]
+		parquet: bool,
+
+		/// Only export the keys that match this "%"-wildcard.
+		#[cfg(feature = "parquet")]
+		#[clap(long, requires = "parquet")]
+		filter: Option<String>,
+	},
+	/// Packs the whole database into a single file.
+	///
+	/// The records are compacted, as by a major compaction. "read"
+	/// can read the file when it is given as the database's directory.
+	Pack {
+		/// The file to create.
+		file: PathBuf,
+	},
+	/// Imports a file made by "export" into a new database.
+	///
+	/// The directory must not have a database in it. If the file
+	/// is truncated or damaged, nothing is imported. An interrupted
+	/// import doesn't resume; run it again.
+	Import {
+		/// The file to read.
+		file: PathBuf,
+	},
+	/// Keeps the records of the matching keys for only some days.
+	///
+	/// The rule is stored in the database, and is applied by "apply-retention".
+	SetRetention {
+		/// Select the keys, "%" is the wildcard.
+		wildcard: String,
+
+		/// Keep the records for this many days, or 0 to remove the rule.
+		days: u64,
+	},
+	/// Keeps only the latest record of the matching keys.
+	///
+	/// The wildcard is stored in the database, and the older records
+	/// are removed by "compact --major --latest-only".
+	SetLatestOnly {
+		/// Select the keys, "%" is the wildcard.
+		wildcard: String,
+
+		/// Remove the wildcard instead.
+		#[clap(long)]
+		off: bool,
+	},
+	/// Declares the format of a key, which "add --strict-formats" enforces.
+	DeclareFormat {
+		key: String,
+
+		/// The format, like "uu", or "" to remove the declaration.
+		format: String,
+	},
+	/// Deletes the records that are older than their retention rule allows.
+	ApplyRetention {
+		/// Apply the rules as if it were this time instead of now.
+		#[clap(long)]
+		now: Option<EasyNaiveDateTime>,
+	},
+	/// Deletes the records of the matching keys that are older than a duration.
+	///
+	/// Unlike "set-retention", nothing is stored: each run deletes the
+	/// records that are older than the duration at that time, so run
+	/// it periodically. A major compaction reclaims their space.
+	Retention {
+		/// Keep the records for this long, like "30d" (s, m, h, d or w).
+		#[clap(long, value_parser = retention::parse_duration)]
+		keep: std::time::Duration,
+
+		/// Select the keys, "%" is the wildcard.
+		#[clap(long, default_value = "%")]
+		filter: String,
+
+		/// Delete as if it were this time instead of now.
+		#[clap(long)]
+		now: Option<EasyNaiveDateTime>,
+	},
+	/// Verifies the transaction files of the database.
+	///
+	/// Prints each file and whether it passed, and fails if any didn't.
+	/// Without options, does every check. Nothing is modified.
+	#[clap(visible_alias = "verify")]
+	Check {
+		/// Check that the keys are in order, and the timestamps of each key
+		/// are ascending, as they are when written.
+		#[clap(long)]
+		ordering: bool,
+
+		/// Check that each segment can be decompressed, and that its keys
+		/// and records are consistent with its header and their formats.
+		/// This includes --ordering.
+		#[clap(long)]
+		segments: bool,
+
+		/// Warn about segments with the wrong checksum and check the rest
+		/// of their file, instead of failing the file.
+		#[clap(long)]
+		skip_bad_checksums: bool,
+	},
+	/// Describes a single transaction file, without reading a database.
+	///
+	/// Prints its metadata and, for each key, the number of records,
+	/// their time span and formats. For a file of deletions, prints
+	/// what it deletes.
+	Inspect {
+		/// The transaction file.
+		file: PathBuf,
 	},
 }
 
@@ -147,17 +421,65 @@ fn main() -> std::io::Result<()> {
 		Command::Add {
 			format,
 			timestamp_format,
-		} => add(&opt.dir, &format, timestamp_format.as_deref()),
+			timestamp_unit,
+			timezone,
+			comment,
+			ndjson,
+			strict_formats,
+		} => {
+			if ndjson {
+				add_ndjson(&opt.dir, format.as_deref(), strict_formats)
+			} else {
+				add(
+					&opt.dir,
+					&format.unwrap(),
+					timestamp_format.as_deref(),
+					timestamp_unit,
+					timezone.unwrap_or_default(),
+					comment.as_deref(),
+					strict_formats,
+				)
+			}
+		}
 		Command::Compact {
 			major,
 			gegnum,
 			timestamp_format,
-		} => compact(
-			&opt.dir,
-			major,
-			gegnum.as_deref(),
-			timestamp_format.as_deref(),
-		)
+			latest_only,
+			merge_metadata,
+			compression,
+			lz4_level,
+			zstd_level,
+			dry_run,
+		} => {
+			if dry_run {
+				let summary = sonnerie::compact_dry_run(&opt.dir, major)?;
+				println!("transactions\t{}", summary.transactions);
+				println!("deletions\t{}", summary.deletions);
+				println!("records\t{}", summary.records);
+				println!("input_bytes\t{}", summary.input_bytes);
+				println!("estimated_bytes\t{}", summary.estimated_bytes);
+				return Ok(());
+			}
+			let level =
+				match (compression, lz4_level, zstd_level) {
+					(sonnerie::Compression::Lz4, level, None) => level,
+					(sonnerie::Compression::Zstd, None, level) => level,
+					_ => {
+						eprintln!("error: --lz4-level and --zstd-level are for --compression lz4 and zstd");
+						std::process::exit(1);
+					}
+				};
+			compact(
+				&opt.dir,
+				major,
+				gegnum.as_deref(),
+				timestamp_format.as_deref(),
+				latest_only,
+				merge_metadata,
+				(compression, level),
+			)
+		}
 		.expect("compacting"),
 		Command::Delete {
 			filter,
@@ -166,7 +488,15 @@ fn main() -> std::io::Result<()> {
 			after_time,
 			before_time,
 			time,
+			regex,
 		} => {
+			if regex {
+				if let Err(e) = regex::Regex::new(filter.as_deref().unwrap()) {
+					eprintln!("error: {}", e);
+					std::process::exit(1);
+				}
+			}
+
 			let mut after_time = after_time.map(|d| d.0);
 			let mut before_time = before_time.map(|d| d.0);
 
@@ -182,40 +512,260 @@ fn main() -> std::io::Result<()> {
 				after_time,
 				before_time,
 				filter.as_deref(),
+				regex,
 			);
 		}
+		Command::Generate {
+			keys,
+			points_per_key,
+			seed,
+			format,
+		} => {
+			let mut tx = CreateTx::new(&opt.dir)?;
+			generate::generate(
+				&mut tx,
+				&generate::GenerateOptions {
+					keys,
+					points_per_key,
+					seed,
+					format: &format,
+				},
+			)
+			.expect("generating records");
+			tx.commit()?;
+		}
+		Command::Export {
+			file,
+			parallel,
+			shards,
+			#[cfg(feature = "parquet")]
+			parquet,
+			#[cfg(feature = "parquet")]
+			filter,
+		} => {
+			let db = DatabaseReader::new(&opt.dir)?;
+			#[cfg(feature = "parquet")]
+			if parquet {
+				let wildcard = sonnerie::Wildcard::new(filter.as_deref().unwrap_or("%"));
+				let batches = db.get_filter(&wildcard).into_arrow();
+				for (format, path) in sonnerie::parquet::export_parquet(batches, &file)? {
+					println!("{}\t{}", path.display(), format);
+				}
+				return Ok(());
+			}
+			if shards {
+				sonnerie::export::export_parallel(&db, &file)?;
+			} else {
+				let parent = match file.parent() {
+					Some(p) if !p.as_os_str().is_empty() => p,
+					_ => Path::new("."),
+				};
+				let mut out = tempfile::NamedTempFile::new_in(parent)?;
+				{
+					let mut w = std::io::BufWriter::new(out.as_file_mut());
+					if parallel {
+						let shards_dir = tempfile::TempDir::new_in(parent)?;
+						let shards = sonnerie::export::export_parallel(&db, shards_dir.path())?;
+						let mut inputs = shards
+							.iter()
+							.map(|p| Ok(std::io::BufReader::new(File::open(p)?)))
+							.collect::<std::io::Result<Vec<_>>>()?;
+						sonnerie::export::concatenate(&mut inputs, &mut w)?;
+					} else {
+						sonnerie::export::export(&db, &mut w)?;
+					}
+					w.flush()?;
+				}
+				out.as_file().sync_all()?;
+				out.persist(&file)?;
+			}
+		}
+		Command::Pack { file } => {
+			let db = DatabaseReader::new(&opt.dir)?;
+			if let Err(e) = sonnerie::pack::pack(&db, &file) {
+				eprintln!("error: {}", e);
+				std::process::exit(1);
+			}
+		}
+		Command::Import { file } => {
+			let mut input = std::io::BufReader::new(File::open(&file)?);
+			if let Err(e) = sonnerie::export::import(&mut input, &opt.dir) {
+				eprintln!("error: {}", e);
+				std::process::exit(1);
+			}
+		}
+		Command::SetRetention { wildcard, days } => {
+			let mut tx = CreateTx::new(&opt.dir)?;
+			let keep = std::time::Duration::from_secs(days * 24 * 60 * 60);
+			if let Err(e) = retention::set_rule(&mut tx, current_time(), &wildcard, keep) {
+				eprintln!("error: {}", e);
+				std::process::exit(1);
+			}
+			tx.commit()?;
+		}
+		Command::SetLatestOnly { wildcard, off } => {
+			let mut tx = CreateTx::new(&opt.dir)?;
+			if let Err(e) = retention::set_latest_only(&mut tx, current_time(), &wildcard, !off) {
+				eprintln!("error: {}", e);
+				std::process::exit(1);
+			}
+			tx.commit()?;
+		}
+		Command::DeclareFormat { key, format } => {
+			let mut tx = CreateTx::new(&opt.dir)?;
+			let declared = if format.is_empty() {
+				declared_formats::undeclare(&mut tx, current_time(), &key)
+			} else {
+				declared_formats::declare(&mut tx, current_time(), &key, &format)
+			};
+			if let Err(e) = declared {
+				eprintln!("error: {}", e);
+				std::process::exit(1);
+			}
+			tx.commit()?;
+		}
+		Command::ApplyRetention { now } => {
+			let now = now.map_or_else(current_time, |n| n.0);
+			match retention::apply(&opt.dir, now) {
+				Ok(applied) => {
+					for (rule, cutoff) in applied {
+						eprintln!("{}: deleted before {}", rule.wildcard, cutoff);
+					}
+				}
+				Err(e) => {
+					eprintln!("error: {}", e);
+					std::process::exit(1);
+				}
+			}
+		}
+		Command::Retention { keep, filter, now } => {
+			let now = now.map_or_else(current_time, |n| n.0);
+			match retention::expire(&opt.dir, now, &filter, keep) {
+				Ok(cutoff) => eprintln!("{}: deleted before {}", filter, cutoff),
+				Err(e) => {
+					eprintln!("error: {}", e);
+					std::process::exit(1);
+				}
+			}
+		}
+		Command::Check {
+			ordering,
+			segments,
+			skip_bad_checksums,
+		} => {
+			// the files are opened one by one, so that a corrupt one is reported
+			let mut paths = vec![];
+			for entry in std::fs::read_dir(&opt.dir)? {
+				let entry = entry?;
+				let name = entry.file_name();
+				let name = name.to_string_lossy();
+				if name == "main" || (name.starts_with("tx.") && !name.ends_with(".tmp")) {
+					paths.push(entry.path());
+				}
+			}
+			// "main" sorts before "tx."
+			paths.sort();
+
+			let mut failed = 0;
+			for path in paths {
+				let file = File::open(&path)?;
+				if file.metadata()?.len() == 0 {
+					continue;
+				}
+				let reader = match Reader::new(file) {
+					Ok(either::Either::Left(reader)) if skip_bad_checksums => {
+						reader.on_checksum_mismatch(sonnerie::ChecksumMismatch::Skip)
+					}
+					Ok(either::Either::Left(reader)) => reader,
+					Ok(either::Either::Right(_)) => {
+						println!("{}\tok\tdeletion", path.display());
+						continue;
+					}
+					Err(e) => {
+						println!("{}\tcorrupt\t{}", path.display(), e);
+						failed += 1;
+						continue;
+					}
+				};
+				if ordering && !segments {
+					match reader.check_ordering() {
+						Ok(records) => println!("{}\tok\t{} records", path.display(), records),
+						Err(e) => {
+							println!("{}\tout of order\t{}", path.display(), e);
+							failed += 1;
+						}
+					}
+				} else {
+					match reader.verify() {
+						Ok(records) => println!("{}\tok\t{} records", path.display(), records),
+						Err(e) => {
+							println!("{}\tcorrupt\t{}", path.display(), e);
+							failed += 1;
+						}
+					}
+				}
+			}
+			if failed != 0 {
+				return Err(std::io::Error::new(
+					std::io::ErrorKind::InvalidData,
+					format!("{} transaction files failed the check", failed),
+				));
+			}
+		}
+		Command::Inspect { file } => {
+			let stdout = std::io::stdout();
+			let mut stdout = std::io::BufWriter::new(stdout.lock());
+			inspect(&mut stdout, Reader::new(File::open(&file)?)?)?;
+			stdout.flush()?;
+		}
 		Command::Read {
 			filter,
 			print_format,
+			format_once,
 			timestamp_format,
 			timestamp_nanos,
 			timestamp_seconds,
+			timestamp_millis,
+			timestamp_micros,
+			timezone,
 			before_key,
 			after_key,
 			before_time,
 			after_time,
 			parallel,
 			columns,
+			float_precision,
+			split_per_key,
+			csv,
+			json,
 		} => {
-			let after_time = after_time.map(|t| {
-				t.0.and_utc()
-					.timestamp_nanos_opt()
-					.expect(EXPECT_TIMESTAMP_CORRECTNESS) as u64
-			});
-			let before_time = before_time.map(|t| {
-				t.0.and_utc()
-					.timestamp_nanos_opt()
-					.expect(EXPECT_TIMESTAMP_CORRECTNESS) as u64
-			});
+			let time_zone = timezone.unwrap_or_default();
+			let in_zone = |t: EasyNaiveDateTime| {
+				time_zone.to_utc(t.0).unwrap_or_else(|| {
+					eprintln!("error: {} doesn't happen in that time zone", t.0);
+					std::process::exit(1);
+				})
+			};
+			let time_range = (
+				after_time.map_or(Bound::Unbounded, |t| Bound::Included(in_zone(t))),
+				before_time.map_or(Bound::Unbounded, |t| Bound::Excluded(in_zone(t))),
+			);
 
 			let stdout = std::io::stdout();
 			let mut stdout = std::io::BufWriter::new(stdout.lock());
-			let db = DatabaseReader::new(&opt.dir)?;
+			let db = if opt.dir.is_file() {
+				DatabaseReader::open_packed(&opt.dir)?
+			} else {
+				DatabaseReader::new(&opt.dir)?
+			};
 			write_empty_file_warnings(&mut std::io::stderr().lock(), db.empty_transaction_files())
 				.unwrap();
 
+			let format_header = formatted::FormatHeader::default();
 			let print_record_format = if print_format {
 				formatted::PrintRecordFormat::Yes
+			} else if format_once {
+				formatted::PrintRecordFormat::OncePerKey(&format_header)
 			} else {
 				formatted::PrintRecordFormat::No
 			};
@@ -223,6 +773,12 @@ fn main() -> std::io::Result<()> {
 				formatted::PrintTimestamp::Nanos
 			} else if timestamp_seconds {
 				formatted::PrintTimestamp::Seconds
+			} else if timestamp_millis {
+				formatted::PrintTimestamp::Millis
+			} else if timestamp_micros {
+				formatted::PrintTimestamp::Micros
+			} else if let Some(time_zone) = timezone {
+				formatted::PrintTimestamp::ZonedFormatString(&timestamp_format, time_zone)
 			} else {
 				formatted::PrintTimestamp::FormatString(&timestamp_format)
 			};
@@ -231,9 +787,48 @@ fn main() -> std::io::Result<()> {
 				.map(|c| choice_string::parse(&c).unwrap())
 				.unwrap_or(choice_string::Selection::All);
 
+			let float_precision = float_precision
+				.map(row_format::FloatPrecision::Digits)
+				.unwrap_or_default();
+
+			macro_rules! print_record {
+				($record:expr, $out:expr, $print_record_format:expr) => {
+					if json {
+						formatted::print_record_json(
+							$record,
+							$out,
+							print_timestamp,
+							$print_record_format,
+							&column_selection,
+							float_precision,
+						)
+					} else if csv {
+						formatted::print_record_csv(
+							$record,
+							$out,
+							print_timestamp,
+							$print_record_format,
+							&column_selection,
+							float_precision,
+						)
+					} else {
+						formatted::print_record_with_precision(
+							$record,
+							$out,
+							print_timestamp,
+							$print_record_format,
+							&column_selection,
+							float_precision,
+						)
+					}
+				};
+			}
+			// RFC 4180 ends lines with CRLF
+			let line_end = if csv { "\r\n" } else { "\n" };
+
 			macro_rules! filter_parallel {
 				($filter:expr) => {{
-					let filter = $filter;
+					let filter = $filter.time_range(time_range);
 
 					use std::io::BufWriter;
 					use std::process::*;
@@ -248,57 +843,48 @@ fn main() -> std::io::Result<()> {
 							.spawn()
 							.unwrap();
 						let stdout = BufWriter::new(child.stdin.take().unwrap());
-						(child, stdout)
+						(child, stdout, formatted::FormatHeader::default())
 					};
 
 					filter
 						.into_par_iter()
-						.for_each_init(subproc, |(_, out), record| {
-							let ts = record.timestamp_nanos();
-							if let Some(after_time) = after_time {
-								if ts < after_time {
-									return;
-								}
-							}
-							if let Some(before_time) = before_time {
-								if ts >= before_time {
-									return;
+						.for_each_init(subproc, |(_, out, header), record| {
+							// each subprocess gets the headers of its own records
+							let print_record_format = match print_record_format {
+								formatted::PrintRecordFormat::OncePerKey(_) => {
+									formatted::PrintRecordFormat::OncePerKey(header)
 								}
-							}
-							formatted::print_record(
-								&record,
-								out,
-								print_timestamp,
-								print_record_format,
-								&column_selection,
-							)
-							.expect("failed to write to subprocess");
-							writeln!(out, "").expect("failed to write to subprocess");
+								other => other,
+							};
+							print_record!(&record, out, print_record_format)
+								.expect("failed to write to subprocess");
+							write!(out, "{}", line_end).expect("failed to write to subprocess");
 						});
 				}};
 			}
 			macro_rules! filter {
 				($filter:expr) => {{
-					for record in $filter {
-						let ts = record.timestamp_nanos();
-						if let Some(after_time) = after_time {
-							if ts < after_time {
-								continue;
-							}
-						}
-						if let Some(before_time) = before_time {
-							if ts >= before_time {
-								continue;
-							}
-						}
-						formatted::print_record(
-							&record,
-							&mut stdout,
-							print_timestamp,
-							print_record_format,
-							&column_selection,
-						)?;
-						writeln!(&mut stdout, "")?;
+					let mut split = split_per_key
+						.as_deref()
+						.map(|dir| {
+							let extension = match (csv, json) {
+								(true, _) => "csv",
+								(_, true) => "jsonl",
+								_ => "txt",
+							};
+							formatted::SplitPerKey::new(dir, extension)
+						})
+						.transpose()?;
+					for record in $filter.time_range(time_range) {
+						let mut out: &mut dyn Write = match &mut split {
+							Some(split) => split.writer(record.key())?,
+							None => &mut stdout,
+						};
+						print_record!(&record, &mut out, print_record_format)?;
+						write!(out, "{}", line_end)?;
+					}
+					if let Some(split) = split {
+						split.finish()?;
 					}
 				}};
 			}
@@ -341,15 +927,53 @@ fn main() -> std::io::Result<()> {
 //
 // delete's approach is to copy what add_from_stream does and call
 // CreateTx::add_record with a prepared bare payload
-fn add(dir: &Path, fmt: &str, ts_format: Option<&str>) {
+fn add(
+	dir: &Path,
+	fmt: &str,
+	ts_format: Option<&str>,
+	ts_unit: formatted::TimestampUnit,
+	time_zone: formatted::TimeZone,
+	comment: Option<&str>,
+	strict_formats: bool,
+) {
 	let db = DatabaseReader::new(dir).expect("opening db");
 	write_empty_file_warnings(&mut std::io::stderr().lock(), db.empty_transaction_files()).unwrap();
 	let mut tx = CreateTx::new(dir).expect("creating tx");
+	if strict_formats {
+		tx.enforce_formats().expect("reading the declared formats");
+	}
 
 	let stdin = std::io::stdin();
 	let mut stdin = stdin.lock();
 
-	formatted::add_from_stream(&mut tx, fmt, &mut stdin, ts_format).expect("adding value");
+	let options = formatted::TextOptions {
+		comment_prefix: comment,
+		timestamp_unit: ts_unit,
+		time_zone,
+		..Default::default()
+	};
+	if let Err(e) = formatted::add_from_stream_opts(&mut tx, fmt, &mut stdin, ts_format, &options) {
+		eprintln!("error: {}", e);
+		std::process::exit(1);
+	}
+	tx.commit().expect("failed to commit transaction");
+}
+
+fn add_ndjson(dir: &Path, fmt: Option<&str>, strict_formats: bool) {
+	let db = DatabaseReader::new(dir).expect("opening db");
+	write_empty_file_warnings(&mut std::io::stderr().lock(), db.empty_transaction_files()).unwrap();
+	let mut tx = CreateTx::new(dir).expect("creating tx");
+	if strict_formats {
+		tx.enforce_formats().expect("reading the declared formats");
+	}
+
+	let stdin = std::io::stdin();
+	let mut stdin = stdin.lock();
+
+	if let Err(e) = formatted::add_from_ndjson(&mut tx, &mut stdin, fmt) {
+		eprintln!("error: {}", e);
+		std::process::exit(1);
+	}
 	tx.commit().expect("failed to commit transaction");
 }
 
@@ -365,6 +989,7 @@ fn delete(
 	after_time: Option<NaiveDateTime>,
 	before_time: Option<NaiveDateTime>,
 	filter: Option<&str>,
+	regex: bool,
 ) {
 	let mut tx = CreateTx::new(dir).expect("creating tx");
 
@@ -383,13 +1008,20 @@ fn delete(
 		})
 		.unwrap_or(u64::MAX);
 
-	tx.delete(
-		first_key.unwrap_or(""),
-		last_key.unwrap_or(""),
-		after_time,
-		before_time,
-		filter.unwrap_or("%"),
-	)
+	let first_key = first_key.unwrap_or("");
+	let last_key = last_key.unwrap_or("");
+	match filter {
+		Some(filter) if regex => {
+			tx.delete_regex(first_key, last_key, after_time, before_time, filter)
+		}
+		filter => tx.delete(
+			first_key,
+			last_key,
+			after_time,
+			before_time,
+			filter.unwrap_or("%"),
+		),
+	}
 	.expect("deleting rows");
 	tx.commit().expect("failed to commit transaction");
 }
@@ -399,11 +1031,37 @@ fn compact(
 	major: bool,
 	gegnum: Option<&std::ffi::OsStr>,
 	ts_format: Option<&str>,
+	latest_only: bool,
+	merge_metadata: bool,
+	(compression, level): (sonnerie::Compression, Option<i32>),
 ) -> Result<(), crate::WriteFailure> {
 	use fs2::FileExt;
 
+	let new_tx = |db: &DatabaseReader| {
+		let mut compacted = match level {
+			Some(level) => CreateTx::new_with_compression_level(dir, compression, level)?,
+			None => CreateTx::new_with_compression(dir, compression)?,
+		};
+		if merge_metadata {
+			for (k, v) in db.merged_tx_metadata() {
+				compacted.set_meta(&k, &v)?;
+			}
+		}
+		Ok::<_, crate::WriteFailure>(compacted)
+	};
+
+	if latest_only {
+		// (--latest-only must rewrite main even if it's the only file)
+		let records = sonnerie::compact::compact_latest_only_with(dir, new_tx)?;
+		eprintln!("compacted {} records", records);
+		return Ok(());
+	}
+
 	let lock = File::create(dir.join(".compact"))?;
 	lock.lock_exclusive()?;
+	if sonnerie::_finish_interrupted_compaction(dir)? {
+		eprintln!("finished removing the files of an interrupted compaction");
+	}
 
 	// We loop to ensure we've processed all the .tx files
 	// As we know the compaction is atomic there is no downside
@@ -424,7 +1082,7 @@ fn compact(
 		}
 		let db = std::sync::Arc::new(db);
 
-		let mut compacted = CreateTx::new(dir)?;
+		let mut compacted = new_tx(&db)?;
 
 		if let Some(gegnum) = gegnum {
 			let mut child = std::process::Command::new("/bin/sh")
@@ -465,7 +1123,14 @@ fn compact(
 
 			let childoutput = child.stdout.take().expect("process had no stdout");
 			let mut childoutput = std::io::BufReader::new(childoutput);
-			formatted::add_from_stream_with_fmt(&mut compacted, &mut childoutput, ts_format)?;
+			if let Err(e) =
+				formatted::add_from_stream_with_fmt(&mut compacted, &mut childoutput, ts_format)
+			{
+				// stop the child rather than leave it running
+				let _ = child.kill();
+				child.wait()?;
+				return Err(e);
+			}
 
 			reader_thread
 				.join()
@@ -485,12 +1150,10 @@ fn compact(
 			}
 			// create the new transaction after opening the database reader
 			let reader = db.get_range(..);
-			let mut n = 0u64;
 			for record in reader {
 				compacted.add_record_raw(record.key(), record.format(), record.raw())?;
-				n += 1;
 			}
-			eprintln!("compacted {} records", n);
+			eprintln!("compacted {} records", compacted.record_count());
 		}
 
 		sonnerie::_purge_compacted_files(compacted, dir, &db, major).expect("failure compacting");
@@ -498,6 +1161,90 @@ fn compact(
 	Ok(())
 }
 
+fn current_time() -> NaiveDateTime {
+	chrono::DateTime::<chrono::Utc>::from(std::time::SystemTime::now()).naive_utc()
+}
+
+fn inspect(
+	out: &mut impl std::io::Write,
+	file: either::Either<Reader, DeleteMarker>,
+) -> std::io::Result<()> {
+	let time = |t: NaiveDateTime| t.format("%F %T%.f");
+	let write_metadata =
+		|out: &mut dyn std::io::Write, metadata: &std::collections::BTreeMap<String, String>| {
+			for (k, v) in metadata {
+				writeln!(
+					out,
+					"metadata\t{}\t{}",
+					escape_string::escape(k),
+					escape_string::escape(v)
+				)?;
+			}
+			Ok::<_, std::io::Error>(())
+		};
+
+	match file {
+		either::Either::Left(reader) => {
+			let keys = reader.key_summaries();
+			writeln!(
+				out,
+				"transaction\t{} keys\t{} records",
+				keys.len(),
+				keys.iter().map(|k| k.records).sum::<u64>()
+			)?;
+			write_metadata(out, reader.metadata())?;
+			if let Some(versions) = reader.segment_versions() {
+				writeln!(
+					out,
+					"segment versions\t{:#06x}\t{:#06x}",
+					versions.start(),
+					versions.end()
+				)?;
+			}
+			for key in keys {
+				writeln!(
+					out,
+					"{}\t{}\t{}\t{}\t{}",
+					escape_string::escape(&key.key),
+					key.records,
+					time(key.first_timestamp),
+					time(key.last_timestamp),
+					key.formats.join(","),
+				)?;
+			}
+		}
+		either::Either::Right(marker) => {
+			writeln!(out, "deletion")?;
+			write_metadata(out, &marker.metadata)?;
+			writeln!(
+				out,
+				"keys\t{}\t{}",
+				escape_string::escape(&marker.first_key),
+				escape_string::escape(&marker.last_key)
+			)?;
+			writeln!(
+				out,
+				"{}\t{}",
+				if marker.regex {
+					"regex"
+				} else if marker.keys {
+					"exact"
+				} else {
+					"wildcard"
+				},
+				escape_string::escape(&marker.wildcard)
+			)?;
+			writeln!(
+				out,
+				"times\t{}\t{}",
+				time(marker.first_timestamp),
+				time(marker.last_timestamp)
+			)?;
+		}
+	}
+	Ok(())
+}
+
 fn write_empty_file_warnings(
 	out: &mut impl std::io::Write,
 	names: &[PathBuf],
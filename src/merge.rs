@@ -3,6 +3,7 @@ use std::collections::BinaryHeap;
 use std::sync::Arc;
 
 type CompareRecord<Record> = Box<dyn Fn(&Record, &Record) -> Ordering + Send + Sync>;
+type PastEnd<Record> = Box<dyn Fn(&Record) -> bool + Send + Sync>;
 
 struct NextRecord<Source, Record>
 where
@@ -12,6 +13,8 @@ where
 	source_index: usize,
 	current_record: Option<Record>,
 	compare_record: Arc<CompareRecord<Record>>,
+	/// equal records come from the lowest `source_index` first
+	oldest_first: bool,
 }
 
 impl<Source: Iterator<Item = Record>, Record> Ord for NextRecord<Source, Record> {
@@ -21,7 +24,14 @@ impl<Source: Iterator<Item = Record>, Record> Ord for NextRecord<Source, Record>
 			other.current_record.as_ref().unwrap(),
 		)
 		.reverse()
-		.then_with(|| self.source_index.cmp(&other.source_index))
+		.then_with(|| {
+			let o = self.source_index.cmp(&other.source_index);
+			if self.oldest_first {
+				o.reverse()
+			} else {
+				o
+			}
+		})
 	}
 }
 
@@ -51,6 +61,9 @@ where
 {
 	sorter: BinaryHeap<NextRecord<Source, Record>>,
 	most_recent: Option<NextRecord<Source, Record>>,
+	keep_duplicates: bool,
+	/// a source whose next item is past the end is dropped
+	past_end: Option<PastEnd<Record>>,
 }
 
 impl<Source, Record> Merge<Source, Record>
@@ -62,6 +75,29 @@ where
 		orig_sources: Vec<(usize, Source)>,
 		compare_record: CompareRecord,
 	) -> Self
+	where
+		CompareRecord: Fn(&Record, &Record) -> Ordering + 'static + Send + Sync,
+	{
+		Self::new_opts(orig_sources, compare_record, false)
+	}
+
+	/// like `new`, but items that compare equal are all yielded,
+	/// the one from the lowest source index first
+	pub fn new_keep_duplicates<CompareRecord>(
+		orig_sources: Vec<(usize, Source)>,
+		compare_record: CompareRecord,
+	) -> Self
+	where
+		CompareRecord: Fn(&Record, &Record) -> Ordering + 'static + Send + Sync,
+	{
+		Self::new_opts(orig_sources, compare_record, true)
+	}
+
+	fn new_opts<CompareRecord>(
+		orig_sources: Vec<(usize, Source)>,
+		compare_record: CompareRecord,
+		keep_duplicates: bool,
+	) -> Self
 	where
 		CompareRecord: Fn(&Record, &Record) -> Ordering + 'static + Send + Sync,
 	{
@@ -77,6 +113,7 @@ where
 					source_index: tx_id,
 					current_record: Some(rec),
 					compare_record: compare_record.clone(),
+					oldest_first: keep_duplicates,
 				});
 			}
 		}
@@ -84,6 +121,35 @@ where
 		Self {
 			sorter,
 			most_recent: None,
+			keep_duplicates,
+			past_end: None,
+		}
+	}
+
+	/// stop at the first item for which `past_end` is true
+	///
+	/// The sources are sorted, so once one of them yields an item past
+	/// the end, it's dropped without being read any further, and the
+	/// merge ends when all of them have been.
+	pub fn until<PastEnd>(mut self, past_end: PastEnd) -> Self
+	where
+		PastEnd: Fn(&Record) -> bool + 'static + Send + Sync,
+	{
+		let sorter = std::mem::take(&mut self.sorter);
+		self.sorter = sorter
+			.into_iter()
+			.filter(|n| !past_end(n.current_record.as_ref().unwrap()))
+			.collect();
+		self.past_end = Some(Box::new(past_end));
+		self
+	}
+
+	/// the next item of `source`, or `None` if it's past the end
+	fn next_of(&self, source: &mut Source) -> Option<Record> {
+		let item = source.next()?;
+		match &self.past_end {
+			Some(past_end) if past_end(&item) => None,
+			_ => Some(item),
 		}
 	}
 
@@ -111,7 +177,7 @@ where
 			let mut best = self.sorter.pop().unwrap();
 			best.current_record = None; // drop current_record before asking for the next one
 
-			let succ_record = best.source.next();
+			let succ_record = self.next_of(&mut best.source);
 			if let Some(succ_record) = succ_record {
 				best.current_record = Some(succ_record);
 				self.sorter.push(best);
@@ -130,11 +196,25 @@ where
 	type Item = (usize, Record);
 
 	fn next(&mut self) -> Option<Self::Item> {
+		if self.keep_duplicates {
+			if let Some(mut most_recent) = self.most_recent.take() {
+				if let Some(current) = self.next_of(&mut most_recent.source) {
+					most_recent.current_record = Some(current);
+					self.sorter.push(most_recent);
+				}
+			}
+			let mut best = self.sorter.pop()?;
+			let item = best.current_record.take().expect("current record is null");
+			let tx_id = best.source_index;
+			self.most_recent = Some(best);
+			return Some((tx_id, item));
+		}
+
 		// refill the most recent one
 		if let Some(mut most_recent) = self.most_recent.take() {
 			let source_index = most_recent.source_index;
 
-			if let Some(current) = most_recent.source.next() {
+			if let Some(current) = self.next_of(&mut most_recent.source) {
 				// we short-circuit putting `current` on the heap again by
 				// testing the current top of the heap
 
@@ -236,6 +316,25 @@ mod tests {
 		assert_eq!(merged.next(), None);
 	}
 	#[test]
+	fn merge_keep_duplicates() {
+		let a = [("b", 1), ("c", 1), ("d", 1)].iter().cloned();
+		let b = [("a", 2), ("c", 2), ("d", 2)].iter().cloned();
+		let merged =
+			crate::merge::Merge::new_keep_duplicates(vec![(1, b), (0, a)], |a, b| a.0.cmp(b.0));
+		let merged: Vec<_> = merged.collect();
+		assert_eq!(
+			merged,
+			vec![
+				(1, ("a", 2)),
+				(0, ("b", 1)),
+				(0, ("c", 1)),
+				(1, ("c", 2)),
+				(0, ("d", 1)),
+				(1, ("d", 2)),
+			]
+		);
+	}
+	#[test]
 	fn merge_count_owns() {
 		let first = Rc::new(0);
 		let a = vec![first.clone(), Rc::new(1)];
@@ -249,4 +348,22 @@ mod tests {
 		assert_eq!(Rc::strong_count(&first), 2);
 		assert_eq!(merged.next(), None);
 	}
+	#[test]
+	fn merge_until() {
+		use std::cell::Cell;
+		let read = Rc::new(Cell::new(0));
+		let counted = |v: Vec<u32>| {
+			let read = read.clone();
+			v.into_iter().inspect(move |_| read.set(read.get() + 1))
+		};
+		let a = counted(vec![1, 4, 9, 10, 11, 12]);
+		let b = counted(vec![2, 4, 8, 20, 21]);
+		let c = counted(vec![30, 31]);
+		let merged = crate::merge::Merge::new(vec![(0, a), (1, b), (2, c)], |a, b| a.cmp(b))
+			.until(|&x| x >= 9);
+		let merged: Vec<_> = merged.map(|(_, x)| x).collect();
+		assert_eq!(merged, vec![1, 2, 4, 8]);
+		// each source is read only up to its first item past the end
+		assert_eq!(read.get(), 3 + 4 + 1);
+	}
 }
@@ -0,0 +1,111 @@
+//! Pack a whole database into a single file.
+//!
+//! This is for places where it's easier to ship one file than a
+//! directory of transactions. A packed file is one or more
+//! transaction files (its sections), one after the other, followed by:
+//!
+//! * for each section, its offset and its length as big-endian `u64`s
+//! * the number of sections as a big-endian `u32`
+//! * the 8 bytes `SONNPACK`
+//!
+//! The first section is the database's `main`, and the others are
+//! transactions (or deletions) in order. [`pack`] writes a major
+//! compaction of a database, which has only one section.
+//! Read a packed file with [`DatabaseReader::open_packed`].
+
+use crate::{CreateTx, DatabaseReader, WriteFailure};
+use std::io::{Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"SONNPACK";
+
+fn invalid(e: &str) -> std::io::Error {
+	std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string())
+}
+
+/// Write every record of `db` into a packed file at `path`
+///
+/// The records are compacted into a single section, so deleted records
+/// and older versions of records are not packed. The file is written
+/// in the directory of `path` and then renamed to it, so it's never
+/// copied, and an existing file at `path` is replaced atomically.
+/// Returns the number of records written.
+pub fn pack(db: &DatabaseReader, path: &Path) -> Result<u64, WriteFailure> {
+	use std::os::unix::fs::PermissionsExt;
+
+	let parent = match path.parent() {
+		Some(p) if !p.as_os_str().is_empty() => p,
+		_ => Path::new("."),
+	};
+	let staging = tempfile::TempDir::new_in(parent)?;
+	let main = staging.path().join("main");
+	let mut tx = CreateTx::new(staging.path())?;
+	let mut records = 0u64;
+	for record in db.get_range(..) {
+		tx.add_record_from(&record)?;
+		records += 1;
+	}
+	tx.commit_to(&main)?;
+
+	// the section is followed by the footer in the same file, but an
+	// empty transaction isn't written at all
+	let mut file = match std::fs::metadata(&main) {
+		Ok(m) => {
+			// a committed transaction is read-only
+			let mut permissions = m.permissions();
+			permissions.set_mode(permissions.mode() | 0o200);
+			std::fs::set_permissions(&main, permissions)?;
+			let mut file = std::fs::OpenOptions::new().append(true).open(&main)?;
+			write_footer(&mut file, &[(0, m.len())])?;
+			file
+		}
+		Err(_) => {
+			let mut file = std::fs::File::create(&main)?;
+			write_footer(&mut file, &[])?;
+			file
+		}
+	};
+	file.flush()?;
+	file.sync_all()?;
+	drop(file);
+	std::fs::rename(&main, path)?;
+	std::fs::File::open(parent)?.sync_all()?;
+	Ok(records)
+}
+
+/// Write the footer for sections at these offsets and lengths
+fn write_footer<W: Write>(out: &mut W, sections: &[(u64, u64)]) -> std::io::Result<()> {
+	for (offset, len) in sections {
+		out.write_all(&offset.to_be_bytes())?;
+		out.write_all(&len.to_be_bytes())?;
+	}
+	let count = u32::try_from(sections.len()).map_err(|_| invalid("too many sections"))?;
+	out.write_all(&count.to_be_bytes())?;
+	out.write_all(MAGIC)
+}
+
+/// Read the offsets and lengths of the sections of a packed file
+pub(crate) fn read_footer(file: &[u8]) -> std::io::Result<Vec<std::ops::Range<usize>>> {
+	let not_packed = || invalid("not a packed sonnerie database");
+	let rest = file.strip_suffix(MAGIC.as_slice()).ok_or_else(not_packed)?;
+	let (rest, count) = rest.split_at(rest.len().checked_sub(4).ok_or_else(not_packed)?);
+	let count = u32::from_be_bytes(count.try_into().unwrap()) as usize;
+	let table_len = count.checked_mul(16).ok_or_else(not_packed)?;
+	let table_start = rest.len().checked_sub(table_len).ok_or_else(not_packed)?;
+
+	let mut table = &rest[table_start..];
+	let mut sections = Vec::with_capacity(count);
+	for _ in 0..count {
+		let mut offset = [0u8; 8];
+		let mut len = [0u8; 8];
+		table.read_exact(&mut offset)?;
+		table.read_exact(&mut len)?;
+		let offset = u64::from_be_bytes(offset) as usize;
+		let end = offset
+			.checked_add(u64::from_be_bytes(len) as usize)
+			.filter(|&end| end <= table_start)
+			.ok_or_else(|| invalid("a section of the packed file is out of bounds"))?;
+		sections.push(offset..end);
+	}
+	Ok(sections)
+}
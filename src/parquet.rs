@@ -0,0 +1,110 @@
+//! Write records to Parquet files.
+//!
+//! Enabled by the `parquet` feature, which also enables `arrow`.
+//! [`export_parquet`] writes the batches of [`crate::arrow`] to Parquet,
+//! so the files have the same columns: `key`, `time`, and the record's
+//! columns numbered from 1.
+//!
+//! A Parquet file has a single schema, but the keys of a range
+//! may have records of several formats. So there's one file for each
+//! format: if all of the records have the same format, it's the file
+//! that was asked for, such as `out.parquet`, and otherwise they're
+//! `out.1.parquet`, `out.2.parquet` and so on, numbered in the order that
+//! their formats were first seen. The format of each file is in its
+//! key-value metadata, as `format`.
+//!
+//! The records are streamed, so only a row group of each file is
+//! in memory at a time.
+
+use crate::arrow::ArrowBatches;
+use ::parquet::arrow::ArrowWriter;
+use ::parquet::basic::Compression;
+use ::parquet::file::properties::WriterProperties;
+use arrow_schema::Schema;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// The number of records in a batch, and so the most that are held for a key
+const BATCH_ROWS: usize = 8192;
+
+/// The number of records in a row group
+const ROW_GROUP_ROWS: usize = 128 * 1024;
+
+/// Write the records of `batches` to Parquet files named after `path`
+///
+/// Returns the format and the path of each file, in the order that
+/// the formats were first seen. The files are only created once all
+/// of the records have been written; if there are no records, no file is.
+///
+/// ```no_run
+/// # let db = sonnerie::DatabaseReader::new(std::path::Path::new("")).unwrap();
+/// let wildcard = sonnerie::Wildcard::new("sensor.%");
+/// let batches = db.get_filter(&wildcard).into_arrow();
+/// for (format, path) in sonnerie::parquet::export_parquet(batches, "out.parquet".as_ref()).unwrap() {
+///     println!("{}: {}", format, path.display());
+/// }
+/// ```
+pub fn export_parquet(
+	batches: ArrowBatches<'_>,
+	path: &Path,
+) -> std::io::Result<Vec<(String, PathBuf)>> {
+	let parent = match path.parent() {
+		Some(p) if !p.as_os_str().is_empty() => p,
+		_ => Path::new("."),
+	};
+	let properties = WriterProperties::builder()
+		.set_compression(Compression::LZ4_RAW)
+		.set_max_row_group_size(ROW_GROUP_ROWS)
+		.build();
+
+	// in the order that the formats were first seen
+	let mut files: Vec<(String, ArrowWriter<tempfile::NamedTempFile>)> = vec![];
+	let mut by_format = HashMap::new();
+
+	for batch in batches.max_rows(BATCH_ROWS) {
+		let format = batch.schema().metadata()["format"].clone();
+		let idx = match by_format.get(&format) {
+			Some(&idx) => idx,
+			None => {
+				// the key is in the metadata of each batch, but not the file's
+				let schema = Schema::new_with_metadata(
+					batch.schema().fields().clone(),
+					HashMap::from([("format".to_string(), format.clone())]),
+				);
+				let file = tempfile::NamedTempFile::new_in(parent)?;
+				let writer = ArrowWriter::try_new(file, Arc::new(schema), Some(properties.clone()))
+					.map_err(std::io::Error::other)?;
+				files.push((format.clone(), writer));
+				by_format.insert(format, files.len() - 1);
+				files.len() - 1
+			}
+		};
+		files[idx].1.write(&batch).map_err(std::io::Error::other)?;
+	}
+
+	let count = files.len();
+	let mut written = Vec::with_capacity(count);
+	for (n, (format, writer)) in files.into_iter().enumerate() {
+		let file = writer.into_inner().map_err(std::io::Error::other)?;
+		file.as_file().sync_all()?;
+		let dest = if count == 1 {
+			path.to_owned()
+		} else {
+			numbered_path(path, n + 1)
+		};
+		file.persist(&dest)?;
+		written.push((format, dest));
+	}
+	Ok(written)
+}
+
+/// `out.parquet` becomes `out.{n}.parquet`
+fn numbered_path(path: &Path, n: usize) -> PathBuf {
+	let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+	let name = match path.extension() {
+		Some(ext) => format!("{}.{}.{}", stem, n, ext.to_string_lossy()),
+		None => format!("{}.{}", stem, n),
+	};
+	path.with_file_name(name)
+}
@@ -0,0 +1,381 @@
+//! Store Prometheus samples, for its remote write and remote read.
+//!
+//! Enabled by the `prometheus` feature, which also builds the
+//! `sonnerie-prometheus` server. Prometheus sends and receives
+//! protobuf messages compressed with snappy (in its block format);
+//! [`decode_write_request`], [`decode_read_request`] and
+//! [`encode_read_response`] do that, and [`write`] and [`read`] store
+//! and read the samples.
+//!
+//! Each series is a key, and each sample is a record of format `F`
+//! (its value) at its timestamp. The key is the series' labels, like
+//! Prometheus writes them: the metric name (the label `__name__`), then
+//! the other labels sorted by name in braces, such as
+//! `http_requests_total{code="200",method="GET"}`. In the label values,
+//! `\`, `"` and a line feed are written `\\`, `\"` and `\n`. A label with
+//! an empty value is the same as no label, so it's left out, as are the
+//! braces if there are no other labels. So a set of labels is always
+//! the same key, and [`labels_from_key`] reverses [`series_key`].
+//!
+//! A remote read with an equality matcher on `__name__` only reads
+//! the keys of that metric (the name, and the keys that start with the
+//! name and `{`); otherwise it reads every key.
+
+use crate::{DatabaseReader, SortingCreateTx, WriteFailure};
+use std::path::Path;
+
+/// A remote write, with the samples of any number of series
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct WriteRequest {
+	#[prost(message, repeated, tag = "1")]
+	pub timeseries: Vec<TimeSeries>,
+}
+
+/// The labels of a series and some of its samples
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TimeSeries {
+	#[prost(message, repeated, tag = "1")]
+	pub labels: Vec<Label>,
+	#[prost(message, repeated, tag = "2")]
+	pub samples: Vec<Sample>,
+}
+
+/// A label of a series
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Label {
+	#[prost(string, tag = "1")]
+	pub name: String,
+	#[prost(string, tag = "2")]
+	pub value: String,
+}
+
+/// A value of a series
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Sample {
+	#[prost(double, tag = "1")]
+	pub value: f64,
+	/// Milliseconds since the epoch
+	#[prost(int64, tag = "2")]
+	pub timestamp: i64,
+}
+
+/// A remote read, of one or more queries
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ReadRequest {
+	#[prost(message, repeated, tag = "1")]
+	pub queries: Vec<Query>,
+}
+
+/// The series that match all of the matchers, and their samples in a time range
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Query {
+	/// Milliseconds since the epoch (inclusive)
+	#[prost(int64, tag = "1")]
+	pub start_timestamp_ms: i64,
+	/// Milliseconds since the epoch (inclusive)
+	#[prost(int64, tag = "2")]
+	pub end_timestamp_ms: i64,
+	#[prost(message, repeated, tag = "3")]
+	pub matchers: Vec<LabelMatcher>,
+}
+
+/// Select the series by the value of a label
+///
+/// A series that doesn't have the label is matched as if its
+/// value were empty.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct LabelMatcher {
+	#[prost(enumeration = "MatchType", tag = "1")]
+	pub r#type: i32,
+	#[prost(string, tag = "2")]
+	pub name: String,
+	#[prost(string, tag = "3")]
+	pub value: String,
+}
+
+/// How a [`LabelMatcher`] compares the value
+#[derive(Clone, Copy, Debug, PartialEq, Eq, prost::Enumeration)]
+pub enum MatchType {
+	/// Equal
+	Eq = 0,
+	/// Not equal
+	Neq = 1,
+	/// Matches the regular expression, which is anchored at both ends
+	Re = 2,
+	/// Doesn't match the regular expression
+	Nre = 3,
+}
+
+/// The results of a [`ReadRequest`], one for each query, in the same order
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ReadResponse {
+	#[prost(message, repeated, tag = "1")]
+	pub results: Vec<QueryResult>,
+}
+
+/// The series that a [`Query`] matched
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct QueryResult {
+	#[prost(message, repeated, tag = "1")]
+	pub timeseries: Vec<TimeSeries>,
+}
+
+/// Decompress and decode the body of a remote write
+pub fn decode_write_request(body: &[u8]) -> std::io::Result<WriteRequest> {
+	decode(body)
+}
+
+/// Decompress and decode the body of a remote read
+pub fn decode_read_request(body: &[u8]) -> std::io::Result<ReadRequest> {
+	decode(body)
+}
+
+/// Encode and compress the body of the response to a remote read
+pub fn encode_read_response(response: &ReadResponse) -> Vec<u8> {
+	let encoded = prost::Message::encode_to_vec(response);
+	snap::raw::Encoder::new()
+		.compress_vec(&encoded)
+		.expect("the response is too large to compress")
+}
+
+fn decode<M: prost::Message + Default>(body: &[u8]) -> std::io::Result<M> {
+	let encoded = snap::raw::Decoder::new()
+		.decompress_vec(body)
+		.map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+	M::decode(&encoded[..]).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+/// The key of the series with these labels
+pub fn series_key(labels: &[Label]) -> String {
+	let mut labels: Vec<&Label> = labels.iter().filter(|l| !l.value.is_empty()).collect();
+	labels.sort_by(|a, b| a.name.cmp(&b.name));
+
+	let mut key = String::new();
+	if let Some(name) = labels.iter().find(|l| l.name == "__name__") {
+		key += &name.value;
+	}
+	let mut others = labels.iter().filter(|l| l.name != "__name__").peekable();
+	if others.peek().is_some() {
+		key.push('{');
+		for (idx, label) in others.enumerate() {
+			if idx > 0 {
+				key.push(',');
+			}
+			key += &label.name;
+			key += "=\"";
+			for c in label.value.chars() {
+				match c {
+					'\\' => key += "\\\\",
+					'"' => key += "\\\"",
+					'\n' => key += "\\n",
+					c => key.push(c),
+				}
+			}
+			key.push('"');
+		}
+		key.push('}');
+	}
+	key
+}
+
+/// The labels of the series whose key is `key`, as made by [`series_key`]
+///
+/// The metric name comes first, and then the other labels sorted by
+/// name. Returns `None` if the key isn't in that form.
+pub fn labels_from_key(key: &str) -> Option<Vec<Label>> {
+	let label = |name: &str, value: String| Label {
+		name: name.to_owned(),
+		value,
+	};
+	let (name, rest) = match key.find('{') {
+		Some(brace) => (&key[..brace], &key[brace..]),
+		None => (key, ""),
+	};
+	let mut labels = vec![];
+	if !name.is_empty() {
+		labels.push(label("__name__", name.to_owned()));
+	}
+	if rest.is_empty() {
+		return Some(labels);
+	}
+
+	let mut rest = rest.strip_prefix('{')?.strip_suffix('}')?;
+	while !rest.is_empty() {
+		let (name, after) = rest.split_once("=\"")?;
+		let mut value = String::new();
+		let mut chars = after.char_indices();
+		let end = loop {
+			match chars.next()? {
+				(_, '\\') => match chars.next()? {
+					(_, '\\') => value.push('\\'),
+					(_, '"') => value.push('"'),
+					(_, 'n') => value.push('\n'),
+					_ => return None,
+				},
+				(i, '"') => break i,
+				(_, c) => value.push(c),
+			}
+		};
+		labels.push(label(name, value));
+		rest = &after[end + 1..];
+		if !rest.is_empty() {
+			rest = rest.strip_prefix(',')?;
+		}
+	}
+	Some(labels)
+}
+
+/// Add the samples of a remote write to the database at `dir`, as one transaction
+///
+/// The series may be in any order, as they're sorted with a
+/// [`SortingCreateTx`] that buffers up to `memory_limit` bytes. Samples
+/// before the epoch can't be stored, so they're an error, and then
+/// nothing is committed.
+///
+/// Returns the number of samples that were added.
+pub fn write(dir: &Path, request: &WriteRequest, memory_limit: usize) -> Result<u64, WriteFailure> {
+	let mut tx = SortingCreateTx::new(dir, memory_limit)?;
+	let mut samples = 0u64;
+	let mut data = [0u8; 16];
+	for series in &request.timeseries {
+		let key = series_key(&series.labels);
+		for sample in &series.samples {
+			let timestamp = u64::try_from(sample.timestamp)
+				.ok()
+				.and_then(|ms| ms.checked_mul(1_000_000))
+				.ok_or(WriteFailure::UnableToParseTimestamp)?;
+			data[..8].copy_from_slice(&timestamp.to_be_bytes());
+			data[8..].copy_from_slice(&sample.value.to_be_bytes());
+			tx.add_record_raw(&key, "F", &data)?;
+			samples += 1;
+		}
+	}
+	tx.commit()?;
+	Ok(samples)
+}
+
+/// Answer the queries of a remote read
+///
+/// Only the records of format `F` are samples; records of other
+/// formats, and keys that aren't made by [`series_key`], are skipped.
+/// Fails if a matcher has an invalid regular expression.
+pub fn read(db: &DatabaseReader, request: &ReadRequest) -> std::io::Result<ReadResponse> {
+	let results = request
+		.queries
+		.iter()
+		.map(|query| query_series(db, query).map(|timeseries| QueryResult { timeseries }))
+		.collect::<std::io::Result<_>>()?;
+	Ok(ReadResponse { results })
+}
+
+fn query_series(db: &DatabaseReader, query: &Query) -> std::io::Result<Vec<TimeSeries>> {
+	let matchers = query
+		.matchers
+		.iter()
+		.map(Matcher::new)
+		.collect::<std::io::Result<Vec<_>>>()?;
+
+	// the series of a metric are the keys that start with its name
+	let metric = query
+		.matchers
+		.iter()
+		.find(|m| m.name == "__name__" && m.r#type == MatchType::Eq as i32)
+		.map(|m| m.value.as_str());
+	let nanos = |ms: i64| (ms.max(0) as u64).saturating_mul(1_000_000);
+	let (start, end) = (
+		nanos(query.start_timestamp_ms),
+		nanos(query.end_timestamp_ms),
+	);
+
+	let in_time = move |r: &crate::Record| {
+		let t = r.timestamp_nanos();
+		t >= start && t <= end
+	};
+
+	// a metric's keys are its name, and then its name with other labels
+	let with_labels = metric.map(|m| (format!("{}{{", m), format!("{}|", m)));
+	let records: Box<dyn Iterator<Item = crate::Record>> = match (metric, &with_labels) {
+		(Some(metric), Some((first, last))) => Box::new(
+			db.get_range(metric..=metric)
+				.filter_values(in_time)
+				.into_iter()
+				.chain(
+					db.get_range(first.as_str()..last.as_str())
+						.filter_values(in_time),
+				),
+		),
+		_ => Box::new(db.get_range(..).filter_values(in_time).into_iter()),
+	};
+
+	let mut series: Vec<TimeSeries> = vec![];
+	// the key of the last series, or of the last key that was skipped
+	let mut last_key = String::new();
+	let mut matched = false;
+	for record in records {
+		if record.key() != last_key {
+			last_key = record.key().to_owned();
+			matched = false;
+			if let Some(labels) = labels_from_key(record.key()) {
+				if matchers.iter().all(|m| m.matches(&labels)) {
+					matched = true;
+					series.push(TimeSeries {
+						labels,
+						samples: vec![],
+					});
+				}
+			}
+		}
+		if matched && record.format() == "F" {
+			series.last_mut().unwrap().samples.push(Sample {
+				value: record.get(0),
+				timestamp: (record.timestamp_nanos() / 1_000_000) as i64,
+			});
+		}
+	}
+	series.retain(|s| !s.samples.is_empty());
+	Ok(series)
+}
+
+/// A [`LabelMatcher`] with its regular expression compiled
+struct Matcher<'q> {
+	name: &'q str,
+	value: &'q str,
+	regex: Option<regex::Regex>,
+	negate: bool,
+}
+
+impl<'q> Matcher<'q> {
+	fn new(m: &'q LabelMatcher) -> std::io::Result<Matcher<'q>> {
+		let match_type = MatchType::try_from(m.r#type).map_err(|_| {
+			std::io::Error::new(
+				std::io::ErrorKind::InvalidInput,
+				format!("unknown matcher type {}", m.r#type),
+			)
+		})?;
+		let regex = match match_type {
+			MatchType::Re | MatchType::Nre => Some(
+				regex::Regex::new(&format!("^(?:{})$", m.value))
+					.map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?,
+			),
+			MatchType::Eq | MatchType::Neq => None,
+		};
+		Ok(Matcher {
+			name: &m.name,
+			value: &m.value,
+			regex,
+			negate: matches!(match_type, MatchType::Neq | MatchType::Nre),
+		})
+	}
+
+	fn matches(&self, labels: &[Label]) -> bool {
+		let value = labels
+			.iter()
+			.find(|l| l.name == self.name)
+			.map_or("", |l| l.value.as_str());
+		let matched = match &self.regex {
+			Some(regex) => regex.is_match(value),
+			None => value == self.value,
+		};
+		matched != self.negate
+	}
+}
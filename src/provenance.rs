@@ -0,0 +1,150 @@
+//! Read records along with the segment that each is stored in.
+
+use crate::{DatabaseReader, Deletions, Record, StringKeyRangeReader};
+use std::path::Path;
+
+/// Identifies a compressed segment of a transaction file
+///
+/// Files are never modified, so a segment with the same file and
+/// offset always has the same contents (until a compaction removes
+/// the file). This is the same as the [`offset`](crate::RawSegment::offset)
+/// and [`compressed_len`](crate::RawSegment::compressed_len) of
+/// [`Reader::raw_segments`](crate::Reader::raw_segments).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SegmentId<'d> {
+	/// The transaction file, as in [`DatabaseReader::transaction_paths`]
+	pub file: &'d Path,
+	/// Where the segment starts in the file
+	pub offset: usize,
+	/// The size of the segment's compressed data
+	pub compressed_len: usize,
+}
+
+impl DatabaseReader {
+	/// Read a range of keys, along with the segment that each record was read from
+	///
+	/// This yields the same records as [`get_range`](Self::get_range):
+	/// when transactions have a record with the same key and timestamp,
+	/// only the newest is yielded, with the segment it's in.
+	///
+	/// ```no_run
+	/// # let db = sonnerie::DatabaseReader::new(std::path::Path::new("")).unwrap();
+	/// let mut segments = std::collections::HashSet::new();
+	/// for (_record, segment) in db.get_range_with_segments(..) {
+	///     segments.insert(segment);
+	/// }
+	/// ```
+	pub fn get_range_with_segments<'d>(
+		&'d self,
+		range: impl std::ops::RangeBounds<&'d str> + 'd + Clone,
+	) -> WithSegments<'d> {
+		let range: crate::CowStringRange<'d> = crate::disassemble_range_bound(range).into();
+		let mut sources = Vec::with_capacity(self.txes.len());
+		for (txid, path, reader) in &self.txes {
+			let mut source = Source {
+				txid: *txid,
+				path,
+				reader: reader.get_filter_range_with_budget(
+					None,
+					"",
+					range.clone(),
+					None,
+					None,
+					self.segment_cache.as_deref(),
+				),
+				segment: None,
+				head: None,
+			};
+			source.advance();
+			if source.head.is_some() {
+				sources.push(source);
+			}
+		}
+
+		WithSegments {
+			sources,
+			filter_out: Deletions::new(self.filter_out.iter().map(|(txid, _path, dm)| (*txid, dm))),
+		}
+	}
+}
+
+struct Source<'d> {
+	txid: usize,
+	path: &'d Path,
+	reader: StringKeyRangeReader<'d, 'd>,
+	/// the segment that `head` is in, kept so its length is only found once
+	segment: Option<SegmentId<'d>>,
+	/// the record `reader` is at, None if it's finished
+	head: Option<Record>,
+}
+
+impl<'d> Source<'d> {
+	fn advance(&mut self) {
+		self.head = self.reader.next();
+		if self.head.is_none() {
+			return;
+		}
+		let segment = self
+			.reader
+			.segment
+			.as_ref()
+			.expect("a record is in a segment");
+		if self.segment.map(|s| s.offset) != Some(segment.segment_offset) {
+			self.segment = Some(SegmentId {
+				file: self.path,
+				offset: segment.segment_offset,
+				compressed_len: self.reader.reader.segments.stored_payload(segment).len(),
+			});
+		}
+	}
+}
+
+/// The records and their segments, created by [`DatabaseReader::get_range_with_segments`]
+pub struct WithSegments<'d> {
+	/// the transactions that have more records
+	sources: Vec<Source<'d>>,
+	filter_out: Deletions<'d>,
+}
+
+impl<'d> Iterator for WithSegments<'d> {
+	type Item = (Record, SegmentId<'d>);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		loop {
+			// the lowest key and timestamp, ties go to the newest transaction
+			let newest = self
+				.sources
+				.iter()
+				.enumerate()
+				.min_by(|(_, a), (_, b)| {
+					let (ra, rb) = (a.head.as_ref().unwrap(), b.head.as_ref().unwrap());
+					ra.key_timestamp()
+						.cmp(&rb.key_timestamp())
+						.then(a.txid.cmp(&b.txid).reverse())
+				})
+				.map(|(index, _)| index)?;
+
+			let txid = self.sources[newest].txid;
+			let segment = self.sources[newest].segment.unwrap();
+			let record = self.sources[newest].head.take().unwrap();
+			self.sources[newest].advance();
+
+			// the records with the same key and timestamp are replaced by this one
+			for source in &mut self.sources {
+				while source
+					.head
+					.as_ref()
+					.is_some_and(|r| r.key_timestamp() == record.key_timestamp())
+				{
+					source.advance();
+				}
+			}
+			self.sources.retain(|s| s.head.is_some());
+
+			let (key, timestamp) = record.key_timestamp();
+			if !self.filter_out.contains(txid, key, timestamp) {
+				return Some((record, segment));
+			}
+		}
+	}
+}
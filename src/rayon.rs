@@ -47,6 +47,48 @@ impl<'k> UnindexedProducer for RecordProducer<'k> {
 	}
 }
 
+struct ChunksProducer<'k> {
+	chunks: RecordChunks<'k>,
+}
+
+impl<'k> ParallelIterator for RecordChunks<'k> {
+	type Item = Vec<Record>;
+
+	fn drive_unindexed<C>(self, consumer: C) -> C::Result
+	where
+		C: UnindexedConsumer<Self::Item>,
+	{
+		bridge_unindexed(ChunksProducer { chunks: self }, consumer)
+	}
+}
+
+impl<'k> UnindexedProducer for ChunksProducer<'k> {
+	type Item = Vec<Record>;
+
+	fn split(self) -> (ChunksProducer<'k>, Option<ChunksProducer<'k>>) {
+		let n = self.chunks.n;
+		if let Some((first, second)) = self.chunks.reader.split() {
+			(
+				ChunksProducer {
+					chunks: RecordChunks { reader: first, n },
+				},
+				Some(ChunksProducer {
+					chunks: RecordChunks { reader: second, n },
+				}),
+			)
+		} else {
+			(self, None)
+		}
+	}
+
+	fn fold_with<F>(self, folder: F) -> F
+	where
+		F: Folder<Self::Item>,
+	{
+		folder.consume_iter(self.chunks)
+	}
+}
+
 #[cfg(feature = "by-key")]
 struct KeyProducer<'k> {
 	reader: DatabaseKeyReader<'k>,
@@ -0,0 +1,124 @@
+//! Count records without reading them.
+
+use crate::{DatabaseReader, Wildcard};
+use std::collections::BTreeMap;
+use std::ops::{Bound, RangeBounds};
+
+impl DatabaseReader {
+	/// The number of records in a range of keys
+	///
+	/// This is the same as `get_range(range).into_iter().count()`, and
+	/// is always exact, but is usually much faster. When the database has
+	/// no delete markers, the segments in the range are decompressed,
+	/// but only the headers of their keys are read, and the records aren't
+	/// decoded or merged between transactions. A key that is in more than
+	/// one transaction may have a record at the same time in two of them,
+	/// which is only counted once, so the records of those keys are read.
+	///
+	/// When the database has delete markers, the records
+	/// are all read, as they would be by [`get_range`](Self::get_range).
+	pub fn count_range<'d>(&'d self, range: impl RangeBounds<&'d str> + 'd + Clone) -> u64 {
+		if !self.filter_out.is_empty() {
+			return self.get_range(range).into_iter().count() as u64;
+		}
+		self.count_records(
+			range.start_bound().cloned(),
+			range.end_bound().cloned(),
+			"",
+			|_| true,
+		)
+	}
+
+	/// The number of records whose keys match `wildcard`
+	///
+	/// This is the same as `get_filter(wildcard).into_iter().count()`,
+	/// and is exact, as with [`count_range`](Self::count_range).
+	pub fn count_filter(&self, wildcard: &Wildcard) -> u64 {
+		if !self.filter_out.is_empty() {
+			return self.get_filter(wildcard).into_iter().count() as u64;
+		}
+		let matcher = wildcard.matcher();
+		let prefix = wildcard.prefix();
+		let matches = |key: &str| match &matcher {
+			_ if wildcard.is_exact() => key == prefix,
+			Some(m) => m.is_match(key),
+			None => key.starts_with(prefix),
+		};
+		let start = if prefix.is_empty() {
+			Bound::Unbounded
+		} else {
+			Bound::Included(prefix)
+		};
+		self.count_records(start, Bound::Unbounded, prefix, matches)
+	}
+
+	/// Count the records of the keys in `start..end` that start with `prefix`
+	/// and `matches`, from the headers of the keys where possible
+	fn count_records(
+		&self,
+		start: Bound<&str>,
+		end: Bound<&str>,
+		prefix: &str,
+		matches: impl Fn(&str) -> bool,
+	) -> u64 {
+		let after_start = |key: &str| match start {
+			Bound::Included(s) => key >= s,
+			Bound::Excluded(s) => key > s,
+			Bound::Unbounded => true,
+		};
+		let before_end = |key: &str| match end {
+			Bound::Included(e) => key <= e,
+			Bound::Excluded(e) => key < e,
+			Bound::Unbounded => true,
+		};
+
+		// for each key, its number of records and of transactions
+		let mut counts: BTreeMap<String, (u64, usize)> = BTreeMap::new();
+		for (_txid, _path, reader) in &self.txes {
+			let mut segment = match start {
+				Bound::Included(k) | Bound::Excluded(k) => reader.segments.find(k),
+				Bound::Unbounded => reader.segments.first(),
+			};
+			let mut last_key: Option<String> = None;
+			while let Some(seg) = segment {
+				if !before_end(seg.first_key)
+					|| (seg.first_key > prefix && !seg.first_key.starts_with(prefix))
+				{
+					break;
+				}
+				segment = reader.segments.segment_after(&seg);
+				if !after_start(seg.last_key) {
+					continue;
+				}
+				let cache = self.segment_cache.as_deref();
+				for (key, n) in reader.segment_record_counts(&seg, cache) {
+					if !after_start(&key) || !before_end(&key) || !matches(&key) {
+						continue;
+					}
+					// a key may continue into the next segment
+					let new_tx = last_key.as_deref() != Some(key.as_str());
+					if new_tx {
+						last_key = Some(key.clone());
+					}
+					let entry = counts.entry(key).or_insert((0, 0));
+					entry.0 += n;
+					if new_tx {
+						entry.1 += 1;
+					}
+				}
+			}
+		}
+
+		counts
+			.iter()
+			.map(|(key, &(n, txes))| {
+				if txes > 1 {
+					let key = key.as_str();
+					self.get_range(key..=key).into_iter().count() as u64
+				} else {
+					n
+				}
+			})
+			.sum()
+	}
+}
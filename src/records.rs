@@ -9,6 +9,7 @@ use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
 /// Stores a single timestamp for a single key of data
 ///
 /// Internally copy-on-write so cheap to copy
+#[derive(Clone)]
 pub struct Record {
 	pub(crate) key_pos: usize,
 	pub(crate) key_len: usize,
@@ -21,17 +22,30 @@ pub struct Record {
 
 impl std::fmt::Debug for Record {
 	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-		write!(f, "Record {{ key={}, t={}", self.key(), self.time())?;
+		match self.time_checked() {
+			Some(t) => write!(f, "Record {{ key={}, t={}", self.key(), t)?,
+			None => write!(
+				f,
+				"Record {{ key={}, t={}ns",
+				self.key(),
+				self.timestamp_nanos()
+			)?,
+		}
 
 		for (idx, c) in self.format().chars().enumerate() {
 			match c {
 				'f' => write!(f, ", {}", self.get::<f32>(idx))?,
 				'F' => write!(f, ", {}", self.get::<f64>(idx))?,
+				'c' => write!(f, ", {}", self.get::<i8>(idx))?,
+				'C' => write!(f, ", {}", self.get::<u8>(idx))?,
+				'h' => write!(f, ", {}", self.get::<i16>(idx))?,
+				'H' => write!(f, ", {}", self.get::<u16>(idx))?,
 				'i' => write!(f, ", {}", self.get::<i32>(idx))?,
 				'I' => write!(f, ", {}", self.get::<i64>(idx))?,
 				'u' => write!(f, ", {}", self.get::<u32>(idx))?,
 				'U' => write!(f, ", {}", self.get::<u64>(idx))?,
 				's' => write!(f, ", \"{}\"", self.get::<&str>(idx).escape_default())?,
+				'B' => write!(f, ", {:?}", self.get::<&[u8]>(idx))?,
 				a => panic!("unknown format column '{a}'"),
 			}
 		}
@@ -54,12 +68,46 @@ impl Record {
 		unsafe { std::str::from_utf8_unchecked(d) }
 	}
 
+	/// The format character of column `col` (counting from 0), or
+	/// `None` if the record doesn't have that many columns
+	///
+	/// This tells you which types [`get`](Self::get) can read the column as.
+	pub fn format_char(&self, col: usize) -> Option<char> {
+		self.format().as_bytes().get(col).map(|&c| c as char)
+	}
+
+	/// True if column `col` is an integer or floating point number
+	pub fn is_numeric(&self, col: usize) -> bool {
+		matches!(
+			self.format_char(col),
+			Some('c' | 'C' | 'h' | 'H' | 'i' | 'I' | 'u' | 'U' | 'f' | 'F')
+		)
+	}
+
+	/// True if column `col` is a string
+	pub fn is_string(&self, col: usize) -> bool {
+		self.format_char(col) == Some('s')
+	}
+
+	/// True if column `col` is a byte string
+	pub fn is_bytes(&self, col: usize) -> bool {
+		self.format_char(col) == Some('B')
+	}
+
 	/// A decoded timestamp for this record
+	///
+	/// # Panics
+	/// If the timestamp can't be represented as a `NaiveDateTime`,
+	/// see [`time_checked`](Self::time_checked).
 	pub fn time(&self) -> chrono::NaiveDateTime {
-		let ts = self.timestamp_nanos();
-		chrono::DateTime::from_timestamp((ts / 1_000_000_000) as i64, (ts % 1_000_000_000) as u32)
-			.unwrap()
-			.naive_utc()
+		self.time_checked()
+			.expect("record timestamp is out of range")
+	}
+
+	/// A decoded timestamp for this record, or `None` if it
+	/// can't be represented as a `NaiveDateTime`
+	pub fn time_checked(&self) -> Option<chrono::NaiveDateTime> {
+		crate::formatted::timestamp_to_time(self.timestamp_nanos())
 	}
 
 	/// The raw timestamp for this record as nanoseconds
@@ -68,6 +116,13 @@ impl Record {
 		byteorder::BigEndian::read_u64(ts)
 	}
 
+	/// The key and the timestamp, which is the order of records in a database
+	///
+	/// Compare these to compare two records' positions.
+	pub fn key_timestamp(&self) -> (&str, u64) {
+		(self.key(), self.timestamp_nanos())
+	}
+
 	/// Read a single column returning possible errors
 	///
 	/// Errors can include an invalid data stream or incompatible types.
@@ -77,39 +132,78 @@ impl Record {
 	/// it can be read into a `u32` or a `u64`. However, it's a failure to read the column
 	/// as a `u32` if the column stores a `U`, even if the stored value itself can be
 	/// represented in a `u32`.
+	///
+	/// A record with an empty format has only a timestamp, so reading
+	/// any of its columns is an error.
 	pub fn get_checked<'a, T: FromRecord<'a>>(&'a self, col: usize) -> std::io::Result<T> {
+		let (fmt_char, from) = self.column_data(col)?;
+		FromRecord::get(fmt_char, from)
+	}
+
+	/// The format character of column `col`, and the data starting at that column
+	pub(crate) fn column_data(&self, col: usize) -> std::io::Result<(u8, &[u8])> {
 		let fmt = self.format().as_bytes();
 		let mut from = &self.raw()[TIMESTAMP_SIZE..];
 
+		if fmt.is_empty() {
+			return Err(std::io::Error::new(
+				std::io::ErrorKind::UnexpectedEof,
+				format!("column {} out of range: the record has no columns", col),
+			));
+		}
 		if fmt.len() <= col {
 			return Err(std::io::Error::new(
 				std::io::ErrorKind::UnexpectedEof,
-				"column out of range",
+				format!(
+					"column {} out of range: the record has {} columns",
+					col,
+					fmt.len()
+				),
 			));
 		}
 
-		for code in fmt.iter().take(col) {
-			match code {
-				b'i' | b'u' | b'f' => from = &from[4..],
-				b'I' | b'U' | b'F' => from = &from[8..],
-				b's' => {
-					let (len, tail) = unsigned_varint::decode::u64(from).map_err(|e| {
-						std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{:?}", e))
-					})?;
-					from = &tail[len as usize..];
-				}
-				a => {
-					return Err(std::io::Error::new(
-						std::io::ErrorKind::InvalidData,
-						format!("format string contains invalid '{}'", *a as char),
-					))
-				}
+		// each column is checked to fit in the record, and skipped up to `col`
+		for (idx, &code) in fmt.iter().enumerate().take(col + 1) {
+			let next = skip_column(code, from, self.value_len - from.len())?;
+			if idx < col {
+				from = next;
 			}
 		}
 
-		let fmt_char = fmt[col];
+		Ok((fmt[col], from))
+	}
 
-		FromRecord::get(fmt_char, from)
+	/// Each column, decoded by the record's format
+	///
+	/// This reads a record whose format isn't known in advance.
+	/// Panics if the data doesn't match the format, like [`get`](Self::get);
+	/// [`columns_checked`](Self::columns_checked) yields the error instead.
+	///
+	/// ```no_run
+	/// # let db = sonnerie::DatabaseReader::new(std::path::Path::new("")).unwrap();
+	/// for record in db.get_range(..) {
+	///     for column in record.columns() {
+	///         match column {
+	///             sonnerie::ColumnValue::Str(s) => println!("{}", s),
+	///             other => println!("{:?}", other),
+	///         }
+	///     }
+	/// }
+	/// ```
+	pub fn columns(&self) -> impl Iterator<Item = ColumnValue<'_>> + '_ {
+		self.columns_checked()
+			.map(|c| c.expect("unable to read column"))
+	}
+
+	/// Each column, decoded by the record's format, returning possible errors
+	///
+	/// If a column can't be decoded, its error is the last item.
+	pub fn columns_checked(&self) -> Columns<'_> {
+		Columns {
+			format: self.format().as_bytes(),
+			data: &self.raw()[TIMESTAMP_SIZE..],
+			value_len: self.value_len,
+		}
 	}
 
 	/// Read a column, turning errors into panics
@@ -126,11 +220,231 @@ impl Record {
 		self.get(0)
 	}
 
+	/// This record as a line of text, without a newline
+	///
+	/// The same text that [`print_record`](crate::formatted::print_record)
+	/// writes with all of the columns, including the escaping of the key
+	/// and of string columns.
+	pub fn to_line(
+		&self,
+		print_timestamp: crate::formatted::PrintTimestamp<'_>,
+		print_record_format: crate::formatted::PrintRecordFormat<'_>,
+	) -> String {
+		let mut out = vec![];
+		crate::formatted::print_record(
+			self,
+			&mut out,
+			print_timestamp,
+			print_record_format,
+			&choice_string::Selection::All,
+		)
+		.expect("writing to a Vec");
+		String::from_utf8(out).expect("records are utf-8")
+	}
+
 	/// The encoded payload of this data. The timestamp and as 8
 	/// bytes in nanoseconds, and then each column in turn
 	pub fn raw(&self) -> &[u8] {
 		&self.data[self.value_pos..self.value_pos + self.value_len]
 	}
+
+	/// A copy of this record that doesn't share its buffer
+	///
+	/// A record that was read shares the buffer that its segment was
+	/// decompressed into, usually around a megabyte, with the other records
+	/// of that segment; the buffer is kept until all of them are dropped.
+	/// The copy has only this record's key, format and value, so it's
+	/// worth making for the few records that are kept for long, such as
+	/// the latest of each key, but not for each record that's read.
+	pub fn into_owned(self) -> Record {
+		if self.data.len() == self.key_len + self.fmt_len + self.value_len {
+			return self;
+		}
+		let raw = self.raw();
+		Record::from_parts(
+			self.key(),
+			self.format(),
+			self.timestamp_nanos(),
+			&raw[TIMESTAMP_SIZE..],
+		)
+	}
+}
+
+impl Record {
+	/// A record that isn't in a segment, from its parts
+	///
+	/// `columns` is the data of the columns that follow the timestamp.
+	pub(crate) fn from_parts(key: &str, format: &str, timestamp: u64, columns: &[u8]) -> Record {
+		let mut data =
+			Vec::with_capacity(key.len() + format.len() + TIMESTAMP_SIZE + columns.len());
+		data.extend_from_slice(key.as_bytes());
+		data.extend_from_slice(format.as_bytes());
+		data.extend_from_slice(&timestamp.to_be_bytes());
+		data.extend_from_slice(columns);
+		Record {
+			key_pos: 0,
+			key_len: key.len(),
+			fmt_pos: key.len(),
+			fmt_len: format.len(),
+			value_pos: key.len() + format.len(),
+			value_len: TIMESTAMP_SIZE + columns.len(),
+			data: Arc::new(data),
+		}
+	}
+}
+
+/// The data that follows the column of `code` at the start of `from`, at `pos` in a record
+fn skip_column(code: u8, from: &[u8], pos: usize) -> std::io::Result<&[u8]> {
+	Ok(match code {
+		b'c' | b'C' => skip_fixed(from, 1, pos)?,
+		b'h' | b'H' => skip_fixed(from, 2, pos)?,
+		b'i' | b'u' | b'f' => skip_fixed(from, 4, pos)?,
+		b'I' | b'U' | b'F' => skip_fixed(from, 8, pos)?,
+		b's' | b'B' => crate::row_format::split_varint_bytes(from, pos)?.1,
+		a => {
+			return Err(std::io::Error::new(
+				std::io::ErrorKind::InvalidData,
+				format!("format string contains invalid '{}'", a as char),
+			))
+		}
+	})
+}
+
+/// The data that follows a fixed-size column of `size` bytes, at `pos` in a record
+fn skip_fixed(from: &[u8], size: usize, pos: usize) -> std::io::Result<&[u8]> {
+	from.get(size..).ok_or_else(|| {
+		std::io::Error::new(
+			std::io::ErrorKind::InvalidData,
+			format!(
+				"the column at byte {} needs {} bytes, but only {} remain",
+				pos,
+				size,
+				from.len()
+			),
+		)
+	})
+}
+
+/// The value of a column of any type, see [`Record::columns`]
+///
+/// Values of the same type are ordered by their value.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum ColumnValue<'a> {
+	/// `c`
+	I8(i8),
+	/// `C`
+	U8(u8),
+	/// `h`
+	I16(i16),
+	/// `H`
+	U16(u16),
+	/// `i`
+	I32(i32),
+	/// `u`
+	U32(u32),
+	/// `I`
+	I64(i64),
+	/// `U`
+	U64(u64),
+	/// `f`
+	F32(f32),
+	/// `F`
+	F64(f64),
+	/// `s`
+	Str(&'a str),
+	/// `B`
+	Bytes(&'a [u8]),
+}
+
+impl ColumnValue<'_> {
+	/// The format character of this value's type
+	pub fn format_char(&self) -> char {
+		match self {
+			ColumnValue::I8(_) => 'c',
+			ColumnValue::U8(_) => 'C',
+			ColumnValue::I16(_) => 'h',
+			ColumnValue::U16(_) => 'H',
+			ColumnValue::I32(_) => 'i',
+			ColumnValue::U32(_) => 'u',
+			ColumnValue::I64(_) => 'I',
+			ColumnValue::U64(_) => 'U',
+			ColumnValue::F32(_) => 'f',
+			ColumnValue::F64(_) => 'F',
+			ColumnValue::Str(_) => 's',
+			ColumnValue::Bytes(_) => 'B',
+		}
+	}
+}
+
+/// Stores the value as the column of its type
+impl ToRecord for ColumnValue<'_> {
+	fn store(&self, buf: &mut Vec<u8>) {
+		match self {
+			ColumnValue::I8(v) => v.store(buf),
+			ColumnValue::U8(v) => v.store(buf),
+			ColumnValue::I16(v) => v.store(buf),
+			ColumnValue::U16(v) => v.store(buf),
+			ColumnValue::I32(v) => v.store(buf),
+			ColumnValue::U32(v) => v.store(buf),
+			ColumnValue::I64(v) => v.store(buf),
+			ColumnValue::U64(v) => v.store(buf),
+			ColumnValue::F32(v) => v.store(buf),
+			ColumnValue::F64(v) => v.store(buf),
+			ColumnValue::Str(v) => v.store(buf),
+			ColumnValue::Bytes(v) => v.store(buf),
+		}
+	}
+	fn format_char(&self) -> u8 {
+		ColumnValue::format_char(self) as u8
+	}
+	fn size(&self) -> usize {
+		match self {
+			ColumnValue::I8(v) => v.size(),
+			ColumnValue::U8(v) => v.size(),
+			ColumnValue::I16(v) => v.size(),
+			ColumnValue::U16(v) => v.size(),
+			ColumnValue::I32(v) => v.size(),
+			ColumnValue::U32(v) => v.size(),
+			ColumnValue::I64(v) => v.size(),
+			ColumnValue::U64(v) => v.size(),
+			ColumnValue::F32(v) => v.size(),
+			ColumnValue::F64(v) => v.size(),
+			ColumnValue::Str(v) => v.size(),
+			ColumnValue::Bytes(v) => v.size(),
+		}
+	}
+	fn variable_size(&self) -> bool {
+		matches!(self, ColumnValue::Str(_) | ColumnValue::Bytes(_))
+	}
+}
+
+/// Iterates over the columns of a record, see [`Record::columns_checked`]
+pub struct Columns<'a> {
+	format: &'a [u8],
+	data: &'a [u8],
+	value_len: usize,
+}
+
+impl<'a> Iterator for Columns<'a> {
+	type Item = std::io::Result<ColumnValue<'a>>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let (&code, format) = self.format.split_first()?;
+		let value = skip_column(code, self.data, self.value_len - self.data.len())
+			.and_then(|next| Ok((ColumnValue::get(code, self.data)?, next)));
+		match value {
+			Ok((value, next)) => {
+				self.format = format;
+				self.data = next;
+				Some(Ok(value))
+			}
+			Err(e) => {
+				// there's no telling where the next column is
+				self.format = &[];
+				Some(Err(e))
+			}
+		}
+	}
 }
 
 /// Implements conversions from Rust types to Sonnerie records
@@ -141,6 +455,64 @@ pub trait ToRecord {
 	fn variable_size(&self) -> bool;
 }
 
+impl ToRecord for i8 {
+	fn store(&self, buf: &mut Vec<u8>) {
+		buf.write_i8(*self).unwrap();
+	}
+	fn format_char(&self) -> u8 {
+		b'c'
+	}
+	fn size(&self) -> usize {
+		1
+	}
+	fn variable_size(&self) -> bool {
+		false
+	}
+}
+impl ToRecord for u8 {
+	fn store(&self, buf: &mut Vec<u8>) {
+		buf.write_u8(*self).unwrap();
+	}
+	fn format_char(&self) -> u8 {
+		b'C'
+	}
+	fn size(&self) -> usize {
+		1
+	}
+	fn variable_size(&self) -> bool {
+		false
+	}
+}
+
+impl ToRecord for i16 {
+	fn store(&self, buf: &mut Vec<u8>) {
+		buf.write_i16::<BigEndian>(*self).unwrap();
+	}
+	fn format_char(&self) -> u8 {
+		b'h'
+	}
+	fn size(&self) -> usize {
+		2
+	}
+	fn variable_size(&self) -> bool {
+		false
+	}
+}
+impl ToRecord for u16 {
+	fn store(&self, buf: &mut Vec<u8>) {
+		buf.write_u16::<BigEndian>(*self).unwrap();
+	}
+	fn format_char(&self) -> u8 {
+		b'H'
+	}
+	fn size(&self) -> usize {
+		2
+	}
+	fn variable_size(&self) -> bool {
+		false
+	}
+}
+
 impl ToRecord for i32 {
 	fn store(&self, buf: &mut Vec<u8>) {
 		buf.write_i32::<BigEndian>(*self).unwrap();
@@ -249,6 +621,39 @@ impl ToRecord for &str {
 	}
 }
 
+impl ToRecord for &[u8] {
+	fn store(&self, buf: &mut Vec<u8>) {
+		let mut lenbuf = unsigned_varint::encode::usize_buffer();
+		buf.extend_from_slice(unsigned_varint::encode::usize(self.len(), &mut lenbuf));
+		buf.extend_from_slice(self);
+	}
+	fn format_char(&self) -> u8 {
+		b'B'
+	}
+	fn size(&self) -> usize {
+		let mut buf = unsigned_varint::encode::usize_buffer();
+		unsigned_varint::encode::usize(self.len(), &mut buf).len() + self.len()
+	}
+	fn variable_size(&self) -> bool {
+		true
+	}
+}
+
+impl ToRecord for Vec<u8> {
+	fn store(&self, buf: &mut Vec<u8>) {
+		self.as_slice().store(buf)
+	}
+	fn format_char(&self) -> u8 {
+		self.as_slice().format_char()
+	}
+	fn size(&self) -> usize {
+		self.as_slice().size()
+	}
+	fn variable_size(&self) -> bool {
+		self.as_slice().variable_size()
+	}
+}
+
 impl ToRecord for String {
 	fn store(&self, buf: &mut Vec<u8>) {
 		self.as_str().store(buf)
@@ -375,7 +780,7 @@ impl RecordBuilder for &[&dyn ToRecord] {
 		self.iter().for_each(|v| fmt.push(v.format_char().into()));
 	}
 	fn variable_size(&self) -> bool {
-		self.iter().map(|m| m.variable_size()).any(|a| a)
+		self.iter().any(|m| m.variable_size())
 	}
 	fn size(&self) -> usize {
 		self.iter().map(|m| m.size()).sum::<usize>()
@@ -390,7 +795,7 @@ impl RecordBuilder for [&dyn ToRecord] {
 		self.iter().for_each(|v| fmt.push(v.format_char().into()));
 	}
 	fn variable_size(&self) -> bool {
-		self.iter().map(|m| m.variable_size()).any(|a| a)
+		self.iter().any(|m| m.variable_size())
 	}
 	fn size(&self) -> usize {
 		self.iter().map(|m| m.size()).sum::<usize>()
@@ -405,7 +810,7 @@ impl<const N: usize> RecordBuilder for &[&dyn ToRecord; N] {
 		self.iter().for_each(|v| fmt.push(v.format_char().into()));
 	}
 	fn variable_size(&self) -> bool {
-		self.iter().map(|m| m.variable_size()).any(|a| a)
+		self.iter().any(|m| m.variable_size())
 	}
 	fn size(&self) -> usize {
 		self.iter().map(|m| m.size()).sum::<usize>()
@@ -415,113 +820,327 @@ impl<const N: usize> RecordBuilder for &[&dyn ToRecord; N] {
 	}
 }
 
+/// Builds a record with a number of columns that's only known at runtime
+///
+/// Each column's format character and encoding are accumulated as it's
+/// pushed, so the record can be assembled in a loop, unlike with
+/// [`record()`], and without the `&[&dyn ToRecord]` boilerplate:
+///
+/// ```no_run
+/// # let mut transaction = sonnerie::CreateTx::new(std::path::Path::new("")).unwrap();
+/// let mut columns = sonnerie::RecordVec::new().push_str("sensor");
+/// for reading in [1.5, 2.5] {
+///     columns = columns.push_f64(reading);
+/// }
+/// transaction.add_record_dynamic(
+///    "key name",
+///    "2010-01-01T00:00:01".parse().unwrap(),
+///    &columns,
+///  ).unwrap();
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecordVec {
+	format: compact_str::CompactString,
+	data: Vec<u8>,
+	variable_size: bool,
+}
+
+impl RecordVec {
+	/// A record with no columns yet
+	pub fn new() -> RecordVec {
+		Self::default()
+	}
+
+	/// Add a column of any type that implements [`ToRecord`]
+	pub fn push_value(&mut self, value: &dyn ToRecord) {
+		self.format.push(value.format_char() as char);
+		self.variable_size |= value.variable_size();
+		value.store(&mut self.data);
+	}
+
+	/// Add an `i8` column (`c`)
+	pub fn push_i8(mut self, value: i8) -> Self {
+		self.push_value(&value);
+		self
+	}
+	/// Add a `u8` column (`C`)
+	pub fn push_u8(mut self, value: u8) -> Self {
+		self.push_value(&value);
+		self
+	}
+	/// Add an `i16` column (`h`)
+	pub fn push_i16(mut self, value: i16) -> Self {
+		self.push_value(&value);
+		self
+	}
+	/// Add a `u16` column (`H`)
+	pub fn push_u16(mut self, value: u16) -> Self {
+		self.push_value(&value);
+		self
+	}
+	/// Add an `i32` column (`i`)
+	pub fn push_i32(mut self, value: i32) -> Self {
+		self.push_value(&value);
+		self
+	}
+	/// Add a `u32` column (`u`)
+	pub fn push_u32(mut self, value: u32) -> Self {
+		self.push_value(&value);
+		self
+	}
+	/// Add an `i64` column (`I`)
+	pub fn push_i64(mut self, value: i64) -> Self {
+		self.push_value(&value);
+		self
+	}
+	/// Add a `u64` column (`U`)
+	pub fn push_u64(mut self, value: u64) -> Self {
+		self.push_value(&value);
+		self
+	}
+	/// Add an `f32` column (`f`)
+	pub fn push_f32(mut self, value: f32) -> Self {
+		self.push_value(&value);
+		self
+	}
+	/// Add an `f64` column (`F`)
+	pub fn push_f64(mut self, value: f64) -> Self {
+		self.push_value(&value);
+		self
+	}
+	/// Add a string column (`s`)
+	pub fn push_str(mut self, value: &str) -> Self {
+		self.push_value(&value);
+		self
+	}
+	/// Add a bytes column (`B`)
+	pub fn push_bytes(mut self, value: &[u8]) -> Self {
+		self.push_value(&value);
+		self
+	}
+
+	/// The format string of the columns so far
+	pub fn format(&self) -> &str {
+		&self.format
+	}
+
+	/// The number of columns so far
+	pub fn len(&self) -> usize {
+		self.format.len()
+	}
+
+	/// True if there are no columns
+	pub fn is_empty(&self) -> bool {
+		self.format.is_empty()
+	}
+}
+
+impl RecordBuilder for &RecordVec {
+	fn format_str(&self, fmt: &mut compact_str::CompactString) {
+		fmt.push_str(&self.format);
+	}
+	fn variable_size(&self) -> bool {
+		self.variable_size
+	}
+	fn size(&self) -> usize {
+		self.data.len()
+	}
+	fn store(&self, buf: &mut Vec<u8>) {
+		buf.extend_from_slice(&self.data);
+	}
+}
+
 /// Implements conversions from [`Record`] columns to Rust types
 pub trait FromRecord<'a>: Sized {
 	fn get(fmt_char: u8, bytes: &'a [u8]) -> std::io::Result<Self>;
 }
 
+/// The error for reading a column of format `fmt_char` as the type `requested`
+fn cannot_decode(requested: &str, fmt_char: u8) -> std::io::Error {
+	let narrower = match (requested, fmt_char) {
+		("i8", b'h' | b'i' | b'I')
+		| ("u8", b'H' | b'u' | b'U')
+		| ("i16", b'i' | b'I')
+		| ("u16", b'u' | b'U')
+		| ("i32", b'I')
+		| ("u32", b'U')
+		| ("f32", b'F') => ": would lose precision",
+		_ => "",
+	};
+	std::io::Error::new(
+		std::io::ErrorKind::InvalidData,
+		format!(
+			"cannot decode {} from '{}'{}",
+			requested,
+			(fmt_char as char).escape_default(),
+			narrower
+		),
+	)
+}
+
+impl<'a> FromRecord<'a> for i8 {
+	fn get(fmt_char: u8, bytes: &'a [u8]) -> std::io::Result<Self> {
+		match fmt_char {
+			b'c' => Ok(bytes[0] as i8),
+			_ => Err(cannot_decode("i8", fmt_char)),
+		}
+	}
+}
+
+impl<'a> FromRecord<'a> for i16 {
+	fn get(fmt_char: u8, bytes: &'a [u8]) -> std::io::Result<Self> {
+		match fmt_char {
+			b'c' => Ok(bytes[0] as i8 as i16),
+			b'h' => Ok(BigEndian::read_i16(bytes)),
+			_ => Err(cannot_decode("i16", fmt_char)),
+		}
+	}
+}
+
 impl<'a> FromRecord<'a> for i32 {
 	fn get(fmt_char: u8, bytes: &'a [u8]) -> std::io::Result<Self> {
-		if fmt_char != b'i' {
-			return Err(std::io::Error::new(
-				std::io::ErrorKind::InvalidData,
-				format!("cannot decode i32 from '{}'", fmt_char as char),
-			));
+		match fmt_char {
+			b'c' => Ok(bytes[0] as i8 as i32),
+			b'h' => Ok(BigEndian::read_i16(bytes) as i32),
+			b'i' => Ok(BigEndian::read_i32(bytes)),
+			_ => Err(cannot_decode("i32", fmt_char)),
 		}
-		Ok(BigEndian::read_i32(bytes))
 	}
 }
 
 impl<'a> FromRecord<'a> for i64 {
 	fn get(fmt_char: u8, bytes: &'a [u8]) -> std::io::Result<Self> {
-		if fmt_char == b'i' {
-			Ok(BigEndian::read_i32(bytes) as i64)
-		} else if fmt_char == b'I' {
-			Ok(BigEndian::read_i64(bytes))
-		} else {
-			Err(std::io::Error::new(
-				std::io::ErrorKind::InvalidData,
-				format!("cannot decode i64 from '{}'", fmt_char as char),
-			))
+		match fmt_char {
+			b'c' => Ok(bytes[0] as i8 as i64),
+			b'h' => Ok(BigEndian::read_i16(bytes) as i64),
+			b'i' => Ok(BigEndian::read_i32(bytes) as i64),
+			b'I' => Ok(BigEndian::read_i64(bytes)),
+			_ => Err(cannot_decode("i64", fmt_char)),
+		}
+	}
+}
+
+impl<'a> FromRecord<'a> for u8 {
+	fn get(fmt_char: u8, bytes: &'a [u8]) -> std::io::Result<Self> {
+		match fmt_char {
+			b'C' => Ok(bytes[0]),
+			_ => Err(cannot_decode("u8", fmt_char)),
+		}
+	}
+}
+
+impl<'a> FromRecord<'a> for u16 {
+	fn get(fmt_char: u8, bytes: &'a [u8]) -> std::io::Result<Self> {
+		match fmt_char {
+			b'C' => Ok(bytes[0] as u16),
+			b'H' => Ok(BigEndian::read_u16(bytes)),
+			_ => Err(cannot_decode("u16", fmt_char)),
 		}
 	}
 }
 
 impl<'a> FromRecord<'a> for u32 {
 	fn get(fmt_char: u8, bytes: &[u8]) -> std::io::Result<Self> {
-		if fmt_char != b'u' {
-			return Err(std::io::Error::new(
-				std::io::ErrorKind::InvalidData,
-				format!("cannot decode u32 from '{}'", fmt_char as char),
-			));
+		match fmt_char {
+			b'C' => Ok(bytes[0] as u32),
+			b'H' => Ok(BigEndian::read_u16(bytes) as u32),
+			b'u' => Ok(BigEndian::read_u32(bytes)),
+			_ => Err(cannot_decode("u32", fmt_char)),
 		}
-		Ok(BigEndian::read_u32(bytes))
 	}
 }
 
 impl<'a> FromRecord<'a> for u64 {
 	fn get(fmt_char: u8, bytes: &'a [u8]) -> std::io::Result<Self> {
-		if fmt_char == b'u' {
-			Ok(BigEndian::read_u32(bytes) as u64)
-		} else if fmt_char == b'U' {
-			Ok(BigEndian::read_u64(bytes))
-		} else {
-			Err(std::io::Error::new(
-				std::io::ErrorKind::InvalidData,
-				format!("cannot decode u64 from '{}'", fmt_char as char),
-			))
+		match fmt_char {
+			b'C' => Ok(bytes[0] as u64),
+			b'H' => Ok(BigEndian::read_u16(bytes) as u64),
+			b'u' => Ok(BigEndian::read_u32(bytes) as u64),
+			b'U' => Ok(BigEndian::read_u64(bytes)),
+			_ => Err(cannot_decode("u64", fmt_char)),
 		}
 	}
 }
 
+/// Refuses `F` columns, because they would lose precision
 impl<'a> FromRecord<'a> for f32 {
 	fn get(fmt_char: u8, bytes: &'a [u8]) -> std::io::Result<Self> {
-		if fmt_char != b'f' {
-			return Err(std::io::Error::new(
-				std::io::ErrorKind::InvalidData,
-				format!("cannot decode f32 from '{}'", fmt_char as char),
-			));
+		match fmt_char {
+			b'f' => Ok(BigEndian::read_f32(bytes)),
+			_ => Err(cannot_decode("f32", fmt_char)),
 		}
-		Ok(BigEndian::read_f32(bytes))
 	}
 }
 
 impl<'a> FromRecord<'a> for f64 {
 	fn get(fmt_char: u8, bytes: &'a [u8]) -> std::io::Result<Self> {
-		if fmt_char == b'f' {
-			Ok(BigEndian::read_f32(bytes) as f64)
-		} else if fmt_char == b'F' {
-			Ok(BigEndian::read_f64(bytes))
-		} else {
-			Err(std::io::Error::new(
-				std::io::ErrorKind::InvalidData,
-				format!("cannot decode f64 from '{}'", fmt_char as char),
-			))
+		match fmt_char {
+			b'f' => Ok(BigEndian::read_f32(bytes) as f64),
+			b'F' => Ok(BigEndian::read_f64(bytes)),
+			_ => Err(cannot_decode("f64", fmt_char)),
 		}
 	}
 }
 
 impl<'a> FromRecord<'a> for String {
 	fn get(fmt_char: u8, bytes: &'a [u8]) -> std::io::Result<Self> {
-		let s: &str = FromRecord::get(fmt_char, bytes)?;
-		Ok(s.to_string())
+		decode_str("String", fmt_char, bytes).map(|s| s.to_string())
 	}
 }
 
 impl<'a> FromRecord<'a> for &'a str {
 	fn get(fmt_char: u8, bytes: &'a [u8]) -> std::io::Result<Self> {
-		if fmt_char != b's' {
-			return Err(std::io::Error::new(
-				std::io::ErrorKind::InvalidData,
-				format!("cannot decode String from '{}'", fmt_char as char),
-			));
-		}
+		decode_str("&str", fmt_char, bytes)
+	}
+}
 
-		let (len, tail) = unsigned_varint::decode::u64(bytes).map_err(|e| {
-			std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{:?}", e))
-		})?;
+/// Reads a column of any format
+impl<'a> FromRecord<'a> for ColumnValue<'a> {
+	fn get(fmt_char: u8, bytes: &'a [u8]) -> std::io::Result<Self> {
+		Ok(match fmt_char {
+			b'c' => ColumnValue::I8(FromRecord::get(fmt_char, bytes)?),
+			b'C' => ColumnValue::U8(FromRecord::get(fmt_char, bytes)?),
+			b'h' => ColumnValue::I16(FromRecord::get(fmt_char, bytes)?),
+			b'H' => ColumnValue::U16(FromRecord::get(fmt_char, bytes)?),
+			b'i' => ColumnValue::I32(FromRecord::get(fmt_char, bytes)?),
+			b'u' => ColumnValue::U32(FromRecord::get(fmt_char, bytes)?),
+			b'I' => ColumnValue::I64(FromRecord::get(fmt_char, bytes)?),
+			b'U' => ColumnValue::U64(FromRecord::get(fmt_char, bytes)?),
+			b'f' => ColumnValue::F32(FromRecord::get(fmt_char, bytes)?),
+			b'F' => ColumnValue::F64(FromRecord::get(fmt_char, bytes)?),
+			b's' => ColumnValue::Str(FromRecord::get(fmt_char, bytes)?),
+			b'B' => ColumnValue::Bytes(FromRecord::get(fmt_char, bytes)?),
+			_ => return Err(cannot_decode("ColumnValue", fmt_char)),
+		})
+	}
+}
+
+/// Reads `B` columns, and also the bytes of `s` columns
+impl<'a> FromRecord<'a> for &'a [u8] {
+	fn get(fmt_char: u8, bytes: &'a [u8]) -> std::io::Result<Self> {
+		decode_bytes("&[u8]", fmt_char, bytes)
+	}
+}
+
+/// Reads `B` columns, and also the bytes of `s` columns
+impl<'a> FromRecord<'a> for Vec<u8> {
+	fn get(fmt_char: u8, bytes: &'a [u8]) -> std::io::Result<Self> {
+		decode_bytes("Vec<u8>", fmt_char, bytes).map(|b| b.to_vec())
+	}
+}
 
-		std::str::from_utf8(&tail[..len as usize])
-			.map_err(|k| std::io::Error::new(std::io::ErrorKind::InvalidData, k))
+fn decode_bytes<'a>(requested: &str, fmt_char: u8, bytes: &'a [u8]) -> std::io::Result<&'a [u8]> {
+	if fmt_char != b'B' && fmt_char != b's' {
+		return Err(cannot_decode(requested, fmt_char));
 	}
+	Ok(crate::row_format::split_varint_bytes(bytes, 0)?.0)
+}
+
+fn decode_str<'a>(requested: &str, fmt_char: u8, bytes: &'a [u8]) -> std::io::Result<&'a str> {
+	if fmt_char != b's' {
+		return Err(cannot_decode(requested, fmt_char));
+	}
+
+	let (s, _) = crate::row_format::split_varint_bytes(bytes, 0)?;
+	std::str::from_utf8(s).map_err(|k| std::io::Error::new(std::io::ErrorKind::InvalidData, k))
 }
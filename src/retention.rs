@@ -0,0 +1,287 @@
+//! Retention rules that are stored in the database itself.
+//!
+//! Each rule says how long the records of the keys matching a
+//! [`Wildcard`] are kept, like "`temp.%` for 30 days". The rule for
+//! a wildcard is stored in the key [`RETENTION_PREFIX`] followed by
+//! the wildcard, as a record of the number of seconds to keep (the
+//! format `U`). The record with the latest timestamp applies, and
+//! a rule of zero seconds removes it.
+//!
+//! [`apply`] deletes the records that are older than their rule allows.
+//! The keys that start with [`SETTINGS_PREFIX`], such as the rules
+//! themselves, are never deleted by a rule, so a rule may match every key.
+//!
+//! A key can also be "latest-only", for series of which only the
+//! current value matters. Its wildcard is stored in the key
+//! [`LATEST_ONLY_PREFIX`] followed by the wildcard, as a record of
+//! `1` (the format `U`), or `0` to remove it. A major compaction with
+//! [`compact_latest_only`](crate::compact::compact_latest_only) (or `compact --major
+//! --latest-only`) keeps only the latest record of each matching key,
+//! except for the settings, whose records are all kept.
+
+use crate::{record, CreateTx, DatabaseReader, Record, Wildcard, WriteFailure};
+use chrono::NaiveDateTime;
+use std::path::Path;
+use std::time::Duration;
+
+/// The keys of the rules and of the database's other settings start with this
+pub const SETTINGS_PREFIX: &str = "@sonnerie.";
+
+/// The rules are stored in the keys that start with this
+pub const RETENTION_PREFIX: &str = "@sonnerie.retention/";
+
+/// The latest-only wildcards are stored in the keys that start with this
+pub const LATEST_ONLY_PREFIX: &str = "@sonnerie.latest-only/";
+
+/// How long to keep the records of the keys matching `wildcard`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetentionRule {
+	pub wildcard: String,
+	pub keep: Duration,
+}
+
+/// Add a rule to keep the keys matching `wildcard` for `keep`, as of `now`
+///
+/// A `keep` of zero removes the rule for `wildcard`. The rule
+/// is a record in the database, so it's only in effect
+/// after `tx` is committed. `wildcard` may match the keys of
+/// the settings, like `%` does, but they aren't deleted.
+pub fn set_rule(
+	tx: &mut CreateTx,
+	now: NaiveDateTime,
+	wildcard: &str,
+	keep: Duration,
+) -> Result<(), WriteFailure> {
+	let key = format!("{}{}", RETENTION_PREFIX, wildcard);
+	tx.add_record(&key, now, record(keep.as_secs()))
+}
+
+/// Read the rules that are in effect, sorted by wildcard
+pub fn rules(db: &DatabaseReader) -> Vec<RetentionRule> {
+	let mut rules = std::collections::BTreeMap::new();
+	let filter = Wildcard::new(&format!("{}%", RETENTION_PREFIX));
+	// the records are in order of time, so later rules replace earlier ones
+	for r in db.get_filter(&filter) {
+		let Ok(seconds) = r.get_checked::<u64>(0) else {
+			continue;
+		};
+		rules.insert(r.key()[RETENTION_PREFIX.len()..].to_owned(), seconds);
+	}
+	rules
+		.into_iter()
+		.filter(|(_, seconds)| *seconds != 0)
+		.map(|(wildcard, seconds)| RetentionRule {
+			wildcard,
+			keep: Duration::from_secs(seconds),
+		})
+		.collect()
+}
+
+/// Delete the records in the database at `dir` that their rules don't keep at `now`
+///
+/// Each rule is applied as a deletion of the matching keys' records before
+/// `now` minus the rule's duration, in its own transactions. A rule whose
+/// records were already deleted up to that time, by an earlier `apply`
+/// that hasn't been compacted yet, isn't applied again, so running this
+/// often doesn't add a deletion each time.
+/// Returns each rule that was applied, with the time before which it deleted.
+pub fn apply(
+	dir: &Path,
+	now: NaiveDateTime,
+) -> Result<Vec<(RetentionRule, NaiveDateTime)>, WriteFailure> {
+	let db = DatabaseReader::new(dir)?;
+	let mut applied = vec![];
+	for rule in rules(&db) {
+		let cutoff = cutoff(now, rule.keep);
+		if already_deleted(&db, &rule.wildcard, cutoff) {
+			continue;
+		}
+		delete_before(dir, cutoff, &rule.wildcard)?;
+		applied.push((rule, cutoff));
+	}
+	Ok(applied)
+}
+
+/// Whether `db` has deletions of the records of `wildcard` before
+/// `cutoff` or later, in each of its [`key_ranges`]
+fn already_deleted(db: &DatabaseReader, wildcard: &str, cutoff: NaiveDateTime) -> bool {
+	let epoch = chrono::DateTime::UNIX_EPOCH.naive_utc();
+	key_ranges(wildcard).iter().all(|&(first, last)| {
+		db.delete_markers().any(|(_, m)| {
+			!m.regex
+				&& !m.keys && m.wildcard == wildcard
+				&& m.first_key == first
+				&& m.last_key == last
+				&& m.first_timestamp == epoch
+				&& m.last_timestamp >= cutoff
+		})
+	})
+}
+
+/// The ranges of keys (the last excluded, `""` for no limit)
+/// that a deletion of `wildcard` has, so that it doesn't delete the settings
+fn key_ranges(wildcard: &str) -> Vec<(&'static str, &'static str)> {
+	// the key after all of those that start with SETTINGS_PREFIX
+	const AFTER_SETTINGS: &str = "@sonnerie/";
+	let prefix = Wildcard::new(wildcard).prefix().to_owned();
+	if prefix.starts_with(SETTINGS_PREFIX) {
+		vec![]
+	} else if SETTINGS_PREFIX.starts_with(&prefix) {
+		vec![("", SETTINGS_PREFIX), (AFTER_SETTINGS, "")]
+	} else {
+		// every key with the prefix is before or after the settings
+		vec![("", "")]
+	}
+}
+
+/// Delete the records of the keys matching `wildcard` that are older than `keep` at `now`
+///
+/// Unlike [`apply`], this doesn't need a rule: it's a deletion of the
+/// records before `now` minus `keep`, so running it periodically keeps
+/// only the last `keep` of the records. Returns the time before which
+/// it deleted. As with a rule, the keys of the settings (the rules, the
+/// latest-only wildcards and the declared formats) aren't deleted.
+///
+/// Like any deletion, the records are only hidden from reads until
+/// a major compaction removes them and reclaims their space.
+pub fn expire(
+	dir: &Path,
+	now: NaiveDateTime,
+	wildcard: &str,
+	keep: Duration,
+) -> Result<NaiveDateTime, WriteFailure> {
+	let cutoff = cutoff(now, keep);
+	delete_before(dir, cutoff, wildcard)?;
+	Ok(cutoff)
+}
+
+/// `now` minus `keep`, but not before the epoch
+fn cutoff(now: NaiveDateTime, keep: Duration) -> NaiveDateTime {
+	let epoch = chrono::DateTime::UNIX_EPOCH.naive_utc();
+	chrono::Duration::from_std(keep)
+		.ok()
+		.and_then(|keep| now.checked_sub_signed(keep))
+		.unwrap_or(epoch)
+		.max(epoch)
+}
+
+/// Delete the records of `wildcard` before `cutoff`, except for the settings
+///
+/// That's a transaction for each of the [`key_ranges`].
+fn delete_before(dir: &Path, cutoff: NaiveDateTime, wildcard: &str) -> Result<(), WriteFailure> {
+	let cutoff_nanos = cutoff
+		.and_utc()
+		.timestamp_nanos_opt()
+		.ok_or(WriteFailure::UnableToParseTimestamp)?;
+
+	for (first_key, last_key) in key_ranges(wildcard) {
+		let mut tx = CreateTx::new(dir)?;
+		tx.delete(first_key, last_key, 0, cutoff_nanos as u64, wildcard)?;
+		tx.commit()?;
+	}
+	Ok(())
+}
+
+/// Parse a duration like `30d`: a number followed by `s`, `m`, `h`, `d` or `w`
+/// for seconds, minutes, hours, days or weeks
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+	let split = s
+		.find(|c: char| !c.is_ascii_digit())
+		.ok_or_else(|| format!("{:?} has no unit, like \"30d\"", s))?;
+	let (number, unit) = s.split_at(split);
+	let number: u64 = number
+		.parse()
+		.map_err(|_| format!("{:?} doesn't begin with a number", s))?;
+	let unit = match unit {
+		"s" => 1,
+		"m" => 60,
+		"h" => 60 * 60,
+		"d" => 24 * 60 * 60,
+		"w" => 7 * 24 * 60 * 60,
+		_ => return Err(format!("{:?} isn't one of the units s, m, h, d or w", unit)),
+	};
+	number
+		.checked_mul(unit)
+		.map(Duration::from_secs)
+		.ok_or_else(|| format!("{:?} is too long", s))
+}
+
+/// Make the keys matching `wildcard` latest-only, as of `now`, or stop if `enabled` is false
+///
+/// Like [`set_rule`], this is a record in the database, so it's only
+/// in effect after `tx` is committed. It takes effect at the next
+/// compaction with [`compact_latest_only`](crate::compact::compact_latest_only).
+/// `wildcard` may match the keys of the settings, like `%` does, but
+/// their earlier records aren't removed.
+pub fn set_latest_only(
+	tx: &mut CreateTx,
+	now: NaiveDateTime,
+	wildcard: &str,
+	enabled: bool,
+) -> Result<(), WriteFailure> {
+	let key = format!("{}{}", LATEST_ONLY_PREFIX, wildcard);
+	tx.add_record(&key, now, record(enabled as u64))
+}
+
+/// Read the latest-only wildcards that are in effect, sorted
+pub fn latest_only(db: &DatabaseReader) -> Vec<String> {
+	let mut wildcards = std::collections::BTreeMap::new();
+	let filter = Wildcard::new(&format!("{}%", LATEST_ONLY_PREFIX));
+	// the records are in order of time, so later records replace earlier ones
+	for r in db.get_filter(&filter) {
+		let Ok(enabled) = r.get_checked::<u64>(0) else {
+			continue;
+		};
+		wildcards.insert(r.key()[LATEST_ONLY_PREFIX.len()..].to_owned(), enabled);
+	}
+	wildcards
+		.into_iter()
+		.filter(|(_, enabled)| *enabled != 0)
+		.map(|(wildcard, _)| wildcard)
+		.collect()
+}
+
+/// Of the keys that match any of `wildcards`, keep only the last record of each
+///
+/// The records must be sorted by key and then by time, as they're
+/// read from a database, so the last record of a key is its latest.
+/// The records of other keys, and of the keys that start with
+/// [`SETTINGS_PREFIX`], are all kept.
+pub fn keep_latest<I: IntoIterator<Item = Record>>(
+	records: I,
+	wildcards: &[String],
+) -> impl Iterator<Item = Record> {
+	let wildcards: Vec<(Option<regex::Regex>, String)> = wildcards
+		.iter()
+		.map(|w| {
+			let w = Wildcard::new(w);
+			(w.as_regex(), w.prefix().to_owned())
+		})
+		.collect();
+	let matches = move |key: &str| {
+		!key.starts_with(SETTINGS_PREFIX)
+			&& wildcards.iter().any(|(re, prefix)| match re {
+				Some(re) => re.is_match(key),
+				None => key.starts_with(prefix.as_str()),
+			})
+	};
+
+	let mut records = records.into_iter().peekable();
+	// the key of the previous record, and whether it's latest-only
+	let mut current: Option<(String, bool)> = None;
+	std::iter::from_fn(move || loop {
+		let r = records.next()?;
+		let latest_only = match &current {
+			Some((key, latest_only)) if key == r.key() => *latest_only,
+			_ => {
+				let latest_only = matches(r.key());
+				current = Some((r.key().to_owned(), latest_only));
+				latest_only
+			}
+		};
+		if latest_only && records.peek().is_some_and(|next| next.key() == r.key()) {
+			continue;
+		}
+		return Some(r);
+	})
+}
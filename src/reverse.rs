@@ -0,0 +1,130 @@
+//! Read a key's records from the newest to the oldest.
+
+use crate::database_reader::Deletions;
+use crate::key_reader::{Reader, StringKeyRangeReader};
+use crate::merge::Merge;
+use crate::segment::Segment;
+use crate::{DatabaseReader, Record};
+use chrono::NaiveDateTime;
+
+impl DatabaseReader {
+	/// Get the records of `key`, from the newest to the oldest
+	///
+	/// This is the opposite order of [`get`](Self::get), and is for reading
+	/// the last few records of a key without reading all of the older ones:
+	///
+	/// ```no_run
+	/// # let db = sonnerie::DatabaseReader::new(std::path::Path::new("")).unwrap();
+	/// let last_ten: Vec<_> = db.get_reverse("temperature").take(10).collect();
+	/// ```
+	///
+	/// Records are stored from the oldest to the newest, so each
+	/// transaction's segments that have `key` are decompressed from the
+	/// last one backwards, and the records of `key` in a segment are kept
+	/// until they've all been yielded. That's one segment's worth of
+	/// records for each transaction at a time, which is usually about
+	/// as much memory as reading forward, because a key's records are
+	/// usually in one segment of each transaction. Deleted records
+	/// aren't yielded, and of the records with the same timestamp, only
+	/// the one from the newest transaction is.
+	pub fn get_reverse<'d>(&'d self, key: &'d str) -> ReverseRecordIterator<'d> {
+		let mut readers = Vec::with_capacity(self.txes.len());
+		for (txid, _path, reader) in self.txes.iter() {
+			let iter = ReverseKeyReader {
+				reader,
+				key,
+				segments: reader.key_segments(key),
+				cache: self.segment_cache.as_deref(),
+				current: vec![],
+			};
+			readers.push((*txid, iter));
+		}
+		let merge = Merge::new(readers, |a: &Record, b: &Record| {
+			b.timestamp_nanos().cmp(&a.timestamp_nanos())
+		});
+
+		let filter_out =
+			Deletions::new(self.filter_out.iter().map(|(txid, _path, dm)| (*txid, dm)));
+
+		ReverseRecordIterator {
+			filter_out,
+			merge: Box::new(merge),
+		}
+	}
+
+	/// Get the record of `key` at exactly `timestamp`, if there is one
+	///
+	/// Like [`get_asof`](Self::get_asof), this reads the key's records
+	/// from the newest, so it's fastest for recent timestamps.
+	pub fn get_at(&self, key: &str, timestamp: NaiveDateTime) -> Option<Record> {
+		self.get_asof(key, timestamp)
+			.filter(|r| r.time() == timestamp)
+	}
+
+	/// Get the newest record of `key` at or before `timestamp`
+	///
+	/// This is the value the key had at that time, as in an "as-of join".
+	/// The key's segments are read from the newest (see
+	/// [`get_reverse`](Self::get_reverse)) until one with a record
+	/// at or before `timestamp`, so the newer segments are
+	/// decompressed but the older ones aren't.
+	pub fn get_asof(&self, key: &str, timestamp: NaiveDateTime) -> Option<Record> {
+		let at = timestamp.and_utc();
+		let at = match at.timestamp_nanos_opt() {
+			Some(nanos) => u64::try_from(nanos).ok()?,
+			// there are no records before 1970
+			None if at.timestamp() < 0 => return None,
+			None => u64::MAX,
+		};
+		self.get_reverse(key).find(|r| r.timestamp_nanos() <= at)
+	}
+}
+
+/// An iterator over the records of a key, from the newest
+/// to the oldest, see [`DatabaseReader::get_reverse`]
+pub struct ReverseRecordIterator<'d> {
+	filter_out: Deletions<'d>,
+	merge: Box<Merge<ReverseKeyReader<'d>, Record>>,
+}
+
+impl Iterator for ReverseRecordIterator<'_> {
+	type Item = Record;
+
+	fn next(&mut self) -> Option<Record> {
+		let filter_out = &self.filter_out;
+		self.merge
+			.by_ref()
+			.find(|(txid, record)| {
+				let (key, timestamp) = record.key_timestamp();
+				!filter_out.contains(*txid, key, timestamp)
+			})
+			.map(|(_, record)| record)
+	}
+}
+
+/// The records of a key in one transaction, from the newest to the oldest
+struct ReverseKeyReader<'d> {
+	reader: &'d Reader,
+	key: &'d str,
+	/// the segments that haven't been read yet, the last one is read next
+	segments: Vec<Segment<'d>>,
+	cache: Option<&'d crate::SegmentCache>,
+	/// the records of the segment that's being read, the last one is yielded next
+	current: Vec<Record>,
+}
+
+impl Iterator for ReverseKeyReader<'_> {
+	type Item = Record;
+
+	fn next(&mut self) -> Option<Record> {
+		loop {
+			if let Some(record) = self.current.pop() {
+				return Some(record);
+			}
+			let segment = self.segments.pop()?;
+			let records: StringKeyRangeReader =
+				self.reader.get_in_segment(self.key, segment, self.cache);
+			self.current = records.collect();
+		}
+	}
+}
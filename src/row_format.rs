@@ -54,6 +54,10 @@ impl RowFormat for RowFormatImpl {
 ///
 /// the text format is one character per term, these are the characters:
 ///
+/// * `c` -> 8-bit signed integer
+/// * `C` -> 8-bit unsigned integer
+/// * `h` -> 16-bit signed integer
+/// * `H` -> 16-bit unsigned integer
 /// * `i` -> 32-bit signed integer
 /// * `I` -> 64-bit signed integer
 /// * `u` -> 32-bit unsigned integer
@@ -61,10 +65,12 @@ impl RowFormat for RowFormatImpl {
 /// * `f` -> 32-bit unsigned float
 /// * `F` -> 64-bit unsigned float
 /// * `s` -> variable size string type
+/// * `B` -> variable size bytes
 ///
 /// Potential future types:
 /// * decimal
 /// * large integers, floats (128 bit, 256 bit)
+///
 /// to indicate "typical size"). The typical size is useful
 /// for knowing how big to make the blocks
 pub fn parse_row_format(human: &str) -> Box<dyn RowFormat> {
@@ -74,6 +80,22 @@ pub fn parse_row_format(human: &str) -> Box<dyn RowFormat> {
 
 	for t in human.bytes() {
 		match t {
+			b'c' => {
+				size += 1;
+				elements.push(Box::new(ElementI8));
+			}
+			b'C' => {
+				size += 1;
+				elements.push(Box::new(ElementU8));
+			}
+			b'h' => {
+				size += 2;
+				elements.push(Box::new(ElementI16));
+			}
+			b'H' => {
+				size += 2;
+				elements.push(Box::new(ElementU16));
+			}
 			b'i' => {
 				size += 4;
 				elements.push(Box::new(ElementI32));
@@ -102,6 +124,10 @@ pub fn parse_row_format(human: &str) -> Box<dyn RowFormat> {
 				has_size = false;
 				elements.push(Box::new(ElementString));
 			}
+			b'B' => {
+				has_size = false;
+				elements.push(Box::new(ElementBytes));
+			}
 			a => {
 				panic!("invalid format character '{}'", a);
 			}
@@ -114,6 +140,34 @@ pub fn parse_row_format(human: &str) -> Box<dyn RowFormat> {
 	})
 }
 
+/// Split a varint length and that many bytes from the start of `data`,
+/// returning those bytes and what follows them
+///
+/// `pos` is where `data` starts (such as in a record's data), and is
+/// only used to say where the error is. Fails if the varint is invalid or
+/// if it's longer than the bytes that follow it, instead of slicing
+/// out of bounds.
+pub(crate) fn split_varint_bytes(data: &[u8], pos: usize) -> std::io::Result<(&[u8], &[u8])> {
+	let (len, tail) = unsigned_varint::decode::u64(data).map_err(|e| {
+		std::io::Error::new(
+			std::io::ErrorKind::InvalidData,
+			format!("invalid length at byte {}: {}", pos, e),
+		)
+	})?;
+	if len > tail.len() as u64 {
+		return Err(std::io::Error::new(
+			std::io::ErrorKind::InvalidData,
+			format!(
+				"the length at byte {} is {}, but only {} bytes follow it",
+				pos,
+				len,
+				tail.len()
+			),
+		));
+	}
+	Ok(tail.split_at(len as usize))
+}
+
 pub fn row_format_size(human: &str) -> Option<usize> {
 	let human = human.as_bytes();
 
@@ -121,13 +175,15 @@ pub fn row_format_size(human: &str) -> Option<usize> {
 
 	for t in human {
 		match t {
+			b'c' | b'C' => size += 1,
+			b'h' | b'H' => size += 2,
 			b'i' => size += 4,
 			b'u' => size += 4,
 			b'I' => size += 8,
 			b'U' => size += 8,
 			b'f' => size += 4,
 			b'F' => size += 8,
-			b's' => return None,
+			b's' | b'B' => return None,
 			b'\x7f' => return None,
 			a => {
 				panic!("invalid format character '{}'", a);
@@ -138,6 +194,16 @@ pub fn row_format_size(human: &str) -> Option<usize> {
 	Some(size)
 }
 
+/// How many decimal places to print floating point values with
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum FloatPrecision {
+	/// The shortest representation that parses back into exactly the same value
+	#[default]
+	Shortest,
+	/// Always print this many digits after the decimal point
+	Digits(usize),
+}
+
 pub trait Element {
 	fn to_stored_format<'s>(&self, from: &'s str, dest: &mut Vec<u8>) -> Result<&'s str, String>;
 	fn to_protocol_format<'a>(
@@ -145,6 +211,116 @@ pub trait Element {
 		from: &'a [u8],
 		dest: &mut dyn ::std::io::Write,
 	) -> ::std::io::Result<&'a [u8]>;
+	/// Like `to_protocol_format`, but floating point values are
+	/// printed with the given precision
+	fn to_protocol_format_with_precision<'a>(
+		&self,
+		from: &'a [u8],
+		dest: &mut dyn ::std::io::Write,
+		_precision: FloatPrecision,
+	) -> ::std::io::Result<&'a [u8]> {
+		self.to_protocol_format(from, dest)
+	}
+}
+
+struct ElementI8;
+impl Element for ElementI8 {
+	fn to_stored_format<'s>(&self, from: &'s str, dest: &mut Vec<u8>) -> Result<&'s str, String> {
+		let (t, rest) = split_one(from).unwrap();
+
+		let v: i8 = t
+			.parse()
+			.map_err(|e| format!("while parsing {}: {}", t, e))?;
+		dest.push(v as u8);
+
+		Ok(rest)
+	}
+	fn to_protocol_format<'a>(
+		&self,
+		from: &'a [u8],
+		dest: &mut dyn ::std::io::Write,
+	) -> ::std::io::Result<&'a [u8]> {
+		let v = from[0] as i8;
+		write!(dest, "{}", v)?;
+		Ok(&from[1..])
+	}
+}
+
+struct ElementU8;
+impl Element for ElementU8 {
+	fn to_stored_format<'s>(&self, from: &'s str, dest: &mut Vec<u8>) -> Result<&'s str, String> {
+		let (t, rest) = split_one(from).unwrap();
+
+		let v: u8 = t
+			.parse()
+			.map_err(|e| format!("while parsing {}: {}", t, e))?;
+		dest.push(v);
+
+		Ok(rest)
+	}
+	fn to_protocol_format<'a>(
+		&self,
+		from: &'a [u8],
+		dest: &mut dyn ::std::io::Write,
+	) -> ::std::io::Result<&'a [u8]> {
+		let v = from[0];
+		write!(dest, "{}", v)?;
+		Ok(&from[1..])
+	}
+}
+
+struct ElementI16;
+impl Element for ElementI16 {
+	fn to_stored_format<'s>(&self, from: &'s str, dest: &mut Vec<u8>) -> Result<&'s str, String> {
+		let at = dest.len();
+		dest.resize(at + 2, 0);
+		let dest = &mut dest[at..];
+
+		let (t, rest) = split_one(from).unwrap();
+
+		let v = t
+			.parse()
+			.map_err(|e| format!("while parsing {}: {}", t, e))?;
+		BigEndian::write_i16(dest, v);
+
+		Ok(rest)
+	}
+	fn to_protocol_format<'a>(
+		&self,
+		from: &'a [u8],
+		dest: &mut dyn ::std::io::Write,
+	) -> ::std::io::Result<&'a [u8]> {
+		let v: i16 = BigEndian::read_i16(&from[0..2]);
+		write!(dest, "{}", v)?;
+		Ok(&from[2..])
+	}
+}
+
+struct ElementU16;
+impl Element for ElementU16 {
+	fn to_stored_format<'s>(&self, from: &'s str, dest: &mut Vec<u8>) -> Result<&'s str, String> {
+		let at = dest.len();
+		dest.resize(at + 2, 0);
+		let dest = &mut dest[at..];
+
+		let (t, rest) = split_one(from).unwrap();
+
+		let v = t
+			.parse()
+			.map_err(|e| format!("while parsing {}: {}", t, e))?;
+		BigEndian::write_u16(dest, v);
+
+		Ok(rest)
+	}
+	fn to_protocol_format<'a>(
+		&self,
+		from: &'a [u8],
+		dest: &mut dyn ::std::io::Write,
+	) -> ::std::io::Result<&'a [u8]> {
+		let v: u16 = BigEndian::read_u16(&from[0..2]);
+		write!(dest, "{}", v)?;
+		Ok(&from[2..])
+	}
 }
 
 struct ElementI32;
@@ -265,7 +441,7 @@ impl Element for ElementF32 {
 		let (t, rest) = split_one(from).unwrap();
 
 		let v = if t == "nan" {
-			::std::f32::NAN
+			f32::NAN
 		} else {
 			t.parse()
 				.map_err(|e| format!("while parsing {}: {}", t, e))?
@@ -278,9 +454,20 @@ impl Element for ElementF32 {
 		&self,
 		from: &'a [u8],
 		dest: &mut dyn ::std::io::Write,
+	) -> ::std::io::Result<&'a [u8]> {
+		self.to_protocol_format_with_precision(from, dest, FloatPrecision::Shortest)
+	}
+	fn to_protocol_format_with_precision<'a>(
+		&self,
+		from: &'a [u8],
+		dest: &mut dyn ::std::io::Write,
+		precision: FloatPrecision,
 	) -> ::std::io::Result<&'a [u8]> {
 		let v: f32 = BigEndian::read_f32(&from[0..4]);
-		write!(dest, "{:.17}", v)?;
+		match precision {
+			FloatPrecision::Shortest => write!(dest, "{}", v)?,
+			FloatPrecision::Digits(digits) => write!(dest, "{:.*}", digits, v)?,
+		}
 		Ok(&from[4..])
 	}
 }
@@ -295,7 +482,7 @@ impl Element for ElementF64 {
 		let (t, rest) = split_one(from).unwrap();
 
 		let v = if t == "nan" {
-			::std::f64::NAN
+			f64::NAN
 		} else {
 			t.parse()
 				.map_err(|e| format!("while parsing {}: {}", t, e))?
@@ -308,9 +495,20 @@ impl Element for ElementF64 {
 		&self,
 		from: &'a [u8],
 		dest: &mut dyn ::std::io::Write,
+	) -> ::std::io::Result<&'a [u8]> {
+		self.to_protocol_format_with_precision(from, dest, FloatPrecision::Shortest)
+	}
+	fn to_protocol_format_with_precision<'a>(
+		&self,
+		from: &'a [u8],
+		dest: &mut dyn ::std::io::Write,
+		precision: FloatPrecision,
 	) -> ::std::io::Result<&'a [u8]> {
 		let v: f64 = BigEndian::read_f64(&from[0..8]);
-		write!(dest, "{:.17}", v)?;
+		match precision {
+			FloatPrecision::Shortest => write!(dest, "{}", v)?,
+			FloatPrecision::Digits(digits) => write!(dest, "{:.*}", digits, v)?,
+		}
 		Ok(&from[8..])
 	}
 }
@@ -331,13 +529,45 @@ impl Element for ElementString {
 		from: &'a [u8],
 		dest: &mut dyn ::std::io::Write,
 	) -> ::std::io::Result<&'a [u8]> {
-		let (len, tail) = unsigned_varint::decode::u64(from).map_err(|e| {
-			std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{:?}", e))
-		})?;
-
-		let s = std::str::from_utf8(&tail[0..len as usize])
+		let (s, tail) = split_varint_bytes(from, 0)?;
+		let s = std::str::from_utf8(s)
 			.map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
 		write!(dest, "{}", escape_string::escape(s))?;
-		Ok(&tail[len as usize..])
+		Ok(tail)
+	}
+}
+
+/// Bytes, which are written as text in hexadecimal
+pub(crate) struct ElementBytes;
+impl Element for ElementBytes {
+	fn to_stored_format<'s>(&self, from: &'s str, dest: &mut Vec<u8>) -> Result<&'s str, String> {
+		let (head, tail) = split_one(from)
+			.ok_or_else(|| format!("Unable to parse \"{}\" as hexadecimal bytes", from))?;
+		if head.len() % 2 != 0 {
+			return Err(format!(
+				"\"{}\" has an odd number of hexadecimal digits",
+				head
+			));
+		}
+		let bytes = (0..head.len())
+			.step_by(2)
+			.map(|i| u8::from_str_radix(head.get(i..i + 2).unwrap_or("x"), 16))
+			.collect::<Result<Vec<u8>, _>>()
+			.map_err(|e| format!("while parsing {} as hexadecimal: {}", head, e))?;
+		let mut buf = unsigned_varint::encode::usize_buffer();
+		dest.extend_from_slice(unsigned_varint::encode::usize(bytes.len(), &mut buf));
+		dest.extend_from_slice(&bytes);
+		Ok(tail)
+	}
+	fn to_protocol_format<'a>(
+		&self,
+		from: &'a [u8],
+		dest: &mut dyn ::std::io::Write,
+	) -> ::std::io::Result<&'a [u8]> {
+		let (bytes, tail) = split_varint_bytes(from, 0)?;
+		for b in bytes {
+			write!(dest, "{:02x}", b)?;
+		}
+		Ok(tail)
 	}
 }
@@ -1,5 +1,6 @@
 use byteorder::{BigEndian, ByteOrder};
 use static_init::dynamic;
+use std::collections::BTreeMap;
 
 pub(crate) const SEGMENT_INVOCATION: &[u8; 14] = b"@TSDB_SEGMENT_";
 pub(crate) const ESCAPE_SEGMENT_INVOCATION: &[u8; 16] = b"@TSDB_SEGMENT_\xff\xff";
@@ -29,6 +30,10 @@ pub(crate) struct Segment<'data> {
 	pub(crate) prev_size: usize,
 	pub(crate) this_key_prev: usize,
 	pub(crate) segment_version: u16,
+	/// what `payload` is compressed with
+	pub(crate) compression: crate::Compression,
+	/// the CRC-32 of the compressed payload (without escapes), since version 0x0102
+	pub(crate) checksum: Option<u32>,
 	pub(crate) stride: usize, // bytes from the start of the invocation to the next invocation
 }
 
@@ -85,11 +90,11 @@ impl<'data> Segment<'data> {
 					}
 
 					let first_key = &header[at..at + len1];
-					let first_key = std::str::from_utf8(first_key).expect("first_key is not utf-8");
+					let first_key = keys_utf8(first_key)?;
 
 					let at = at + len1;
 					let last_key = &header[at..at + len2];
-					let last_key = std::str::from_utf8(last_key).expect("last_key is not utf-8");
+					let last_key = keys_utf8(last_key)?;
 
 					let header_len = 18 + len1 + len2;
 					let payload = &header[header_len..header_len + len3];
@@ -102,13 +107,37 @@ impl<'data> Segment<'data> {
 						prev_size,
 						this_key_prev: 0,
 						segment_version,
+						compression: crate::Compression::Lz4,
+						checksum: None,
 						stride: SEGMENT_INVOCATION.len() + header_len + len3,
 					});
 				}
 
-				0x0100 => {
+				0x0100..=0x0102 => {
 					use unsigned_varint::decode::u32 as v32;
-					let from = &header[2..];
+					let mut from = &header[2..];
+
+					// version 0x0101 and later have a byte for the codec
+					let mut compression = crate::Compression::Lz4;
+					if segment_version >= 0x0101 {
+						let (&codec, rest) = from.split_first()?;
+						let Some(c) = crate::Compression::from_codec(codec) else {
+							eprintln!("warning: invalid segment codec {}", codec);
+							return None;
+						};
+						compression = c;
+						from = rest;
+					}
+
+					// and version 0x0102 has a checksum
+					let mut checksum = None;
+					if segment_version >= 0x0102 {
+						if from.len() < 4 {
+							return None;
+						}
+						checksum = Some(BigEndian::read_u32(&from[..4]));
+						from = &from[4..];
+					}
 
 					// the length of the first key
 					let (len1, from) = v32(from).ok()?;
@@ -134,9 +163,9 @@ impl<'data> Segment<'data> {
 
 					let header_len = len1 + len2 + (header.len() - from.len());
 					let first_key = &from[0..len1];
-					let first_key = std::str::from_utf8(first_key).expect("first_key is not utf-8");
+					let first_key = keys_utf8(first_key)?;
 					let last_key = &from[len1..len1 + len2];
-					let last_key = std::str::from_utf8(last_key).expect("last_key is not utf-8");
+					let last_key = keys_utf8(last_key)?;
 
 					let payload = &header[header_len..header_len + len3];
 
@@ -148,6 +177,8 @@ impl<'data> Segment<'data> {
 						prev_size,
 						this_key_prev,
 						segment_version,
+						compression,
+						checksum,
 						stride: SEGMENT_INVOCATION.len() + header_len + len3,
 					});
 				}
@@ -165,3 +196,104 @@ impl<'data> Segment<'data> {
 		}
 	}
 }
+
+/// Split a segment's payload as it's stored into the pieces
+/// of the compressed data, without the escapes
+pub(crate) fn unescaped(from: &[u8]) -> smallvec::SmallVec<[&[u8]; 4]> {
+	let mut pieces = smallvec::smallvec![];
+	let mut start = 0;
+	while let Some(pos) = find_escape_segment_invocation(&from[start..]) {
+		pieces.push(&from[start..pos + start]);
+		pieces.push(&SEGMENT_INVOCATION[..]);
+		start = start + pos + ESCAPE_SEGMENT_INVOCATION.len();
+	}
+	pieces.push(&from[start..]);
+	pieces
+}
+
+/// The CRC-32 of a segment's compressed data, from its payload as it's stored
+pub(crate) fn payload_checksum(stored: &[u8]) -> u32 {
+	let mut crc = crate::crc32::Crc32::new();
+	for piece in unescaped(stored) {
+		crc.update(piece);
+	}
+	crc.finish()
+}
+
+/// A key of a segment header, or `None` (with a warning) if it isn't utf-8
+fn keys_utf8(key: &[u8]) -> Option<&str> {
+	match std::str::from_utf8(key) {
+		Ok(key) => Some(key),
+		Err(e) => {
+			eprintln!("warning: invalid segment key: {}", e);
+			None
+		}
+	}
+}
+
+/// A transaction file may begin with a metadata header,
+/// which precedes the first segment.
+pub(crate) const METADATA_INVOCATION: &[u8; 15] = b"@TSDB_METADATA_";
+
+/// Encode the metadata header of a transaction file.
+///
+/// The format is the invocation, a u16 version (0x0100), a varint
+/// count of entries, then for each entry a varint length and the
+/// key, then a varint length and the value.
+pub(crate) fn encode_metadata(meta: &BTreeMap<String, String>) -> Vec<u8> {
+	fn wv(out: &mut Vec<u8>, v: usize) {
+		let mut buf = unsigned_varint::encode::usize_buffer();
+		out.extend_from_slice(unsigned_varint::encode::usize(v, &mut buf));
+	}
+
+	let mut out = vec![];
+	out.extend_from_slice(METADATA_INVOCATION);
+	out.extend_from_slice(&0x0100u16.to_be_bytes());
+	wv(&mut out, meta.len());
+	for (k, v) in meta {
+		wv(&mut out, k.len());
+		out.extend_from_slice(k.as_bytes());
+		wv(&mut out, v.len());
+		out.extend_from_slice(v.as_bytes());
+	}
+	out
+}
+
+/// Decode the metadata header at the beginning of a transaction file.
+///
+/// Files without a header have no metadata.
+pub(crate) fn decode_metadata(data: &[u8]) -> std::io::Result<BTreeMap<String, String>> {
+	use std::io::{Error, ErrorKind};
+	let invalid = |e: &str| Error::new(ErrorKind::InvalidData, format!("metadata header: {}", e));
+
+	let mut meta = BTreeMap::new();
+	let Some(from) = data.strip_prefix(&METADATA_INVOCATION[..]) else {
+		return Ok(meta);
+	};
+	if from.len() < 2 {
+		return Err(invalid("truncated"));
+	}
+	let version = BigEndian::read_u16(&from[0..2]);
+	if version != 0x0100 {
+		return Err(invalid(&format!("unknown version {:#06x}", version)));
+	}
+
+	fn read_string(from: &[u8]) -> Option<(String, &[u8])> {
+		let (len, from) = unsigned_varint::decode::usize(from).ok()?;
+		if from.len() < len {
+			return None;
+		}
+		let s = std::str::from_utf8(&from[..len]).ok()?;
+		Some((s.to_owned(), &from[len..]))
+	}
+
+	let (count, mut from) =
+		unsigned_varint::decode::usize(&from[2..]).map_err(|_| invalid("truncated"))?;
+	for _ in 0..count {
+		let (k, rest) = read_string(from).ok_or_else(|| invalid("invalid key"))?;
+		let (v, rest) = read_string(rest).ok_or_else(|| invalid("invalid value"))?;
+		meta.insert(k, v);
+		from = rest;
+	}
+	Ok(meta)
+}
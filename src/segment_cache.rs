@@ -0,0 +1,157 @@
+//! Keep recently decompressed segments in memory.
+
+use antidote::Mutex;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Identifies the contents of a transaction file, so that a cache
+/// never returns the segments of a file that has since been replaced
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum FileIdentity {
+	File {
+		dev: u64,
+		ino: u64,
+		len: u64,
+		mtime: i64,
+		mtime_nsec: i64,
+	},
+	/// a file that was read into memory; each gets its own number
+	Memory(u64),
+}
+
+impl FileIdentity {
+	pub(crate) fn of(file: &std::fs::File) -> std::io::Result<FileIdentity> {
+		use std::os::unix::fs::MetadataExt;
+		let m = file.metadata()?;
+		Ok(FileIdentity::File {
+			dev: m.dev(),
+			ino: m.ino(),
+			len: m.len(),
+			mtime: m.mtime(),
+			mtime_nsec: m.mtime_nsec(),
+		})
+	}
+
+	pub(crate) fn memory() -> FileIdentity {
+		static NEXT: AtomicU64 = AtomicU64::new(0);
+		FileIdentity::Memory(NEXT.fetch_add(1, Ordering::Relaxed))
+	}
+}
+
+/// A cache of decompressed segments, shared between readers
+///
+/// Give it to [`DatabaseReader::with_segment_cache`](crate::DatabaseReader::with_segment_cache)
+/// so that reading the same segments again doesn't decompress them again.
+/// When the decompressed segments exceed the cache's size, the least
+/// recently used ones are dropped.
+///
+/// Segments are identified by the file they're in (its inode, size and
+/// modification time, not only its name) and their position in that file.
+/// Therefore the same cache can be given to each new `DatabaseReader`
+/// as the database changes: the segments of files that were removed or
+/// replaced (such as by a compaction) are no longer found, and eventually dropped.
+pub struct SegmentCache {
+	budget: usize,
+	state: Mutex<CacheState>,
+	hits: AtomicU64,
+	misses: AtomicU64,
+}
+
+#[derive(Default)]
+struct CacheState {
+	entries: HashMap<(FileIdentity, usize), CacheEntry>,
+	/// the keys of `entries` by when they were last used
+	recency: BTreeMap<u64, (FileIdentity, usize)>,
+	clock: u64,
+	bytes: usize,
+}
+
+struct CacheEntry {
+	data: Arc<Vec<u8>>,
+	last_used: u64,
+}
+
+impl SegmentCache {
+	/// Create a cache that holds up to `bytes` of decompressed segments
+	pub fn new(bytes: usize) -> SegmentCache {
+		SegmentCache {
+			budget: bytes,
+			state: Mutex::new(CacheState::default()),
+			hits: AtomicU64::new(0),
+			misses: AtomicU64::new(0),
+		}
+	}
+
+	/// How many times a segment was found in the cache
+	pub fn hits(&self) -> u64 {
+		self.hits.load(Ordering::Relaxed)
+	}
+
+	/// How many times a segment had to be decompressed
+	pub fn misses(&self) -> u64 {
+		self.misses.load(Ordering::Relaxed)
+	}
+
+	/// The size of the decompressed segments currently in the cache
+	pub fn bytes(&self) -> usize {
+		self.state.lock().bytes
+	}
+
+	/// Get the decompressed segment at `offset` in `file`, or
+	/// decompress it with `decode` and remember it
+	///
+	/// `decode` runs without the lock held, so two threads may
+	/// decompress the same segment at the same time. If it returns
+	/// `None` (the segment is damaged), nothing is remembered.
+	pub(crate) fn get_or_decode(
+		&self,
+		file: FileIdentity,
+		offset: usize,
+		decode: impl FnOnce() -> Option<Vec<u8>>,
+	) -> Option<Arc<Vec<u8>>> {
+		let key = (file, offset);
+		{
+			let mut state = self.state.lock();
+			let state = &mut *state;
+			state.clock += 1;
+			if let Some(entry) = state.entries.get_mut(&key) {
+				state.recency.remove(&entry.last_used);
+				entry.last_used = state.clock;
+				state.recency.insert(state.clock, key);
+				self.hits.fetch_add(1, Ordering::Relaxed);
+				return Some(entry.data.clone());
+			}
+		}
+
+		self.misses.fetch_add(1, Ordering::Relaxed);
+		let data = Arc::new(decode()?);
+		if data.len() > self.budget {
+			return Some(data);
+		}
+
+		let mut state = self.state.lock();
+		let state = &mut *state;
+		state.clock += 1;
+		let entry = CacheEntry {
+			data: data.clone(),
+			last_used: state.clock,
+		};
+		if let Some(old) = state.entries.insert(key, entry) {
+			state.recency.remove(&old.last_used);
+			state.bytes -= old.data.len();
+		}
+		state.recency.insert(state.clock, key);
+		state.bytes += data.len();
+
+		while state.bytes > self.budget {
+			let (_, oldest) = state.recency.pop_first().expect("cache is not empty");
+			let removed = state
+				.entries
+				.remove(&oldest)
+				.expect("recency matches entries");
+			state.bytes -= removed.data.len();
+		}
+		Some(data)
+	}
+}
@@ -4,30 +4,120 @@ use crate::Segment;
 use chrono::DateTime;
 use chrono::NaiveDateTime;
 use either::Either;
+use std::collections::BTreeMap;
 use std::io::Read;
 use std::io::Seek;
 
 pub(crate) struct SegmentReader {
-	map: memmap::Mmap,
+	map: Storage,
 	len: usize,
+	metadata: BTreeMap<String, String>,
+	pub(crate) identity: crate::segment_cache::FileIdentity,
+}
+
+/// The bytes of a transaction file
+enum Storage {
+	Mapped(memmap::Mmap),
+	Owned(Vec<u8>),
+	/// a transaction file that's part of a larger file, see [`crate::pack`]
+	Section(std::sync::Arc<memmap::Mmap>, std::ops::Range<usize>),
+}
+
+impl std::ops::Deref for Storage {
+	type Target = [u8];
+	fn deref(&self) -> &[u8] {
+		match self {
+			Storage::Mapped(m) => m,
+			Storage::Owned(v) => v,
+			Storage::Section(m, range) => &m[range.clone()],
+		}
+	}
 }
 
 impl SegmentReader {
 	pub(crate) fn open(
 		file: &mut std::fs::File,
+	) -> std::io::Result<Either<SegmentReader, DeleteMarker>> {
+		let len = file.seek(std::io::SeekFrom::End(0))? as usize;
+		let identity = crate::segment_cache::FileIdentity::of(file)?;
+		let map = match unsafe { memmap::Mmap::map(file) } {
+			Ok(map) => map,
+			// some filesystems can't be mapped, so read the file instead
+			Err(_) => return Self::read(file, len, identity),
+		};
+		if is_being_appended(file) {
+			// the last segment may not be entirely written yet
+			let len = complete_len(&map);
+			return Self::from_storage(
+				Storage::Section(std::sync::Arc::new(map), 0..len),
+				len,
+				identity,
+			);
+		}
+		Self::from_storage(Storage::Mapped(map), len, identity)
+	}
+
+	/// read all of `file` (which is `len` bytes long) into memory instead of mapping it
+	pub(crate) fn read(
+		file: &mut std::fs::File,
+		len: usize,
+		identity: crate::segment_cache::FileIdentity,
+	) -> std::io::Result<Either<SegmentReader, DeleteMarker>> {
+		let mut bytes = Vec::with_capacity(len);
+		file.seek(std::io::SeekFrom::Start(0))?;
+		file.read_to_end(&mut bytes)?;
+		if is_being_appended(file) {
+			bytes.truncate(complete_len(&bytes));
+		}
+		let len = bytes.len();
+		Self::from_storage(Storage::Owned(bytes), len, identity)
+	}
+
+	/// read a transaction file that's `range` of the mapped file `map`
+	pub(crate) fn from_section(
+		map: std::sync::Arc<memmap::Mmap>,
+		range: std::ops::Range<usize>,
+	) -> std::io::Result<Either<SegmentReader, DeleteMarker>> {
+		let len = range.len();
+		// sections don't have an identity of their own
+		let identity = crate::segment_cache::FileIdentity::memory();
+		Self::from_storage(Storage::Section(map, range), len, identity)
+	}
+
+	/// read a transaction file that's already in memory
+	pub(crate) fn from_bytes(
+		bytes: Vec<u8>,
+	) -> std::io::Result<Either<SegmentReader, DeleteMarker>> {
+		let len = bytes.len();
+		let identity = crate::segment_cache::FileIdentity::memory();
+		Self::from_storage(Storage::Owned(bytes), len, identity)
+	}
+
+	fn from_storage(
+		map: Storage,
+		len: usize,
+		identity: crate::segment_cache::FileIdentity,
 	) -> std::io::Result<Either<SegmentReader, DeleteMarker>> {
 		use byteorder::BigEndian;
 		use byteorder::ByteOrder as _;
 		use Either::*;
 
-		let len = file.seek(std::io::SeekFrom::End(0))? as usize;
-		let map = unsafe { memmap::Mmap::map(file)? };
-		let reader = SegmentReader { map, len };
+		let metadata = crate::segment::decode_metadata(&map[..])?;
+		let reader = SegmentReader {
+			map,
+			len,
+			metadata,
+			identity,
+		};
+
+		let invalid = |e: String| std::io::Error::new(std::io::ErrorKind::InvalidData, e);
 
 		if let Some(segment) = reader.first() {
 			// read the payload of the segment and check its first few bytes
 			let mut buffer = vec![];
-			decode_into_with_unescaping(&mut buffer, segment.payload);
+			decoder_with_unescaping(&segment)
+				.read_to_end(&mut buffer)
+				.map_err(|e| invalid(format!("the first segment can't be decoded: {}", e)))?;
 
 			// bytes 0 .. 4 are the key length
 			// bytes 4 .. 8 are the format string length
@@ -37,30 +127,54 @@ impl SegmentReader {
 			// we need to read from bytes 12 + key_length to
 			// 12 + key_length + fmt_length to get the format string
 
-			let key_length = BigEndian::read_u32(&buffer[0..4]);
-			let format_length = BigEndian::read_u32(&buffer[4..8]);
-
-			let fmt_from = (12 + key_length) as usize;
-			let fmt_to = (12 + key_length + format_length) as usize;
+			if buffer.len() < 12 {
+				return Err(invalid("the first segment is truncated".to_string()));
+			}
+			let key_length = BigEndian::read_u32(&buffer[0..4]) as usize;
+			let format_length = BigEndian::read_u32(&buffer[4..8]) as usize;
+
+			let fmt_from = 12 + key_length;
+			let fmt_to = 12 + key_length + format_length;
+			if fmt_to > buffer.len() {
+				return Err(invalid(
+					"the format of the first segment is truncated".to_string(),
+				));
+			}
 
 			if &buffer[fmt_from..fmt_to] == "\u{007f}".as_bytes() {
 				// first varint will be the size of payload minus 8 bytes
 				// it will be disregarded
 				let (_payload_len, next_slice) = unsigned_varint::decode::usize(&buffer[fmt_to..])
-					.expect("Failed to read varint: not enough bytes");
-
-				// second varint will be the size of first key
-				let (fkey_len, next_slice) = unsigned_varint::decode::usize(next_slice)
-					.expect("Failed to read varint; not enough bytes");
-				// if second varint is nonzero, the next set of bytes is the
-				// first key
-				let first_key_slice = &next_slice[0..fkey_len];
+					.map_err(|e| invalid(format!("the deletion is truncated: {}", e)))?;
+
+				// where a slice of `buffer` starts in it, for errors
+				let pos = |slice: &[u8]| buffer.len() - slice.len();
+
+				// second varint will be the size of first key, followed
+				// by the first key
+				let (first_key_slice, next_slice) =
+					crate::row_format::split_varint_bytes(next_slice, pos(next_slice))?;
 				let first_key = String::from_utf8(first_key_slice.to_owned())
-					.expect("Failed to read string: not a valid utf-8 string");
-				assert_eq!(first_key, segment.first_key);
+					.map_err(|e| invalid(format!("the deletion's first key: {}", e)))?;
+				if first_key != segment.first_key {
+					return Err(invalid(format!(
+						"the deletion's first key {:?} isn't its segment's, {:?}",
+						first_key, segment.first_key
+					)));
+				}
+
+				if next_slice.len() < 16 {
+					return Err(std::io::Error::new(
+						std::io::ErrorKind::InvalidData,
+						format!(
+							"the timestamps of a deletion at byte {} are truncated",
+							pos(next_slice)
+						),
+					));
+				}
 
 				// first 8 bytes being the first timestamp
-				let ts_slice = &next_slice[fkey_len..];
+				let ts_slice = &next_slice[0..8];
 				let ts_u64 = BigEndian::read_u64(ts_slice);
 				let start_ts = DateTime::from_timestamp(
 					(ts_u64 / 1_000_000_000) as i64,
@@ -69,7 +183,7 @@ impl SegmentReader {
 				.unwrap();
 
 				// next 8 bytes being the last timestamp
-				let ts_slice = &next_slice[fkey_len + 8..fkey_len + 16];
+				let ts_slice = &next_slice[8..16];
 				let ts_u64 = BigEndian::read_u64(ts_slice);
 				let end_ts = DateTime::from_timestamp(
 					(ts_u64 / 1_000_000_000) as i64,
@@ -78,35 +192,53 @@ impl SegmentReader {
 				.unwrap();
 
 				// next set of bytes is a varint containing the length of the
-				// wildcard
-				let (wc_len, next_slice) =
-					unsigned_varint::decode::usize(&next_slice[fkey_len + 16..])
-						.expect("Failed to read varint: not enough bytes");
-
-				// read, from the next slice, the slice for the filter string
-				let wildcard_slice = &next_slice[0..wc_len];
-				let wildcard = String::from_utf8(wildcard_slice.to_vec()).unwrap();
+				// wildcard, followed by the wildcard
+				let next_slice = &next_slice[16..];
+				let (wildcard_slice, next_slice) =
+					crate::row_format::split_varint_bytes(next_slice, pos(next_slice))?;
+				let wildcard = String::from_utf8(wildcard_slice.to_vec())
+					.map_err(|e| invalid(format!("the deletion's wildcard: {}", e)))?;
 
 				// next set of bytes is also another varint containing the
-				// length of the last key
-				let (lkey_len, next_slice) = unsigned_varint::decode::usize(&next_slice[wc_len..])
-					.expect("Failed to read varint: not enough bytes");
-
-				// read from the next slice, the slice for the last key
-				let last_key_slice = &next_slice[0..lkey_len];
+				// length of the last key, followed by the last key
+				let (last_key_slice, next_slice) =
+					crate::row_format::split_varint_bytes(next_slice, pos(next_slice))?;
 				let last_key = String::from_utf8(last_key_slice.to_owned())
-					.expect("Failed to read string: not a valid utf-8 string");
+					.map_err(|e| invalid(format!("the deletion's last key: {}", e)))?;
 				// unlike here, we cannot test whether segment's last key equals
 				// this last key because the interface that sets the first key
 				// up in the segment header bases on the first key in a
 				// different interface
 
+				// newer versions follow it with the kind of filter; a kind
+				// from a later version is refused rather than taken for a
+				// wildcard, which would delete other records
+				let (regex, keys) = match next_slice.first() {
+					None => (false, false),
+					Some(&crate::create_tx::DELETE_FILTER_REGEX) => (true, false),
+					Some(&crate::create_tx::DELETE_FILTER_KEYS) => (false, true),
+					Some(kind) => {
+						return Err(invalid(format!(
+							"the deletion has an unknown kind of filter, {}",
+							kind
+						)))
+					}
+				};
+				if regex {
+					if let Err(e) = regex::Regex::new(&wildcard) {
+						return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e));
+					}
+				}
+
 				let marker = DeleteMarker {
 					first_key,
 					last_key,
 					first_timestamp: start_ts.naive_utc(),
 					last_timestamp: end_ts.naive_utc(),
 					wildcard,
+					regex,
+					keys,
+					metadata: reader.metadata,
 				};
 
 				return Ok(Right(marker));
@@ -118,25 +250,52 @@ impl SegmentReader {
 
 	/// instructs the OS I'm going to sequentially read starting here
 	pub(crate) fn advise(&self, from: &Segment) {
-		use libc::{c_void, sysconf, _SC_PAGESIZE};
-		let pagesize = unsafe { sysconf(_SC_PAGESIZE) as usize };
-
 		let from = from.payload.as_ptr() as usize;
-		let aligned_from = from & !(pagesize - 1);
+		let end_map = self.map[self.map.len()..].as_ptr() as usize;
+		self.madvise(from, end_map - from, libc::POSIX_MADV_SEQUENTIAL);
+	}
 
+	/// instructs the OS I'm only going to read a few segments starting here,
+	/// so it shouldn't read ahead
+	pub(crate) fn advise_random(&self, from: &Segment) {
+		let from = from.payload.as_ptr() as usize;
 		let end_map = self.map[self.map.len()..].as_ptr() as usize;
+		self.madvise(from, end_map - from, libc::POSIX_MADV_RANDOM);
+	}
 
-		let len = end_map - aligned_from;
+	/// instructs the OS to start reading the `bytes` after `segment`,
+	/// which are likely the segments that are read next
+	pub(crate) fn prefetch_after(&self, segment: &Segment, bytes: usize) {
+		let start = segment.segment_offset + segment.stride;
+		let end = (start + bytes).min(self.map.len());
+		if start >= end {
+			return;
+		}
+		let from = self.map[start..].as_ptr() as usize;
+		self.madvise(from, end - start, libc::POSIX_MADV_WILLNEED);
+	}
+
+	fn madvise(&self, from: usize, len: usize, advice: libc::c_int) {
+		use libc::{c_void, sysconf, _SC_PAGESIZE};
+		if let Storage::Owned(_) = self.map {
+			return;
+		}
+		let pagesize = unsafe { sysconf(_SC_PAGESIZE) as usize };
+		let aligned_from = from & !(pagesize - 1);
 
 		unsafe {
 			libc::posix_madvise(
 				aligned_from as *mut c_void,
-				len,
-				libc::POSIX_MADV_SEQUENTIAL,
+				len + (from - aligned_from),
+				advice,
 			);
 		}
 	}
 
+	pub(crate) fn metadata(&self) -> &BTreeMap<String, String> {
+		&self.metadata
+	}
+
 	pub(crate) fn number_of_bytes(&self) -> usize {
 		self.len
 	}
@@ -162,11 +321,11 @@ impl SegmentReader {
 		Ok(())
 	}
 
-	pub(crate) fn first(&self) -> Option<Segment> {
+	pub(crate) fn first(&self) -> Option<Segment<'_>> {
 		Segment::scan(&self.map[..], 0)
 	}
 
-	pub(crate) fn scan_from(&self, pos: usize) -> Option<Segment> {
+	pub(crate) fn scan_from(&self, pos: usize) -> Option<Segment<'_>> {
 		Segment::scan(&self.map[pos..], pos)
 	}
 
@@ -235,7 +394,10 @@ impl SegmentReader {
 
 	/// do a binary search for the first segment after the one
 	/// that contains `key`.
-	pub(crate) fn find_after(&self, cmp: impl Fn(&str) -> std::cmp::Ordering) -> Option<Segment> {
+	pub(crate) fn find_after(
+		&self,
+		cmp: impl Fn(&str) -> std::cmp::Ordering,
+	) -> Option<Segment<'_>> {
 		let mut begin = 0;
 		let mut end = self.len - 1;
 
@@ -291,6 +453,25 @@ impl SegmentReader {
 		}
 	}
 
+	/// The payload of `segment` as it is stored, that is, with the
+	/// escaped segment invocations, which aren't included in `payload`
+	pub(crate) fn stored_payload<'s>(&'s self, segment: &Segment<'s>) -> &'s [u8] {
+		let start = segment.payload.as_ptr() as usize - self.map.as_ptr() as usize;
+		let rest = &self.map[start..];
+		&rest[..stored_len(rest, segment.payload.len()).unwrap_or(rest.len())]
+	}
+
+	/// Whether the payload of `segment` has the checksum in its header,
+	/// which is true of segments from before version 0x0102 that don't have one
+	pub(crate) fn checksum_matches(&self, segment: &Segment) -> bool {
+		match segment.checksum {
+			Some(checksum) => {
+				checksum == crate::segment::payload_checksum(self.stored_payload(segment))
+			}
+			None => true,
+		}
+	}
+
 	pub(crate) fn segment_after<'s>(&'s self, segment: &Segment<'s>) -> Option<Segment<'s>> {
 		let data = &self.map;
 		let next = segment.segment_offset + segment.stride;
@@ -298,19 +479,77 @@ impl SegmentReader {
 	}
 }
 
-pub(crate) fn decode_into_with_unescaping(into: &mut Vec<u8>, from: &[u8]) {
-	let mut segmented: smallvec::SmallVec<[_; 4]> = smallvec::smallvec![];
-	{
-		let mut start = 0;
-		while let Some(pos) = crate::segment::find_escape_segment_invocation(&from[start..]) {
-			segmented.push(&from[start..pos + start]);
-			segmented.push(crate::segment::SEGMENT_INVOCATION);
-			start = start + pos + crate::segment::ESCAPE_SEGMENT_INVOCATION.len();
+/// The length of a payload whose length without escapes is `payload_len`,
+/// as it's stored at the start of `rest`, or `None` if `rest` is too short
+fn stored_len(rest: &[u8], payload_len: usize) -> Option<usize> {
+	// each escape adds two bytes, and the payload can't have an
+	// unescaped invocation, so each invocation in it is an escape
+	let mut len = payload_len;
+	loop {
+		let mut escapes = 0;
+		let mut at = 0;
+		let stored = rest.get(..len)?;
+		while let Some(pos) = crate::segment::find_segment_invocation(&stored[at..]) {
+			escapes += 1;
+			at += pos + crate::segment::SEGMENT_INVOCATION.len();
+		}
+		let stored_len = payload_len + 2 * escapes;
+		if stored_len == len {
+			return Some(len);
 		}
-		segmented.push(&from[start..]);
+		len = stored_len;
 	}
+}
 
-	let mut reader: Option<Box<dyn Read>> = None;
+/// Whether `file` is a transaction that's still being appended
+/// to, see [`CreateTx::checkpoint`](crate::CreateTx::checkpoint)
+fn is_being_appended(file: &std::fs::File) -> bool {
+	match fs2::FileExt::try_lock_shared(file) {
+		Ok(()) => {
+			let _ = fs2::FileExt::unlock(file);
+			false
+		}
+		Err(e) => e.kind() == fs2::lock_contended_error().kind(),
+	}
+}
+
+/// The length of the segments of `data` that are entirely in it
+///
+/// The segments are found by searching backwards from the end,
+/// in larger and larger windows.
+pub(crate) fn complete_len(data: &[u8]) -> usize {
+	let mut window = 64 * 1024;
+	loop {
+		let start = data.len().saturating_sub(window);
+		let mut end = None;
+		let mut segment = Segment::scan(&data[start..], start);
+		while let Some(s) = segment {
+			let payload_start = s.payload.as_ptr() as usize - data.as_ptr() as usize;
+			let Some(len) = stored_len(&data[payload_start..], s.payload.len()) else {
+				break;
+			};
+			end = Some(payload_start + len);
+			let next = s.segment_offset + s.stride;
+			segment = Segment::scan(&data[next..], next);
+		}
+		match end {
+			Some(end) => return end,
+			None if start == 0 => return data.len(),
+			None => window *= 2,
+		}
+	}
+}
+
+pub(crate) fn decode_into_with_unescaping(into: &mut Vec<u8>, segment: &Segment) {
+	let mut decoder = decoder_with_unescaping(segment);
+	decoder.read_to_end(into).expect("segment decoding 2");
+}
+
+/// A reader of the decompressed contents of a segment's payload
+pub(crate) fn decoder_with_unescaping<'s>(segment: &Segment<'s>) -> Box<dyn Read + Send + 's> {
+	let segmented = crate::segment::unescaped(segment.payload);
+
+	let mut reader: Option<Box<dyn Read + Send>> = None;
 
 	for segment in segmented {
 		if let Some(head) = reader {
@@ -320,8 +559,13 @@ pub(crate) fn decode_into_with_unescaping(into: &mut Vec<u8>, from: &[u8]) {
 		}
 	}
 
-	let mut decoder = lz4::Decoder::new(reader.expect("empty segment")).expect("lz4 decoding");
-	decoder.read_to_end(into).expect("lz4 decoding 2");
+	let reader = reader.expect("empty segment");
+	match segment.compression {
+		crate::Compression::Lz4 => Box::new(lz4::Decoder::new(reader).expect("lz4 decoding")),
+		crate::Compression::Zstd => {
+			Box::new(zstd::stream::read::Decoder::new(reader).expect("zstd decoding"))
+		}
+	}
 }
 
 #[derive(Debug, Clone)]
@@ -331,4 +575,35 @@ pub struct DeleteMarker {
 	pub first_timestamp: NaiveDateTime,
 	pub last_timestamp: NaiveDateTime,
 	pub wildcard: String,
+	/// `wildcard` is a regular expression instead of a `%`-wildcard,
+	/// see [`CreateTx::delete_regex`](crate::CreateTx::delete_regex)
+	pub regex: bool,
+	/// `wildcard` is a list of keys, one on each line, instead of
+	/// a `%`-wildcard, see [`CreateTx::delete_keys`](crate::CreateTx::delete_keys)
+	pub keys: bool,
+	/// The metadata of the transaction that contains this marker
+	pub metadata: BTreeMap<String, String>,
+}
+
+impl DeleteMarker {
+	/// If this marker deletes the records of every key that are
+	/// before a certain time, that time in nanoseconds
+	///
+	/// These markers are made by [`CreateTx::expire_before`](crate::CreateTx::expire_before)
+	/// (or an equivalent [`CreateTx::delete`](crate::CreateTx::delete)),
+	/// and are cheaper to apply while reading than other markers.
+	pub fn expires_before(&self) -> Option<crate::Timestamp> {
+		let epoch = DateTime::from_timestamp(0, 0).unwrap().naive_utc();
+		if !self.first_key.is_empty()
+			|| !self.last_key.is_empty()
+			|| self.wildcard != "%"
+			|| self.regex
+			|| self.keys
+			|| self.first_timestamp != epoch
+		{
+			return None;
+		}
+		let t = self.last_timestamp.and_utc();
+		Some(t.timestamp() as u64 * 1_000_000_000 + t.timestamp_subsec_nanos() as u64)
+	}
 }
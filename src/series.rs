@@ -0,0 +1,189 @@
+//! Read a key whose records have one numeric column.
+
+use crate::{DatabaseReader, Deletions, StringKeyRangeReader, Timestamp};
+use byteorder::{BigEndian, ByteOrder};
+
+/// A type that a single-column series can be read as, see [`DatabaseReader::get_series`]
+pub trait SeriesValue: Copy {
+	/// The format character of the column
+	const FORMAT: u8;
+	/// Decode the column from the data that follows the timestamp
+	fn decode(data: &[u8]) -> Self;
+}
+
+macro_rules! series_value {
+	($t:ty, $format:literal, $read:path) => {
+		impl SeriesValue for $t {
+			const FORMAT: u8 = $format;
+			fn decode(data: &[u8]) -> Self {
+				$read(data)
+			}
+		}
+	};
+}
+
+series_value!(f64, b'F', BigEndian::read_f64);
+series_value!(f32, b'f', BigEndian::read_f32);
+series_value!(u64, b'U', BigEndian::read_u64);
+series_value!(u32, b'u', BigEndian::read_u32);
+series_value!(i64, b'I', BigEndian::read_i64);
+series_value!(i32, b'i', BigEndian::read_i32);
+
+impl DatabaseReader {
+	/// Read the timestamps and values of a key whose format is the single column `T`
+	///
+	/// This yields the same records as [`get`](Self::get), but without
+	/// making a [`Record`](crate::Record) of each: the values are
+	/// decoded directly from the decompressed segments, which is much faster.
+	/// The timestamps are in nanoseconds since the epoch.
+	///
+	/// Fails if the first record of the key in any transaction isn't
+	/// of the format `T` (for example `F` for `f64`). If a later record
+	/// has another format, the series ends with an error of the kind
+	/// [`InvalidData`](std::io::ErrorKind::InvalidData).
+	///
+	/// ```no_run
+	/// # let db = sonnerie::DatabaseReader::new(std::path::Path::new("")).unwrap();
+	/// let mut sum = 0.0;
+	/// for sample in db.get_series::<f64>("temperature").unwrap() {
+	///     let (_, v) = sample.unwrap();
+	///     sum += v;
+	/// }
+	/// ```
+	pub fn get_series<'d, T: SeriesValue>(
+		&'d self,
+		key: &'d str,
+	) -> std::io::Result<Series<'d, T>> {
+		let mut sources = Vec::with_capacity(self.txes.len());
+		for (txid, _path, reader) in &self.txes {
+			let mut source = SeriesSource {
+				txid: *txid,
+				reader: reader.get_filter_range_with_budget(
+					None,
+					"",
+					crate::disassemble_range_bound(key..=key).into(),
+					None,
+					None,
+					self.segment_cache.as_deref(),
+				),
+				head: None,
+			};
+			source.advance(key)?;
+			if source.head.is_some() {
+				sources.push(source);
+			}
+		}
+
+		Ok(Series {
+			key,
+			sources,
+			filter_out: Deletions::new(self.filter_out.iter().map(|(txid, _path, dm)| (*txid, dm))),
+			error: None,
+		})
+	}
+
+	/// Read a key of the format `F`, see [`get_series`](Self::get_series)
+	pub fn get_f64<'d>(&'d self, key: &'d str) -> std::io::Result<Series<'d, f64>> {
+		self.get_series(key)
+	}
+
+	/// Read a key of the format `f`, see [`get_series`](Self::get_series)
+	pub fn get_f32<'d>(&'d self, key: &'d str) -> std::io::Result<Series<'d, f32>> {
+		self.get_series(key)
+	}
+
+	/// Read a key of the format `U`, see [`get_series`](Self::get_series)
+	pub fn get_u64<'d>(&'d self, key: &'d str) -> std::io::Result<Series<'d, u64>> {
+		self.get_series(key)
+	}
+
+	/// Read a key of the format `u`, see [`get_series`](Self::get_series)
+	pub fn get_u32<'d>(&'d self, key: &'d str) -> std::io::Result<Series<'d, u32>> {
+		self.get_series(key)
+	}
+
+	/// Read a key of the format `I`, see [`get_series`](Self::get_series)
+	pub fn get_i64<'d>(&'d self, key: &'d str) -> std::io::Result<Series<'d, i64>> {
+		self.get_series(key)
+	}
+}
+
+struct SeriesSource<'d, T> {
+	txid: usize,
+	reader: StringKeyRangeReader<'d, 'd>,
+	/// the timestamp and value of the record `reader` is at, None if it's finished
+	head: Option<(Timestamp, T)>,
+}
+
+impl<T: SeriesValue> SeriesSource<'_, T> {
+	fn advance(&mut self, key: &str) -> std::io::Result<()> {
+		self.head = match self.reader.next_raw() {
+			None => None,
+			Some((format, data)) if format == [T::FORMAT] => Some((
+				BigEndian::read_u64(data),
+				T::decode(&data[crate::TIMESTAMP_SIZE..]),
+			)),
+			Some((format, _)) => {
+				return Err(std::io::Error::new(
+					std::io::ErrorKind::InvalidData,
+					format!(
+						"key {:?} has the format {:?}, not {:?}",
+						key,
+						String::from_utf8_lossy(format),
+						T::FORMAT as char,
+					),
+				))
+			}
+		};
+		Ok(())
+	}
+}
+
+/// The timestamps and values of a key, created by [`DatabaseReader::get_series`]
+pub struct Series<'d, T> {
+	key: &'d str,
+	/// the transactions that have more records
+	sources: Vec<SeriesSource<'d, T>>,
+	filter_out: Deletions<'d>,
+	/// a record of another format, which ends the series
+	error: Option<std::io::Error>,
+}
+
+impl<T: SeriesValue> Iterator for Series<'_, T> {
+	type Item = std::io::Result<(Timestamp, T)>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		loop {
+			if let Some(e) = self.error.take() {
+				return Some(Err(e));
+			}
+
+			// the lowest timestamp, ties go to the newest transaction
+			let (txid, (timestamp, value)) = self
+				.sources
+				.iter()
+				.map(|s| (s.txid, s.head.unwrap()))
+				.min_by(|(a_txid, (a, _)), (b_txid, (b, _))| {
+					a.cmp(b).then(a_txid.cmp(b_txid).reverse())
+				})?;
+
+			// all the records with the same timestamp are replaced by this one
+			for source in &mut self.sources {
+				if source.head.unwrap().0 == timestamp {
+					if let Err(e) = source.advance(self.key) {
+						self.error = Some(e);
+					}
+				}
+			}
+			if self.error.is_some() {
+				self.sources.clear();
+			} else {
+				self.sources.retain(|s| s.head.is_some());
+			}
+
+			if !self.filter_out.contains(txid, self.key, timestamp) {
+				return Some(Ok((timestamp, value)));
+			}
+		}
+	}
+}
@@ -0,0 +1,200 @@
+//! Add records in any order by sorting them before they're written.
+
+use crate::formatted::{read_record_frames, write_frame};
+use crate::{CreateTx, WriteFailure};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io::{BufReader, BufWriter, Seek};
+use std::path::{Path, PathBuf};
+
+/// A record as it's buffered: its key, format and data (with the timestamp)
+type Frame = (String, String, Vec<u8>);
+
+/// Create a transaction from records that aren't in order
+///
+/// Unlike [`CreateTx`], records may be added in any order. They're
+/// buffered in memory, and each time the buffer has more than
+/// `memory_limit` bytes, it's sorted and written to a temporary file (a run).
+/// [`commit`](Self::commit) merges the runs and what remains of the
+/// buffer into the transaction, so only one record of each run is
+/// in memory at a time.
+///
+/// When records are added at the same key and timestamp, only the last
+/// one added is kept.
+pub struct SortingCreateTx {
+	dir: PathBuf,
+	memory_limit: usize,
+	buffer: Vec<Frame>,
+	buffered_bytes: usize,
+	runs: Vec<std::fs::File>,
+}
+
+impl SortingCreateTx {
+	/// Start a transaction in the database at `dir`, which buffers
+	/// up to about `memory_limit` bytes of records at a time
+	///
+	/// The runs are unnamed temporary files in `dir`, so they're removed
+	/// even if the transaction is never committed.
+	pub fn new(dir: &Path, memory_limit: usize) -> std::io::Result<SortingCreateTx> {
+		Ok(SortingCreateTx {
+			dir: dir.to_owned(),
+			memory_limit,
+			buffer: vec![],
+			buffered_bytes: 0,
+			runs: vec![],
+		})
+	}
+
+	/// Add a record, like [`CreateTx::add_record`], but in any order
+	pub fn add_record(
+		&mut self,
+		key: &str,
+		timestamp: chrono::NaiveDateTime,
+		values: impl crate::RecordBuilder,
+	) -> Result<(), WriteFailure> {
+		let timestamp = timestamp
+			.and_utc()
+			.timestamp_nanos_opt()
+			.ok_or(WriteFailure::UnableToParseTimestamp)? as crate::Timestamp;
+
+		let mut format = compact_str::CompactString::default();
+		values.format_str(&mut format);
+		let mut data = Vec::with_capacity(crate::TIMESTAMP_SIZE + values.size());
+		data.extend_from_slice(&timestamp.to_be_bytes());
+		values.store(&mut data);
+		self.push((key.to_owned(), format.to_string(), data))
+	}
+
+	/// Add a record with the given key, format, and payload, like
+	/// [`CreateTx::add_record_raw`], but in any order
+	///
+	/// The data must match the format (otherwise you can corrupt
+	/// the database). The data also encodes the timestamp.
+	pub fn add_record_raw(
+		&mut self,
+		key: &str,
+		format: &str,
+		data: &[u8],
+	) -> Result<(), WriteFailure> {
+		if data.len() < crate::TIMESTAMP_SIZE {
+			return Err(std::io::Error::new(
+				std::io::ErrorKind::InvalidInput,
+				"the data of a record must begin with its timestamp",
+			)
+			.into());
+		}
+		self.push((key.to_owned(), format.to_owned(), data.to_vec()))
+	}
+
+	/// The number of runs that were written to temporary files so far
+	pub fn runs(&self) -> usize {
+		self.runs.len()
+	}
+
+	fn push(&mut self, frame: Frame) -> Result<(), WriteFailure> {
+		self.buffered_bytes += frame_size(&frame);
+		self.buffer.push(frame);
+		if self.buffered_bytes > self.memory_limit {
+			self.spill()?;
+		}
+		Ok(())
+	}
+
+	/// Sort the buffer and write it to a new run
+	fn spill(&mut self) -> std::io::Result<()> {
+		sort(&mut self.buffer);
+		let mut out = BufWriter::new(tempfile::tempfile_in(&self.dir)?);
+		for (key, format, data) in self.buffer.drain(..) {
+			write_frame(&mut out, &key, &format, &data)?;
+		}
+		let mut file = out.into_inner().map_err(|e| e.into_error())?;
+		file.rewind()?;
+		self.runs.push(file);
+		self.buffered_bytes = 0;
+		Ok(())
+	}
+
+	/// Merge the records into a transaction and commit it
+	///
+	/// On successful completion, the data is on disk, as
+	/// with [`CreateTx::commit`].
+	pub fn commit(mut self) -> Result<(), WriteFailure> {
+		sort(&mut self.buffer);
+
+		let mut sources: Vec<Source> = vec![];
+		for run in self.runs {
+			sources.push(Box::new(read_record_frames(BufReader::new(run))));
+		}
+		// the buffer has the newest records, so it comes last
+		sources.push(Box::new(self.buffer.into_iter().map(Ok)));
+
+		// ordered by key, timestamp and then source, so that of
+		// the records at the same key and timestamp, the last one added
+		// comes last
+		let mut heap = BinaryHeap::new();
+		for index in 0..sources.len() {
+			next_from(&mut heap, &mut sources, index)?;
+		}
+
+		let mut tx = CreateTx::new(&self.dir)?;
+		// the record to write, unless the next one replaces it
+		let mut pending: Option<(String, crate::Timestamp, String, Vec<u8>)> = None;
+		while let Some(Reverse((key, timestamp, index, format, data))) = heap.pop() {
+			next_from(&mut heap, &mut sources, index)?;
+			let replaced = pending
+				.as_ref()
+				.is_some_and(|p| p.0 == key && p.1 == timestamp);
+			if let Some((key, _, format, data)) = pending.replace((key, timestamp, format, data)) {
+				if !replaced {
+					tx.add_record_raw(&key, &format, &data)?;
+				}
+			}
+		}
+		if let Some((key, _, format, data)) = pending {
+			tx.add_record_raw(&key, &format, &data)?;
+		}
+		tx.commit()?;
+		Ok(())
+	}
+}
+
+type Source = Box<dyn Iterator<Item = std::io::Result<Frame>>>;
+
+/// a record of the merge: its key, timestamp, source, format and data
+type Entry = Reverse<(String, crate::Timestamp, usize, String, Vec<u8>)>;
+
+/// read the next record of `sources[index]` into `heap`
+fn next_from(
+	heap: &mut BinaryHeap<Entry>,
+	sources: &mut [Source],
+	index: usize,
+) -> std::io::Result<()> {
+	if let Some(frame) = sources[index].next() {
+		let (key, format, data) = frame?;
+		if data.len() < crate::TIMESTAMP_SIZE {
+			return Err(std::io::Error::new(
+				std::io::ErrorKind::InvalidData,
+				"a run has a record without a timestamp",
+			));
+		}
+		heap.push(Reverse((key, timestamp(&data), index, format, data)));
+	}
+	Ok(())
+}
+
+fn timestamp(data: &[u8]) -> crate::Timestamp {
+	u64::from_be_bytes(data[..crate::TIMESTAMP_SIZE].try_into().unwrap())
+}
+
+/// about how much memory a buffered record takes
+fn frame_size((key, format, data): &Frame) -> usize {
+	std::mem::size_of::<Frame>() + key.len() + format.len() + data.len()
+}
+
+/// sort by key and timestamp, keeping the order in which they were added otherwise
+fn sort(frames: &mut [Frame]) {
+	frames.sort_by(|a, b| {
+		a.0.cmp(&b.0)
+			.then_with(|| timestamp(&a.2).cmp(&timestamp(&b.2)))
+	});
+}
@@ -403,6 +403,41 @@ fn violate_time_order() {
 	}
 }
 
+#[test]
+fn add_with_fmt_names_bad_line() {
+	let t = tempfile::TempDir::new().unwrap();
+
+	let mut data = String::new();
+	for i in 1..=60 {
+		if i == 50 {
+			data += &format!("k {} u nope\n", i);
+		} else {
+			data += &format!("k {} u {}\n", i, i);
+		}
+	}
+	let mut tx = CreateTx::new(t.path()).unwrap();
+	let e = add_from_stream_with_fmt(&mut tx, &mut std::io::Cursor::new(data), None).unwrap_err();
+	match e {
+		crate::WriteFailure::InvalidLine { line, message } => {
+			assert_eq!(line, 50);
+			assert!(message.contains("at 50"), "{}", message);
+			assert!(message.contains("nope"), "{}", message);
+		}
+		e => panic!("{:?}", e),
+	}
+
+	let mut row_data = vec![];
+	for (line, bad) in [
+		("k x u 1", "\"x\""),
+		("k 1 uq 1", "'q'"),
+		("k", "no timestamp"),
+	] {
+		let e = crate::formatted::parse_line_with_fmt(line, 7, None, &mut row_data).unwrap_err();
+		assert!(e.to_string().starts_with("line 7: "), "{}", e);
+		assert!(e.to_string().contains(bad), "{}", e);
+	}
+}
+
 #[test]
 fn multicolumn_string() {
 	let t = tempfile::TempDir::new().unwrap();
@@ -455,6 +490,142 @@ fn multicolumn_string() {
 	assert!(i.next().is_none());
 
 	assert_eq!(&String::from_utf8(out).unwrap(), data,);
+
+	let lines: Vec<String> = o
+		.get_range("a".."z")
+		.map(|r| {
+			r.to_line(
+				PrintTimestamp::FormatString("%F_%T"),
+				PrintRecordFormat::Yes,
+			)
+		})
+		.collect();
+	assert_eq!(lines.join("\n"), data);
+	for r in o.get_range("a".."z") {
+		let mut out = vec![];
+		print_record(
+			&r,
+			&mut out,
+			PrintTimestamp::Nanos,
+			PrintRecordFormat::No,
+			&choice_string::Selection::All,
+		)
+		.unwrap();
+		assert_eq!(
+			r.to_line(PrintTimestamp::Nanos, PrintRecordFormat::No)
+				.as_bytes(),
+			&out[..]
+		);
+	}
+}
+
+#[test]
+fn record_vec() {
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+
+	let columns: Vec<Box<dyn crate::ToRecord>> =
+		vec![Box::new(42u32), Box::new(22.0f32), Box::new("x y")];
+	let mut dynamic = crate::RecordVec::new();
+	for c in &columns {
+		dynamic.push_value(c.as_ref());
+	}
+	assert_eq!(dynamic.format(), "ufs");
+	assert_eq!(dynamic.len(), 3);
+	assert_eq!(
+		dynamic,
+		crate::RecordVec::new()
+			.push_u32(42)
+			.push_f32(22.0)
+			.push_str("x y")
+	);
+
+	let mut tx = CreateTx::new(t.path()).unwrap();
+	tx.add_record_dynamic("a", ts(1), &dynamic).unwrap();
+	tx.add_record("b", ts(1), record(42u32).add(22.0f32).add("x y"))
+		.unwrap();
+	assert!(tx
+		.add_record_dynamic("c", ts(1), &crate::RecordVec::new())
+		.is_err());
+	tx.add_record_dynamic(
+		"d",
+		ts(1),
+		&crate::RecordVec::new()
+			.push_i32(-1)
+			.push_i64(-2)
+			.push_u64(3)
+			.push_f64(4.5),
+	)
+	.unwrap();
+	tx.commit().unwrap();
+
+	let db = DatabaseReader::new(t.path()).unwrap();
+	let a = db.get("a").into_iter().next().unwrap();
+	let b = db.get("b").into_iter().next().unwrap();
+	assert_eq!((a.format(), a.raw()), (b.format(), b.raw()));
+	assert_eq!(a.get::<String>(2), "x y");
+	let d = db.get("d").into_iter().next().unwrap();
+	assert_eq!(d.format(), "iIUF");
+	assert_eq!(d.get::<f64>(3), 4.5);
+	assert_eq!(db.get("c").into_iter().count(), 0);
+}
+
+#[test]
+fn keys_with_control_characters() {
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+	let keys = ["a\nb", "c\td e", "f\r\n", "g\\h\x0bi"];
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		for (i, key) in keys.iter().enumerate() {
+			tx.add_record(key, ts(1), record(i as u32).add("x\ny"))
+				.unwrap();
+		}
+		tx.commit().unwrap();
+	}
+	let db = DatabaseReader::new(t.path()).unwrap();
+
+	let mut dump = vec![];
+	for r in db.get_range(..) {
+		print_record(
+			&r,
+			&mut dump,
+			PrintTimestamp::Nanos,
+			PrintRecordFormat::Yes,
+			&choice_string::Selection::All,
+		)
+		.unwrap();
+		dump.push(b'\n');
+	}
+	// each record is still on one line
+	assert_eq!(dump.iter().filter(|&&b| b == b'\n').count(), keys.len());
+
+	let copy = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(copy.path().join("main")).unwrap();
+	{
+		let mut tx = CreateTx::new(copy.path()).unwrap();
+		add_from_stream_with_fmt(&mut tx, &mut &dump[..], None).unwrap();
+		tx.commit().unwrap();
+	}
+	let copy = DatabaseReader::new(copy.path()).unwrap();
+	let mut sorted = keys;
+	sorted.sort();
+	assert!(copy
+		.get_range(..)
+		.into_iter()
+		.map(|r| (r.key().to_owned(), r.get::<u32>(0), r.get::<String>(1)))
+		.eq(db.get_range(..).into_iter().map(|r| (
+			r.key().to_owned(),
+			r.get::<u32>(0),
+			r.get::<String>(1)
+		))));
+	assert!(copy
+		.get_range(..)
+		.into_iter()
+		.map(|r| r.key().to_owned())
+		.eq(sorted.iter().map(|k| k.to_string())));
 }
 
 #[test]
@@ -477,6 +648,104 @@ fn write() {
 	let _ = o.find("a").unwrap();
 }
 
+#[test]
+fn missing_main() {
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+	let t = tempfile::TempDir::new().unwrap();
+	{
+		use std::io::Write;
+		// so that the transactions aren't committed as `main`
+		let mut main = std::fs::File::create(t.path().join("main")).unwrap();
+		main.write_all(&[0u8]).unwrap();
+	}
+	for key in ["a", "b"] {
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.add_record(key, ts(1), record(1u32)).unwrap();
+		tx.add_record(key, ts(2), record(2u32)).unwrap();
+		tx.commit().unwrap();
+	}
+	std::fs::remove_file(t.path().join("main")).unwrap();
+
+	let db = DatabaseReader::new(t.path()).unwrap();
+	assert_eq!(db.transaction_paths().len(), 2);
+	assert!(db.empty_transaction_files().is_empty());
+	let records: Vec<(String, u32)> = db
+		.get_range(..)
+		.into_iter()
+		.map(|r| (r.key().to_owned(), r.get(0)))
+		.collect();
+	assert_eq!(
+		records,
+		[("a", 1), ("a", 2), ("b", 1), ("b", 2)]
+			.map(|(k, v)| (k.to_owned(), v))
+			.to_vec()
+	);
+
+	let e = DatabaseReader::new_strict(t.path()).err().unwrap();
+	assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+
+	let empty = tempfile::TempDir::new().unwrap();
+	let db = DatabaseReader::new(empty.path()).unwrap();
+	assert_eq!(db.get_range(..).into_iter().count(), 0);
+	std::fs::File::create(empty.path().join("main")).unwrap();
+	let db = DatabaseReader::new_strict(empty.path()).unwrap();
+	assert_eq!(db.empty_transaction_files(), [empty.path().join("main")]);
+}
+
+#[test]
+fn sorting_create_tx() {
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+
+	const N: u64 = 1_000_000;
+	let mut tx = crate::SortingCreateTx::new(t.path(), 4 << 20).unwrap();
+	for i in 0..N {
+		// a permutation of 0..N, since 7919 is coprime with N
+		let i = i * 7919 % N;
+		tx.add_record(
+			&format!("k{:04}", i % 1000),
+			ts((i / 1000) as i64),
+			record(i as u32),
+		)
+		.unwrap();
+	}
+	// replaces the record that was added at the same key and timestamp
+	tx.add_record("k0005", ts(3), record("replaced")).unwrap();
+	assert!(tx.runs() > 2);
+	tx.commit().unwrap();
+
+	let db = DatabaseReader::new(t.path()).unwrap();
+	let mut count = 0;
+	for (n, r) in db.get_range(..).into_iter().enumerate() {
+		let (key, time) = (n as u64 / 1000, n as u64 % 1000);
+		assert_eq!(r.key(), format!("k{:04}", key));
+		assert_eq!(r.time(), ts(time as i64));
+		if (key, time) == (5, 3) {
+			assert_eq!(r.get::<String>(0), "replaced");
+		} else {
+			assert_eq!(r.get::<u32>(0) as u64, time * 1000 + key);
+		}
+		count += 1;
+	}
+	assert_eq!(count, N);
+
+	let mut tx = crate::SortingCreateTx::new(t.path(), 1 << 20).unwrap();
+	assert!(tx.add_record_raw("a", "u", &[0, 1]).is_err());
+	tx.add_record("b", ts(2), record(2u32)).unwrap();
+	tx.add_record("a", ts(2), record(1u32)).unwrap();
+	assert_eq!(tx.runs(), 0);
+	tx.commit().unwrap();
+	let db = DatabaseReader::new(t.path()).unwrap();
+	assert_eq!(
+		db.get_range(.."c")
+			.into_iter()
+			.map(|r| r.get::<u32>(0))
+			.collect::<Vec<_>>(),
+		[1, 2]
+	);
+}
+
 #[test]
 fn database_merge1() {
 	let t = tempfile::TempDir::new().unwrap();
@@ -665,7 +934,7 @@ fn homogenic_types() {
 		"\
 			a\t2010-01-01_00:00:01\tu\t42\n\
 			a\t2010-01-01_00:00:02\tu\t84\n\
-			a\t2010-01-01_00:00:03\tf\t32.50000000000000000\n\
+			a\t2010-01-01_00:00:03\tf\t32.5\n\
 			a\t2010-01-01_00:00:04\ts\tHello\n\
 		"
 	);
@@ -701,6 +970,34 @@ fn keys_split() {
 	assert_eq!(s, 1050000);
 }
 
+#[test]
+fn read_unmapped() {
+	let (t, _db) = make_big_database(100);
+	let path = t.path().join("main");
+	let mut f = std::fs::File::open(&path).unwrap();
+	let len = f.metadata().unwrap().len() as usize;
+	let identity = crate::segment_cache::FileIdentity::of(&f).unwrap();
+	let read = SegmentReader::read(&mut f, len, identity)
+		.unwrap()
+		.left()
+		.unwrap();
+	let read = Reader::from_segments(read);
+	let mapped = Reader::new(std::fs::File::open(&path).unwrap())
+		.unwrap()
+		.left()
+		.unwrap();
+
+	let mut count = 0;
+	for (a, b) in read.get_range(..).zip(mapped.get_range(..)) {
+		assert_eq!(a.key(), b.key());
+		assert_eq!(a.raw(), b.raw());
+		count += 1;
+	}
+	assert_eq!(count, mapped.get_range(..).count());
+	assert_eq!(count, read.get_range(..).count());
+	assert!(count > 0);
+}
+
 #[test]
 #[cfg(feature = "by-key")]
 fn parallel_split1() {
@@ -1035,6 +1332,308 @@ fn delete_all() {
 	assert_eq!(0, db.get_range(..).into_par_iter().count());
 }
 
+#[test]
+fn expire_before() {
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		for s in 1..=3 {
+			tx.add_record("a", ts(s), record(s as u32)).unwrap();
+		}
+		tx.add_record("b", ts(1), record(1u32)).unwrap();
+		tx.add_record("b", ts(5), record(5u32)).unwrap();
+		tx.commit().unwrap();
+	}
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.expire_before(3_000_000_000).unwrap();
+		tx.commit().unwrap();
+	}
+	{
+		// written after the expiry, so it stays
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.add_record("c", ts(1), record(1u32)).unwrap();
+		tx.commit().unwrap();
+	}
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.delete("b", "c", 0, u64::MAX, "%").unwrap();
+		tx.commit().unwrap();
+	}
+
+	let db = DatabaseReader::new(t.path()).unwrap();
+	let markers: Vec<_> = db
+		.delete_txes_paths()
+		.map(|p| {
+			Reader::new(std::fs::File::open(p).unwrap())
+				.unwrap()
+				.right()
+				.unwrap()
+				.expires_before()
+		})
+		.collect();
+	assert_eq!(markers, vec![Some(3_000_000_000), None]);
+
+	let records: Vec<(String, u32)> = db
+		.get_range(..)
+		.into_iter()
+		.map(|r| (r.key().to_owned(), r.value()))
+		.collect();
+	assert_eq!(records, vec![("a".to_owned(), 3), ("c".to_owned(), 1)]);
+
+	let mut scanner = db.scan_headers(..);
+	let mut headers = vec![];
+	while let Some(h) = scanner.next() {
+		headers.push((h.key.to_owned(), h.timestamp));
+	}
+	assert_eq!(
+		headers,
+		vec![
+			("a".to_owned(), 3_000_000_000),
+			("c".to_owned(), 1_000_000_000)
+		]
+	);
+}
+
+#[test]
+fn retention() {
+	use crate::retention::*;
+	use std::time::Duration;
+
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let day = |d: i64| {
+		chrono::DateTime::from_timestamp(d * 86400, 0)
+			.unwrap()
+			.naive_utc()
+	};
+
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		set_rule(&mut tx, day(0), "other.%", Duration::from_secs(86400)).unwrap();
+		set_rule(&mut tx, day(0), "temp.%", Duration::from_secs(86400)).unwrap();
+		tx.commit().unwrap();
+	}
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		// replaces the first rules
+		set_rule(&mut tx, day(1), "other.%", Duration::ZERO).unwrap();
+		set_rule(&mut tx, day(1), "temp.%", Duration::from_secs(30 * 86400)).unwrap();
+		for key in ["other.a", "temp.a", "temp.b"] {
+			for d in [1, 40, 70] {
+				tx.add_record(key, day(d), record(d as u32)).unwrap();
+			}
+		}
+		tx.commit().unwrap();
+	}
+
+	let db = DatabaseReader::new(t.path()).unwrap();
+	assert_eq!(
+		rules(&db),
+		vec![RetentionRule {
+			wildcard: "temp.%".to_owned(),
+			keep: Duration::from_secs(30 * 86400),
+		}]
+	);
+
+	let remaining = || -> Vec<(String, u32)> {
+		DatabaseReader::new(t.path())
+			.unwrap()
+			.get_range("a"..)
+			.into_iter()
+			.map(|r| (r.key().to_owned(), r.value()))
+			.collect()
+	};
+
+	let applied = apply(t.path(), day(60)).unwrap();
+	assert_eq!(applied.len(), 1);
+	assert_eq!(applied[0].1, day(30));
+	let expected = |temp_from: u32| -> Vec<(String, u32)> {
+		let mut e = vec![];
+		for key in ["other.a", "temp.a", "temp.b"] {
+			for d in [1, 40, 70] {
+				if !key.starts_with("temp.") || d >= temp_from {
+					e.push((key.to_owned(), d));
+				}
+			}
+		}
+		e
+	};
+	assert_eq!(remaining(), expected(40));
+
+	// later, the next records are too old
+	apply(t.path(), day(80)).unwrap();
+	assert_eq!(remaining(), expected(70));
+
+	// the rules themselves are kept
+	assert_eq!(rules(&DatabaseReader::new(t.path()).unwrap()).len(), 1);
+
+	// a rule already applied up to now isn't applied again
+	let markers = || {
+		DatabaseReader::new(t.path())
+			.unwrap()
+			.delete_markers()
+			.count()
+	};
+	let before = markers();
+	assert!(apply(t.path(), day(80)).unwrap().is_empty());
+	assert!(apply(t.path(), day(79)).unwrap().is_empty());
+	assert_eq!(markers(), before);
+	assert_eq!(apply(t.path(), day(81)).unwrap().len(), 1);
+	assert_eq!(markers(), before + 1);
+
+	// a rule for every key deletes all but the settings
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		set_rule(&mut tx, day(90), "%", Duration::from_secs(86400)).unwrap();
+		tx.commit().unwrap();
+	}
+	let applied = apply(t.path(), day(100)).unwrap();
+	assert_eq!(applied.len(), 2);
+	assert!(remaining().is_empty());
+	assert_eq!(rules(&DatabaseReader::new(t.path()).unwrap()).len(), 2);
+	// each is a deletion before and after the settings
+	assert_eq!(markers(), before + 4);
+	assert!(apply(t.path(), day(100)).unwrap().is_empty());
+}
+
+#[test]
+fn retention_expire() {
+	use crate::retention::*;
+	use std::time::Duration;
+
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let day = |d: i64| {
+		chrono::DateTime::from_timestamp(d * 86400, 0)
+			.unwrap()
+			.naive_utc()
+	};
+
+	assert_eq!(parse_duration("30d"), Ok(Duration::from_secs(30 * 86400)));
+	assert_eq!(parse_duration("2w"), Ok(Duration::from_secs(14 * 86400)));
+	assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+	assert!(parse_duration("30").is_err());
+	assert!(parse_duration("d").is_err());
+	assert!(parse_duration("30y").is_err());
+
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		for key in ["other.a", "temp.a"] {
+			for d in [1, 40, 70] {
+				tx.add_record(key, day(d), record(d as u32)).unwrap();
+			}
+		}
+		tx.commit().unwrap();
+	}
+	let remaining = || -> Vec<(String, u32)> {
+		DatabaseReader::new(t.path())
+			.unwrap()
+			.get_range("a"..)
+			.into_iter()
+			.map(|r| (r.key().to_owned(), r.value()))
+			.collect()
+	};
+
+	let keep = parse_duration("30d").unwrap();
+	assert_eq!(expire(t.path(), day(60), "temp.%", keep).unwrap(), day(30));
+	assert_eq!(
+		remaining(),
+		vec![
+			("other.a".to_owned(), 1),
+			("other.a".to_owned(), 40),
+			("other.a".to_owned(), 70),
+			("temp.a".to_owned(), 40),
+			("temp.a".to_owned(), 70),
+		]
+	);
+
+	// every key, as of a later time
+	assert_eq!(expire(t.path(), day(80), "%", keep).unwrap(), day(50));
+	assert_eq!(
+		remaining(),
+		vec![("other.a".to_owned(), 70), ("temp.a".to_owned(), 70)]
+	);
+
+	// the rules aren't deleted along with the records
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		set_rule(&mut tx, day(80), "temp.%", keep).unwrap();
+		tx.commit().unwrap();
+	}
+	assert_eq!(expire(t.path(), day(200), "%", keep).unwrap(), day(170));
+	assert!(remaining().is_empty());
+	assert_eq!(rules(&DatabaseReader::new(t.path()).unwrap()).len(), 1);
+}
+
+#[test]
+fn latest_only() {
+	use crate::retention::*;
+
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		set_latest_only(&mut tx, ts(0), "status.%", true).unwrap();
+		set_latest_only(&mut tx, ts(0), "temp.%", true).unwrap();
+		for key in ["status.a", "temp.a"] {
+			for s in 1..=3 {
+				tx.add_record(key, ts(s), crate::record(s as u32)).unwrap();
+			}
+		}
+		tx.commit().unwrap();
+	}
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		// matches the settings, whose records are all kept
+		set_latest_only(&mut tx, ts(1), "@sonnerie.%", true).unwrap();
+		set_latest_only(&mut tx, ts(1), "temp.%", false).unwrap();
+		tx.add_record("status.a", ts(4), crate::record(4u32))
+			.unwrap();
+		tx.commit().unwrap();
+	}
+	assert_eq!(
+		latest_only(&DatabaseReader::new(t.path()).unwrap()),
+		vec!["@sonnerie.%".to_owned(), "status.%".to_owned()]
+	);
+
+	crate::compact::compact_latest_only(t.path()).unwrap();
+
+	let db = DatabaseReader::new(t.path()).unwrap();
+	assert_eq!(db.num_txes(), 1);
+	let remaining: Vec<(String, u32)> = db
+		.get_range("a"..)
+		.into_iter()
+		.map(|r| (r.key().to_owned(), r.value()))
+		.collect();
+	assert_eq!(
+		remaining,
+		vec![
+			("status.a".to_owned(), 4),
+			("temp.a".to_owned(), 1),
+			("temp.a".to_owned(), 2),
+			("temp.a".to_owned(), 3),
+		]
+	);
+	let temp_wildcard = format!("{}temp.%", LATEST_ONLY_PREFIX);
+	assert_eq!(
+		db.get_range(&temp_wildcard[..]..)
+			.into_iter()
+			.filter(|r| r.key() == temp_wildcard)
+			.count(),
+		2
+	);
+	assert_eq!(
+		latest_only(&db),
+		vec!["@sonnerie.%".to_owned(), "status.%".to_owned()]
+	);
+}
+
 #[test]
 fn delete_quantum_choice_eraser_compact() {
 	let t = tempfile::TempDir::new().unwrap();
@@ -1270,3 +1869,5113 @@ delete_test!(prefix, "a%");
 delete_test!(infix, "%a%");
 delete_test!(suffix, "%a");
 delete_test!(inter, "a%a");
+
+#[test]
+fn generate_deterministic() {
+	use std::hash::{Hash, Hasher};
+
+	let make = |seed| {
+		let t = tempfile::TempDir::new().unwrap();
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		crate::generate::generate(
+			&mut tx,
+			&crate::generate::GenerateOptions {
+				keys: 200,
+				points_per_key: 300,
+				seed,
+				format: "Fus",
+			},
+		)
+		.unwrap();
+		tx.commit_to(&t.path().join("main")).expect("committed");
+
+		let main = std::fs::read(t.path().join("main")).unwrap();
+		let mut hasher = std::collections::hash_map::DefaultHasher::new();
+		main.hash(&mut hasher);
+
+		let count = DatabaseReader::new(t.path()).unwrap().get_range(..).count();
+		(hasher.finish(), count)
+	};
+
+	let (first, count) = make(42);
+	assert_eq!(count, 200 * 300);
+	let (second, _) = make(42);
+	assert_eq!(first, second);
+	let (other, _) = make(43);
+	assert_ne!(first, other);
+}
+
+#[test]
+fn estimate_key_count() {
+	let (t, db) = make_big_database(3000);
+
+	let distinct = |db: &DatabaseReader, w: &str| {
+		let w = crate::Wildcard::new(w);
+		let mut keys: Vec<String> = db.get_filter(&w).map(|r| r.key().to_owned()).collect();
+		keys.dedup();
+		keys.len() as u64
+	};
+	for w in ["%", "b%", "ca%", "%a", "b%c%", "cab", "~nothing%"] {
+		let wildcard = crate::Wildcard::new(w);
+		let exact = db.estimate_key_count(&wildcard);
+		assert_eq!(exact, distinct(&db, w), "{}", w);
+		assert!(
+			db.estimate_key_count_sampled(&wildcard, 0) <= exact,
+			"{}",
+			w
+		);
+	}
+
+	let all = db.estimate_key_count(&crate::Wildcard::new("%"));
+	let sampled = db.estimate_key_count_sampled(&crate::Wildcard::new("%"), 4);
+	assert!(sampled.abs_diff(all) < all / 4, "{} {}", sampled, all);
+
+	// a key in another transaction is only counted once
+	let mut tx = CreateTx::new(t.path()).unwrap();
+	tx.add_record_raw("b", "u", &[0; 12]).unwrap();
+	tx.add_record_raw("bz", "u", &[0; 12]).unwrap();
+	tx.commit().unwrap();
+	let db = DatabaseReader::new(t.path()).unwrap();
+	let b = crate::Wildcard::new("b%");
+	assert_eq!(db.estimate_key_count(&b), distinct(&db, "b%"));
+}
+
+#[test]
+fn keys() {
+	let (t, db) = make_big_database(3000);
+
+	let distinct = |db: &DatabaseReader, range: (std::ops::Bound<&str>, std::ops::Bound<&str>)| {
+		let mut keys: Vec<String> = db
+			.get_range(range)
+			.into_iter()
+			.map(|r| r.key().to_owned())
+			.collect();
+		keys.dedup();
+		keys
+	};
+	use std::ops::Bound::*;
+	let ranges = [
+		(Unbounded, Unbounded),
+		(Included("b"), Excluded("c")),
+		(Excluded("ca"), Included("cab")),
+		(Included("ba~"), Unbounded),
+		(Unbounded, Excluded("abc")),
+		(Included("~"), Unbounded),
+	];
+	for range in ranges {
+		let keys: Vec<String> = db.keys(range).collect();
+		assert_eq!(keys, distinct(&db, range), "{:?}", range);
+		assert_eq!(db.key_count(range), keys.len() as u64);
+	}
+	assert_eq!(db.keys(..).take(3).count(), 3);
+
+	// keys in several transactions are only listed once, and a key is
+	// only removed when all of its records are deleted
+	let all = db.key_count(..);
+	let had_b = db.key_count("b"..="b");
+	let mut tx = CreateTx::new(t.path()).unwrap();
+	tx.add_record_raw("b", "u", &[0; 12]).unwrap();
+	tx.add_record_raw("bzz", "u", &[0; 12]).unwrap();
+	tx.add_record_raw("bzzz", "u", &[0; 12]).unwrap();
+	tx.commit().unwrap();
+	let mut tx = CreateTx::new(t.path()).unwrap();
+	tx.delete("bzz", "bzzz", 0, u64::MAX, "%").unwrap();
+	tx.commit().unwrap();
+	let mut tx = CreateTx::new(t.path()).unwrap();
+	tx.delete("bzzz", "", 1, u64::MAX, "%").unwrap();
+	tx.commit().unwrap();
+
+	let db = DatabaseReader::new(t.path()).unwrap();
+	let keys: Vec<String> = db.keys("bz".."c").collect();
+	assert_eq!(keys, ["bzzz"]);
+	assert_eq!(db.key_count(..), all + (1 - had_b) + 1);
+}
+
+#[test]
+fn count_range() {
+	let (t, db) = make_big_database(3000);
+
+	let check = |db: &DatabaseReader| {
+		for range in [("", "~"), ("b", "c"), ("ca", "cab~"), ("ba~", "bb")] {
+			assert_eq!(
+				db.count_range(range.0..range.1),
+				db.get_range(range.0..range.1).into_iter().count() as u64,
+				"{:?}",
+				range
+			);
+		}
+		assert_eq!(
+			db.count_range(..),
+			db.get_range(..).into_iter().count() as u64
+		);
+		for w in ["%", "b%", "ca%", "%a", "cab", "~nothing%"] {
+			let wildcard = crate::Wildcard::new(w);
+			assert_eq!(
+				db.count_filter(&wildcard),
+				db.get_filter(&wildcard).into_iter().count() as u64,
+				"{}",
+				w
+			);
+		}
+	};
+	check(&db);
+
+	// a record at the same time in another transaction is counted once,
+	// and a key may have more than one format
+	let key = db.get_range(..).into_iter().nth(5000).unwrap();
+	let mut tx = CreateTx::new(t.path()).unwrap();
+	tx.add_record_raw(key.key(), key.format(), key.raw())
+		.unwrap();
+	tx.add_record_raw("b", "u", &[0; 12]).unwrap();
+	tx.add_record_raw("b", "s", &[0, 0, 0, 0, 0, 0, 0, 1, 3, b'a', b'b', b'c'])
+		.unwrap();
+	tx.commit().unwrap();
+	let db = DatabaseReader::new(t.path()).unwrap();
+	check(&db);
+
+	// with deletions, the records are read
+	let mut tx = CreateTx::new(t.path()).unwrap();
+	tx.delete("b", "c", 0, 100, "%").unwrap();
+	tx.commit().unwrap();
+	let db = DatabaseReader::new(t.path()).unwrap();
+	check(&db);
+}
+
+#[test]
+fn get_keys_scattered() {
+	let (_t, db) = make_big_database(3000);
+
+	let names: Vec<String> = make_keynames().take(3000).collect();
+	let mut wanted: Vec<&str> = names.iter().step_by(397).map(|k| k.as_str()).collect();
+	// unordered, duplicated, and missing keys
+	wanted.reverse();
+	wanted.push(&names[397]);
+	wanted.push("~does not exist");
+
+	let mut sorted = wanted.clone();
+	sorted.sort();
+	sorted.dedup();
+
+	let expected: Vec<(String, u64, Vec<u8>)> = sorted
+		.iter()
+		.flat_map(|k| db.get(k).into_iter())
+		.map(|r| (r.key().to_owned(), r.timestamp_nanos(), r.raw().to_owned()))
+		.collect();
+	assert!(expected.len() > 1000);
+
+	let got: Vec<(String, u64, Vec<u8>)> = db
+		.get_keys(&wanted)
+		.into_iter()
+		.map(|r| (r.key().to_owned(), r.timestamp_nanos(), r.raw().to_owned()))
+		.collect();
+	assert_eq!(expected, got);
+
+	assert_eq!(db.get_keys(&[]).into_iter().count(), 0);
+}
+
+#[test]
+fn tx_metadata() {
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+
+	// a transaction without metadata
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.add_record("a", "2010-01-01T00:00:01".parse().unwrap(), record(1u32))
+			.unwrap();
+		tx.commit().unwrap();
+	}
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.set_meta("author", "ingest-v2").unwrap();
+		tx.set_meta("comment", "backfill").unwrap();
+		tx.set_meta("comment", "backfill @TSDB_").unwrap();
+		assert!(tx.set_meta("bad", "@TSDB_SEGMENT_").is_err());
+		// the invocation formed by the key, the value's length ('M') and the value
+		let value = format!("ENT_{}", "x".repeat(73));
+		assert!(tx.set_meta("@TSDB_SEG", &value).is_err());
+		tx.set_meta("@TSDB_SEG", &value[..76]).unwrap();
+		tx.add_record("b", "2010-01-01T00:00:02".parse().unwrap(), record(2u32))
+			.unwrap();
+		assert!(tx.set_meta("late", "x").is_err());
+		tx.commit().unwrap();
+	}
+	// only metadata
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.set_meta("note", "nothing new").unwrap();
+		tx.commit().unwrap();
+	}
+
+	let db = DatabaseReader::new(t.path()).unwrap();
+	let paths = db.transaction_paths();
+	assert_eq!(paths.len(), 3);
+	assert!(db.tx_metadata(&paths[0]).unwrap().is_empty());
+	assert_eq!(db.tx_metadata(&paths[2]).unwrap()["note"], "nothing new");
+	let meta = db.tx_metadata(&paths[1]).unwrap();
+	assert_eq!(meta.len(), 3);
+	assert_eq!(meta["author"], "ingest-v2");
+	assert_eq!(meta["comment"], "backfill @TSDB_");
+	assert!(db.tx_metadata(&t.path().join("nope")).is_none());
+
+	let records: Vec<(String, u32)> = db
+		.get_range(..)
+		.into_iter()
+		.map(|r| (r.key().to_owned(), r.value()))
+		.collect();
+	assert_eq!(records, vec![("a".to_owned(), 1), ("b".to_owned(), 2)]);
+
+	let merged = db.merged_tx_metadata();
+	assert_eq!(merged["author"], "ingest-v2");
+}
+
+#[test]
+fn check_ordering() {
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+	let file = |records: &[(&str, i64)]| -> Vec<u8> {
+		let t = tempfile::TempDir::new().unwrap();
+		std::fs::File::create(t.path().join("main")).unwrap();
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		for (key, s) in records {
+			tx.add_record(key, ts(*s), record(1u32)).unwrap();
+		}
+		tx.commit().unwrap();
+		std::fs::read(t.path().join("main")).unwrap()
+	};
+	let check = |bytes: Vec<u8>| {
+		Reader::from_bytes(bytes)
+			.unwrap()
+			.left()
+			.unwrap()
+			.check_ordering()
+	};
+
+	let clean = file(&[("a", 1), ("a", 2), ("b", 1)]);
+	assert_eq!(check(clean.clone()).unwrap(), 3);
+
+	// the writer won't write these, so concatenate two files
+	let descending = [file(&[("a", 1), ("a", 5)]), file(&[("a", 3)])].concat();
+	match check(descending) {
+		Err(crate::WriteFailure::TimeOrderingViolation { first, second, key }) => {
+			assert_eq!((first, second, key.as_str()), (ts(5), ts(3), "a"));
+		}
+		r => panic!("{:?}", r),
+	}
+	let repeated = [file(&[("a", 1)]), file(&[("a", 1)])].concat();
+	assert!(check(repeated).is_err());
+
+	let keys = [file(&[("b", 1)]), file(&[("a", 2)])].concat();
+	match check(keys) {
+		Err(crate::WriteFailure::KeyOrderingViolation { first, second }) => {
+			assert_eq!((first.as_str(), second.as_str()), ("b", "a"));
+		}
+		r => panic!("{:?}", r),
+	}
+
+	let later_key = [clean, file(&[("c", 0)])].concat();
+	assert_eq!(check(later_key).unwrap(), 4);
+}
+
+#[test]
+fn segment_versions() {
+	use byteorder::{BigEndian, WriteBytesExt};
+	use std::io::Write;
+
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let mut tx = CreateTx::new(t.path()).unwrap();
+	tx.add_record("b", chrono::DateTime::UNIX_EPOCH.naive_utc(), record(2u32))
+		.unwrap();
+	tx.commit().unwrap();
+	let current = std::fs::read(t.path().join("main")).unwrap();
+	let reader = Reader::from_bytes(current.clone()).unwrap().left().unwrap();
+	assert_eq!(
+		reader
+			.raw_segments()
+			.map(|s| s.version())
+			.collect::<Vec<_>>(),
+		[crate::SEGMENT_VERSION]
+	);
+	assert_eq!(
+		reader.segment_versions(),
+		Some(crate::SEGMENT_VERSION..=crate::SEGMENT_VERSION)
+	);
+
+	// a segment in the original format (version 0) with the key `a`
+	let mut data = vec![];
+	data.write_u32::<BigEndian>(1).unwrap();
+	data.write_u32::<BigEndian>(1).unwrap();
+	data.write_u32::<BigEndian>(0).unwrap();
+	data.write_u32::<BigEndian>(12).unwrap();
+	data.extend_from_slice(b"au");
+	data.write_u64::<BigEndian>(0).unwrap();
+	data.write_u32::<BigEndian>(1).unwrap();
+	let mut encoder = lz4::EncoderBuilder::new().build(vec![]).unwrap();
+	encoder.write_all(&data).unwrap();
+	let (payload, result) = encoder.finish();
+	result.unwrap();
+
+	let mut old = crate::segment::SEGMENT_INVOCATION.to_vec();
+	old.write_u16::<BigEndian>(0).unwrap();
+	old.write_u32::<BigEndian>(1).unwrap();
+	old.write_u32::<BigEndian>(1).unwrap();
+	old.write_u32::<BigEndian>(payload.len() as u32).unwrap();
+	old.write_u32::<BigEndian>(0).unwrap();
+	old.extend_from_slice(b"aa");
+	old.extend_from_slice(&payload);
+
+	old.extend_from_slice(&current);
+	let reader = Reader::from_bytes(old).unwrap().left().unwrap();
+	assert_eq!(
+		reader
+			.raw_segments()
+			.map(|s| s.version())
+			.collect::<Vec<_>>(),
+		[0, crate::SEGMENT_VERSION]
+	);
+	assert_eq!(reader.segment_versions(), Some(0..=crate::SEGMENT_VERSION));
+	assert_eq!(
+		reader
+			.get_range(..)
+			.map(|r| (r.key().to_owned(), r.get::<u32>(0)))
+			.collect::<Vec<_>>(),
+		[("a".to_owned(), 1), ("b".to_owned(), 2)]
+	);
+
+	let empty = Reader::from_bytes(vec![1]).unwrap().left().unwrap();
+	assert_eq!(empty.segment_versions(), None);
+}
+
+#[test]
+fn inspect_transaction_file() {
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.set_meta("source", "backup").unwrap();
+		tx.add_record("a", ts(1), record(1u32)).unwrap();
+		tx.add_record("a", ts(5), record(2u32)).unwrap();
+		tx.add_record("a", ts(7), record(2.5f64)).unwrap();
+		tx.add_record("b", ts(3), record("x")).unwrap();
+		tx.commit().unwrap();
+	}
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.delete("a", "b", 2_000_000_000, 6_000_000_000, "%")
+			.unwrap();
+		tx.commit().unwrap();
+	}
+
+	let db = DatabaseReader::new(t.path()).unwrap();
+	let tx_path = &db.transaction_paths()[0];
+	let reader = Reader::new(std::fs::File::open(tx_path).unwrap())
+		.unwrap()
+		.left()
+		.unwrap();
+	assert_eq!(reader.metadata()["source"], "backup");
+	assert_eq!(
+		reader.key_summaries(),
+		vec![
+			crate::KeySummary {
+				key: "a".to_owned(),
+				formats: vec!["u".to_owned(), "F".to_owned()],
+				records: 3,
+				first_timestamp: ts(1),
+				last_timestamp: ts(7),
+			},
+			crate::KeySummary {
+				key: "b".to_owned(),
+				formats: vec!["s".to_owned()],
+				records: 1,
+				first_timestamp: ts(3),
+				last_timestamp: ts(3),
+			},
+		]
+	);
+
+	let delete_path = db.delete_txes_paths().next().unwrap();
+	let marker = Reader::new(std::fs::File::open(delete_path).unwrap())
+		.unwrap()
+		.right()
+		.unwrap();
+	assert_eq!(marker.first_key, "a");
+	assert_eq!(marker.last_key, "b");
+	assert_eq!(marker.wildcard, "%");
+	assert_eq!(marker.first_timestamp, ts(2));
+	assert_eq!(marker.last_timestamp, ts(6));
+}
+
+#[test]
+fn zero_columns() {
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		crate::formatted::add_from_stream(&mut tx, "", &mut &b"a 1\na 2\n"[..], None).unwrap();
+		crate::formatted::add_from_stream_with_fmt(&mut tx, &mut &b"b 3\nb 4 u 5\n"[..], None)
+			.unwrap();
+		tx.commit().unwrap();
+	}
+
+	let db = DatabaseReader::new(t.path()).unwrap();
+	let records: Vec<crate::Record> = db.get_range(..).into_iter().collect();
+	assert_eq!(records.len(), 4);
+
+	let r = &records[0];
+	assert_eq!(r.format(), "");
+	assert_eq!(r.raw().len(), 8);
+	assert_eq!(r.timestamp_nanos(), 1);
+	let e = r.get_checked::<u32>(0).unwrap_err();
+	assert!(e.to_string().contains("no columns"), "{}", e);
+	assert_eq!(
+		format!("{:?}", r),
+		"Record { key=a, t=1970-01-01 00:00:00.000000001 }"
+	);
+
+	let mut out = vec![];
+	for r in &records {
+		crate::formatted::print_record(
+			r,
+			&mut out,
+			crate::formatted::PrintTimestamp::Nanos,
+			crate::formatted::PrintRecordFormat::Yes,
+			&choice_string::Selection::All,
+		)
+		.unwrap();
+		out.push(b'\n');
+	}
+	assert_eq!(
+		std::str::from_utf8(&out).unwrap(),
+		"a\t1\na\t2\nb\t3\nb\t4\tu\t5\n"
+	);
+}
+
+#[test]
+fn compact_column_merge() {
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+
+	const MINUTE: u64 = 60_000_000_000;
+	const HOUR: u64 = 60 * MINUTE;
+
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		let mut input = String::new();
+		for key in ["a", "b"] {
+			// from 00:50 to 02:10, every 10 minutes
+			for minute in (50..=130).step_by(10) {
+				input += &format!("{} {} {} m{}\n", key, minute * MINUTE, minute, minute);
+			}
+		}
+		crate::formatted::add_from_stream(&mut tx, "us", &mut input.as_bytes(), None).unwrap();
+		// a key that can't be merged
+		tx.add_record_raw("c", "u", &[0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 1])
+			.unwrap();
+		tx.add_record_raw("c", "u", &[0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 2])
+			.unwrap();
+		tx.commit().unwrap();
+	}
+
+	crate::compact::compact_with_column_merge(t.path(), HOUR, 1, |values| values.join(&b'|'))
+		.unwrap();
+
+	let db = DatabaseReader::new(t.path()).unwrap();
+	assert_eq!(db.num_txes(), 1);
+	let records: Vec<(String, u64, u32, String)> = db
+		.get_range(.."c")
+		.into_iter()
+		.map(|r| {
+			(
+				r.key().to_owned(),
+				r.timestamp_nanos(),
+				r.get(0),
+				r.get::<&str>(1).to_owned(),
+			)
+		})
+		.collect();
+
+	let mut expected = vec![];
+	for key in ["a", "b"] {
+		expected.push((key.to_owned(), 50 * MINUTE, 50, "m50".to_owned()));
+		expected.push((
+			key.to_owned(),
+			60 * MINUTE,
+			60,
+			"m60|m70|m80|m90|m100|m110".to_owned(),
+		));
+		expected.push((key.to_owned(), 120 * MINUTE, 120, "m120|m130".to_owned()));
+	}
+	assert_eq!(records, expected);
+	assert_eq!(db.get("c").into_iter().count(), 2);
+}
+
+#[test]
+fn scan_headers() {
+	let (t, db) = make_big_database(1000);
+	drop(db);
+	let names: Vec<String> = make_keynames().take(1000).collect();
+	let (del_first, del_last) = (names[100].as_str(), names[200].as_str());
+
+	// overlap some of the records, and delete some others
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		for ts in 0..2000u64 {
+			let mut buf = [0; 12];
+			byteorder::BigEndian::write_u64(&mut buf[..], ts);
+			tx.add_record_raw(&names[50], "u", &buf[..]).unwrap();
+		}
+		tx.commit().unwrap();
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.delete(del_first, del_last, 100, 500, "%").unwrap();
+		tx.commit().unwrap();
+	}
+	let db = DatabaseReader::new(t.path()).unwrap();
+
+	let expected: Vec<(String, u64)> = db
+		.get_range(..)
+		.into_iter()
+		.map(|r| (r.key().to_owned(), r.timestamp_nanos()))
+		.collect();
+
+	let deleted = |k: &str| k >= del_first && k < del_last;
+	assert!(expected.iter().any(|(k, ts)| deleted(k) && *ts < 100));
+	assert!(!expected
+		.iter()
+		.any(|(k, ts)| deleted(k) && (100..500).contains(ts)));
+
+	let mut got = Vec::with_capacity(expected.len());
+	let mut scanner = db.scan_headers(..);
+	while let Some(header) = scanner.next() {
+		got.push((header.key.to_owned(), header.timestamp));
+	}
+	assert_eq!(expected.len(), got.len());
+	assert_eq!(expected, got);
+
+	let mut scanner = db.scan_headers(names[50].as_str()..=names[50].as_str());
+	let mut n = 0;
+	while let Some(header) = scanner.next() {
+		assert_eq!(header.key, names[50]);
+		n += 1;
+	}
+	assert!(n >= 2000);
+}
+
+#[test]
+fn print_record_csv() {
+	use crate::formatted::{PrintRecordFormat, PrintTimestamp};
+	use crate::row_format::FloatPrecision;
+
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.add_record(
+			"a,b",
+			ts(1),
+			record("he said \"hi\"").add(1.25f64).add(3u32),
+		)
+		.unwrap();
+		tx.add_record("c", ts(2), record("two\nlines").add(0.5f32).add(-4i8))
+			.unwrap();
+		tx.add_record("d", ts(3), record("plain").add(&b"\x00\xff"[..]))
+			.unwrap();
+		tx.commit().unwrap();
+	}
+	let db = DatabaseReader::new(t.path()).unwrap();
+	let print = |timestamp, format, columns: choice_string::Selection, precision| {
+		let mut out = vec![];
+		for r in db.get_range(..) {
+			crate::formatted::print_record_csv(
+				&r, &mut out, timestamp, format, &columns, precision,
+			)
+			.unwrap();
+			out.extend_from_slice(b"\r\n");
+		}
+		String::from_utf8(out).unwrap()
+	};
+
+	assert_eq!(
+		print(
+			PrintTimestamp::Seconds,
+			PrintRecordFormat::No,
+			choice_string::Selection::All,
+			FloatPrecision::Shortest
+		),
+		"\"a,b\",1,\"he said \"\"hi\"\"\",1.25,3\r\n\
+		c,2,\"two\nlines\",0.5,-4\r\n\
+		d,3,plain,00ff\r\n"
+	);
+	assert_eq!(
+		print(
+			PrintTimestamp::FormatString("%F, %T"),
+			PrintRecordFormat::Yes,
+			choice_string::parse("2").unwrap(),
+			FloatPrecision::Digits(1)
+		),
+		"\"a,b\",\"1970-01-01, 00:00:01\",sFu,1.2\r\n\
+		c,\"1970-01-01, 00:00:02\",sfc,0.5\r\n\
+		d,\"1970-01-01, 00:00:03\",sB,00ff\r\n"
+	);
+}
+
+#[test]
+fn print_record_json() {
+	use crate::formatted::{PrintRecordFormat, PrintTimestamp};
+	use crate::row_format::FloatPrecision;
+
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.add_record("a\"b", ts(1), record("tab\there").add(1.25f64).add(3u32))
+			.unwrap();
+		tx.add_record("c", ts(2), record(f32::NAN).add(-4i8).add(&b"\x00\xff"[..]))
+			.unwrap();
+		tx.commit().unwrap();
+	}
+	let db = DatabaseReader::new(t.path()).unwrap();
+	let print = |timestamp, format, precision| {
+		let mut out = vec![];
+		for r in db.get_range(..) {
+			crate::formatted::print_record_json(
+				&r,
+				&mut out,
+				timestamp,
+				format,
+				&choice_string::Selection::All,
+				precision,
+			)
+			.unwrap();
+			out.push(b'\n');
+		}
+		String::from_utf8(out).unwrap()
+	};
+
+	let nanos = print(
+		PrintTimestamp::Nanos,
+		PrintRecordFormat::No,
+		FloatPrecision::Shortest,
+	);
+	assert_eq!(
+		nanos,
+		"{\"key\":\"a\\\"b\",\"timestamp\":1000000000,\"values\":[\"tab\\there\",1.25,3]}\n\
+		{\"key\":\"c\",\"timestamp\":2000000000,\"values\":[null,-4,\"00ff\"]}\n"
+	);
+	assert_eq!(
+		print(
+			PrintTimestamp::FormatString("%FT%T"),
+			PrintRecordFormat::Yes,
+			FloatPrecision::Digits(1)
+		)
+		.lines()
+		.next()
+		.unwrap(),
+		"{\"key\":\"a\\\"b\",\"timestamp\":\"1970-01-01T00:00:01\",\"format\":\"sFu\",\
+		\"values\":[\"tab\\there\",1.2,3]}"
+	);
+
+	// the output is read back by add_from_ndjson
+	let t2 = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t2.path().join("main")).unwrap();
+	let mut tx = CreateTx::new(t2.path()).unwrap();
+	let first = nanos.lines().next().unwrap();
+	crate::formatted::add_from_ndjson(&mut tx, &mut first.as_bytes(), Some("sFu")).unwrap();
+	tx.commit().unwrap();
+	let db2 = DatabaseReader::new(t2.path()).unwrap();
+	let r = db2.get("a\"b").into_iter().next().unwrap();
+	assert_eq!(r.time(), ts(1));
+	assert_eq!(r.get::<&str>(0), "tab\there");
+	assert_eq!(r.get::<f64>(1), 1.25);
+	assert_eq!(r.get::<u32>(2), 3);
+}
+
+#[test]
+fn float_precision_round_trip() {
+	use crate::row_format::FloatPrecision;
+
+	let f32s = [
+		32.5f32,
+		0.1,
+		-1.0e-30,
+		3.4028235e38,
+		f32::MIN_POSITIVE,
+		1.0 / 3.0,
+	];
+	let f64s = [
+		32.5f64,
+		0.1 + 0.2,
+		-1.0e-300,
+		1.7976931348623157e308,
+		5e-324,
+		1.0 / 3.0,
+	];
+
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		for (i, (a, b)) in f32s.iter().zip(&f64s).enumerate() {
+			tx.add_record(
+				"a",
+				chrono::DateTime::from_timestamp(i as i64, 0)
+					.unwrap()
+					.naive_utc(),
+				record(*a).add(*b),
+			)
+			.unwrap();
+		}
+		tx.commit().unwrap();
+	}
+
+	let print = |precision| {
+		let db = DatabaseReader::new(t.path()).unwrap();
+		let mut out = vec![];
+		for r in db.get_range(..) {
+			crate::formatted::print_record_with_precision(
+				&r,
+				&mut out,
+				PrintTimestamp::Nanos,
+				PrintRecordFormat::Yes,
+				&choice_string::Selection::All,
+				precision,
+			)
+			.unwrap();
+			out.push(b'\n');
+		}
+		String::from_utf8(out).unwrap()
+	};
+
+	let shortest = print(FloatPrecision::Shortest);
+	assert!(
+		shortest.starts_with("a\t0\tfF\t32.5 32.5\n"),
+		"{}",
+		shortest
+	);
+	assert_eq!(
+		print(FloatPrecision::Digits(2)).lines().next().unwrap(),
+		"a\t0\tfF\t32.50 32.50"
+	);
+
+	// reimport and check that every value has the same bits
+	let t2 = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t2.path().join("main")).unwrap();
+	{
+		let mut tx = CreateTx::new(t2.path()).unwrap();
+		add_from_stream_with_fmt(&mut tx, &mut shortest.as_bytes(), None).unwrap();
+		tx.commit().unwrap();
+	}
+	let db = DatabaseReader::new(t2.path()).unwrap();
+	let values: Vec<(u32, u64)> = db
+		.get_range(..)
+		.into_iter()
+		.map(|r| (r.get::<f32>(0).to_bits(), r.get::<f64>(1).to_bits()))
+		.collect();
+	let expected: Vec<(u32, u64)> = f32s
+		.iter()
+		.zip(&f64s)
+		.map(|(a, b)| (a.to_bits(), b.to_bits()))
+		.collect();
+	assert_eq!(values, expected);
+}
+
+#[test]
+fn copy_verbatim() {
+	let (t, db) = make_big_database(600);
+	let original = std::fs::read(t.path().join("main")).unwrap();
+	let reader = Reader::new(std::fs::File::open(t.path().join("main")).unwrap())
+		.unwrap()
+		.left()
+		.unwrap();
+	let segments: Vec<_> = reader.raw_segments().collect();
+	assert!(segments.len() > 3);
+
+	// copying every record
+	let t2 = tempfile::TempDir::new().unwrap();
+	{
+		let mut tx = CreateTx::new(t2.path()).unwrap();
+		for r in db.get_range(..) {
+			tx.add_record_from(&r).unwrap();
+		}
+		tx.commit_to(&t2.path().join("main")).unwrap();
+	}
+	assert!(std::fs::read(t2.path().join("main")).unwrap() == original);
+
+	// copying every segment
+	let t3 = tempfile::TempDir::new().unwrap();
+	{
+		let mut tx = CreateTx::new(t3.path()).unwrap();
+		assert_eq!(tx.copy_from(reader.raw_segments()).unwrap(), segments.len());
+		tx.commit_to(&t3.path().join("main")).unwrap();
+	}
+	assert!(std::fs::read(t3.path().join("main")).unwrap() == original);
+
+	// records, then some segments
+	let t4 = tempfile::TempDir::new().unwrap();
+	{
+		let mut tx = CreateTx::new(t4.path()).unwrap();
+		tx.add_record_raw("a", "u", &[0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 1])
+			.unwrap();
+		tx.copy_from(reader.raw_segments().skip(2)).unwrap();
+		assert!(tx
+			.add_record_raw(segments.last().unwrap().last_key(), "u", &[0; 12])
+			.is_err());
+		tx.add_record_raw("zz", "u", &[0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 1])
+			.unwrap();
+		tx.commit_to(&t4.path().join("main")).unwrap();
+	}
+	let db4 = DatabaseReader::new(t4.path()).unwrap();
+	// (the keys are small enough that none of them spans two segments)
+	let first_copied = segments[2].first_key();
+	let expected: Vec<(String, u64, Vec<u8>)> =
+		std::iter::once(("a".to_owned(), 1, vec![0, 0, 0, 1]))
+			.chain(reader.get_range(first_copied..).map(|r| {
+				(
+					r.key().to_owned(),
+					r.timestamp_nanos(),
+					r.raw()[8..].to_owned(),
+				)
+			}))
+			.chain(std::iter::once(("zz".to_owned(), 1, vec![0, 0, 0, 1])))
+			.collect();
+	let got: Vec<(String, u64, Vec<u8>)> = db4
+		.get_range(..)
+		.into_iter()
+		.map(|r| {
+			(
+				r.key().to_owned(),
+				r.timestamp_nanos(),
+				r.raw()[8..].to_owned(),
+			)
+		})
+		.collect();
+	assert_eq!(expected.len(), got.len());
+	assert!(expected == got);
+}
+
+#[test]
+fn prune_empty() {
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+
+	let long_ago = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+	let touch = |name: &str| {
+		let f = std::fs::File::create(t.path().join(name)).unwrap();
+		f.set_modified(long_ago).unwrap();
+	};
+
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.add_record_raw("a", "u", &[0; 12]).unwrap();
+		tx.commit_to(&t.path().join("tx.0000000000000003")).unwrap();
+	}
+	touch("tx.0000000000000001");
+	touch("tx.0000000000000002.tmp");
+	touch("tx.12");
+	// a transaction that's being committed right now
+	let now = std::time::SystemTime::now()
+		.duration_since(std::time::UNIX_EPOCH)
+		.unwrap()
+		.as_nanos();
+	std::fs::File::create(t.path().join(format!("tx.{:016x}", now))).unwrap();
+
+	let removed = crate::prune_empty(t.path()).unwrap();
+	assert_eq!(removed, vec![t.path().join("tx.0000000000000001")]);
+
+	let mut left: Vec<String> = std::fs::read_dir(t.path())
+		.unwrap()
+		.map(|e| e.unwrap().file_name().into_string().unwrap())
+		.collect();
+	left.sort();
+	assert_eq!(
+		left,
+		vec![
+			".compact".to_owned(),
+			"main".to_owned(),
+			"tx.0000000000000002.tmp".to_owned(),
+			"tx.0000000000000003".to_owned(),
+			"tx.12".to_owned(),
+			format!("tx.{:016x}", now),
+		]
+	);
+	assert_eq!(
+		DatabaseReader::new(t.path())
+			.unwrap()
+			.get("a")
+			.into_iter()
+			.count(),
+		1
+	);
+}
+
+#[test]
+fn filter_values() {
+	let t = tempfile::TempDir::new().unwrap();
+	let data = "\
+		a\t2010-01-01_00:00:01\tu\t42\n\
+		a\t2010-01-01_00:00:02\tu\t84\n\
+		a\t2010-01-01_00:00:03\tu\t66\n\
+		b\t2010-01-01_00:00:01\tFf\t34.0\t22.0\n\
+		b\t2010-01-01_00:00:02\tFf\t3.1415\t2.7182\n\
+		b\t2010-01-01_00:00:03\tsf\tx\t30.5\n\
+		c\t2010-01-01_00:00:01\tss\tHello\\ World Rustacean\n\
+		";
+
+	{
+		let mut tx = CreateTx::new(t.path()).expect("creating tx");
+
+		add_from_stream_with_fmt(&mut tx, &mut std::io::Cursor::new(data), Some("%F_%T"))
+			.expect("writing");
+		tx.commit_to(&t.path().join("main")).expect("committed");
+	}
+	let r = DatabaseReader::new(t.path()).unwrap();
+
+	let b: Vec<f64> = r
+		.get("b")
+		.filter_column_gt(0, 10.0)
+		.into_iter()
+		.map(|m| m.get(0))
+		.collect();
+	assert_eq!(b, vec![34.0]);
+
+	for (col, threshold) in [(0, 10.0), (1, 2.5), (1, 25.0), (0, 60.0), (2, 0.0)] {
+		let specialized: Vec<(String, u64)> = r
+			.get_range(..)
+			.filter_column_gt(col, threshold)
+			.into_iter()
+			.map(|m| (m.key().to_owned(), m.timestamp_nanos()))
+			.collect();
+		let general: Vec<(String, u64)> = r
+			.get_range(..)
+			.filter_values(move |m| {
+				m.get_checked::<f64>(col)
+					.or_else(|_| m.get_checked::<f32>(col).map(f64::from))
+					.or_else(|_| m.get_checked::<u32>(col).map(f64::from))
+					.is_ok_and(|v| v > threshold)
+			})
+			.into_iter()
+			.map(|m| (m.key().to_owned(), m.timestamp_nanos()))
+			.collect();
+		assert_eq!(specialized, general, "col={} threshold={}", col, threshold);
+	}
+
+	let both: Vec<u32> = r
+		.get_range(..)
+		.filter_column_gt(0, 50.0)
+		.filter_values(|m| m.key() == "a")
+		.into_par_iter()
+		.map(|m| m.value())
+		.collect();
+	assert_eq!(both, vec![84, 66]);
+}
+
+#[test]
+fn get_range_time() {
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		for key in ["a", "b", "c"] {
+			for s in 1..=5 {
+				tx.add_record(key, ts(s), record(s as u32)).unwrap();
+			}
+		}
+		tx.commit().unwrap();
+	}
+	let r = DatabaseReader::new(t.path()).unwrap();
+	let read = |records: crate::DatabaseRecordReader| -> Vec<(String, u32)> {
+		records
+			.into_iter()
+			.map(|m| (m.key().to_owned(), m.value()))
+			.collect()
+	};
+	let pairs = |v: &[(&str, u32)]| -> Vec<(String, u32)> {
+		v.iter().map(|(k, v)| (k.to_string(), *v)).collect()
+	};
+
+	assert_eq!(
+		read(r.get_range_time("a"..="b", ts(2)..ts(4))),
+		pairs(&[("a", 2), ("a", 3), ("b", 2), ("b", 3)])
+	);
+	assert_eq!(
+		read(r.get_range_time("c".., ts(4)..)),
+		pairs(&[("c", 4), ("c", 5)])
+	);
+	assert_eq!(
+		read(r.get_range_time(.., ..=ts(1))),
+		pairs(&[("a", 1), ("b", 1), ("c", 1)])
+	);
+	// before the epoch
+	assert_eq!(read(r.get_range_time(.., ..ts(-10))), pairs(&[]));
+	assert_eq!(read(r.get_range_time("c".., ts(-10)..)).len(), 5);
+	// the ranges intersect
+	assert_eq!(
+		read(r.get("b").time_range(ts(2)..).time_range(..ts(3))),
+		pairs(&[("b", 2)])
+	);
+	let parallel: Vec<u32> = r
+		.get_range_time(.., ts(5)..)
+		.into_par_iter()
+		.map(|m| m.value())
+		.collect();
+	assert_eq!(parallel, [5, 5, 5]);
+}
+
+#[test]
+fn add_record_if_latest() {
+	let t = tempfile::TempDir::new().unwrap();
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.add_record("a", ts(10), &[&1u32 as &dyn crate::ToRecord])
+			.unwrap();
+		tx.add_record("a", ts(20), &[&2u32 as &dyn crate::ToRecord])
+			.unwrap();
+		tx.commit_to(&t.path().join("main")).unwrap();
+	}
+
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.add_record_if_latest("a", Some(ts(20)), ts(30), &[&3u32 as &dyn crate::ToRecord])
+			.unwrap();
+		tx.add_record_if_latest("b", None, ts(30), &[&4u32 as &dyn crate::ToRecord])
+			.unwrap();
+		tx.commit().unwrap();
+	}
+
+	let mut tx = CreateTx::new(t.path()).unwrap();
+	match tx.add_record_if_latest("a", Some(ts(20)), ts(40), &[&5u32 as &dyn crate::ToRecord]) {
+		Err(crate::WriteFailure::StaleLatest {
+			key,
+			expected,
+			actual,
+		}) => {
+			assert_eq!(key, "a");
+			assert_eq!(expected, Some(ts(20)));
+			assert_eq!(actual, Some(ts(30)));
+		}
+		other => panic!("expected StaleLatest, got {:?}", other),
+	}
+	assert!(matches!(
+		tx.add_record_if_latest("c", Some(ts(1)), ts(40), &[&5u32 as &dyn crate::ToRecord]),
+		Err(crate::WriteFailure::StaleLatest { actual: None, .. })
+	));
+	drop(tx);
+
+	let r = DatabaseReader::new(t.path()).unwrap();
+	let all: Vec<(String, u32)> = r
+		.get_range(..)
+		.into_iter()
+		.map(|m| (m.key().to_owned(), m.get(0)))
+		.collect();
+	assert_eq!(
+		all,
+		vec![
+			("a".into(), 1),
+			("a".into(), 2),
+			("a".into(), 3),
+			("b".into(), 4)
+		]
+	);
+
+	// the snapshot is opened by the first call, not by `new`
+	let mut tx = CreateTx::new(t.path()).unwrap();
+	let mut other = CreateTx::new(t.path()).unwrap();
+	other
+		.add_record("b", ts(40), &[&6u32 as &dyn crate::ToRecord])
+		.unwrap();
+	other.commit().unwrap();
+	assert!(matches!(
+		tx.add_record_if_latest("b", Some(ts(30)), ts(50), &[&7u32 as &dyn crate::ToRecord]),
+		Err(crate::WriteFailure::StaleLatest { actual: Some(t), .. }) if t == ts(40)
+	));
+	let mut other = CreateTx::new(t.path()).unwrap();
+	other
+		.add_record("c", ts(40), &[&8u32 as &dyn crate::ToRecord])
+		.unwrap();
+	other.commit().unwrap();
+	tx.add_record_if_latest("c", None, ts(50), &[&9u32 as &dyn crate::ToRecord])
+		.unwrap();
+}
+
+#[test]
+fn ndjson() {
+	let t = tempfile::TempDir::new().unwrap();
+	let data = r#"{"key":"b","t":"2010-01-01T00:00:02Z","v":[2.5,"y"]}
+{"key":"a","t":1262304003000000000,"v":[3,true]}
+
+{"key":"b","t":"2010-01-01T00:00:01","v":[-1.0,"x"]}
+{"key":"a","t":"2010-01-01 00:00:01.5","v":[1,false]}
+"#;
+
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		add_from_ndjson(&mut tx, &mut std::io::Cursor::new(data), None).unwrap();
+		tx.commit_to(&t.path().join("main")).unwrap();
+	}
+
+	let r = DatabaseReader::new(t.path()).unwrap();
+	let mut out = vec![];
+	for record in r.get_range(..) {
+		print_record(
+			&record,
+			&mut out,
+			PrintTimestamp::FormatString("%FT%T%.f"),
+			PrintRecordFormat::Yes,
+			&choice_string::Selection::All,
+		)
+		.unwrap();
+		out.push(b'\n');
+	}
+	assert_eq!(
+		String::from_utf8(out).unwrap(),
+		"\
+		a\t2010-01-01T00:00:01.500\tUu\t1 0\n\
+		a\t2010-01-01T00:00:03\tUu\t3 1\n\
+		b\t2010-01-01T00:00:01\tFs\t-1 x\n\
+		b\t2010-01-01T00:00:02\tFs\t2.5 y\n\
+		"
+	);
+
+	// an explicit format
+	let mut tx = CreateTx::new(t.path()).unwrap();
+	add_from_ndjson(
+		&mut tx,
+		&mut std::io::Cursor::new(r#"{"key":"c","t":1,"v":[-4,4]}"#),
+		Some("if"),
+	)
+	.unwrap();
+
+	let errors = [
+		(
+			"{\"key\":\"a\",\"t\":1,\"v\":[1]}\n{\"key\":\"a\",\"t\":2,\"v\":[1.5]}",
+			2,
+		),
+		("\n\n{\"key\":\"a\",\"t\":1,\"v\":[1]", 3),
+		("{\"key\":\"a\",\"t\":\"yesterday\",\"v\":[1]}", 1),
+		("{\"key\":\"a\",\"t\":1,\"v\":[null]}", 1),
+		("{\"t\":1,\"v\":[1]}", 1),
+	];
+	for (input, expected_line) in errors {
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		match add_from_ndjson(&mut tx, &mut std::io::Cursor::new(input), None) {
+			Err(crate::WriteFailure::InvalidLine { line, .. }) => {
+				assert_eq!(line, expected_line, "{}", input)
+			}
+			other => panic!("{:?} for {}", other, input),
+		}
+	}
+	let mut tx = CreateTx::new(t.path()).unwrap();
+	assert!(matches!(
+		add_from_ndjson(
+			&mut tx,
+			&mut std::io::Cursor::new(r#"{"key":"c","t":1,"v":[-4,"x"]}"#),
+			Some("uf"),
+		),
+		Err(crate::WriteFailure::InvalidLine { line: 1, .. })
+	));
+
+	// byte strings are hexadecimal
+	for bad in [r#""0""#, r#""zz""#, "12"] {
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		let line = format!(r#"{{"key":"d","t":1,"v":[{}]}}"#, bad);
+		assert!(
+			matches!(
+				add_from_ndjson(&mut tx, &mut std::io::Cursor::new(line), Some("B")),
+				Err(crate::WriteFailure::InvalidLine { line: 1, .. })
+			),
+			"{}",
+			bad
+		);
+	}
+	let mut tx = CreateTx::new(t.path()).unwrap();
+	add_from_ndjson(
+		&mut tx,
+		&mut std::io::Cursor::new(r#"{"key":"d","t":1,"v":["00fF7f",""]}"#),
+		Some("BB"),
+	)
+	.unwrap();
+	tx.commit().unwrap();
+	let r = DatabaseReader::new(t.path()).unwrap();
+	let d = r.get("d").into_iter().next().unwrap();
+	assert!(d.is_bytes(0) && !d.is_string(0));
+	assert_eq!(d.get::<&[u8]>(0), [0x00, 0xff, 0x7f]);
+	assert!(d.get::<&[u8]>(1).is_empty());
+}
+
+#[test]
+fn timestamp_units() {
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+
+	let mut tx = CreateTx::new(t.path()).unwrap();
+	let options = TextOptions {
+		timestamp_unit: TimestampUnit::Millis,
+		..TextOptions::default()
+	};
+	add_from_stream_opts(
+		&mut tx,
+		"u",
+		&mut std::io::Cursor::new("a 1600000000123 1\n"),
+		None,
+		&options,
+	)
+	.unwrap();
+	let options = TextOptions {
+		timestamp_unit: TimestampUnit::Micros,
+		..TextOptions::default()
+	};
+	add_from_stream_with_fmt_opts(
+		&mut tx,
+		&mut std::io::Cursor::new("b 1600000000123456 u 2\n"),
+		None,
+		&options,
+	)
+	.unwrap();
+	tx.commit().unwrap();
+
+	let r = DatabaseReader::new(t.path()).unwrap();
+	let records: Vec<_> = r.get_range(..).into_iter().collect();
+	assert_eq!(records[0].timestamp_nanos(), 1_600_000_000_123_000_000);
+	assert_eq!(records[1].timestamp_nanos(), 1_600_000_000_123_456_000);
+
+	let print = |record, print_timestamp| {
+		let mut out = vec![];
+		print_record(
+			record,
+			&mut out,
+			print_timestamp,
+			PrintRecordFormat::No,
+			&choice_string::Selection::All,
+		)
+		.unwrap();
+		String::from_utf8(out).unwrap()
+	};
+	assert_eq!(
+		print(&records[1], PrintTimestamp::Millis),
+		"b\t1600000000123\t2"
+	);
+	assert_eq!(
+		print(&records[1], PrintTimestamp::Micros),
+		"b\t1600000000123456\t2"
+	);
+	assert_eq!("seconds".parse(), Ok(TimestampUnit::Seconds));
+
+	// too large to be nanoseconds
+	let mut tx = CreateTx::new(t.path()).unwrap();
+	let options = TextOptions {
+		timestamp_unit: TimestampUnit::Seconds,
+		..TextOptions::default()
+	};
+	assert!(matches!(
+		add_from_stream_with_fmt_opts(
+			&mut tx,
+			&mut std::io::Cursor::new("b 100000000000 u 2\n"),
+			None,
+			&options,
+		),
+		Err(crate::WriteFailure::InvalidLine { line: 1, .. })
+	));
+}
+
+#[test]
+fn time_zones() {
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+
+	let offset: TimeZone = "+05:30".parse().unwrap();
+	assert_eq!(
+		"-0800".parse(),
+		Ok(TimeZone::Fixed(
+			chrono::FixedOffset::west_opt(8 * 3600).unwrap()
+		))
+	);
+	assert_eq!("UTC".parse(), Ok(TimeZone::Utc));
+	assert!("+5".parse::<TimeZone>().is_err());
+	assert!("+05:60".parse::<TimeZone>().is_err());
+
+	let mut tx = CreateTx::new(t.path()).unwrap();
+	let options = TextOptions {
+		time_zone: offset,
+		..TextOptions::default()
+	};
+	add_from_stream_with_fmt_opts(
+		&mut tx,
+		&mut std::io::Cursor::new("a 2020-01-01T05:30:00 u 1\n"),
+		Some("%FT%T"),
+		&options,
+	)
+	.unwrap();
+	tx.commit().unwrap();
+
+	let r = DatabaseReader::new(t.path()).unwrap();
+	let record = r.get("a").into_iter().next().unwrap();
+	// stored in UTC
+	assert_eq!(record.timestamp_nanos(), 1_577_836_800_000_000_000);
+
+	let print = |print_timestamp| {
+		let mut out = vec![];
+		print_record(
+			&record,
+			&mut out,
+			print_timestamp,
+			PrintRecordFormat::No,
+			&choice_string::Selection::All,
+		)
+		.unwrap();
+		String::from_utf8(out).unwrap()
+	};
+	assert_eq!(
+		print(PrintTimestamp::ZonedFormatString("%FT%T%z", offset)),
+		"a\t2020-01-01T05:30:00+0530\t1"
+	);
+	assert_eq!(
+		print(PrintTimestamp::ZonedFormatString("%FT%T", TimeZone::Utc)),
+		"a\t2020-01-01T00:00:00\t1"
+	);
+
+	#[cfg(feature = "timezones")]
+	{
+		let paris: TimeZone = "Europe/Paris".parse().unwrap();
+		let local = |s| chrono::NaiveDateTime::parse_from_str(s, "%FT%T").unwrap();
+		assert_eq!(
+			paris.to_utc(local("2020-07-01T12:00:00")),
+			Some(local("2020-07-01T10:00:00"))
+		);
+		// the clocks go forward at 2:00, and back at 3:00
+		assert_eq!(paris.to_utc(local("2020-03-29T02:30:00")), None);
+		assert_eq!(
+			paris.to_utc(local("2020-10-25T02:30:00")),
+			Some(local("2020-10-25T00:30:00"))
+		);
+		assert_eq!(
+			print(PrintTimestamp::ZonedFormatString("%FT%T %Z", paris)),
+			"a\t2020-01-01T01:00:00 CET\t1"
+		);
+	}
+}
+
+#[test]
+fn influx_line_protocol() {
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let data = r#"# a comment
+weather,region=eu,city=Paris temp=21.5,ok=t,desc="sunny, \"warm\"" 2000000000
+weather,city=Paris,region=eu temp=19,count=3i 1000000000
+
+cpu usage=7u 1000000000
+weather,city=Paris,region=eu temp=22 2000000000
+my\ cpu,host=a\,b load=1 1000000000
+"#;
+	let mut tx = CreateTx::new(t.path()).unwrap();
+	add_from_influx_stream(&mut tx, &mut std::io::Cursor::new(data)).unwrap();
+	tx.commit().unwrap();
+
+	let r = DatabaseReader::new(t.path()).unwrap();
+	let mut out = vec![];
+	for record in r.get_range(..) {
+		print_record(
+			&record,
+			&mut out,
+			PrintTimestamp::Nanos,
+			PrintRecordFormat::Yes,
+			&choice_string::Selection::All,
+		)
+		.unwrap();
+		out.push(b'\n');
+	}
+	assert_eq!(
+		String::from_utf8(out).unwrap(),
+		"\
+		cpu::usage\t1000000000\tU\t7\n\
+		my\\\\\\ cpu:host=a\\\\,b:load\t1000000000\tF\t1\n\
+		weather:city=Paris,region=eu:count\t1000000000\tI\t3\n\
+		weather:city=Paris,region=eu:desc\t2000000000\ts\tsunny,\\ \"warm\"\n\
+		weather:city=Paris,region=eu:ok\t2000000000\tu\t1\n\
+		weather:city=Paris,region=eu:temp\t1000000000\tF\t19\n\
+		weather:city=Paris,region=eu:temp\t2000000000\tF\t22\n\
+		"
+	);
+
+	// without a timestamp, it's now
+	let before = std::time::SystemTime::now()
+		.duration_since(std::time::UNIX_EPOCH)
+		.unwrap()
+		.as_nanos() as u64;
+	let mut tx = CreateTx::new(t.path()).unwrap();
+	add_from_influx_stream(&mut tx, &mut std::io::Cursor::new("now value=1\n")).unwrap();
+	tx.commit().unwrap();
+	let r = DatabaseReader::new(t.path()).unwrap();
+	let record = r.get("now::value").into_iter().next().unwrap();
+	assert!(record.timestamp_nanos() >= before);
+
+	let errors = [
+		("a b=1 1\nx\n", 2),
+		("a b=1i2 1", 1),
+		("a b=\"x 1", 1),
+		("a,t b=1 1", 1),
+		("a b=1 -1", 1),
+		("a =1 1", 1),
+	];
+	for (input, expected_line) in errors {
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		match add_from_influx_stream(&mut tx, &mut std::io::Cursor::new(input)) {
+			Err(crate::WriteFailure::InvalidLine { line, .. }) => {
+				assert_eq!(line, expected_line, "{}", input)
+			}
+			other => panic!("{:?} for {}", other, input),
+		}
+	}
+}
+
+#[test]
+fn chunks() {
+	let (_t, db) = make_big_database(100);
+
+	let flat: Vec<(String, u64)> = db
+		.get_range(..)
+		.into_iter()
+		.map(|r| (r.key().to_owned(), r.timestamp_nanos()))
+		.collect();
+
+	for n in [1, 7, 1000, flat.len(), flat.len() + 1] {
+		let chunks: Vec<Vec<crate::Record>> = db.get_range(..).chunks(n).into_iter().collect();
+		let (last, full) = chunks.split_last().unwrap();
+		assert!(full.iter().all(|c| c.len() == n));
+		assert!(!last.is_empty() && last.len() <= n);
+
+		let flattened: Vec<(String, u64)> = chunks
+			.iter()
+			.flatten()
+			.map(|r| (r.key().to_owned(), r.timestamp_nanos()))
+			.collect();
+		assert_eq!(flattened, flat);
+	}
+
+	let (_t, db) = make_big_database(1000);
+	let sizes: Vec<usize> = db
+		.get_range(..)
+		.chunks(1000)
+		.into_par_iter()
+		.map(|c| c.len())
+		.collect();
+	assert!(sizes.iter().all(|&s| s > 0 && s <= 1000));
+	assert_eq!(sizes.iter().sum::<usize>(), 491739);
+}
+
+#[test]
+fn collate() {
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		for key in ["host1", "host10", "host100", "host2", "other"] {
+			for s in 1..=2 {
+				tx.add_record(key, ts(s), record(s as u32)).unwrap();
+			}
+		}
+		tx.commit().unwrap();
+	}
+	let db = DatabaseReader::new(t.path()).unwrap();
+
+	// compares the numbers at the end of the keys as numbers
+	let natural = |a: &str, b: &str| {
+		let split = |k: &str| {
+			let digits = k.trim_end_matches(|c: char| c.is_ascii_digit());
+			(digits.to_owned(), k[digits.len()..].parse::<u64>().ok())
+		};
+		split(a).cmp(&split(b))
+	};
+	let collated: Vec<(String, u32)> = db
+		.get_range(..)
+		.collate(natural)
+		.map(|r| (r.key().to_owned(), r.get::<u32>(0)))
+		.collect();
+	let expected: Vec<(String, u32)> = ["host1", "host2", "host10", "host100", "other"]
+		.iter()
+		.flat_map(|k| [(k.to_string(), 1), (k.to_string(), 2)])
+		.collect();
+	assert_eq!(collated, expected);
+
+	// each key is read with the same range of time and filters
+	let collated: Vec<(String, u32)> = db
+		.get_range("host"..)
+		.time_range(ts(2)..)
+		.filter_values(|r| r.key() != "host10")
+		.collate(natural)
+		.map(|r| (r.key().to_owned(), r.get::<u32>(0)))
+		.collect();
+	let expected: Vec<(String, u32)> = ["host1", "host2", "host100", "other"]
+		.iter()
+		.map(|k| (k.to_string(), 2))
+		.collect();
+	assert_eq!(collated, expected);
+
+	// ties stay in byte order
+	let keys: Vec<String> = db
+		.get_range(..)
+		.collate(|_, _| std::cmp::Ordering::Equal)
+		.map(|r| r.key().to_owned())
+		.collect();
+	let ordered: Vec<String> = db
+		.get_range(..)
+		.into_iter()
+		.map(|r| r.key().to_owned())
+		.collect();
+	assert_eq!(keys, ordered);
+}
+
+#[test]
+fn from_readers() {
+	let t = tempfile::TempDir::new().unwrap();
+	let add = |data: &str, name: &str| {
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		add_from_stream_with_fmt(&mut tx, &mut std::io::Cursor::new(data), Some("%F_%T")).unwrap();
+		tx.commit_to(&t.path().join(name)).unwrap();
+	};
+	add(
+		"a\t2010-01-01_00:00:01\tu\t1\n\
+		a\t2010-01-01_00:00:02\tu\t2\n\
+		b\t2010-01-01_00:00:01\ts\tx\n",
+		"main",
+	);
+	add(
+		"a\t2010-01-01_00:00:02\tu\t20\n\
+		c\t2010-01-01_00:00:01\tF\t1.5\n",
+		"tx.0000000000000001",
+	);
+	add("b\t2010-01-01_00:00:01\ts\ty\n", "tx.0000000000000002");
+
+	let dump = |db: &DatabaseReader| {
+		let mut out = vec![];
+		for record in db.get_range(..) {
+			print_record(
+				&record,
+				&mut out,
+				PrintTimestamp::Nanos,
+				PrintRecordFormat::Yes,
+				&choice_string::Selection::All,
+			)
+			.unwrap();
+			out.push(b'\n');
+		}
+		String::from_utf8(out).unwrap()
+	};
+
+	let on_disk = DatabaseReader::new(t.path()).unwrap();
+	let read = |name: &str| std::fs::read(t.path().join(name)).unwrap();
+	let main = read("main");
+	let tx1 = read("tx.0000000000000001");
+	let tx2 = read("tx.0000000000000002");
+
+	// the ids are given out of order
+	let in_memory = DatabaseReader::from_readers(
+		Some(&main[..]),
+		vec![(20, &tx2[..]), (10, &tx1[..]), (30, &[][..])],
+	)
+	.unwrap();
+	assert_eq!(dump(&in_memory), dump(&on_disk));
+	assert_eq!(
+		dump(&in_memory),
+		"a\t1262304001000000000\tu\t1\n\
+		a\t1262304002000000000\tu\t20\n\
+		b\t1262304001000000000\ts\ty\n\
+		c\t1262304001000000000\tF\t1.5\n"
+	);
+	assert_eq!(
+		in_memory.empty_transaction_files(),
+		&[std::path::PathBuf::from("tx.000000000000001e")]
+	);
+
+	assert!(DatabaseReader::from_readers(Some(&main[..]), vec![(0, &tx1[..])]).is_err());
+	assert!(DatabaseReader::from_readers(None, vec![(1, &tx1[..]), (1, &tx2[..])]).is_err());
+
+	// ids like the timestamps in the files' names, with an expiry between the transactions
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.expire_before(1262304002000000000).unwrap();
+		tx.commit_to(&t.path().join("expiry")).unwrap();
+	}
+	let expiry = read("expiry");
+	let id = 0x1800_0000_0000_0000;
+	let in_memory = DatabaseReader::from_readers(
+		Some(&main[..]),
+		vec![
+			(id + 1, &tx1[..]),
+			(id + 2, &expiry[..]),
+			(id + 3, &tx2[..]),
+		],
+	)
+	.unwrap();
+	assert_eq!(
+		dump(&in_memory),
+		"a\t1262304002000000000\tu\t20\n\
+		b\t1262304001000000000\ts\ty\n"
+	);
+}
+
+#[test]
+fn with_duplicates() {
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	for (name, value) in [("tx.0000000000000001", 1u32), ("tx.0000000000000002", 2)] {
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.add_record(
+			"a",
+			"2010-01-01T00:00:01".parse().unwrap(),
+			&[&value as &dyn crate::ToRecord],
+		)
+		.unwrap();
+		tx.add_record(
+			"b",
+			"2010-01-01T00:00:01".parse().unwrap(),
+			&[&value as &dyn crate::ToRecord],
+		)
+		.unwrap();
+		tx.commit_to(&t.path().join(name)).unwrap();
+	}
+
+	let r = DatabaseReader::new(t.path()).unwrap();
+	let values = |mode| -> Vec<(String, u32)> {
+		r.get_range(..)
+			.with_duplicates(mode)
+			.into_iter()
+			.map(|m| (m.key().to_owned(), m.get(0)))
+			.collect()
+	};
+	assert_eq!(
+		values(crate::DuplicateMode::Newest),
+		vec![("a".into(), 2), ("b".into(), 2)]
+	);
+	assert_eq!(
+		values(crate::DuplicateMode::All),
+		vec![
+			("a".into(), 1),
+			("a".into(), 2),
+			("b".into(), 1),
+			("b".into(), 2)
+		]
+	);
+}
+
+#[test]
+fn combine_duplicates() {
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+	let versions: [(u64, f64, &str); 3] = [(3, 20.5, "x"), (4, 18.0, "y"), (5, 22.0, "z")];
+	for (count, temperature, name) in versions {
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.add_record(
+			"a",
+			ts(1),
+			record(count).add(temperature).add(temperature).add(name),
+		)
+		.unwrap();
+		tx.add_record("a", ts(2), record(count)).unwrap();
+		tx.add_record("b", ts(1), record(count)).unwrap();
+		tx.commit().unwrap();
+	}
+	{
+		// a version with another format
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.add_record("b", ts(1), record(1u32)).unwrap();
+		tx.commit().unwrap();
+	}
+
+	let r = DatabaseReader::new(t.path()).unwrap();
+	let records: Vec<crate::Record> = r
+		.get_range(..)
+		.combine_duplicates("+<>")
+		.into_iter()
+		.collect();
+	assert_eq!(records.len(), 3);
+	// the counters are summed, the temperatures are the least and most
+	assert_eq!(records[0].get::<u64>(0), 12);
+	assert_eq!(records[0].get::<f64>(1), 18.0);
+	assert_eq!(records[0].get::<f64>(2), 22.0);
+	assert_eq!(records[0].get::<&str>(3), "z");
+	assert_eq!(records[0].time(), ts(1));
+	assert_eq!(records[1].get::<u64>(0), 12);
+	assert_eq!(records[1].time(), ts(2));
+	// the newest version has another format
+	assert_eq!(records[2].format(), "u");
+	assert_eq!(records[2].get::<u32>(0), 1);
+
+	// the filters see the combined records
+	let large: Vec<u64> = r
+		.get_range(..)
+		.combine_duplicates("+")
+		.filter_column_gt(0, 10.0)
+		.into_par_iter()
+		.map(|m| m.get(0))
+		.collect();
+	assert_eq!(large, [12, 12]);
+
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.delete("a", "a\0", 0, u64::MAX, "%").unwrap();
+		tx.commit().unwrap();
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.add_record("a", ts(2), record(7u64)).unwrap();
+		tx.commit().unwrap();
+	}
+	// the deleted versions aren't combined
+	let r = DatabaseReader::new(t.path()).unwrap();
+	let a: Vec<u64> = r
+		.get("a")
+		.combine_duplicates("+")
+		.into_iter()
+		.map(|m| m.get(0))
+		.collect();
+	assert_eq!(a, [7]);
+}
+
+#[test]
+fn join() {
+	let t = tempfile::TempDir::new().unwrap();
+	let data = "\
+		dense\t1\tu\t1\n\
+		dense\t2\tu\t2\n\
+		dense\t3\tu\t3\n\
+		dense\t4\tu\t4\n\
+		dense\t5\tu\t5\n\
+		sparse\t2\tu\t20\n\
+		sparse\t4\tu\t40\n\
+		sparse\t6\tu\t60\n\
+		";
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		add_from_stream_with_fmt(&mut tx, &mut std::io::Cursor::new(data), None).unwrap();
+		tx.commit_to(&t.path().join("main")).unwrap();
+	}
+	let r = DatabaseReader::new(t.path()).unwrap();
+
+	let join = |a, b, kind| -> Vec<(u64, Option<u32>, Option<u32>)> {
+		r.join(a, b, kind)
+			.map(|(ts, a, b)| (ts, a.map(|a| a.get(0)), b.map(|b| b.get(0))))
+			.collect()
+	};
+	use crate::JoinKind::*;
+
+	assert_eq!(
+		join("dense", "dense", Inner),
+		(1..=5)
+			.map(|i| (i, Some(i as u32), Some(i as u32)))
+			.collect::<Vec<_>>()
+	);
+	assert_eq!(
+		join("dense", "sparse", Inner),
+		vec![(2, Some(2), Some(20)), (4, Some(4), Some(40))]
+	);
+	assert_eq!(
+		join("dense", "sparse", AsOf),
+		vec![
+			(1, Some(1), None),
+			(2, Some(2), Some(20)),
+			(3, Some(3), Some(20)),
+			(4, Some(4), Some(40)),
+			(5, Some(5), Some(40)),
+		]
+	);
+	assert_eq!(
+		join("sparse", "dense", Left),
+		vec![
+			(2, Some(20), Some(2)),
+			(4, Some(40), Some(4)),
+			(6, Some(60), None)
+		]
+	);
+	assert_eq!(
+		join("dense", "sparse", Outer),
+		vec![
+			(1, Some(1), None),
+			(2, Some(2), Some(20)),
+			(3, Some(3), None),
+			(4, Some(4), Some(40)),
+			(5, Some(5), None),
+			(6, None, Some(60)),
+		]
+	);
+	assert_eq!(join("missing", "dense", AsOf), vec![]);
+}
+
+#[test]
+fn memory_budget() {
+	let t = tempfile::TempDir::new().unwrap();
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		let add_small = |prefix: &str, tx: &mut CreateTx| {
+			for k in 0..2000u32 {
+				let key = format!("{}{:04}", prefix, k);
+				for ts in 0..3u64 {
+					let mut row = ts.to_be_bytes().to_vec();
+					row.extend_from_slice(&k.to_be_bytes());
+					tx.add_record_raw(&key, "u", &row).unwrap();
+				}
+			}
+		};
+		add_small("a", &mut tx);
+		for ts in 0..200_000u64 {
+			let mut row = ts.to_be_bytes().to_vec();
+			let s = format!("value {}", ts * 7919 % 100_003);
+			row.push(s.len() as u8);
+			row.extend_from_slice(s.as_bytes());
+			tx.add_record_raw("m", "s", &row).unwrap();
+		}
+		add_small("z", &mut tx);
+		tx.commit_to(&t.path().join("main")).unwrap();
+	}
+	let db = DatabaseReader::new(t.path()).unwrap();
+
+	let budget = 16 * 1024;
+	let summary = |r: crate::DatabaseRecordReader| -> Vec<(String, u64, Vec<u8>)> {
+		r.into_iter()
+			.map(|r| (r.key().to_owned(), r.timestamp_nanos(), r.raw().to_vec()))
+			.collect()
+	};
+	for (from, to) in [("", "zzz"), ("a1500", "m"), ("m", "m"), ("z0003", "zzz")] {
+		let budgeted: Vec<_> = db
+			.get_range(from..=to)
+			.memory_budget(budget)
+			.into_iter()
+			.inspect(|r| assert!(r.data.len() < budget + 64, "{}", r.data.len()))
+			.map(|r| (r.key().to_owned(), r.timestamp_nanos(), r.raw().to_vec()))
+			.collect();
+		assert_eq!(
+			budgeted,
+			summary(db.get_range(from..=to)),
+			"{}..={}",
+			from,
+			to
+		);
+	}
+	assert_eq!(
+		summary(db.get_keys(&["a0005", "m", "z1999"]).memory_budget(budget)),
+		summary(db.get_keys(&["a0005", "m", "z1999"]))
+	);
+
+	let count = db
+		.get_range(..)
+		.memory_budget(budget)
+		.into_par_iter()
+		.inspect(|r| assert!(r.data.len() < budget + 64))
+		.count();
+	assert_eq!(count, 212_000);
+
+	// segments that fit in the budget are decoded as they are without one
+	let budget = 64 * 1024 * 1024;
+	let budgeted: Vec<_> = db
+		.get_range(..)
+		.memory_budget(budget)
+		.into_iter()
+		.inspect(|r| assert!(r.data.capacity() < budget / 2, "{}", r.data.capacity()))
+		.map(|r| (r.key().to_owned(), r.timestamp_nanos(), r.data.len()))
+		.collect();
+	let unbudgeted: Vec<_> = db
+		.get_range(..)
+		.into_iter()
+		.map(|r| (r.key().to_owned(), r.timestamp_nanos(), r.data.len()))
+		.collect();
+	assert_eq!(budgeted, unbudgeted);
+}
+
+#[test]
+fn from_record_errors() {
+	let t = tempfile::TempDir::new().unwrap();
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		add_from_stream_with_fmt(&mut tx, &mut &b"a 1 iIuUfFs 1 2 3 4 5.5 6.5 x\n"[..], None)
+			.unwrap();
+		tx.commit_to(&t.path().join("main")).unwrap();
+	}
+	let db = DatabaseReader::new(t.path()).unwrap();
+	let r = db.get("a").into_iter().next().unwrap();
+
+	macro_rules! error {
+		($t:ty, $col:expr) => {
+			r.get_checked::<$t>($col).unwrap_err().to_string()
+		};
+	}
+	assert_eq!(
+		error!(i32, 1),
+		"cannot decode i32 from 'I': would lose precision"
+	);
+	assert_eq!(
+		error!(u32, 3),
+		"cannot decode u32 from 'U': would lose precision"
+	);
+	assert_eq!(
+		error!(f32, 5),
+		"cannot decode f32 from 'F': would lose precision"
+	);
+	assert_eq!(error!(i32, 2), "cannot decode i32 from 'u'");
+	assert_eq!(error!(i64, 3), "cannot decode i64 from 'U'");
+	assert_eq!(error!(u64, 0), "cannot decode u64 from 'i'");
+	assert_eq!(error!(f64, 6), "cannot decode f64 from 's'");
+	assert_eq!(error!(String, 4), "cannot decode String from 'f'");
+	assert_eq!(error!(&str, 0), "cannot decode &str from 'i'");
+
+	// widening is lossless
+	assert_eq!(r.get::<i64>(0), 1);
+	assert_eq!(r.get::<u64>(2), 3);
+	assert_eq!(r.get::<f64>(4), 5.5);
+}
+
+#[test]
+fn format_once_per_key() {
+	let t = tempfile::TempDir::new().unwrap();
+	let data = "\
+		a 1 u 1\n\
+		a 2 u 2\n\
+		a\\ b 1 Fs 1.5 x\n\
+		a\\ b 2 Fs 2.5 y\n\
+		a\\ b 3 Fs 3.5 z\n\
+		c 1 u 3\n\
+		";
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		add_from_stream_with_fmt(&mut tx, &mut std::io::Cursor::new(data), None).unwrap();
+		tx.commit_to(&t.path().join("main")).unwrap();
+	}
+	let db = DatabaseReader::new(t.path()).unwrap();
+
+	let print = |records: &mut dyn Iterator<Item = crate::Record>| {
+		let header = FormatHeader::default();
+		let mut out = vec![];
+		for record in records {
+			print_record(
+				&record,
+				&mut out,
+				PrintTimestamp::Nanos,
+				PrintRecordFormat::OncePerKey(&header),
+				&choice_string::Selection::All,
+			)
+			.unwrap();
+			out.push(b'\n');
+		}
+		String::from_utf8(out).unwrap()
+	};
+	assert_eq!(
+		print(&mut db.get_range(..).into_iter()),
+		"\
+		# a\tu\n\
+		a\t1\t1\n\
+		a\t2\t2\n\
+		# a\\ b\tFs\n\
+		a\\ b\t1\t1.5 x\n\
+		a\\ b\t2\t2.5 y\n\
+		a\\ b\t3\t3.5 z\n\
+		# c\tu\n\
+		c\t1\t3\n\
+		"
+	);
+	// a new header starts over
+	assert_eq!(
+		print(&mut db.get_range("c"..).into_iter()),
+		"# c\tu\nc\t1\t3\n"
+	);
+}
+
+#[test]
+fn export_import() {
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		crate::generate::generate(
+			&mut tx,
+			&crate::generate::GenerateOptions {
+				keys: 50,
+				points_per_key: 2000,
+				seed: 3,
+				format: "Fsu",
+			},
+		)
+		.unwrap();
+		tx.commit().unwrap();
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		add_from_stream_with_fmt(&mut tx, &mut &b"gen.00 1\nzz 2 s a\\ b\n"[..], None).unwrap();
+		tx.commit().unwrap();
+	}
+	let db = DatabaseReader::new(t.path()).unwrap();
+	let dump = |db: &DatabaseReader| -> Vec<(String, String, Vec<u8>)> {
+		db.get_range(..)
+			.into_iter()
+			.map(|r| (r.key().to_owned(), r.format().to_owned(), r.raw().to_vec()))
+			.collect()
+	};
+
+	let mut exported = vec![];
+	let n = crate::export::export(&db, &mut exported).unwrap();
+	assert_eq!(n, 100_002);
+
+	let imported = tempfile::TempDir::new().unwrap();
+	let dir = imported.path().join("db");
+	assert_eq!(crate::export::import(&mut &exported[..], &dir).unwrap(), n);
+	assert_eq!(dump(&DatabaseReader::new(&dir).unwrap()), dump(&db));
+
+	// it won't import over an existing database
+	assert!(crate::export::import(&mut &exported[..], &dir).is_err());
+
+	for len in [0, 10, 100, exported.len() / 2, exported.len() - 1] {
+		let dir = imported.path().join(format!("truncated{}", len));
+		let e = crate::export::import(&mut &exported[..len], &dir).unwrap_err();
+		assert!(
+			std::error::Error::source(&e)
+				.unwrap()
+				.to_string()
+				.contains("truncated"),
+			"{}",
+			e
+		);
+		assert!(!dir.join("main").exists());
+	}
+
+	let mut damaged = exported.clone();
+	damaged[exported.len() / 2] ^= 1;
+	let dir = imported.path().join("damaged");
+	assert!(crate::export::import(&mut &damaged[..], &dir).is_err());
+	assert!(!dir.join("main").exists());
+}
+
+#[test]
+fn export_import_empty_key() {
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let mut tx = CreateTx::new(t.path()).unwrap();
+	tx.add_record("", ts(1), record(1u32)).unwrap();
+	tx.add_record("", ts(2), record(2u32)).unwrap();
+	tx.add_record("a", ts(1), record(3u32)).unwrap();
+	tx.commit().unwrap();
+	let db = DatabaseReader::new(t.path()).unwrap();
+
+	let mut exported = vec![];
+	assert_eq!(crate::export::export(&db, &mut exported).unwrap(), 3);
+	let imported = tempfile::TempDir::new().unwrap();
+	assert_eq!(
+		crate::export::import(&mut &exported[..], imported.path()).unwrap(),
+		3
+	);
+	let db = DatabaseReader::new(imported.path()).unwrap();
+	let records: Vec<(String, u64, u32)> = db
+		.get_range(..)
+		.into_iter()
+		.map(|r| (r.key().to_owned(), r.timestamp_nanos(), r.value()))
+		.collect();
+	assert_eq!(
+		records,
+		[
+			("".to_string(), 1_000_000_000, 1),
+			("".to_string(), 2_000_000_000, 2),
+			("a".to_string(), 1_000_000_000, 3),
+		]
+	);
+}
+
+#[test]
+fn pack() {
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		crate::generate::generate(
+			&mut tx,
+			&crate::generate::GenerateOptions {
+				keys: 20,
+				points_per_key: 500,
+				seed: 11,
+				format: "Fs",
+			},
+		)
+		.unwrap();
+		tx.commit().unwrap();
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.delete("gen.03", "gen.05", 0, u64::MAX, "%").unwrap();
+		tx.commit().unwrap();
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		add_from_stream_with_fmt(&mut tx, &mut &b"gen.00 1 u 5\nzz 2 s a\\ b\n"[..], None).unwrap();
+		tx.commit().unwrap();
+	}
+	let db = DatabaseReader::new(t.path()).unwrap();
+	let dump = |db: &DatabaseReader| -> Vec<(String, String, Vec<u8>)> {
+		db.get_range(..)
+			.into_iter()
+			.map(|r| (r.key().to_owned(), r.format().to_owned(), r.raw().to_vec()))
+			.collect()
+	};
+
+	let packed = t.path().join("db.sodb");
+	let n = crate::pack::pack(&db, &packed).unwrap();
+	assert_eq!(n, 9002);
+	// the staging directory is gone
+	assert_eq!(
+		std::fs::read_dir(t.path())
+			.unwrap()
+			.filter(|e| e.as_ref().unwrap().file_type().unwrap().is_dir())
+			.count(),
+		0
+	);
+
+	let unpacked = DatabaseReader::open_packed(&packed).unwrap();
+	assert_eq!(unpacked.num_txes(), 1);
+	assert_eq!(dump(&unpacked), dump(&db));
+	assert_eq!(
+		unpacked
+			.get("zz")
+			.into_iter()
+			.next()
+			.unwrap()
+			.get::<String>(0),
+		"a b"
+	);
+
+	// not a packed file
+	assert!(DatabaseReader::open_packed(&t.path().join("main")).is_err());
+
+	// an empty database
+	let empty = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(empty.path().join("main")).unwrap();
+	let packed = empty.path().join("empty.sodb");
+	crate::pack::pack(&DatabaseReader::new(empty.path()).unwrap(), &packed).unwrap();
+	assert!(DatabaseReader::open_packed(&packed)
+		.unwrap()
+		.get_range(..)
+		.into_iter()
+		.next()
+		.is_none());
+}
+
+#[test]
+fn record_frames() {
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		crate::generate::generate(
+			&mut tx,
+			&crate::generate::GenerateOptions {
+				keys: 5,
+				points_per_key: 100,
+				seed: 7,
+				format: "FfsU",
+			},
+		)
+		.unwrap();
+		tx.commit().unwrap();
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		add_from_stream_with_fmt(
+			&mut tx,
+			&mut &b"gen.00 1\ngen.01 2 F 0.1\nzz 2 s a\\ b\n"[..],
+			None,
+		)
+		.unwrap();
+		tx.commit().unwrap();
+	}
+	let db = DatabaseReader::new(t.path()).unwrap();
+	let dump = |db: &DatabaseReader| -> Vec<(String, String, Vec<u8>)> {
+		db.get_range(..)
+			.into_iter()
+			.map(|r| (r.key().to_owned(), r.format().to_owned(), r.raw().to_vec()))
+			.collect()
+	};
+
+	let mut frames = vec![];
+	let n = write_record_frames(db.get_range(..), &mut frames).unwrap();
+	assert_eq!(n, 503);
+
+	let copy = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(copy.path().join("main")).unwrap();
+	{
+		let mut tx = CreateTx::new(copy.path()).unwrap();
+		for frame in read_record_frames(&frames[..]) {
+			let (key, format, data) = frame.unwrap();
+			tx.add_record_raw(&key, &format, &data).unwrap();
+		}
+		tx.commit().unwrap();
+	}
+	assert_eq!(dump(&DatabaseReader::new(copy.path()).unwrap()), dump(&db));
+
+	// a frame that's cut off is an error
+	let mut truncated = read_record_frames(&frames[..frames.len() - 1]);
+	assert!(truncated.by_ref().take(502).all(|f| f.is_ok()));
+	assert_eq!(
+		truncated.next().unwrap().unwrap_err().kind(),
+		std::io::ErrorKind::UnexpectedEof
+	);
+}
+
+#[test]
+fn export_parallel() {
+	let (t, db) = make_big_database(16000);
+
+	let mut serial = vec![];
+	let n = crate::export::export(&db, &mut serial).unwrap();
+
+	let shards_dir = t.path().join("shards");
+	// the shards of an earlier export with more of them
+	std::fs::create_dir_all(&shards_dir).unwrap();
+	std::fs::write(shards_dir.join("shard.99999"), b"stale").unwrap();
+	std::fs::write(shards_dir.join("shard.notes"), b"kept").unwrap();
+	// split even when this machine has few cpus
+	let pool = rayon::ThreadPoolBuilder::new()
+		.num_threads(8)
+		.build()
+		.unwrap();
+	let shards = pool
+		.install(|| crate::export::export_parallel(&db, &shards_dir))
+		.unwrap();
+	assert!(db.get_range(..).split().is_some());
+	assert!(shards.len() > 1, "{} shards", shards.len());
+	let mut in_dir: Vec<_> = std::fs::read_dir(&shards_dir)
+		.unwrap()
+		.map(|e| e.unwrap().path())
+		.filter(|p| p.file_name().unwrap() != "shard.notes")
+		.collect();
+	in_dir.sort();
+	assert_eq!(in_dir, shards);
+
+	// each shard is an export by itself
+	let imported = tempfile::TempDir::new().unwrap();
+	let mut input = std::fs::File::open(&shards[0]).unwrap();
+	crate::export::import(&mut input, imported.path()).unwrap();
+	let first = DatabaseReader::new(imported.path()).unwrap();
+	assert!(first.get_range(..).count() > 0);
+	assert!(first
+		.get_range(..)
+		.into_iter()
+		.zip(db.get_range(..))
+		.all(|(a, b)| a.key() == b.key() && a.raw() == b.raw()));
+
+	let mut inputs: Vec<_> = shards
+		.iter()
+		.map(|p| std::io::BufReader::new(std::fs::File::open(p).unwrap()))
+		.collect();
+	let mut concatenated = vec![];
+	assert_eq!(
+		crate::export::concatenate(&mut inputs, &mut concatenated).unwrap(),
+		n
+	);
+	assert_eq!(concatenated, serial);
+}
+
+#[test]
+fn upsert() {
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.upsert_record("a", ts(1), record(1u32)).unwrap();
+		// a retry, with a changed value
+		tx.upsert_record("a", ts(1), record(2u32)).unwrap();
+		tx.upsert_record("a", ts(2), record(5u32)).unwrap();
+		assert!(matches!(
+			tx.upsert_record("a", ts(1), record(9u32)),
+			Err(crate::WriteFailure::TimeOrderingViolation { .. })
+		));
+		tx.add_record("b", ts(1), record(6u32)).unwrap();
+		tx.commit().unwrap();
+	}
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.upsert_record("a", ts(1), record(3u32)).unwrap();
+		tx.commit().unwrap();
+	}
+
+	let values = |db: &DatabaseReader| -> Vec<(String, u64, u32)> {
+		db.get_range(..)
+			.with_duplicates(crate::DuplicateMode::All)
+			.into_iter()
+			.map(|r| {
+				(
+					r.key().to_owned(),
+					r.timestamp_nanos() / 1_000_000_000,
+					r.get(0),
+				)
+			})
+			.collect()
+	};
+	let db = DatabaseReader::new(t.path()).unwrap();
+	assert_eq!(
+		values(&db),
+		vec![
+			("a".into(), 1, 2),
+			("a".into(), 1, 3),
+			("a".into(), 2, 5),
+			("b".into(), 1, 6)
+		]
+	);
+
+	// a major compaction
+	let mut compacted = CreateTx::new(t.path()).unwrap();
+	for record in db.get_range(..) {
+		compacted.add_record_from(&record).unwrap();
+	}
+	crate::compact::purge_compacted_files(compacted, t.path(), &db, true).unwrap();
+
+	let db = DatabaseReader::new(t.path()).unwrap();
+	assert_eq!(db.num_txes(), 1);
+	assert_eq!(
+		values(&db),
+		vec![("a".into(), 1, 3), ("a".into(), 2, 5), ("b".into(), 1, 6)]
+	);
+}
+
+#[test]
+fn diff() {
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let txes: [&[(&str, &str, u32)]; 3] = [
+		&[
+			("a", "2010-01-01T00:00:01", 1),
+			("b", "2010-01-01T00:00:01", 1),
+		],
+		&[
+			("a", "2010-01-01T00:00:02", 2),
+			("c", "2010-01-01T00:00:01", 3),
+		],
+		&[("b", "2010-01-01T00:00:01", 5)],
+	];
+	for (idx, records) in txes.iter().enumerate() {
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		for (key, ts, value) in records.iter() {
+			tx.add_record(key, ts.parse().unwrap(), record(*value))
+				.unwrap();
+		}
+		tx.commit_to(&t.path().join(format!("tx.{:016x}", idx + 1)))
+			.unwrap();
+	}
+
+	let r = DatabaseReader::new(t.path()).unwrap();
+	let txid = |n: usize| r.txid(&t.path().join(format!("tx.{:016x}", n))).unwrap();
+	let diff = |a, b| -> Vec<String> {
+		r.diff(.., a, b)
+			.map(|d| match d {
+				crate::Diff::Added(r) => format!("+{}@{}={}", r.key(), r.time(), r.get::<u32>(0)),
+				crate::Diff::Removed(r) => {
+					format!("-{}@{}={}", r.key(), r.time(), r.get::<u32>(0))
+				}
+				crate::Diff::Changed { before, after } => format!(
+					"~{}@{}={}>{}",
+					after.key(),
+					after.time(),
+					before.get::<u32>(0),
+					after.get::<u32>(0)
+				),
+			})
+			.collect()
+	};
+
+	assert_eq!(
+		diff(txid(1), txid(2)),
+		vec!["+a@2010-01-01 00:00:02=2", "+c@2010-01-01 00:00:01=3"]
+	);
+	assert_eq!(
+		diff(txid(2), txid(1)),
+		vec!["-a@2010-01-01 00:00:02=2", "-c@2010-01-01 00:00:01=3"]
+	);
+	assert_eq!(diff(txid(2), txid(3)), vec!["~b@2010-01-01 00:00:01=1>5"]);
+	assert!(diff(txid(3), txid(3)).is_empty());
+	assert_eq!(
+		r.get("b")
+			.as_of(txid(2))
+			.into_iter()
+			.next()
+			.unwrap()
+			.get::<u32>(0),
+		1
+	);
+	assert_eq!(diff(0, txid(1)).len(), 2);
+}
+
+#[test]
+fn segment_cache() {
+	let (t, db) = make_big_database(200);
+	let key = db
+		.get_range(..)
+		.into_iter()
+		.nth(5000)
+		.unwrap()
+		.key()
+		.to_owned();
+	let cache = std::sync::Arc::new(crate::SegmentCache::new(64 << 20));
+	let db = db.with_segment_cache(cache.clone());
+
+	let count = db.get_range(..).into_iter().count();
+	let segments = cache.misses();
+	assert!(segments > 1);
+	assert_eq!(cache.hits(), 0);
+	assert!(cache.bytes() > 0);
+
+	assert_eq!(db.get_range(..).into_iter().count(), count);
+	assert_eq!(
+		db.get(&key).into_iter().count(),
+		db.get(&key).into_iter().count()
+	);
+	assert_eq!(cache.misses(), segments);
+	assert!(cache.hits() >= segments + 2);
+
+	// a reopened database shares the cache, but sees the new transaction
+	let mut tx = CreateTx::new(t.path()).unwrap();
+	let ts = db.get(&key).into_iter().next().unwrap().time();
+	tx.add_record(&key, ts, record(7u32)).unwrap();
+	tx.commit().unwrap();
+	let db = DatabaseReader::new(t.path())
+		.unwrap()
+		.with_segment_cache(cache.clone());
+	let first = db.get(&key).into_iter().next().unwrap();
+	assert_eq!(first.get::<u32>(0), 7);
+	assert_eq!(cache.misses(), segments + 1);
+
+	// segments larger than the cache aren't kept
+	let tiny = std::sync::Arc::new(crate::SegmentCache::new(1));
+	let db = db.with_segment_cache(tiny.clone());
+	db.get(&key).into_iter().count();
+	db.get(&key).into_iter().count();
+	assert_eq!(tiny.hits(), 0);
+	assert_eq!(tiny.bytes(), 0);
+}
+
+#[test]
+fn read_ahead() {
+	use crate::ReadAhead;
+	let (_t, db) = make_big_database(3000);
+	let read = |read_ahead| -> Vec<(String, u64)> {
+		db.get_range("aa".."ab")
+			.read_ahead(read_ahead)
+			.into_iter()
+			.map(|r| (r.key().to_owned(), r.timestamp_nanos()))
+			.collect()
+	};
+	let auto = read(ReadAhead::Auto);
+	assert!(!auto.is_empty());
+	assert_eq!(read(ReadAhead::Sequential), auto);
+	assert_eq!(read(ReadAhead::Random), auto);
+	assert_eq!(
+		db.get_range(..)
+			.read_ahead(ReadAhead::Sequential)
+			.into_par_iter()
+			.count(),
+		db.get_range(..).into_iter().count()
+	);
+}
+
+#[test]
+fn decompress_ahead() {
+	let (t, _) = make_big_database(3000);
+	{
+		// a second transaction, and a deletion, to merge with
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.add_record(
+			"aaaab",
+			chrono::DateTime::from_timestamp(5, 0).unwrap().naive_utc(),
+			record(1u32),
+		)
+		.unwrap();
+		tx.commit().unwrap();
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.delete("aaac", "aaad", 0, u64::MAX, "%").unwrap();
+		tx.commit().unwrap();
+	}
+	let db = DatabaseReader::new(t.path()).unwrap();
+	assert!(db.txes[0].2.raw_segments().count() > 3);
+	let read = |reader: crate::DatabaseRecordReader| -> Vec<(String, u64)> {
+		reader
+			.into_iter()
+			.map(|r| (r.key().to_owned(), r.timestamp_nanos()))
+			.collect()
+	};
+
+	let all = read(db.get_range(..));
+	assert_eq!(read(db.get_range(..).decompress_ahead(2)), all);
+	let w = crate::Wildcard::new("aaa%b");
+	assert_eq!(
+		read(db.get_filter(&w).decompress_ahead(1)),
+		read(db.get_filter(&w))
+	);
+	assert_eq!(
+		read(db.get_range("aaab".."aaae").decompress_ahead(4)),
+		read(db.get_range("aaab".."aaae"))
+	);
+
+	// with a cache, the segments are decompressed only once
+	let cache = std::sync::Arc::new(crate::SegmentCache::new(1 << 30));
+	let cached = DatabaseReader::new(t.path())
+		.unwrap()
+		.with_segment_cache(cache.clone());
+	assert_eq!(read(cached.get_range(..).decompress_ahead(2)), all);
+	let misses = cache.misses();
+	assert_eq!(read(cached.get_range(..)), all);
+	assert_eq!(cache.misses(), misses);
+
+	// stopping early stops the threads
+	assert_eq!(
+		db.get_range(..)
+			.decompress_ahead(1)
+			.into_iter()
+			.take(10)
+			.count(),
+		10
+	);
+}
+
+#[test]
+fn record_into_owned() {
+	let (_t, db) = make_big_database(200);
+	let (first, key) = {
+		let mut records = db.get_range(..).into_iter();
+		let first = records.next().unwrap();
+		let key = records.nth(1000).unwrap();
+		assert!(key.data.len() > 1 << 16);
+		let copy = key.clone().into_owned();
+		assert!(!std::sync::Arc::ptr_eq(&copy.data, &key.data));
+		(first, copy)
+	};
+	drop(db);
+
+	assert_eq!(
+		key.data.len(),
+		key.key().len() + key.format().len() + key.raw().len()
+	);
+	assert_eq!(std::sync::Arc::strong_count(&key.data), 1);
+	assert_eq!(key.format(), first.format());
+	assert!(key.key() > first.key());
+	assert_eq!(key.columns().count(), 1);
+
+	// a record that was already detached isn't copied again
+	let data = key.data.clone();
+	let again = key.into_owned();
+	assert!(std::sync::Arc::ptr_eq(&again.data, &data));
+}
+
+#[test]
+fn reopen() {
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+	let add = |key: &str, s: i64| {
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.add_record(key, ts(s), record(s as u32)).unwrap();
+		tx.commit().unwrap();
+	};
+	let keys = |db: &DatabaseReader| -> Vec<String> {
+		db.get_range(..)
+			.into_iter()
+			.map(|r| r.key().to_owned())
+			.collect()
+	};
+
+	add("a", 1);
+	let cache = std::sync::Arc::new(crate::SegmentCache::new(1 << 20));
+	let db = DatabaseReader::new(t.path())
+		.unwrap()
+		.with_segment_cache(cache.clone());
+	let mut reading = db.get_range(..).into_iter();
+
+	add("b", 2);
+	assert_eq!(keys(&db), ["a"]);
+	let reopened = db.reopen().unwrap();
+	assert_eq!(keys(&reopened), ["a", "b"]);
+	assert_eq!(reopened.num_txes(), 2);
+	// the first transaction is shared, and so is the cache
+	assert!(std::sync::Arc::ptr_eq(&db.txes[0].2, &reopened.txes[0].2));
+	assert!(!std::sync::Arc::ptr_eq(&db.txes[0].2, &reopened.txes[1].2));
+	assert!(cache.hits() > 0);
+
+	// the iterator from before still reads the old snapshot
+	assert_eq!(reading.next().unwrap().key(), "a");
+	assert!(reading.next().is_none());
+
+	// after a compaction, the new main is opened
+	crate::compact::compact_latest_only(t.path()).unwrap();
+	add("c", 3);
+	let compacted = reopened.reopen().unwrap();
+	assert_eq!(compacted.num_txes(), 2);
+	assert_eq!(keys(&compacted), ["a", "b", "c"]);
+	assert_eq!(keys(&reopened), ["a", "b"]);
+	assert!(!compacted.txes.iter().any(|(_, _, r)| reopened
+		.txes
+		.iter()
+		.any(|(_, _, o)| std::sync::Arc::ptr_eq(r, o))));
+}
+
+#[test]
+fn any_after() {
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+	let nanos = |s: i64| s as u64 * 1_000_000_000;
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.add_record("a", ts(1), record(1.0f64)).unwrap();
+		// enough to span several segments
+		for s in 1..=200000 {
+			tx.add_record("k", ts(s), record(s as f64)).unwrap();
+		}
+		tx.add_record("z", ts(1), record(1.0f64)).unwrap();
+		tx.commit().unwrap();
+	}
+
+	let cache = std::sync::Arc::new(crate::SegmentCache::new(64 << 20));
+	let db = DatabaseReader::new(t.path())
+		.unwrap()
+		.with_segment_cache(cache.clone());
+	assert!(db.any("k", ts(199999)));
+	assert_eq!(cache.misses(), 1);
+	assert!(!db.any("k", ts(200000)));
+	assert!(db.any("k", ts(0)));
+	assert!(db.any(
+		"k",
+		chrono::NaiveDate::from_ymd_opt(1960, 1, 1)
+			.unwrap()
+			.and_hms_opt(0, 0, 0)
+			.unwrap()
+	));
+	assert!(db.any("a", ts(0)));
+	assert!(!db.any("a", ts(1)));
+	assert!(!db.any("j", ts(0)));
+	assert!(!db.any("kk", ts(0)));
+
+	// the newest records are deleted, so the older segments are needed
+	let mut tx = CreateTx::new(t.path()).unwrap();
+	tx.delete("k", "l", nanos(150000), nanos(200001), "%")
+		.unwrap();
+	tx.commit().unwrap();
+	let db = DatabaseReader::new(t.path()).unwrap();
+	assert!(!db.any("k", ts(199999)));
+	assert!(!db.any("k", ts(150000)));
+	assert!(db.any("k", ts(149998)));
+	assert!(db.any("z", ts(0)));
+
+	let mut tx = CreateTx::new(t.path()).unwrap();
+	tx.add_record("k", ts(300000), record(1.0f64)).unwrap();
+	tx.commit().unwrap();
+	let db = DatabaseReader::new(t.path()).unwrap();
+	assert!(db.any("k", ts(199999)));
+	assert!(!db.any("k", ts(300000)));
+}
+
+#[test]
+fn get_series() {
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.add_record("a", ts(1), record(1u32)).unwrap();
+		for s in 0..100000 {
+			tx.add_record("f", ts(s), record(s as f64 / 2.0)).unwrap();
+		}
+		tx.add_record("mixed", ts(1), record(1.0f64).add(2u32))
+			.unwrap();
+		tx.add_record("z", ts(1), record(-1i64)).unwrap();
+		tx.commit().unwrap();
+
+		// replaces some of the records and adds others
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		for s in (50000..150000).step_by(7) {
+			tx.add_record("f", ts(s), record(-(s as f64))).unwrap();
+		}
+		tx.commit().unwrap();
+
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.delete("f", "g", 10 * 1_000_000_000, 20 * 1_000_000_000, "%")
+			.unwrap();
+		tx.commit().unwrap();
+	}
+	let db = DatabaseReader::new(t.path()).unwrap();
+
+	let expected: Vec<(u64, f64)> = db
+		.get("f")
+		.into_iter()
+		.map(|r| (r.timestamp_nanos(), r.value()))
+		.collect();
+	let actual: Vec<(u64, f64)> = db.get_f64("f").unwrap().map(Result::unwrap).collect();
+	assert_eq!(actual.len(), 100000 + 50000 / 7 + 1 - 10);
+	assert_eq!(actual, expected);
+	assert_eq!(actual[9], (9_000_000_000, 4.5));
+	assert_eq!(actual[10], (20_000_000_000, 10.0));
+
+	assert_eq!(
+		db.get_u32("a")
+			.unwrap()
+			.map(Result::unwrap)
+			.collect::<Vec<_>>(),
+		[(1_000_000_000, 1)]
+	);
+	assert_eq!(
+		db.get_i64("z")
+			.unwrap()
+			.map(Result::unwrap)
+			.collect::<Vec<_>>(),
+		[(1_000_000_000, -1)]
+	);
+	assert_eq!(db.get_f32("nothing").unwrap().count(), 0);
+	assert_eq!(
+		db.get_f64("a").err().unwrap().kind(),
+		std::io::ErrorKind::InvalidData
+	);
+	assert!(db.get_u64("f").is_err());
+	assert!(db.get_f64("mixed").is_err());
+}
+
+#[test]
+fn get_series_format_changes() {
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let mut tx = CreateTx::new(t.path()).unwrap();
+	tx.add_record("k", ts(1), record(1.0f64)).unwrap();
+	tx.add_record("k", ts(2), record(2.0f64)).unwrap();
+	tx.add_record("k", ts(3), record(3u32)).unwrap();
+	tx.add_record("k", ts(4), record(4.0f64)).unwrap();
+	tx.commit().unwrap();
+	let db = DatabaseReader::new(t.path()).unwrap();
+
+	let mut series = db.get_f64("k").unwrap();
+	assert_eq!(series.next().unwrap().unwrap(), (1_000_000_000, 1.0));
+	assert_eq!(series.next().unwrap().unwrap(), (2_000_000_000, 2.0));
+	assert_eq!(
+		series.next().unwrap().unwrap_err().kind(),
+		std::io::ErrorKind::InvalidData
+	);
+	assert!(series.next().is_none());
+}
+
+#[test]
+fn get_filter_except() {
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.add_record("a", ts(1), record(1u32)).unwrap();
+		// enough to span several segments
+		for k in 0..100 {
+			for s in 0..5000 {
+				tx.add_record(&format!("b.{:04}", k), ts(s), record(s as u32))
+					.unwrap();
+			}
+		}
+		tx.add_record("horse1", ts(1), record(1u32)).unwrap();
+		tx.add_record("horse1", ts(2), record(2u32)).unwrap();
+		tx.add_record("horse2", ts(1), record(3u32)).unwrap();
+		tx.add_record("horse23", ts(1), record(4u32)).unwrap();
+		tx.commit().unwrap();
+	}
+	let cache = std::sync::Arc::new(crate::SegmentCache::new(64 << 20));
+	let db = DatabaseReader::new(t.path())
+		.unwrap()
+		.with_segment_cache(cache.clone());
+	assert!(db.txes[0].2.raw_segments().count() > 3);
+
+	let values = |r: crate::DatabaseRecordReader| -> Vec<(String, u32)> {
+		r.into_iter()
+			.map(|r| (r.key().to_owned(), r.get(0)))
+			.collect()
+	};
+	let horses = crate::Wildcard::new("horse%");
+	assert_eq!(
+		values(db.get_filter_except(&horses, &[&crate::Wildcard::new("horse2")])),
+		[("horse1", 1), ("horse1", 2), ("horse23", 4)].map(|(k, v)| (k.to_owned(), v))
+	);
+	assert_eq!(
+		values(db.get_filter_except(&horses, &[&crate::Wildcard::new("horse2%")])).len(),
+		2
+	);
+
+	let all = crate::Wildcard::new("%");
+	assert_eq!(
+		values(db.get_filter_except(
+			&all,
+			&[&crate::Wildcard::new("b.%"), &crate::Wildcard::new("%2%")]
+		)),
+		[("a", 1), ("horse1", 1), ("horse1", 2)].map(|(k, v)| (k.to_owned(), v))
+	);
+	// only the segments with `a` and the horses are decompressed
+	assert!(cache.misses() <= 2, "{}", cache.misses());
+
+	assert_eq!(
+		db.get_filter_except(&all, &[]).into_iter().count(),
+		db.get_filter(&all).into_iter().count()
+	);
+}
+
+#[test]
+fn wildcard_suffix() {
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		for key in ["a.tmp", "aba", "abba", "b.tmp", "b.tmp.x", "b.txt", "tmp"] {
+			tx.add_record(key, ts(1), record(1u32)).unwrap();
+		}
+		tx.commit().unwrap();
+	}
+	let db = DatabaseReader::new(t.path()).unwrap();
+	let keys = |w: &str| -> Vec<String> {
+		let w = crate::Wildcard::new(w);
+		db.get_filter(&w)
+			.into_iter()
+			.map(|r| r.key().to_owned())
+			.collect()
+	};
+
+	assert_eq!(crate::Wildcard::new("%.tmp").suffix(), ".tmp");
+	assert_eq!(crate::Wildcard::new("a%b%c").suffix(), "c");
+	assert_eq!(crate::Wildcard::new("a%").suffix(), "");
+	assert_eq!(crate::Wildcard::new("abc").suffix(), "abc");
+
+	assert_eq!(keys("%.tmp"), ["a.tmp", "b.tmp"]);
+	assert_eq!(keys("b%.tmp"), ["b.tmp"]);
+	assert_eq!(keys("%.t%p"), ["a.tmp", "b.tmp"]);
+	assert_eq!(keys("%tmp%"), ["a.tmp", "b.tmp", "b.tmp.x", "tmp"]);
+	// the prefix and suffix don't overlap
+	assert_eq!(keys("ab%ba"), ["abba"]);
+	assert_eq!(keys("ab%a"), ["aba", "abba"]);
+
+	// a cursor keeps the suffix
+	let w = crate::Wildcard::new("%.tmp");
+	let mut cursor = db.get_filter(&w).cursor();
+	assert_eq!(cursor.next().unwrap().key(), "a.tmp");
+	let token: crate::CursorToken = cursor.token().to_string().parse().unwrap();
+	let rest: Vec<String> = db
+		.resume(&token)
+		.into_iter()
+		.map(|r| r.key().to_owned())
+		.collect();
+	assert_eq!(rest, ["b.tmp"]);
+}
+
+#[test]
+fn format_char() {
+	let t = tempfile::TempDir::new().unwrap();
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		add_from_stream_with_fmt(
+			&mut tx,
+			&mut &b"a 1 Ff 1.5 2.5\nb 1 ss x y\nc 1 uI 1 2\n"[..],
+			None,
+		)
+		.unwrap();
+		tx.commit_to(&t.path().join("main")).unwrap();
+	}
+	let db = DatabaseReader::new(t.path()).unwrap();
+	let r: Vec<_> = db.get_range(..).into_iter().collect();
+
+	assert_eq!(r[0].format_char(0), Some('F'));
+	assert_eq!(r[0].format_char(1), Some('f'));
+	assert_eq!(r[0].format_char(2), None);
+	assert!(r[0].is_numeric(1) && !r[0].is_string(1));
+	assert!(!r[0].is_numeric(2) && !r[0].is_string(2));
+
+	assert_eq!(r[1].format_char(1), Some('s'));
+	assert!(r[1].is_string(0) && !r[1].is_numeric(0));
+
+	assert_eq!(r[2].format_char(0), Some('u'));
+	assert!(r[2].is_numeric(0) && r[2].is_numeric(1));
+}
+
+#[test]
+fn text_options() {
+	use crate::formatted::TextOptions;
+	use crate::WriteFailure;
+
+	let load = |text: &str, options: &TextOptions<'_>| -> Result<Vec<String>, WriteFailure> {
+		let t = tempfile::TempDir::new().unwrap();
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		crate::formatted::add_from_stream_opts(&mut tx, "us", &mut text.as_bytes(), None, options)?;
+		tx.commit_to(&t.path().join("main")).unwrap();
+		let db = DatabaseReader::new(t.path()).unwrap();
+		Ok(db
+			.get_range(..)
+			.into_iter()
+			.map(|r| {
+				format!(
+					"{}|{}|{}|{}",
+					r.key(),
+					r.timestamp_nanos(),
+					r.get::<u32>(0),
+					r.get::<&str>(1)
+				)
+			})
+			.collect())
+	};
+
+	let clean = "a 1 10 x\na 2 20 y\\ z\nb\\ c 1 30 w\n";
+	let messy =
+		"# exported data\n\na 1 10 x   \n  # another comment\n\t\na 2 20 y\\ z\r\n\nb\\ c 1 30 w";
+	let comments = TextOptions {
+		comment_prefix: Some("#"),
+		..Default::default()
+	};
+	let expected = load(clean, &TextOptions::default()).unwrap();
+	assert_eq!(expected.len(), 3);
+	assert_eq!(expected[2], "b c|1|30|w");
+	assert_eq!(load(messy, &comments).unwrap(), expected);
+
+	let err = load(
+		"a 1 10 x\n\na 2 20 y\n",
+		&TextOptions {
+			skip_blank_lines: false,
+			..Default::default()
+		},
+	)
+	.unwrap_err();
+	assert!(
+		matches!(err, WriteFailure::InvalidLine { line: 2, .. }),
+		"{:?}",
+		err
+	);
+
+	// an escaped space at the end of a value
+	let keep = TextOptions {
+		trim_trailing_whitespace: false,
+		..Default::default()
+	};
+	assert_eq!(load("a 1 10 x\\ \n", &keep).unwrap(), vec!["a|1|10|x "]);
+}
+
+#[test]
+fn tx_limit() {
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	for n in 1..=3u64 {
+		let mut tx = CreateTx::new_with_limit(t.path(), 2).unwrap();
+		tx.add_record("a", "2010-01-01T00:00:01".parse().unwrap(), record(n))
+			.unwrap();
+		tx.commit_to(&t.path().join(format!("tx.{:016x}", n)))
+			.unwrap();
+	}
+	// empty transactions aren't counted
+	std::fs::File::create(t.path().join("tx.0000000000000004")).unwrap();
+
+	let err = CreateTx::new_with_limit(t.path(), 2).err().unwrap();
+	assert!(
+		matches!(
+			err,
+			crate::WriteFailure::TooManyTransactions { count: 3, limit: 2 }
+		),
+		"{:?}",
+		err
+	);
+	assert!(CreateTx::new_with_limit(t.path(), 3).is_ok());
+
+	let db = DatabaseReader::new(t.path()).unwrap();
+	let mut compacted = CreateTx::new(t.path()).unwrap();
+	for record in db.get_range(..) {
+		compacted.add_record_from(&record).unwrap();
+	}
+	crate::compact::purge_compacted_files(compacted, t.path(), &db, true).unwrap();
+
+	let mut tx = CreateTx::new_with_limit(t.path(), 2).unwrap();
+	tx.add_record("a", "2010-01-01T00:00:02".parse().unwrap(), record(4u64))
+		.unwrap();
+	tx.commit().unwrap();
+	let values: Vec<u64> = DatabaseReader::new(t.path())
+		.unwrap()
+		.get("a")
+		.into_iter()
+		.map(|r| r.get(0))
+		.collect();
+	assert_eq!(values, vec![3, 4]);
+}
+
+#[cfg(feature = "grpc")]
+#[test]
+fn grpc_round_trip() {
+	use crate::grpc::{value::Kind, ReadRequest, Record, SonnerieClient, SonnerieService, Value};
+
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let rt = tokio::runtime::Runtime::new().unwrap();
+
+	let value = |kind| Value { kind: Some(kind) };
+	let record = |key: &str, timestamp, values| Record {
+		key: key.to_owned(),
+		timestamp,
+		values,
+	};
+	// out of order, and with a replaced record
+	let written = vec![
+		record("b", 20, vec![value(Kind::F64(2.5))]),
+		record(
+			"a",
+			10,
+			vec![value(Kind::String("x y".into())), value(Kind::I32(-1))],
+		),
+		record("b", 10, vec![value(Kind::F64(1.0))]),
+		record("c", 10, vec![value(Kind::U64(7)), value(Kind::F32(0.5))]),
+		record("b", 20, vec![value(Kind::F64(3.5))]),
+	];
+
+	rt.block_on(async {
+		let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let addr = listener.local_addr().unwrap();
+		let incoming =
+			tonic::transport::server::TcpIncoming::from_listener(listener, true, None).unwrap();
+		tokio::spawn(
+			tonic::transport::Server::builder()
+				.add_service(SonnerieService::new(t.path()).into_server())
+				.serve_with_incoming(incoming),
+		);
+		let mut client = SonnerieClient::connect(format!("http://{}", addr))
+			.await
+			.unwrap();
+
+		let response = client
+			.write(futures::stream::iter(written.clone()))
+			.await
+			.unwrap();
+		assert_eq!(response.into_inner().records, 5);
+
+		let reader = client.clone();
+		let read = |request: ReadRequest| {
+			let mut client = reader.clone();
+			async move {
+				let mut stream = client.read(request).await.unwrap().into_inner();
+				let mut records = vec![];
+				while let Some(r) = stream.message().await.unwrap() {
+					records.push(r);
+				}
+				records
+			}
+		};
+
+		let all = read(ReadRequest::default()).await;
+		assert_eq!(
+			all,
+			vec![
+				written[1].clone(),
+				written[2].clone(),
+				written[4].clone(),
+				written[3].clone()
+			]
+		);
+
+		let some = read(ReadRequest {
+			first_key: "b".into(),
+			last_key: Some("b".into()),
+			from_time: Some(15),
+			to_time: None,
+		})
+		.await;
+		assert_eq!(some, vec![written[4].clone()]);
+
+		// an invalid record fails the whole write
+		let invalid = vec![
+			record("d", 10, vec![value(Kind::U32(1))]),
+			record("d", 20, vec![Value { kind: None }]),
+		];
+		let err = client
+			.write(futures::stream::iter(invalid))
+			.await
+			.unwrap_err();
+		assert_eq!(err.code(), tonic::Code::InvalidArgument);
+
+		let err = client
+			.write(futures::stream::iter(vec![record("d", u64::MAX, vec![])]))
+			.await
+			.unwrap_err();
+		assert_eq!(err.code(), tonic::Code::InvalidArgument);
+
+		assert_eq!(read(ReadRequest::default()).await.len(), 4);
+
+		// a damaged segment is an error, not the end of the records
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		let mut rng = 1u64;
+		for k in 0..100 {
+			for s in 0..100 {
+				let value: String = (0..100)
+					.map(|_| {
+						rng = rng.wrapping_mul(6364136223846793005).wrapping_add(1);
+						(b'a' + (rng >> 59) as u8) as char
+					})
+					.collect();
+				tx.add_record(
+					&format!("k{:03}", k),
+					crate::write::nanos_to_datetime(s),
+					crate::record(value.as_str()),
+				)
+				.unwrap();
+			}
+		}
+		tx.commit().unwrap();
+		let path = DatabaseReader::new(t.path()).unwrap().transaction_paths()[1].clone();
+		let mut damaged = std::fs::read(&path).unwrap();
+		// in the last segment, since opening a file reads its first
+		let last = damaged.len() - 1;
+		damaged[last] ^= 0x10;
+		std::fs::remove_file(&path).unwrap();
+		std::fs::write(&path, damaged).unwrap();
+		let mut stream = client
+			.read(ReadRequest::default())
+			.await
+			.unwrap()
+			.into_inner();
+		let err = loop {
+			match stream.message().await {
+				Ok(Some(_)) => {}
+				Ok(None) => panic!("the read ended without an error"),
+				Err(e) => break e,
+			}
+		};
+		assert_eq!(err.code(), tonic::Code::Internal);
+		assert!(err.message().contains("checksum"), "{}", err.message());
+	});
+}
+
+#[cfg(feature = "arrow")]
+#[test]
+fn get_range_arrow() {
+	use arrow_array::cast::AsArray;
+	use arrow_array::types::{Float64Type, TimestampNanosecondType, UInt32Type};
+	use arrow_schema::DataType;
+
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+
+	let mut tx = CreateTx::new(t.path()).unwrap();
+	tx.add_record("a", ts(1), record(1u32).add(1.5f64)).unwrap();
+	tx.add_record("a", ts(2), record(2u32).add(2.5f64)).unwrap();
+	tx.add_record("a", ts(3), record("three")).unwrap();
+	for i in 0..5 {
+		tx.add_record("b", ts(i), record(i as u32).add(0.0f64))
+			.unwrap();
+	}
+	tx.commit().unwrap();
+	let db = DatabaseReader::new(t.path()).unwrap();
+
+	let batches: Vec<_> = db.get_range_arrow(..).collect();
+	let shape: Vec<_> = batches
+		.iter()
+		.map(|b| {
+			let metadata = b.schema().metadata().clone();
+			(
+				metadata["key"].clone(),
+				metadata["format"].clone(),
+				b.num_rows(),
+			)
+		})
+		.collect();
+	assert_eq!(
+		shape,
+		vec![
+			("a".to_string(), "uF".to_string(), 2),
+			("a".to_string(), "s".to_string(), 1),
+			("b".to_string(), "uF".to_string(), 5),
+		]
+	);
+
+	let first = &batches[0];
+	assert_eq!(first.schema().field(2).data_type(), &DataType::UInt32);
+	assert_eq!(first.schema().field(3).data_type(), &DataType::Float64);
+	assert_eq!(first.column(0).as_string::<i32>().value(1), "a");
+	assert_eq!(
+		first
+			.column(1)
+			.as_primitive::<TimestampNanosecondType>()
+			.values(),
+		&[1_000_000_000, 2_000_000_000]
+	);
+	assert_eq!(
+		first.column(2).as_primitive::<UInt32Type>().values(),
+		&[1, 2]
+	);
+	assert_eq!(
+		first.column(3).as_primitive::<Float64Type>().values(),
+		&[1.5, 2.5]
+	);
+	assert_eq!(batches[1].column(2).as_string::<i32>().value(0), "three");
+
+	let rows: Vec<_> = db
+		.get_range_arrow("b"..="b")
+		.max_rows(2)
+		.map(|b| b.num_rows())
+		.collect();
+	assert_eq!(rows, vec![2, 2, 1]);
+}
+
+#[cfg(feature = "parquet")]
+#[test]
+fn export_parquet() {
+	use ::parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+
+	let mut tx = CreateTx::new(t.path()).unwrap();
+	for i in 0..10000 {
+		tx.add_record("a", ts(i), record(i as u32).add(0.5f64))
+			.unwrap();
+	}
+	tx.add_record("b", ts(0), record("hello")).unwrap();
+	tx.add_record("c", ts(0), record(1u32).add(1.5f64)).unwrap();
+	tx.commit().unwrap();
+	let db = DatabaseReader::new(t.path()).unwrap();
+
+	let out = tempfile::TempDir::new().unwrap();
+	let read = |path: &std::path::Path| {
+		let file = std::fs::File::open(path).unwrap();
+		let builder = ParquetRecordBatchReaderBuilder::try_new(file).unwrap();
+		let format = builder.schema().metadata()["format"].clone();
+		let rows: usize = builder
+			.build()
+			.unwrap()
+			.map(|b| b.unwrap().num_rows())
+			.sum();
+		(format, rows)
+	};
+
+	let written = crate::parquet::export_parquet(
+		db.get_range(..).into_arrow(),
+		&out.path().join("all.parquet"),
+	)
+	.unwrap();
+	let formats: Vec<_> = written.iter().map(|(f, _)| f.as_str()).collect();
+	assert_eq!(formats, ["uF", "s"]);
+	assert_eq!(written[0].1, out.path().join("all.1.parquet"));
+	assert_eq!(read(&written[0].1), ("uF".to_string(), 10001));
+	assert_eq!(read(&written[1].1), ("s".to_string(), 1));
+
+	let wildcard = crate::Wildcard::new("a%");
+	let written = crate::parquet::export_parquet(
+		db.get_filter(&wildcard).into_arrow(),
+		&out.path().join("a.parquet"),
+	)
+	.unwrap();
+	assert_eq!(written, [("uF".to_string(), out.path().join("a.parquet"))]);
+	assert_eq!(read(&written[0].1), ("uF".to_string(), 10000));
+
+	let wildcard = crate::Wildcard::new("nothing");
+	let written = crate::parquet::export_parquet(
+		db.get_filter(&wildcard).into_arrow(),
+		&out.path().join("none.parquet"),
+	)
+	.unwrap();
+	assert!(written.is_empty());
+	assert!(!out.path().join("none.parquet").exists());
+}
+
+#[cfg(feature = "prometheus")]
+#[test]
+fn prometheus_round_trip() {
+	use crate::prometheus::*;
+
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+
+	let label = |name: &str, value: &str| Label {
+		name: name.to_owned(),
+		value: value.to_owned(),
+	};
+	let series = |labels: Vec<Label>, samples: &[(i64, f64)]| TimeSeries {
+		labels,
+		samples: samples
+			.iter()
+			.map(|&(timestamp, value)| Sample { value, timestamp })
+			.collect(),
+	};
+
+	let requests = vec![
+		label("method", "GET"),
+		label("__name__", "requests"),
+		label("path", "/a \"b\"\n"),
+		label("empty", ""),
+	];
+	let key = series_key(&requests);
+	assert_eq!(key, r#"requests{method="GET",path="/a \"b\"\n"}"#);
+	let mut labels = labels_from_key(&key).unwrap();
+	labels.sort_by(|a, b| a.name.cmp(&b.name));
+	assert_eq!(
+		labels,
+		vec![
+			label("__name__", "requests"),
+			label("method", "GET"),
+			label("path", "/a \"b\"\n"),
+		]
+	);
+	assert_eq!(series_key(&[label("__name__", "up")]), "up");
+	assert_eq!(labels_from_key("up{a=\"x\""), None);
+
+	let write_request = WriteRequest {
+		timeseries: vec![
+			series(
+				vec![label("__name__", "requests"), label("method", "POST")],
+				&[(2000, 3.0), (1000, 1.0)],
+			),
+			series(requests.clone(), &[(1000, 5.0), (3000, 6.0)]),
+			series(vec![label("__name__", "requests_total")], &[(1000, 9.0)]),
+			series(
+				vec![label("__name__", "up"), label("job", "a")],
+				&[(1000, 1.0)],
+			),
+		],
+	};
+	let body = snap::raw::Encoder::new()
+		.compress_vec(&prost::Message::encode_to_vec(&write_request))
+		.unwrap();
+	let decoded = decode_write_request(&body).unwrap();
+	assert_eq!(write(t.path(), &decoded, 1 << 20).unwrap(), 6);
+
+	let before_epoch = WriteRequest {
+		timeseries: vec![series(vec![label("__name__", "up")], &[(-1, 1.0)])],
+	};
+	assert!(write(t.path(), &before_epoch, 1 << 20).is_err());
+
+	let db = DatabaseReader::new(t.path()).unwrap();
+	let matcher = |match_type: MatchType, name: &str, value: &str| LabelMatcher {
+		r#type: match_type as i32,
+		name: name.to_owned(),
+		value: value.to_owned(),
+	};
+	let query = |matchers: Vec<LabelMatcher>| Query {
+		start_timestamp_ms: 1000,
+		end_timestamp_ms: 2000,
+		matchers,
+	};
+	let read_request = ReadRequest {
+		queries: vec![
+			query(vec![matcher(MatchType::Eq, "__name__", "requests")]),
+			query(vec![
+				matcher(MatchType::Re, "__name__", "req.*"),
+				matcher(MatchType::Neq, "method", "GET"),
+			]),
+			query(vec![matcher(MatchType::Eq, "path", "")]),
+		],
+	};
+	let body = snap::raw::Encoder::new()
+		.compress_vec(&prost::Message::encode_to_vec(&read_request))
+		.unwrap();
+	let response = read(&db, &decode_read_request(&body).unwrap()).unwrap();
+	let response: ReadResponse = prost::Message::decode(
+		&snap::raw::Decoder::new()
+			.decompress_vec(&encode_read_response(&response))
+			.unwrap()[..],
+	)
+	.unwrap();
+
+	let summary: Vec<Vec<(String, Vec<_>)>> = response
+		.results
+		.iter()
+		.map(|r| {
+			r.timeseries
+				.iter()
+				.map(|s| {
+					let samples = s.samples.iter().map(|s| (s.timestamp, s.value));
+					(series_key(&s.labels), samples.collect())
+				})
+				.collect()
+		})
+		.collect();
+	let post = r#"requests{method="POST"}"#.to_string();
+	assert_eq!(
+		summary,
+		vec![
+			vec![
+				(key.clone(), vec![(1000, 5.0)]),
+				(post.clone(), vec![(1000, 1.0), (2000, 3.0)])
+			],
+			vec![
+				("requests_total".to_string(), vec![(1000, 9.0)]),
+				(post.clone(), vec![(1000, 1.0), (2000, 3.0)]),
+			],
+			vec![
+				("requests_total".to_string(), vec![(1000, 9.0)]),
+				(post, vec![(1000, 1.0), (2000, 3.0)]),
+				(r#"up{job="a"}"#.to_string(), vec![(1000, 1.0)]),
+			],
+		]
+	);
+}
+
+#[test]
+fn downsample_cache() {
+	use crate::{Aggregate, Bucket, DownsampleCache};
+
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+	let second = 1_000_000_000u64;
+	let bucket_ns = 300 * second;
+
+	let mut tx = CreateTx::new(t.path()).unwrap();
+	for s in (0..600).step_by(60) {
+		tx.add_record("k", ts(s), record(s as f64)).unwrap();
+	}
+	tx.add_record("k", ts(600), record("not a number")).unwrap();
+	tx.commit().unwrap();
+
+	let db = DatabaseReader::new(t.path()).unwrap();
+	let buckets: Vec<Bucket> = db.downsample("k", bucket_ns, Aggregate::Mean, ..).collect();
+	assert_eq!(
+		buckets,
+		vec![
+			Bucket {
+				start: 0,
+				count: 5,
+				value: 120.0
+			},
+			Bucket {
+				start: bucket_ns,
+				count: 5,
+				value: 420.0
+			},
+		]
+	);
+	let max: Vec<f64> = db
+		.downsample("k", bucket_ns, Aggregate::Max, 60 * second..300 * second)
+		.map(|b| b.value)
+		.collect();
+	assert_eq!(max, vec![240.0]);
+
+	let cache = DownsampleCache::new(100);
+	let sum = |db: &DatabaseReader| -> Vec<f64> {
+		cache
+			.downsample(db, "k", bucket_ns, Aggregate::Sum, ..)
+			.iter()
+			.map(|b| b.value)
+			.collect()
+	};
+	assert_eq!(sum(&db), vec![600.0, 2100.0]);
+	assert_eq!(sum(&db), vec![600.0, 2100.0]);
+	assert_eq!((cache.hits(), cache.misses()), (1, 1));
+
+	// another key doesn't change this one
+	let mut tx = CreateTx::new(t.path()).unwrap();
+	tx.add_record("z", ts(30), record(1.0f64)).unwrap();
+	tx.commit().unwrap();
+	let db = DatabaseReader::new(t.path()).unwrap();
+	assert_eq!(sum(&db), vec![600.0, 2100.0]);
+	assert_eq!((cache.hits(), cache.misses()), (2, 1));
+
+	// a backfill into the first bucket, before the key's latest record
+	let mut tx = CreateTx::new(t.path()).unwrap();
+	tx.add_record("k", ts(30), record(1000.0f64)).unwrap();
+	tx.commit().unwrap();
+	let db = DatabaseReader::new(t.path()).unwrap();
+	assert_eq!(sum(&db), vec![1600.0, 2100.0]);
+	assert_eq!((cache.hits(), cache.misses()), (2, 2));
+
+	// so does a deletion
+	let mut tx = CreateTx::new(t.path()).unwrap();
+	tx.delete("k", "l", 0, 100 * second, "%").unwrap();
+	tx.commit().unwrap();
+	let db = DatabaseReader::new(t.path()).unwrap();
+	assert_eq!(sum(&db), vec![540.0, 2100.0]);
+	assert_eq!((cache.hits(), cache.misses()), (2, 3));
+
+	// the least recently used downsample is dropped
+	let small = DownsampleCache::new(3);
+	small.downsample(&db, "k", bucket_ns, Aggregate::Sum, ..);
+	small.downsample(&db, "k", bucket_ns, Aggregate::Min, ..);
+	assert_eq!(small.buckets(), 2);
+	small.downsample(&db, "k", bucket_ns, Aggregate::Min, ..);
+	small.downsample(&db, "k", bucket_ns, Aggregate::Sum, ..);
+	assert_eq!((small.hits(), small.misses()), (1, 3));
+}
+
+#[test]
+fn split_per_key() {
+	use crate::formatted::{key_file_name, key_from_file_name, PrintRecordFormat, PrintTimestamp};
+	use std::io::Write;
+
+	for key in [
+		"plain",
+		"a/b:c",
+		"100%",
+		".hidden",
+		"..",
+		"x\\y\0z",
+		"ünï/cödé",
+		"",
+	] {
+		assert_eq!(
+			key_from_file_name(&key_file_name(key)).as_deref(),
+			Some(key)
+		);
+		assert!(!key_file_name(key).contains(['/', ':', '\\', '\0']));
+		assert!(!key_file_name(key).starts_with('.'));
+		assert!(!key_file_name(key).is_empty());
+	}
+	assert_eq!(key_file_name("a/b:c%"), "a%2Fb%3Ac%25");
+	assert_eq!(key_file_name(""), "%");
+	assert_eq!(key_from_file_name("%"), Some(String::new()));
+	assert_eq!(key_from_file_name("a%2"), None);
+	assert_eq!(key_from_file_name("a%zz"), None);
+
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let mut tx = CreateTx::new(t.path()).unwrap();
+	for (key, s) in [("sensor/1", 1), ("sensor/1", 2), ("sensor:2", 1)] {
+		tx.add_record(
+			key,
+			chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc(),
+			record(s as u32),
+		)
+		.unwrap();
+	}
+	tx.commit().unwrap();
+
+	let db = DatabaseReader::new(t.path()).unwrap();
+	let out = t.path().join("out");
+	let mut split = crate::formatted::SplitPerKey::new(&out, "txt").unwrap();
+	for r in db.get_range(..) {
+		let w = split.writer(r.key()).unwrap();
+		let line = r.to_line(PrintTimestamp::Nanos, PrintRecordFormat::No);
+		writeln!(w, "{}", line).unwrap();
+	}
+	let paths = split.finish().unwrap();
+	assert_eq!(
+		paths,
+		vec![out.join("sensor%2F1.txt"), out.join("sensor%3A2.txt")]
+	);
+	assert_eq!(
+		std::fs::read_to_string(&paths[0]).unwrap(),
+		"sensor/1\t1000000000\t1\nsensor/1\t2000000000\t2\n"
+	);
+	assert_eq!(
+		std::fs::read_to_string(&paths[1]).unwrap(),
+		"sensor:2\t1000000000\t1\n"
+	);
+}
+
+#[test]
+fn corrupt_column_lengths() {
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let mut tx = CreateTx::new(t.path()).unwrap();
+	let data = |columns: &[u8]| {
+		let mut data = 1_000_000_000u64.to_be_bytes().to_vec();
+		data.extend_from_slice(columns);
+		data
+	};
+	// a string whose length claims 100 bytes, but only 3 follow
+	tx.add_record_raw("a", "s", &data(b"\x64abc")).unwrap();
+	// the same, followed by another column
+	tx.add_record_raw("b", "su", &data(b"\x64abc")).unwrap();
+	// a varint that never ends
+	tx.add_record_raw("c", "s", &data(&[0xff; 12])).unwrap();
+	tx.commit().unwrap();
+
+	let db = DatabaseReader::new(t.path()).unwrap();
+	let records: Vec<crate::Record> = db.get_range(..).into_iter().collect();
+	assert_eq!(records.len(), 3);
+
+	let err = records[0].get_checked::<&str>(0).unwrap_err();
+	assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+	assert_eq!(
+		err.to_string(),
+		"the length at byte 8 is 100, but only 3 bytes follow it"
+	);
+	assert!(records[0].get_checked::<String>(0).is_err());
+
+	let err = records[1].get_checked::<u32>(1).unwrap_err();
+	assert_eq!(
+		err.to_string(),
+		"the length at byte 8 is 100, but only 3 bytes follow it"
+	);
+
+	assert!(records[2].get_checked::<&str>(0).is_err());
+
+	// printing fails instead of panicking too
+	for r in &records {
+		let printed = crate::formatted::print_record(
+			r,
+			&mut vec![],
+			crate::formatted::PrintTimestamp::Nanos,
+			crate::formatted::PrintRecordFormat::No,
+			&choice_string::Selection::All,
+		);
+		assert_eq!(printed.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+	}
+}
+
+#[test]
+fn record_columns() {
+	use crate::ColumnValue;
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+
+	let mut tx = CreateTx::new(t.path()).unwrap();
+	tx.add_record(
+		"a",
+		ts(1),
+		record(3u8)
+			.add("three")
+			.add(-3i64)
+			.add(&b"\x03"[..])
+			.add(3.5f64),
+	)
+	.unwrap();
+	tx.add_record("b", ts(1), record(1u32)).unwrap();
+	let mut data = 1_000_000_000u64.to_be_bytes().to_vec();
+	// a u32, then a string whose length claims 100 bytes, but only 3 follow
+	data.extend_from_slice(b"\x00\x00\x00\x07\x64abc");
+	tx.add_record_raw("c", "usu", &data).unwrap();
+	tx.commit().unwrap();
+
+	let db = DatabaseReader::new(t.path()).unwrap();
+	let records: Vec<crate::Record> = db.get_range(..).into_iter().collect();
+	let columns: Vec<ColumnValue> = records[0].columns().collect();
+	assert_eq!(
+		columns,
+		[
+			ColumnValue::U8(3),
+			ColumnValue::Str("three"),
+			ColumnValue::I64(-3),
+			ColumnValue::Bytes(b"\x03"),
+			ColumnValue::F64(3.5),
+		]
+	);
+	let format: String = columns.iter().map(|c| c.format_char()).collect();
+	assert_eq!(format, records[0].format());
+	assert_eq!(records[0].get::<ColumnValue>(2), ColumnValue::I64(-3));
+	assert_eq!(records[1].columns().count(), 1);
+
+	// the error is the last item
+	let columns: Vec<_> = records[2].columns_checked().collect();
+	assert_eq!(columns.len(), 2);
+	assert_eq!(*columns[0].as_ref().unwrap(), ColumnValue::U32(7));
+	assert_eq!(
+		columns[1].as_ref().unwrap_err().to_string(),
+		"the length at byte 12 is 100, but only 3 bytes follow it"
+	);
+}
+
+#[test]
+fn small_integer_columns() {
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+	assert_eq!(crate::row_format::row_format_size("cChHu"), Some(10));
+
+	let mut tx = CreateTx::new(t.path()).unwrap();
+	tx.add_record(
+		"a",
+		ts(1),
+		record(-5i8).add(200u8).add(-300i16).add(60000u16).add(7u32),
+	)
+	.unwrap();
+	crate::formatted::add_from_stream_with_fmt(
+		&mut tx,
+		&mut &b"b 1000000000 cChH -128 255 -32768 65535\n"[..],
+		None,
+	)
+	.unwrap();
+	assert!(crate::formatted::add_from_stream_with_fmt(
+		&mut tx,
+		&mut &b"c 1000000000 C 256\n"[..],
+		None
+	)
+	.is_err());
+	tx.commit().unwrap();
+
+	let db = DatabaseReader::new(t.path()).unwrap();
+	let records: Vec<crate::Record> = db.get_range(..).into_iter().collect();
+	assert_eq!(records.len(), 2);
+	let a = &records[0];
+	assert_eq!(a.format(), "cChHu");
+	assert_eq!(a.get::<i8>(0), -5);
+	assert_eq!(a.get::<i64>(0), -5);
+	assert_eq!(a.get::<u8>(1), 200);
+	assert_eq!(a.get::<u32>(1), 200);
+	assert_eq!(a.get::<i16>(2), -300);
+	assert_eq!(a.get::<i32>(2), -300);
+	assert_eq!(a.get::<u16>(3), 60000);
+	assert_eq!(a.get::<u64>(3), 60000);
+	assert_eq!(a.get::<u32>(4), 7);
+	assert!(a.get_checked::<u8>(0).is_err());
+	assert!(a.get_checked::<i8>(2).is_err());
+	assert!(a.is_numeric(1));
+
+	assert_eq!(
+		records[1].to_line(
+			crate::formatted::PrintTimestamp::Nanos,
+			crate::formatted::PrintRecordFormat::Yes
+		),
+		"b\t1000000000\tcChH\t-128 255 -32768 65535"
+	);
+	assert_eq!(
+		format!("{:?}", a),
+		"Record { key=a, t=1970-01-01 00:00:01, -5, 200, -300, 60000, 7 }"
+	);
+}
+
+#[test]
+fn bytes_columns() {
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+
+	let blob: &[u8] = b"\x00\xffhi";
+	let mut tx = CreateTx::new(t.path()).unwrap();
+	tx.add_record("a", ts(1), record(blob).add(7u32)).unwrap();
+	tx.add_record("a", ts(2), record(Vec::<u8>::new()).add(8u32))
+		.unwrap();
+	tx.add_record(
+		"b",
+		ts(1),
+		&crate::RecordVec::new().push_u32(1).push_bytes(b"\x01"),
+	)
+	.unwrap();
+	crate::formatted::add_from_stream_with_fmt(
+		&mut tx,
+		&mut &b"c 1000000000 Bs 0aff x\n"[..],
+		None,
+	)
+	.unwrap();
+	assert!(
+		crate::formatted::add_from_stream_with_fmt(&mut tx, &mut &b"d 1 B abc\n"[..], None)
+			.is_err()
+	);
+	tx.commit().unwrap();
+
+	let db = DatabaseReader::new(t.path()).unwrap();
+	let records: Vec<crate::Record> = db.get_range(..).into_iter().collect();
+	assert_eq!(records[0].format(), "Bu");
+	assert_eq!(records[0].get::<&[u8]>(0), blob);
+	// the column after the bytes is found
+	assert_eq!(records[0].get::<u32>(1), 7);
+	assert_eq!(records[1].get::<Vec<u8>>(0), Vec::<u8>::new());
+	assert_eq!(records[1].get::<u32>(1), 8);
+	assert_eq!(records[2].get::<Vec<u8>>(1), b"\x01");
+	assert_eq!(records[3].get::<&[u8]>(0), b"\x0a\xff");
+	// strings can be read as bytes, but not the other way around
+	assert_eq!(records[3].get::<&[u8]>(1), b"x");
+	assert!(records[3].get_checked::<&str>(0).is_err());
+	assert!(records[0].get_checked::<&[u8]>(1).is_err());
+
+	let mut out = vec![];
+	crate::formatted::print_record(
+		&records[0],
+		&mut out,
+		crate::formatted::PrintTimestamp::Nanos,
+		crate::formatted::PrintRecordFormat::Yes,
+		&choice_string::Selection::All,
+	)
+	.unwrap();
+	assert_eq!(
+		String::from_utf8(out).unwrap(),
+		"a\t1000000000\tBu\t00ff6869 7"
+	);
+}
+
+#[test]
+fn get_range_with_segments() {
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+
+	// enough records for several segments
+	let mut tx = CreateTx::new(t.path()).unwrap();
+	for k in 0..100 {
+		for s in 0..2000 {
+			tx.add_record(&format!("k{:03}", k), ts(s), record(s as u64 * k))
+				.unwrap();
+		}
+	}
+	tx.commit().unwrap();
+	// replace a record in another transaction
+	let mut tx = CreateTx::new(t.path()).unwrap();
+	tx.add_record("k050", ts(7), record(1u64)).unwrap();
+	tx.commit().unwrap();
+
+	let db = DatabaseReader::new(t.path()).unwrap();
+	let with_segments: Vec<_> = db.get_range_with_segments(..).collect();
+	let records: Vec<_> = db.get_range(..).into_iter().collect();
+	assert_eq!(with_segments.len(), records.len());
+	for ((a, _), b) in with_segments.iter().zip(&records) {
+		assert_eq!((a.key(), a.raw()), (b.key(), b.raw()));
+	}
+
+	let (_, main_path, main) = &db.txes[0];
+	let (_, tx_path, _) = &db.txes[1];
+	let raw_segments: Vec<_> = main.raw_segments().collect();
+	assert!(raw_segments.len() > 2, "{}", raw_segments.len());
+
+	// the records of each segment of main are together, and in its range of keys
+	let mut seen = vec![];
+	for (record, segment) in &with_segments {
+		if record.key() == "k050" && record.timestamp_nanos() == 7_000_000_000 {
+			assert_eq!(segment.file, tx_path.as_path());
+			assert_eq!(record.value::<u64>(), 1);
+			continue;
+		}
+		assert_eq!(segment.file, main_path.as_path());
+		if seen.last() != Some(segment) {
+			assert!(!seen.contains(segment), "{:?} is read twice", segment);
+			seen.push(*segment);
+		}
+		let raw = raw_segments
+			.iter()
+			.find(|s| s.offset() == segment.offset)
+			.unwrap();
+		assert_eq!(raw.compressed_len(), segment.compressed_len);
+		assert!(raw.first_key() <= record.key() && record.key() <= raw.last_key());
+	}
+	assert_eq!(seen.len(), raw_segments.len());
+}
+
+#[test]
+fn cursor_pages() {
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+
+	for tx_keys in [["a b", "c"], ["a b", "d"]] {
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		for k in tx_keys {
+			for s in 0..10 {
+				tx.add_record(k, ts(s), record(s as u32)).unwrap();
+			}
+		}
+		tx.commit().unwrap();
+	}
+	let as_vec = |records: &[crate::Record]| -> Vec<(String, u64)> {
+		records
+			.iter()
+			.map(|r| (r.key().to_owned(), r.timestamp_nanos()))
+			.collect()
+	};
+
+	let wildcard = crate::Wildcard::new("%");
+	let db = DatabaseReader::new(t.path()).unwrap();
+	for (full, first) in [
+		(db.get_range(..), db.get_range(..)),
+		(db.get_range("a b"..="c"), db.get_range("a b"..="c")),
+		(db.get_filter(&wildcard), db.get_filter(&wildcard)),
+		(db.get_keys(&["d", "a b"]), db.get_keys(&["d", "a b"])),
+	] {
+		let full: Vec<_> = full.into_iter().collect();
+		let mut cursor = first.cursor();
+		let mut pages: Vec<_> = cursor.by_ref().take(7).collect();
+		let mut token: crate::CursorToken = cursor.token().to_string().parse().unwrap();
+		loop {
+			// a new reader each time, as after a refresh
+			let db = DatabaseReader::new(t.path()).unwrap();
+			let mut cursor = db.resume(&token).cursor();
+			let page: Vec<_> = cursor.by_ref().take(7).collect();
+			if page.is_empty() {
+				break;
+			}
+			pages.extend(page);
+			let next = cursor.token().to_string().parse().unwrap();
+			drop(cursor);
+			token = next;
+		}
+		assert_eq!(as_vec(&pages), as_vec(&full));
+	}
+
+	// after a compaction, the token continues from the same record
+	let mut cursor = db.get_range(..).cursor();
+	let first: Vec<_> = cursor.by_ref().take(15).collect();
+	let token = cursor.token();
+	crate::compact::compact_with_column_merge(t.path(), 1, 0, |v| v[0].to_vec()).unwrap();
+	let db = DatabaseReader::new(t.path()).unwrap();
+	let rest: Vec<_> = db.resume(&token).into_iter().collect();
+	let mut all = as_vec(&first);
+	all.extend(as_vec(&rest));
+	assert_eq!(
+		all,
+		as_vec(&db.get_range(..).into_iter().collect::<Vec<_>>())
+	);
+
+	assert!("2 u - u p -".parse::<crate::CursorToken>().is_err());
+	assert!("1 u t5 u p - -".parse::<crate::CursorToken>().is_err());
+
+	// the records of the key that were read are skipped, also when they
+	// have different lengths and the segment is read a piece at a time
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let mut tx = CreateTx::new(t.path()).unwrap();
+	for s in 0..20000 {
+		let value = format!("{}:{}", s, "x".repeat(s as usize % 200));
+		tx.add_record("e", ts(s), record(value)).unwrap();
+	}
+	tx.add_record("f", ts(0), record("f")).unwrap();
+	tx.commit().unwrap();
+	let db = DatabaseReader::new(t.path()).unwrap();
+	let mut cursor = db.get_range(..).cursor();
+	assert_eq!(cursor.by_ref().nth(12345).unwrap().time(), ts(12345));
+	let token = cursor.token();
+	for budget in [None, Some(1000)] {
+		let mut reader = db.resume(&token);
+		if let Some(budget) = budget {
+			reader = reader.memory_budget(budget);
+		}
+		let rest: Vec<_> = reader.into_iter().collect();
+		assert_eq!(rest.len(), 20000 - 12346 + 1);
+		assert!(rest[0].get::<&str>(0).starts_with("12346:"));
+		assert_eq!(rest.last().unwrap().key(), "f");
+	}
+}
+
+#[test]
+fn declared_formats() {
+	use crate::declared_formats::*;
+
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		assert!(declare(&mut tx, ts(0), "a", "ux").is_err());
+		assert!(declare(&mut tx, ts(0), "a", "").is_err());
+		declare(&mut tx, ts(0), "a", "uu").unwrap();
+		declare(&mut tx, ts(0), "b", "F").unwrap();
+		tx.commit().unwrap();
+	}
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		undeclare(&mut tx, ts(1), "b").unwrap();
+		tx.commit().unwrap();
+	}
+	let db = DatabaseReader::new(t.path()).unwrap();
+	assert_eq!(
+		declared(&db).into_iter().collect::<Vec<_>>(),
+		vec![("a".to_owned(), "uu".to_owned())]
+	);
+
+	let mut tx = CreateTx::new(t.path()).unwrap();
+	tx.enforce_formats().unwrap();
+	tx.add_record("a", ts(1), record(1u32).add(2u32)).unwrap();
+	let e = tx.add_record("a", ts(2), record(1u32)).unwrap_err();
+	assert_eq!(
+		e.to_string(),
+		"the key `a` is declared with the format `uu`, but the record has the format `u`"
+	);
+	let e = tx.add_record_raw("a", "uuu", &[0; 20]).unwrap_err();
+	assert!(
+		matches!(&e, crate::WriteFailure::FormatMismatch { expected, actual, .. } if expected == "uu" && actual == "uuu"),
+		"{}",
+		e
+	);
+	tx.add_record("a", ts(3), record(3u32).add(4u32)).unwrap();
+	// keys without a declaration can have any format
+	tx.add_record("b", ts(1), record(1u32)).unwrap();
+	tx.add_record("b", ts(2), record("x")).unwrap();
+	tx.commit().unwrap();
+
+	let db = DatabaseReader::new(t.path()).unwrap();
+	let formats: Vec<String> = db
+		.get("a")
+		.into_iter()
+		.map(|r| r.format().to_owned())
+		.collect();
+	assert_eq!(formats, ["uu", "uu"]);
+}
+
+#[test]
+fn create_tx_counts() {
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+
+	let mut tx = CreateTx::new(t.path()).unwrap();
+	assert_eq!(tx.record_count(), 0);
+	assert_eq!(tx.uncompressed_bytes(), 0);
+	assert_eq!(tx.compressed_bytes(), 0);
+	// enough for several segments
+	for key in ["a", "b", "c"] {
+		for s in 0..100_000 {
+			tx.add_record(key, ts(s), record(s as u64)).unwrap();
+		}
+	}
+	assert_eq!(tx.record_count(), 300_000);
+	assert_eq!(tx.uncompressed_bytes(), 3 * (12 + 1 + 1) + 300_000 * 16);
+	// the last segment isn't written until the commit
+	let compressed = tx.compressed_bytes();
+	assert!(compressed > 0);
+	assert!(compressed < tx.uncompressed_bytes());
+	tx.commit().unwrap();
+	assert!(std::fs::metadata(t.path().join("main")).unwrap().len() > compressed);
+}
+
+#[test]
+fn add_record_many() {
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+	let nanos = |s: u64| s * 1_000_000_000;
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.add_record("a", ts(1), record(1u32)).unwrap();
+		tx.add_record_many("a", (2..=4).map(|s| (nanos(s), record(s as u32))))
+			.unwrap();
+		let rows: &[(u64, &str)] = &[(1, "x"), (2, "y")];
+		tx.add_record_many("b", rows.iter().map(|&(s, v)| (nanos(s), record(v))))
+			.unwrap();
+
+		match tx.add_record_many("c", [(nanos(5), record(5u32)), (nanos(5), record(6u32))]) {
+			Err(crate::WriteFailure::TimeOrderingViolation { first, second, key }) => {
+				assert_eq!((first, second, key.as_str()), (ts(5), ts(5), "c"));
+			}
+			other => panic!("unexpected {:?}", other),
+		}
+		tx.commit().unwrap();
+	}
+	let r = DatabaseReader::new(t.path()).unwrap();
+	let got: Vec<_> = r
+		.get_range(..)
+		.into_iter()
+		.map(|m| (m.key().to_owned(), m.time(), m.format().to_owned()))
+		.collect();
+	assert_eq!(
+		got,
+		vec![
+			("a".to_owned(), ts(1), "u".to_owned()),
+			("a".to_owned(), ts(2), "u".to_owned()),
+			("a".to_owned(), ts(3), "u".to_owned()),
+			("a".to_owned(), ts(4), "u".to_owned()),
+			("b".to_owned(), ts(1), "s".to_owned()),
+			("b".to_owned(), ts(2), "s".to_owned()),
+			("c".to_owned(), ts(5), "u".to_owned()),
+		]
+	);
+}
+
+#[test]
+fn zstd_compression() {
+	use crate::Compression;
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+	let keys: Vec<String> = (0..10).map(|k| format!("a{}", k)).collect();
+	let expected = || {
+		keys.iter()
+			.flat_map(|k| (0..20_000).map(move |s| (k.clone(), s)))
+	};
+
+	// enough for several segments, in a transaction with zstd and one with LZ4
+	let mut tx = CreateTx::new_with_compression(t.path(), Compression::Zstd).unwrap();
+	for (key, s) in expected() {
+		tx.add_record(&key, ts(s as i64), record(s)).unwrap();
+	}
+	tx.commit().unwrap();
+	let mut tx = CreateTx::new(t.path()).unwrap();
+	tx.add_record("b", ts(1), record(1u64)).unwrap();
+	tx.commit().unwrap();
+
+	let reader = Reader::new(std::fs::File::open(t.path().join("main")).unwrap())
+		.unwrap()
+		.left()
+		.unwrap();
+	let segments: Vec<_> = reader.raw_segments().collect();
+	assert!(segments.len() > 1);
+	assert!(segments
+		.iter()
+		.all(|s| s.version() == crate::SEGMENT_VERSION && s.compression() == Compression::Zstd));
+
+	let read = |db: &DatabaseReader, range: std::ops::RangeTo<&str>| -> Vec<(String, u64)> {
+		db.get_range(range)
+			.into_iter()
+			.map(|r| (r.key().to_owned(), r.get::<u64>(0)))
+			.collect()
+	};
+	let db = DatabaseReader::new(t.path()).unwrap();
+	assert_eq!(read(&db, .."b"), expected().collect::<Vec<_>>());
+	assert_eq!(db.get("b").into_iter().count(), 1);
+
+	// copying segments keeps their compression
+	let copy = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(copy.path().join("main")).unwrap();
+	let mut tx = CreateTx::new(copy.path()).unwrap();
+	assert_eq!(tx.copy_from(reader.raw_segments()).unwrap(), segments.len());
+	tx.commit().unwrap();
+	let db = DatabaseReader::new(copy.path()).unwrap();
+	assert_eq!(read(&db, .."b"), expected().collect::<Vec<_>>());
+}
+
+#[test]
+fn compression_levels() {
+	use crate::Compression;
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+	let main_size = |compression, level| {
+		let t = tempfile::TempDir::new().unwrap();
+		std::fs::File::create(t.path().join("main")).unwrap();
+		let mut tx = CreateTx::new_with_compression_level(t.path(), compression, level).unwrap();
+		for k in 0..10 {
+			for s in 0..20_000 {
+				let reading = format!("reading {} of sensor {}", s % 100, k);
+				tx.add_record(
+					&format!("k{}", k),
+					ts(s),
+					record(reading).add((s % 7) as u32),
+				)
+				.unwrap();
+			}
+		}
+		tx.commit().unwrap();
+		std::fs::metadata(t.path().join("main")).unwrap().len()
+	};
+
+	assert!(main_size(Compression::Lz4, 12) < main_size(Compression::Lz4, 0));
+	assert!(main_size(Compression::Zstd, 19) < main_size(Compression::Zstd, 1));
+
+	let t = tempfile::TempDir::new().unwrap();
+	for (compression, level) in [(Compression::Lz4, 13), (Compression::Zstd, 0)] {
+		let e = CreateTx::new_with_compression_level(t.path(), compression, level)
+			.err()
+			.unwrap();
+		assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+	}
+}
+
+#[test]
+fn latest() {
+	use rayon::prelude::*;
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		for s in 1..=3 {
+			tx.add_record("gone", ts(s), record(s as u32)).unwrap();
+		}
+		for s in 1..=5 {
+			tx.add_record("many", ts(s), record(s as u32)).unwrap();
+		}
+		tx.add_record("one", ts(4), record(40u32)).unwrap();
+		tx.add_record("partly", ts(1), record(1u32)).unwrap();
+		tx.add_record("partly", ts(5), record(5u32)).unwrap();
+		tx.commit().unwrap();
+	}
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.add_record("many", ts(6), record(6u32)).unwrap();
+		tx.commit().unwrap();
+	}
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.delete("gone", "gonf", 0, u64::MAX, "%").unwrap();
+		tx.commit().unwrap();
+	}
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.delete("partly", "partlz", 5_000_000_000, 6_000_000_000, "%")
+			.unwrap();
+		tx.commit().unwrap();
+	}
+
+	let db = DatabaseReader::new(t.path()).unwrap();
+	let read = |records: crate::DatabaseRecordReader| -> Vec<(String, u32)> {
+		records
+			.into_iter()
+			.map(|r| (r.key().to_owned(), r.value()))
+			.collect()
+	};
+	let pairs = |v: &[(&str, u32)]| -> Vec<(String, u32)> {
+		v.iter().map(|(k, v)| (k.to_string(), *v)).collect()
+	};
+
+	let all = crate::Wildcard::new("%");
+	assert_eq!(
+		read(db.latest(&all)),
+		pairs(&[("many", 6), ("one", 40), ("partly", 1)])
+	);
+	assert_eq!(
+		read(db.get_range(..).time_range(..ts(5)).latest()),
+		pairs(&[("many", 4), ("one", 40), ("partly", 1)])
+	);
+	assert_eq!(
+		read(db.get_keys(&["gone", "one"]).latest()),
+		pairs(&[("one", 40)])
+	);
+	assert_eq!(db.get_range(..).latest().into_par_iter().count(), 3);
+
+	#[cfg(feature = "by-key")]
+	assert_eq!(
+		db.get_filter_keys(&all)
+			.into_iter()
+			.map(|k| {
+				let r = k.latest().unwrap();
+				(r.key().to_owned(), r.value())
+			})
+			.collect::<Vec<_>>(),
+		pairs(&[("many", 6), ("one", 40), ("partly", 1)])
+	);
+}
+
+#[test]
+fn get_reverse() {
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		// enough of other keys for several segments
+		for s in 0..100_000 {
+			tx.add_record("j", ts(s), record(s as u32)).unwrap();
+		}
+		for s in 1..=5 {
+			tx.add_record("k", ts(s), record(s as u32)).unwrap();
+		}
+		for s in 0..100_000 {
+			tx.add_record("l", ts(s), record(s as u32)).unwrap();
+		}
+		tx.commit().unwrap();
+	}
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.add_record("k", ts(3), record(30u32)).unwrap();
+		tx.add_record("k", ts(7), record(7u32)).unwrap();
+		tx.commit().unwrap();
+	}
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.delete("k", "l", 4_000_000_000, 5_000_000_000, "%")
+			.unwrap();
+		tx.commit().unwrap();
+	}
+
+	let db = DatabaseReader::new(t.path()).unwrap();
+	let reverse: Vec<(chrono::NaiveDateTime, u32)> =
+		db.get_reverse("k").map(|r| (r.time(), r.value())).collect();
+	assert_eq!(
+		reverse,
+		vec![(ts(7), 7), (ts(5), 5), (ts(3), 30), (ts(2), 2), (ts(1), 1)]
+	);
+	let mut forward: Vec<_> = db
+		.get("k")
+		.into_iter()
+		.map(|r| (r.time(), r.value()))
+		.collect();
+	forward.reverse();
+	assert_eq!(reverse, forward);
+
+	let last: Vec<u32> = db.get_reverse("l").take(3).map(|r| r.value()).collect();
+	assert_eq!(last, vec![99_999, 99_998, 99_997]);
+	assert_eq!(db.get_reverse("m").count(), 0);
+}
+
+#[test]
+fn get_at_and_asof() {
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+	// the key is in a segment of each transaction, with a gap between them
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		for s in 1..=3 {
+			tx.add_record("k", ts(s), record(s as u32)).unwrap();
+		}
+		tx.commit().unwrap();
+	}
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.add_record("j", ts(1), record(0u32)).unwrap();
+		for s in 10..=12 {
+			tx.add_record("k", ts(s), record(s as u32)).unwrap();
+		}
+		tx.commit().unwrap();
+	}
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.add_record("k", ts(2), record(20u32)).unwrap();
+		tx.commit().unwrap();
+	}
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.delete("k", "l", 11_000_000_000, 12_000_000_000, "%")
+			.unwrap();
+		tx.commit().unwrap();
+	}
+
+	let db = DatabaseReader::new(t.path()).unwrap();
+	let asof = |s| {
+		db.get_asof("k", ts(s))
+			.map(|r| (r.time(), r.value::<u32>()))
+	};
+	assert_eq!(asof(0), None);
+	assert_eq!(asof(2), Some((ts(2), 20)));
+	assert_eq!(asof(5), Some((ts(3), 3)));
+	assert_eq!(asof(10), Some((ts(10), 10)));
+	assert_eq!(asof(11), Some((ts(10), 10)));
+	assert_eq!(asof(100), Some((ts(12), 12)));
+	assert!(db.get_asof("m", ts(100)).is_none());
+
+	let at = |s| db.get_at("k", ts(s)).map(|r| r.value::<u32>());
+	assert_eq!(at(2), Some(20));
+	assert_eq!(at(3), Some(3));
+	assert_eq!(at(5), None);
+	assert_eq!(at(11), None);
+	assert_eq!(at(12), Some(12));
+}
+
+#[test]
+fn delete_regex() {
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+	let keys = [
+		"sensor-1-hum",
+		"sensor-1-temp",
+		"sensor-1-temp2",
+		"sensor-12-temp",
+		"sensor-x-temp",
+	];
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		for key in keys {
+			tx.add_record(key, ts(1), record(1u32)).unwrap();
+		}
+		tx.commit().unwrap();
+	}
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		assert!(tx.delete_regex("", "", 0, u64::MAX, "sensor-(").is_err());
+		tx.delete_regex("", "", 0, u64::MAX, r"^sensor-\d+-temp$")
+			.unwrap();
+		tx.commit().unwrap();
+	}
+
+	let db = DatabaseReader::new(t.path()).unwrap();
+	assert_eq!(db.filter_out.len(), 1);
+	assert!(db.filter_out[0].2.regex);
+	assert_eq!(db.filter_out[0].2.wildcard, r"^sensor-\d+-temp$");
+	let remaining: Vec<_> = db
+		.get_range(..)
+		.into_iter()
+		.map(|r| r.key().to_owned())
+		.collect();
+	assert_eq!(
+		remaining,
+		["sensor-1-hum", "sensor-1-temp2", "sensor-x-temp"]
+	);
+
+	// "%" isn't a wildcard in a regex
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.delete_regex("", "", 0, u64::MAX, "%").unwrap();
+		tx.commit().unwrap();
+	}
+	let db = DatabaseReader::new(t.path()).unwrap();
+	assert_eq!(db.get_range(..).into_iter().count(), 3);
+}
+
+#[test]
+fn delete_keys() {
+	let (t, db) = make_big_database(3000);
+	let all: Vec<String> = db
+		.get_filter_keys(&crate::Wildcard::new("%"))
+		.into_iter()
+		.map(|r| r.key().to_owned())
+		.collect();
+	let before: std::collections::HashMap<String, usize> = all
+		.iter()
+		.map(|k| (k.clone(), db.get(k).into_iter().count()))
+		.collect();
+	let deleted: Vec<&str> = all
+		.iter()
+		.step_by(2)
+		.take(1000)
+		.map(|k| k.as_str())
+		.collect();
+	assert_eq!(deleted.len(), 1000);
+
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		assert!(tx.delete_keys(&[], 0, u64::MAX).is_err());
+		assert!(tx.delete_keys(&["a\nb"], 0, u64::MAX).is_err());
+		tx.delete_keys(&deleted, 0, 500).unwrap();
+		tx.commit().unwrap();
+	}
+
+	let db = DatabaseReader::new(t.path()).unwrap();
+	assert_eq!(db.filter_out.len(), 1);
+	assert!(db.filter_out[0].2.keys);
+	assert_eq!(db.filter_out[0].2.first_key, deleted[0]);
+	let deleted: std::collections::HashSet<&str> = deleted.into_iter().collect();
+
+	let mut after = std::collections::HashMap::new();
+	for r in db.get_range(..).into_iter() {
+		if deleted.contains(r.key()) {
+			assert!(r.timestamp_nanos() >= 500);
+		}
+		*after.entry(r.key().to_owned()).or_insert(0) += 1;
+	}
+	for (key, count) in before {
+		let remaining = after.get(&key).copied().unwrap_or(0);
+		if deleted.contains(key.as_str()) {
+			assert_eq!(remaining, count.saturating_sub(500));
+		} else {
+			assert_eq!(remaining, count);
+		}
+	}
+
+	// a kind of filter from a later version isn't taken for a wildcard
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.add_delete_marker("a", "", 0, 500, "a", Some(3)).unwrap();
+		tx.commit().unwrap();
+	}
+	let e = DatabaseReader::new(t.path()).err().unwrap();
+	assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+	assert!(e.to_string().contains("unknown kind"), "{}", e);
+}
+
+#[test]
+fn delete_markers() {
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		for key in ["a", "b", "c"] {
+			tx.add_record(key, ts(1), record(1u32)).unwrap();
+		}
+		tx.commit().unwrap();
+	}
+	assert_eq!(
+		DatabaseReader::new(t.path())
+			.unwrap()
+			.delete_markers()
+			.count(),
+		0
+	);
+
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.delete("a", "c", 0, 5_000_000_000, "%").unwrap();
+		tx.commit().unwrap();
+	}
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.delete_keys(&["c"], 0, u64::MAX).unwrap();
+		tx.commit().unwrap();
+	}
+
+	let db = DatabaseReader::new(t.path()).unwrap();
+	let markers: Vec<_> = db.delete_markers().collect();
+	assert_eq!(markers.len(), 2);
+	let paths: Vec<&std::path::Path> = db.delete_txes_paths().collect();
+	assert_eq!(markers.iter().map(|(p, _)| *p).collect::<Vec<_>>(), paths);
+
+	let (_, range) = markers[0];
+	assert_eq!(
+		(range.first_key.as_str(), range.last_key.as_str()),
+		("a", "c")
+	);
+	assert_eq!(
+		(range.first_timestamp, range.last_timestamp),
+		(ts(0), ts(5))
+	);
+	assert_eq!(range.wildcard, "%");
+	assert!(!range.keys);
+	let (_, keys) = markers[1];
+	assert!(keys.keys);
+	assert_eq!(keys.wildcard, "c");
+	assert_eq!(db.get_range(..).into_iter().count(), 0);
+}
+
+#[test]
+fn compact_dry_run() {
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+	for s in 1..=3 {
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.add_record("a", ts(s), record(s as u32)).unwrap();
+		tx.add_record("b", ts(s), record(s as u32)).unwrap();
+		tx.commit().unwrap();
+	}
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.delete("b", "c", 0, u64::MAX, "%").unwrap();
+		tx.commit().unwrap();
+	}
+	let files = || {
+		let mut names: Vec<_> = std::fs::read_dir(t.path())
+			.unwrap()
+			.map(|e| e.unwrap().file_name())
+			.collect();
+		names.sort();
+		names
+	};
+	let before = files();
+
+	// the first transaction became main
+	let minor = crate::compact_dry_run(t.path(), false).unwrap();
+	assert_eq!(minor.transactions, 2);
+	assert_eq!(minor.deletions, 1);
+	assert_eq!(minor.records, 2);
+
+	let major = crate::compact_dry_run(t.path(), true).unwrap();
+	assert_eq!(major.transactions, 3);
+	assert_eq!(major.records, 3);
+	assert!(major.input_bytes > minor.input_bytes);
+	assert!(major.estimated_bytes > minor.estimated_bytes);
+	assert_eq!(files(), before);
+
+	// it doesn't wait for a compaction that holds the lock
+	let lock = std::fs::File::open(t.path().join(".compact")).unwrap();
+	fs2::FileExt::lock_exclusive(&lock).unwrap();
+	assert_eq!(crate::compact_dry_run(t.path(), true).unwrap(), major);
+}
+
+#[test]
+fn interrupted_compaction() {
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+	for s in 1..=3 {
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.add_record("a", ts(s), record(s as u32)).unwrap();
+		tx.add_record("b", ts(s), record(s as u32)).unwrap();
+		tx.commit().unwrap();
+	}
+	let files = || {
+		let mut names: Vec<_> = std::fs::read_dir(t.path())
+			.unwrap()
+			.map(|e| e.unwrap().file_name().into_string().unwrap())
+			.collect();
+		names.sort();
+		names
+	};
+	let read = || {
+		let db = DatabaseReader::new(t.path()).unwrap();
+		db.get_range(..)
+			.into_iter()
+			.map(|r| (r.key().to_owned(), r.time(), r.value::<u32>()))
+			.collect::<Vec<_>>()
+	};
+	let before = files();
+	let records = read();
+	assert_eq!(records.len(), 6);
+
+	// interrupted before the commit: the files are kept
+	{
+		let db = DatabaseReader::new(t.path()).unwrap();
+		let main = t.path().join("main");
+		crate::compact::write_journal(t.path(), &main, &db.transaction_paths()[1..]).unwrap();
+	}
+	assert!(crate::compact::finish_interrupted(t.path()).unwrap());
+	assert_eq!(files(), before);
+	assert_eq!(read(), records);
+
+	// interrupted after the commit, before removing the files
+	{
+		let db = DatabaseReader::new(t.path()).unwrap();
+		let mut compacted = CreateTx::new(t.path()).unwrap();
+		for record in db.get_range(..) {
+			compacted
+				.add_record_raw(record.key(), record.format(), record.raw())
+				.unwrap();
+		}
+		crate::compact::commit_compacted(compacted, t.path(), &db, true).unwrap();
+	}
+	assert!(files().contains(&".compact-journal".to_owned()));
+	// the records are in main and the transactions, which is read the same
+	assert_eq!(read(), records);
+
+	assert!(crate::compact::finish_interrupted(t.path()).unwrap());
+	assert!(!crate::compact::finish_interrupted(t.path()).unwrap());
+	assert_eq!(files(), [".compact", "main"]);
+	assert_eq!(read(), records);
+
+	// and it can be compacted again
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.add_record("c", ts(1), record(1u32)).unwrap();
+		tx.commit().unwrap();
+	}
+	crate::compact::compact_latest_only(t.path()).unwrap();
+	assert_eq!(files(), [".compact", "main"]);
+	assert_eq!(read().len(), 7);
+}
+
+#[test]
+fn record_time_checked() {
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	{
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.add_record_raw("a", "u", &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1])
+			.unwrap();
+		tx.add_record_raw(
+			"b",
+			"u",
+			&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0, 0, 0, 2],
+		)
+		.unwrap();
+		tx.commit().unwrap();
+	}
+	let db = DatabaseReader::new(t.path()).unwrap();
+	let records: Vec<_> = db.get_range(..).into_iter().collect();
+	assert_eq!(records[0].time_checked(), Some(records[0].time()));
+	assert_eq!(records[0].time().and_utc().timestamp(), 0);
+	let last = records[1].time_checked().unwrap();
+	assert_eq!(last.and_utc().timestamp_nanos_opt(), None);
+	assert_eq!(
+		last.and_utc().timestamp(),
+		(u64::MAX / 1_000_000_000) as i64
+	);
+
+	let mut out = vec![];
+	crate::formatted::print_record(
+		&records[1],
+		&mut out,
+		crate::formatted::PrintTimestamp::FormatString("%F"),
+		crate::formatted::PrintRecordFormat::No,
+		&choice_string::Selection::All,
+	)
+	.unwrap();
+	assert_eq!(String::from_utf8(out).unwrap(), "b\t2554-07-21\t2");
+	assert!(format!("{:?}", records[1]).starts_with("Record { key=b, t=2554-07-21 "));
+}
+
+#[test]
+fn corrupt_transaction_file() {
+	use byteorder::{BigEndian, WriteBytesExt};
+	use std::io::Write;
+
+	// a version 0 segment with the key `a` and this payload
+	let segment = |payload: &[u8]| {
+		let mut s = crate::segment::SEGMENT_INVOCATION.to_vec();
+		s.write_u16::<BigEndian>(0).unwrap();
+		s.write_u32::<BigEndian>(1).unwrap();
+		s.write_u32::<BigEndian>(1).unwrap();
+		s.write_u32::<BigEndian>(payload.len() as u32).unwrap();
+		s.write_u32::<BigEndian>(0).unwrap();
+		s.extend_from_slice(b"aa");
+		s.extend_from_slice(payload);
+		s
+	};
+	let lz4 = |data: &[u8]| {
+		let mut encoder = lz4::EncoderBuilder::new().build(vec![]).unwrap();
+		encoder.write_all(data).unwrap();
+		let (payload, result) = encoder.finish();
+		result.unwrap();
+		payload
+	};
+
+	// not lz4
+	assert!(Reader::from_bytes(segment(b"garbage")).is_err());
+	// too short to have a key
+	assert!(Reader::from_bytes(segment(&lz4(&[0, 0, 0, 1]))).is_err());
+	// the format extends past the data
+	let mut data = vec![];
+	data.write_u32::<BigEndian>(1).unwrap();
+	data.write_u32::<BigEndian>(100).unwrap();
+	data.write_u32::<BigEndian>(0).unwrap();
+	data.extend_from_slice(b"au");
+	assert!(Reader::from_bytes(segment(&lz4(&data))).is_err());
+	// a deletion that ends after its format
+	let mut data = vec![];
+	data.write_u32::<BigEndian>(1).unwrap();
+	data.write_u32::<BigEndian>(1).unwrap();
+	data.write_u32::<BigEndian>(0).unwrap();
+	data.extend_from_slice(b"a\x7f");
+	assert!(Reader::from_bytes(segment(&lz4(&data))).is_err());
+
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+	for s in 1..=2 {
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.add_record("b", ts(s), record(s as u32)).unwrap();
+		tx.commit().unwrap();
+	}
+	let corrupt = t.path().join("tx.0000000000000001");
+	std::fs::write(&corrupt, segment(b"garbage")).unwrap();
+
+	// the corrupt file is left out
+	let db = DatabaseReader::new(t.path()).unwrap();
+	assert_eq!(
+		db.corrupt_transaction_files(),
+		std::slice::from_ref(&corrupt)
+	);
+	assert_eq!(db.num_txes(), 2);
+	assert_eq!(db.get_range(..).into_iter().count(), 2);
+
+	// and isn't removed by compacting
+	crate::compact::compact_latest_only(t.path()).unwrap();
+	assert!(corrupt.exists());
+	let db = DatabaseReader::new(t.path()).unwrap();
+	assert_eq!(db.num_txes(), 1);
+	assert_eq!(db.get_range(..).into_iter().count(), 2);
+
+	// but a corrupt main is an error
+	std::fs::remove_file(&corrupt).unwrap();
+	std::fs::remove_file(t.path().join("main")).unwrap();
+	std::fs::write(t.path().join("main"), segment(b"garbage")).unwrap();
+	let e = DatabaseReader::new(t.path()).err().unwrap();
+	assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+	assert!(e.to_string().contains("main"));
+}
+
+#[test]
+fn verify() {
+	use byteorder::{BigEndian, WriteBytesExt};
+	use std::io::Write;
+
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+	{
+		// enough incompressible data for a few segments
+		let mut rng = 1u64;
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		for k in 0..300 {
+			for s in 0..100 {
+				let value: String = (0..100)
+					.map(|_| {
+						rng = rng.wrapping_mul(6364136223846793005).wrapping_add(1);
+						(b'a' + (rng >> 59) as u8) as char
+					})
+					.collect();
+				tx.add_record(
+					&format!("k{:03}", k),
+					ts(s),
+					record(s as u32).add(value.as_str()),
+				)
+				.unwrap();
+			}
+		}
+		tx.commit().unwrap();
+	}
+	let bytes = std::fs::read(t.path().join("main")).unwrap();
+	let reader = Reader::from_bytes(bytes.clone()).unwrap().left().unwrap();
+	let offsets: Vec<_> = reader.raw_segments().map(|s| s.offset()).collect();
+	assert!(offsets.len() > 2);
+	assert_eq!(reader.verify().unwrap(), 30000);
+
+	// truncated
+	let truncated = bytes[..bytes.len() - 10].to_vec();
+	let reader = Reader::from_bytes(truncated).unwrap().left().unwrap();
+	let e = reader.verify().unwrap_err();
+	assert!(e.to_string().contains("aren't a segment"), "{}", e);
+
+	// corrupt in the second segment
+	let mut corrupt = bytes.clone();
+	let at = (offsets[1] + offsets[2]) / 2;
+	for b in &mut corrupt[at..at + 16] {
+		*b ^= 0x55;
+	}
+	let reader = Reader::from_bytes(corrupt).unwrap().left().unwrap();
+	let e = reader.verify().unwrap_err();
+	assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+	assert!(
+		e.to_string()
+			.starts_with(&format!("segment at byte {}:", offsets[1])),
+		"{}",
+		e
+	);
+
+	// a version 0 segment with the keys `a` to `b` and this payload
+	let segment = |data: &[u8]| {
+		let mut encoder = lz4::EncoderBuilder::new().build(vec![]).unwrap();
+		encoder.write_all(data).unwrap();
+		let (payload, result) = encoder.finish();
+		result.unwrap();
+		let mut s = crate::segment::SEGMENT_INVOCATION.to_vec();
+		s.write_u16::<BigEndian>(0).unwrap();
+		s.write_u32::<BigEndian>(1).unwrap();
+		s.write_u32::<BigEndian>(1).unwrap();
+		s.write_u32::<BigEndian>(payload.len() as u32).unwrap();
+		s.write_u32::<BigEndian>(0).unwrap();
+		s.extend_from_slice(b"ab");
+		s.extend_from_slice(&payload);
+		Reader::from_bytes(s).unwrap().left().unwrap().verify()
+	};
+	let key = |key: &[u8], format: &[u8], records: &[u8]| {
+		let mut data = vec![];
+		data.write_u32::<BigEndian>(key.len() as u32).unwrap();
+		data.write_u32::<BigEndian>(format.len() as u32).unwrap();
+		data.write_u32::<BigEndian>(0).unwrap();
+		data.write_u32::<BigEndian>(records.len() as u32).unwrap();
+		data.extend_from_slice(key);
+		data.extend_from_slice(format);
+		data.extend_from_slice(records);
+		data
+	};
+	let one = [0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 7];
+	assert_eq!(segment(&key(b"a", b"u", &one)).unwrap(), 1);
+	// a record that's too short for its format
+	assert!(segment(&key(b"a", b"u", &one[..10])).is_err());
+	// a key that isn't in the header's range
+	assert!(segment(&key(b"c", b"u", &one)).is_err());
+	// an invalid format
+	assert!(segment(&key(b"a", b"x", &one)).is_err());
+	// a string column that's longer than its record
+	assert!(segment(&key(
+		b"a",
+		b"s",
+		&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 0, 0, 1, 9]
+	))
+	.is_err());
+	// keys out of order
+	let mut data = key(b"b", b"u", &one);
+	data.extend(key(b"a", b"u", &one));
+	assert!(segment(&data).is_err());
+}
+
+#[test]
+fn segment_checksum() {
+	assert_eq!(crate::crc32::crc32(b"123456789"), 0xcbf4_3926);
+
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+	{
+		let mut rng = 1u64;
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		for k in 0..300 {
+			for s in 0..100 {
+				let value: String = (0..100)
+					.map(|_| {
+						rng = rng.wrapping_mul(6364136223846793005).wrapping_add(1);
+						(b'a' + (rng >> 59) as u8) as char
+					})
+					.collect();
+				tx.add_record(
+					&format!("k{:03}", k),
+					ts(s),
+					record(s as u32).add(value.as_str()),
+				)
+				.unwrap();
+			}
+		}
+		tx.commit().unwrap();
+	}
+	let bytes = std::fs::read(t.path().join("main")).unwrap();
+	let reader = Reader::from_bytes(bytes.clone()).unwrap().left().unwrap();
+	assert_eq!(
+		reader.segment_versions(),
+		Some(crate::SEGMENT_VERSION..=crate::SEGMENT_VERSION)
+	);
+	let offsets: Vec<_> = reader.raw_segments().map(|s| s.offset()).collect();
+	assert!(offsets.len() > 2);
+
+	// flip a bit in the second segment
+	let mut corrupt = bytes.clone();
+	corrupt[(offsets[1] + offsets[2]) / 2] ^= 0x10;
+
+	let reader = Reader::from_bytes(corrupt.clone()).unwrap().left().unwrap();
+	let e = reader.verify().unwrap_err();
+	assert_eq!(
+		e.to_string(),
+		format!("segment at byte {}: wrong checksum", offsets[1])
+	);
+
+	let reader = reader.on_checksum_mismatch(crate::ChecksumMismatch::Skip);
+	let records = reader.verify().unwrap();
+	assert!(records > 0 && records < 30000, "{}", records);
+	assert_eq!(reader.get_range(..).count() as u64, records);
+
+	// a corrupt segment isn't copied
+	let t2 = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t2.path().join("main")).unwrap();
+	let mut tx = CreateTx::new(t2.path()).unwrap();
+	assert!(tx.copy_from(reader.raw_segments()).is_err());
+
+	// and a database can skip it too
+	std::fs::write(t.path().join("main"), &corrupt).unwrap();
+	let db = DatabaseReader::new(t.path()).unwrap();
+	assert!(
+		std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| db.get_range(..).count()))
+			.is_err()
+	);
+	let cache = std::sync::Arc::new(crate::SegmentCache::new(1 << 30));
+	let db = db
+		.with_segment_cache(cache.clone())
+		.on_checksum_mismatch(crate::ChecksumMismatch::Skip);
+	assert_eq!(db.get_range(..).count() as u64, records);
+	let misses = cache.misses();
+	assert_eq!(db.reopen().unwrap().get_range(..).count() as u64, records);
+	// the damaged segment isn't cached
+	assert_eq!(cache.misses(), misses + 1);
+}
+
+#[test]
+fn commit_without_sync() {
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+	let options = crate::CommitOptions {
+		sync_file: false,
+		sync_dir: false,
+	};
+	for i in 0..3 {
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		tx.add_record("a", ts(i), record(i as u32)).unwrap();
+		tx.commit_with(options).unwrap();
+	}
+	// the first one is `main`
+	let txes = std::fs::read_dir(t.path())
+		.unwrap()
+		.filter(|e| {
+			let name = e.as_ref().unwrap().file_name();
+			let name = name.to_str().unwrap();
+			name.starts_with("tx.") && !name.ends_with(".tmp")
+		})
+		.count();
+	assert_eq!(txes, 2);
+
+	let db = DatabaseReader::new(t.path()).unwrap();
+	let values: Vec<u32> = db.get("a").into_iter().map(|r| r.get(0)).collect();
+	assert_eq!(values, [0, 1, 2]);
+}
+
+#[test]
+fn checkpoint() {
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+	let count = || {
+		DatabaseReader::new(t.path())
+			.unwrap()
+			.get_range(..)
+			.into_iter()
+			.count()
+	};
+	let tx_files = || {
+		std::fs::read_dir(t.path())
+			.unwrap()
+			.map(|e| e.unwrap().path())
+			.filter(|p| p.file_name().unwrap().to_str().unwrap().starts_with("tx."))
+			.collect::<Vec<_>>()
+	};
+
+	let mut tx = CreateTx::new(t.path()).unwrap();
+	// nothing to make visible yet
+	tx.checkpoint().unwrap();
+	assert!(tx_files().is_empty());
+
+	tx.add_record("a", ts(0), record(0u32)).unwrap();
+	tx.add_record("a", ts(1), record(1u32)).unwrap();
+	tx.checkpoint().unwrap();
+	assert_eq!(count(), 2);
+	let files = tx_files();
+	assert_eq!(files.len(), 1);
+
+	// the last key can continue after a checkpoint, but not go back in time
+	assert!(tx.add_record("a", ts(1), record(1u32)).is_err());
+	tx.add_record("a", ts(2), record(2u32)).unwrap();
+	tx.add_record("b", ts(0), record(3u32)).unwrap();
+	assert_eq!(count(), 2);
+	tx.checkpoint().unwrap();
+	assert_eq!(count(), 4);
+	assert_eq!(tx_files(), files);
+	assert!(tx.add_record("a", ts(3), record(4u32)).is_err());
+
+	// a reader that's opened while the last segment is being written
+	let bytes = std::fs::read(&files[0]).unwrap();
+	let reader = Reader::from_bytes(bytes.clone()).unwrap().left().unwrap();
+	let offsets: Vec<_> = reader.raw_segments().map(|s| s.offset()).collect();
+	assert_eq!(offsets.len(), 2);
+	assert_eq!(crate::segment_reader::complete_len(&bytes), bytes.len());
+	for cut in offsets[1]..bytes.len() {
+		assert_eq!(
+			crate::segment_reader::complete_len(&bytes[..cut]),
+			offsets[1],
+			"{}",
+			cut
+		);
+	}
+
+	tx.add_record("c", ts(0), record(5u32)).unwrap();
+	tx.commit().unwrap();
+	assert_eq!(tx_files(), files);
+	let db = DatabaseReader::new(t.path()).unwrap();
+	let records: Vec<(String, u32)> = db
+		.get_range(..)
+		.into_iter()
+		.map(|r| (r.key().to_owned(), r.get(0)))
+		.collect();
+	assert_eq!(
+		records,
+		[
+			("a".to_owned(), 0),
+			("a".to_owned(), 1),
+			("a".to_owned(), 2),
+			("b".to_owned(), 3),
+			("c".to_owned(), 5)
+		]
+	);
+	let reader = Reader::new(std::fs::File::open(&files[0]).unwrap())
+		.unwrap()
+		.left()
+		.unwrap();
+	assert_eq!(reader.verify().unwrap(), 5);
+}
+
+#[test]
+fn get_range_stops_at_end() {
+	let ts = |s| chrono::DateTime::from_timestamp(s, 0).unwrap().naive_utc();
+	let t = tempfile::TempDir::new().unwrap();
+	std::fs::File::create(t.path().join("main")).unwrap();
+	let text = "x".repeat(1000);
+
+	// each key is larger than a segment, so that a segment begins with it
+	for tx_keys in [b'a'..=b'z', b'k'..=b'p'] {
+		let mut tx = CreateTx::new(t.path()).unwrap();
+		for key in tx_keys {
+			let key = (key as char).to_string();
+			for n in 0..1100 {
+				tx.add_record(&key, ts(n), record(text.as_str())).unwrap();
+			}
+		}
+		tx.commit().unwrap();
+	}
+
+	let cache = std::sync::Arc::new(crate::SegmentCache::new(1 << 30));
+	let db = DatabaseReader::new(t.path())
+		.unwrap()
+		.with_segment_cache(cache.clone());
+	let keys: Vec<_> = db
+		.get_range("m".."n")
+		.into_iter()
+		.map(|r| r.key().to_owned())
+		.collect();
+	assert_eq!(keys.len(), 1100);
+	assert!(keys.iter().all(|k| k == "m"));
+	let touched = cache.misses();
+
+	// none of the segments of "n" were decompressed
+	assert_eq!(db.get_range("n".."o").into_iter().count(), 1100);
+	assert_eq!(cache.hits(), 0);
+	assert!(cache.misses() > touched);
+
+	// and reading "m" again decompresses nothing more
+	let misses = cache.misses();
+	assert_eq!(db.get_range("m"..="m").into_iter().count(), 1100);
+	assert_eq!(cache.misses(), misses);
+	assert_eq!(cache.hits(), touched);
+}
+
+#[test]
+fn parallel_time_range() {
+	let ts = |s| chrono::DateTime::from_timestamp_nanos(s);
+	let (_t, db) = make_big_database(10000);
+
+	let reader = || db.get_range_time(.., ts(100).naive_utc()..ts(250).naive_utc());
+	// the range is big enough to be split, and each half has the time range
+	let (first, second) = reader().split().expect("split");
+	let halves = first.into_iter().count() + second.into_iter().count();
+
+	let serial = reader().into_iter().count();
+	assert_eq!(halves, serial);
+	assert_eq!(reader().into_par_iter().count(), serial);
+	assert!(serial < db.get_range(..).into_iter().count() / 4);
+	assert!(reader()
+		.into_par_iter()
+		.all(|r| (100..250).contains(&r.timestamp_nanos())));
+}
@@ -24,6 +24,23 @@ impl Wildcard {
 		}
 	}
 
+	/// Returns the longest possible fixed suffix.
+	///
+	/// If no "%" is in the filter, then the entire string is returned,
+	/// Otherwise everything after the last "%" is returned, which may
+	/// be an empty string.
+	///
+	/// Together with the [`prefix`](Self::prefix), this tells how
+	/// costly a read with this filter is; see
+	/// [`DatabaseReader::get_filter`](crate::DatabaseReader::get_filter).
+	pub fn suffix(&self) -> &str {
+		if let Some(o) = self.w.rfind('%') {
+			&self.w[o + 1..]
+		} else {
+			&self.w[..]
+		}
+	}
+
 	/// returns true if this search can only match a single key.
 	///
 	/// This only happens when there is no "%" in the filter.
@@ -81,4 +98,91 @@ impl Wildcard {
 			None
 		}
 	}
+
+	/// What the keys of a read must match, besides being in the range
+	/// of the prefix, or None if that's all that's needed
+	pub(crate) fn matcher(&self) -> Option<KeyMatcher> {
+		let regex = self.as_regex()?;
+		let percents = self.w.matches('%').count();
+		if percents == 0 {
+			return Some(KeyMatcher::from_regex(regex));
+		}
+		Some(KeyMatcher {
+			regex,
+			prefix: self.prefix().to_owned(),
+			suffix: self.suffix().to_owned(),
+			only_affixes: percents == 1,
+		})
+	}
+}
+
+/// Checks a key against a wildcard's regex, after
+/// the cheaper checks of its fixed prefix and suffix
+#[derive(Clone, Debug)]
+pub(crate) struct KeyMatcher {
+	regex: regex::Regex,
+	prefix: String,
+	suffix: String,
+	/// the wildcard is `prefix%suffix`, so the regex isn't needed
+	only_affixes: bool,
+}
+
+impl KeyMatcher {
+	/// Check keys only against `regex`
+	pub(crate) fn from_regex(regex: regex::Regex) -> KeyMatcher {
+		KeyMatcher {
+			regex,
+			prefix: String::new(),
+			suffix: String::new(),
+			only_affixes: false,
+		}
+	}
+
+	pub(crate) fn is_match(&self, key: &str) -> bool {
+		key.len() >= self.prefix.len() + self.suffix.len()
+			&& key.starts_with(&self.prefix)
+			&& key.ends_with(&self.suffix)
+			&& (self.only_affixes || self.regex.is_match(key))
+	}
+
+	/// The regex, which matches the same keys
+	pub(crate) fn as_str(&self) -> &str {
+		self.regex.as_str()
+	}
+}
+
+/// Keys that are excluded from a read, see
+/// [`DatabaseReader::get_filter_except`](crate::DatabaseReader::get_filter_except)
+pub(crate) struct Exclusions {
+	regexes: Vec<regex::Regex>,
+	/// the wildcards that only need their prefix
+	prefixes: Vec<String>,
+}
+
+impl Exclusions {
+	pub(crate) fn new(wildcards: &[&Wildcard]) -> Exclusions {
+		let mut regexes = vec![];
+		let mut prefixes = vec![];
+		for w in wildcards {
+			match w.as_regex() {
+				Some(re) => regexes.push(re),
+				None => prefixes.push(w.prefix().to_owned()),
+			}
+		}
+		Exclusions { regexes, prefixes }
+	}
+
+	/// true if `key` matches any of the wildcards
+	pub(crate) fn contains(&self, key: &str) -> bool {
+		self.prefixes.iter().any(|p| key.starts_with(p.as_str()))
+			|| self.regexes.iter().any(|re| re.is_match(key))
+	}
+
+	/// true if every key from `first` to `last` matches one of the wildcards
+	pub(crate) fn contains_all(&self, first: &str, last: &str) -> bool {
+		// the keys between two keys with a prefix have that prefix too
+		self.prefixes
+			.iter()
+			.any(|p| first.starts_with(p.as_str()) && last.starts_with(p.as_str()))
+	}
 }
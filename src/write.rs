@@ -3,6 +3,7 @@ use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
 use crossbeam::channel;
 use thiserror::Error;
 
+use std::collections::BTreeMap;
 use std::io::Write;
 use std::sync::Arc;
 
@@ -33,6 +34,15 @@ pub(crate) struct Writer<W: Write + Send + 'static> {
 	thread_handles: Vec<std::thread::JoinHandle<std::io::Result<()>>>,
 	/// a counter to keep each thread writing its output in the right order
 	thread_ordering: usize,
+	/// the only format that each of these keys may have, see [`CreateTx::enforce_formats`](crate::CreateTx::enforce_formats)
+	declared_formats: Option<BTreeMap<String, String>>,
+	/// signaled by the worker threads when they've written a segment
+	writer_notifier: Arc<Condvar>,
+	/// the number of records added
+	record_count: u64,
+	/// the size of the segments sent to the worker threads, before compression
+	stored_uncompressed: u64,
+	compression: Compression,
 }
 
 struct WriterState<W: Write + Send> {
@@ -41,6 +51,10 @@ struct WriterState<W: Write + Send> {
 	writer: W,
 	stored_size_last_key: u32,
 	last_key: Vec<u8>,
+	/// written before the first segment
+	preamble: Vec<u8>,
+	/// the number of bytes written to `writer`
+	written: u64,
 }
 
 struct Header {
@@ -52,10 +66,79 @@ struct WorkerMessage {
 	counter: usize,
 	header: Header,   // not to compress
 	payload: Vec<u8>, // to compress
+	/// if set, `payload` is already compressed and escaped,
+	/// and this is its length before escaping
+	precompressed: Option<usize>,
+	/// what `payload` is (or will be) compressed with
+	compression: Compression,
+}
+
+/// How the segments of a transaction are compressed
+///
+/// Segments are written in [`SEGMENT_VERSION`](crate::SEGMENT_VERSION),
+/// which has a byte for the codec. Segments compressed with
+/// [`Zstd`](Self::Zstd) are smaller but slower to write; they're useful
+/// for data that's rarely rewritten, such as in a major compaction.
+///
+/// The compression level, see [`levels`](Self::levels), only
+/// affects the writer and isn't stored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+	/// LZ4
+	#[default]
+	Lz4,
+	/// Zstandard
+	Zstd,
+}
+
+impl Compression {
+	/// The compression levels of this codec, where higher levels
+	/// are slower and compress better
+	///
+	/// Both codecs are at level 9 by default.
+	pub fn levels(self) -> std::ops::RangeInclusive<i32> {
+		match self {
+			Compression::Lz4 => 0..=12,
+			Compression::Zstd => 1..=22,
+		}
+	}
+
+	pub(crate) const DEFAULT_LEVEL: i32 = 9;
+
+	/// The codec byte of a version 0x0101 or later segment
+	pub(crate) fn codec(self) -> u8 {
+		match self {
+			Compression::Lz4 => 0,
+			Compression::Zstd => 1,
+		}
+	}
+
+	pub(crate) fn from_codec(codec: u8) -> Option<Compression> {
+		match codec {
+			0 => Some(Compression::Lz4),
+			1 => Some(Compression::Zstd),
+			_ => None,
+		}
+	}
+}
+
+impl std::str::FromStr for Compression {
+	type Err = String;
+	fn from_str(s: &str) -> Result<Compression, String> {
+		match s {
+			"lz4" => Ok(Compression::Lz4),
+			"zstd" => Ok(Compression::Zstd),
+			s => Err(format!(
+				"unknown compression {:?} (expected lz4 or zstd)",
+				s
+			)),
+		}
+	}
 }
 
 /// A reason a write could not be completed
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum WriteFailure {
 	/// The key `second` does not come lexicographically after `first`, but they were added in that order
 	#[error("the key `{second}` does not come lexicographically after `{first}`, but they were added in that order")]
@@ -76,10 +159,43 @@ pub enum WriteFailure {
 	/// The timestamp was not in the expected format
 	#[error("Unable to parse the timestamp")]
 	UnableToParseTimestamp,
+	/// Line `line` (counting from 1) of the input couldn't be parsed
+	#[error("line {line}: {message}")]
+	InvalidLine { line: usize, message: String },
+	/// The latest record of `key` wasn't at the expected time (`None` means no records)
+	#[error("the latest record of `{key}` is at {actual:?}, not at {expected:?}")]
+	StaleLatest {
+		key: String,
+		expected: Option<chrono::NaiveDateTime>,
+		actual: Option<chrono::NaiveDateTime>,
+	},
+	/// The database has `count` transactions, which is more than `limit`,
+	/// see [`CreateTx::new_with_limit`](crate::CreateTx::new_with_limit)
+	#[error("the database has {count} transactions, more than the limit of {limit}")]
+	TooManyTransactions { count: usize, limit: usize },
+	/// The record of `key` has the format `actual`, but `expected` is declared for it,
+	/// see [`CreateTx::enforce_formats`](crate::CreateTx::enforce_formats)
+	#[error("the key `{key}` is declared with the format `{expected}`, but the record has the format `{actual}`")]
+	FormatMismatch {
+		key: String,
+		expected: String,
+		actual: String,
+	},
+}
+
+pub(crate) fn nanos_to_datetime(ts: crate::Timestamp) -> chrono::NaiveDateTime {
+	chrono::DateTime::from_timestamp((ts / 1_000_000_000) as i64, (ts % 1_000_000_000) as u32)
+		.unwrap()
+		.naive_utc()
 }
 
 impl<W: Write + Send> Writer<W> {
-	fn new_internal(writer: W, disable_compression: bool) -> Writer<W> {
+	fn new_internal(
+		writer: W,
+		compression: Compression,
+		level: i32,
+		disable_compression: bool,
+	) -> Writer<W> {
 		let num_worker_threads = 4usize;
 
 		let writer_state = WriterState {
@@ -88,6 +204,8 @@ impl<W: Write + Send> Writer<W> {
 			writer,
 			stored_size_last_key: 0,
 			last_key: vec![],
+			preamble: vec![],
+			written: 0,
 		};
 
 		let writer_state = Arc::new(Mutex::new(writer_state));
@@ -101,7 +219,13 @@ impl<W: Write + Send> Writer<W> {
 			let writer_notifier = writer_notifier.clone();
 			let recv = recv.clone();
 			let h = std::thread::spawn(move || {
-				worker_thread(recv, &writer_state, &writer_notifier, disable_compression)
+				worker_thread(
+					recv,
+					&writer_state,
+					&writer_notifier,
+					level,
+					disable_compression,
+				)
 			});
 			thread_handles.push(h);
 		}
@@ -119,10 +243,70 @@ impl<W: Write + Send> Writer<W> {
 			thread_handles,
 			thread_ordering: 0,
 			current_record_size: None,
+			declared_formats: None,
+			writer_notifier,
+			record_count: 0,
+			stored_uncompressed: 0,
+			compression,
 		}
 	}
 	pub fn new(writer: W) -> Writer<W> {
-		Self::new_internal(writer, false)
+		Self::new_internal(writer, Compression::Lz4, Compression::DEFAULT_LEVEL, false)
+	}
+
+	/// Compress the segments with `compression` at `level`, which
+	/// must be in [`Compression::levels`]
+	pub(crate) fn new_with_compression(
+		writer: W,
+		compression: Compression,
+		level: i32,
+	) -> Writer<W> {
+		debug_assert!(compression.levels().contains(&level));
+		Self::new_internal(writer, compression, level, false)
+	}
+
+	/// Reject the records of these keys that don't have their format
+	pub(crate) fn set_declared_formats(&mut self, formats: BTreeMap<String, String>) {
+		self.declared_formats = Some(formats);
+	}
+
+	/// true if no records have been added yet
+	pub(crate) fn is_empty(&self) -> bool {
+		self.current_key_data.is_empty() && self.thread_ordering == 0
+	}
+
+	/// The number of records added
+	pub(crate) fn record_count(&self) -> u64 {
+		self.record_count
+	}
+
+	/// The size of the added records' segments before compression,
+	/// including those that haven't been compressed yet
+	pub(crate) fn uncompressed_bytes(&self) -> u64 {
+		self.stored_uncompressed
+			+ self.current_segment_data.len() as u64
+			+ self.current_key_data.len() as u64
+	}
+
+	/// The number of bytes written so far
+	///
+	/// Waits for the worker threads to write the segments they
+	/// were given. Records that aren't in a full segment yet are
+	/// only written by [`finish`](Self::finish).
+	pub(crate) fn compressed_bytes(&self) -> u64 {
+		let mut wl = self.writer_state.as_ref().unwrap().lock();
+		while wl.counter != self.thread_ordering {
+			wl = self.writer_notifier.wait(wl);
+		}
+		wl.written
+	}
+
+	/// Set the bytes that come before the first segment
+	///
+	/// Must be called before any records are added.
+	pub(crate) fn set_preamble(&mut self, preamble: Vec<u8>) {
+		assert!(self.is_empty(), "preamble set after records were added");
+		self.writer_state.as_ref().unwrap().lock().preamble = preamble;
 	}
 
 	fn new_key_begin(&mut self, key: &str, format: &str) {
@@ -159,17 +343,30 @@ impl<W: Write + Send> Writer<W> {
 		self.last_segment_key = self.last_key.clone();
 	}
 
-	pub(crate) fn add_record_base(
-		&mut self,
+	/// Check that a record with `key` and `timestamp` can be added next
+	pub(crate) fn check_order(
+		&self,
 		key: &str,
 		timestamp: crate::Timestamp,
-		format: &str,
-		serialize_values: impl FnOnce(&mut Vec<u8>),
 	) -> std::result::Result<(), WriteFailure> {
 		if self.current_key_data.is_empty() {
-			// this is the first key ever seen
-			self.new_key_begin(key, format);
-			self.first_segment_key.replace_range(.., key);
+			// this is the first key ever seen, or the first after a raw
+			// segment or `flush_segments`, after which the same key may continue
+			let continues = !self.last_format.is_empty() && key == self.last_key;
+			if continues && timestamp <= self.current_timestamp {
+				return Err(WriteFailure::TimeOrderingViolation {
+					key: key.to_string(),
+					first: nanos_to_datetime(self.current_timestamp),
+					second: nanos_to_datetime(timestamp),
+				});
+			}
+			if self.thread_ordering != 0 && !continues && key.as_bytes() <= self.last_key.as_bytes()
+			{
+				return Err(WriteFailure::KeyOrderingViolation {
+					second: key.to_string(),
+					first: self.last_key.clone(),
+				});
+			}
 		} else {
 			if key.as_bytes() < self.last_key.as_bytes() {
 				return Err(WriteFailure::KeyOrderingViolation {
@@ -179,23 +376,46 @@ impl<W: Write + Send> Writer<W> {
 			}
 
 			if key.as_bytes() == self.last_key.as_bytes() && timestamp <= self.current_timestamp {
-				let first = chrono::DateTime::from_timestamp(
-					(self.current_timestamp / 1_000_000_000) as i64,
-					(self.current_timestamp % 1_000_000_000) as u32,
-				)
-				.unwrap();
-				let second = chrono::DateTime::from_timestamp(
-					(timestamp / 1_000_000_000) as i64,
-					(timestamp % 1_000_000_000) as u32,
-				)
-				.unwrap();
 				return Err(WriteFailure::TimeOrderingViolation {
 					key: key.to_string(),
-					first: first.naive_utc(),
-					second: second.naive_utc(),
+					first: nanos_to_datetime(self.current_timestamp),
+					second: nanos_to_datetime(timestamp),
 				});
 			}
+		}
+		Ok(())
+	}
+
+	pub(crate) fn add_record_base(
+		&mut self,
+		key: &str,
+		timestamp: crate::Timestamp,
+		format: &str,
+		serialize_values: impl FnOnce(&mut Vec<u8>),
+	) -> std::result::Result<(), WriteFailure> {
+		if let Some(declared) = &self.declared_formats {
+			// only check when the key or format changes
+			let changed = self.current_key_data.is_empty()
+				|| key != self.last_key
+				|| format != self.last_format;
+			match declared.get(key) {
+				Some(expected) if changed && expected != format => {
+					return Err(WriteFailure::FormatMismatch {
+						key: key.to_owned(),
+						expected: expected.clone(),
+						actual: format.to_owned(),
+					});
+				}
+				_ => {}
+			}
+		}
+		self.check_order(key, timestamp)?;
 
+		if self.current_key_data.is_empty() {
+			// this is the first key ever seen, or the first after a raw segment
+			self.new_key_begin(key, format);
+			self.first_segment_key.replace_range(.., key);
+		} else {
 			if key != self.last_key || format != self.last_format {
 				self.flush_current_key();
 				self.new_key_begin(key, format);
@@ -211,6 +431,7 @@ impl<W: Write + Send> Writer<W> {
 
 		self.current_timestamp = timestamp;
 		serialize_values(&mut self.current_key_data);
+		self.record_count += 1;
 
 		Ok(())
 	}
@@ -223,14 +444,39 @@ impl<W: Write + Send> Writer<W> {
 	) -> std::result::Result<(), WriteFailure> {
 		let mut fmt = compact_str::CompactString::default();
 		values.format_str(&mut fmt);
+		self.add_values(key, timestamp, &fmt, values)
+	}
 
+	/// Add many records of one key, reusing the buffer of their format
+	pub(crate) fn add_records<R: crate::RecordBuilder>(
+		&mut self,
+		key: &str,
+		records: impl IntoIterator<Item = (crate::Timestamp, R)>,
+	) -> std::result::Result<(), WriteFailure> {
+		let mut fmt = compact_str::CompactString::default();
+		for (timestamp, values) in records {
+			fmt.clear();
+			values.format_str(&mut fmt);
+			self.add_values(key, timestamp, &fmt, values)?;
+		}
+		Ok(())
+	}
+
+	/// Add a record whose format is `fmt`, which is `values`' format
+	fn add_values(
+		&mut self,
+		key: &str,
+		timestamp: crate::Timestamp,
+		fmt: &str,
+		values: impl crate::RecordBuilder,
+	) -> std::result::Result<(), WriteFailure> {
 		let expected_size = values.size();
 		let variable_size = values.variable_size();
 
 		self.add_record_base(
 			key,
 			timestamp,
-			&fmt,
+			fmt,
 			|buf|
 			{
 				if variable_size
@@ -279,6 +525,91 @@ impl<W: Write + Send> Writer<W> {
 		})
 	}
 
+	/// Add a record whose data is known to match its format,
+	/// such as one read from another database
+	pub(crate) fn add_record_verbatim(
+		&mut self,
+		key: &str,
+		format: &str,
+		data: &[u8],
+	) -> std::result::Result<(), WriteFailure> {
+		let constant_size = if key == self.last_key && format == self.last_format {
+			self.current_record_size
+		} else {
+			crate::row_format::row_format_size(format).map(|m| m + crate::TIMESTAMP_SIZE)
+		};
+		let timestamp = BigEndian::read_u64(&data[0..8]);
+
+		self.add_record_base(key, timestamp, format, |buf| {
+			if constant_size.is_none() {
+				let mut lenbuf = unsigned_varint::encode::usize_buffer();
+				buf.write_all(unsigned_varint::encode::usize(data.len() - 8, &mut lenbuf))
+					.unwrap();
+			}
+			buf.write_all(data).unwrap();
+		})
+	}
+
+	/// Write an already compressed segment
+	///
+	/// `stored` is the payload as stored in a file (escaped), `compressed_len`
+	/// is its length before escaping. All of the keys in the segment must come
+	/// after the records added so far, and records added after it must have
+	/// keys that come after `last_key`. Successive raw segments must come
+	/// from the same file, in order, and were compressed with `compression`.
+	pub(crate) fn add_raw_segment(
+		&mut self,
+		first_key: &str,
+		last_key: &str,
+		stored: &[u8],
+		compressed_len: usize,
+		compression: Compression,
+	) -> std::result::Result<(), WriteFailure> {
+		// a key may continue from one raw segment into the next (they
+		// come from the same file), but not from added records, whose
+		// timestamps we'd have to compare
+		let ordered = if self.current_key_data.is_empty() {
+			self.thread_ordering == 0 || first_key.as_bytes() >= self.last_key.as_bytes()
+		} else {
+			first_key.as_bytes() > self.last_key.as_bytes()
+		};
+		if !ordered {
+			return Err(WriteFailure::KeyOrderingViolation {
+				second: first_key.to_string(),
+				first: self.last_key.clone(),
+			});
+		}
+
+		if !self.current_key_data.is_empty() {
+			self.flush_current_key();
+		}
+		if !self.current_segment_data.is_empty() {
+			self.store_current_segment()?;
+		}
+
+		let message = WorkerMessage {
+			counter: self.thread_ordering,
+			header: Header {
+				first_key: first_key.as_bytes().to_owned(),
+				last_key: last_key.as_bytes().to_owned(),
+			},
+			payload: stored.to_owned(),
+			precompressed: Some(compressed_len),
+			compression,
+		};
+		self.thread_ordering += 1;
+		self.worker_threads
+			.as_ref()
+			.unwrap()
+			.send(message)
+			.expect("failed to send data to worker");
+
+		self.last_key.replace_range(.., last_key);
+		self.last_format.clear();
+		self.last_segment_key.replace_range(.., last_key);
+		Ok(())
+	}
+
 	/// send the current segment to a worker thread to get written
 	pub fn store_current_segment(&mut self) -> std::io::Result<()> {
 		let header = Header {
@@ -290,11 +621,14 @@ impl<W: Write + Send> Writer<W> {
 			&mut self.current_segment_data,
 			Vec::with_capacity(SEGMENT_SIZE_EXTRA),
 		);
+		self.stored_uncompressed += payload.len() as u64;
 
 		let message = WorkerMessage {
 			counter: self.thread_ordering,
 			header,
 			payload,
+			precompressed: None,
+			compression: self.compression,
 		};
 		self.thread_ordering += 1;
 
@@ -307,6 +641,29 @@ impl<W: Write + Send> Writer<W> {
 		Ok(())
 	}
 
+	/// Write the records added so far, ending the current segment,
+	/// and wait until they're written
+	///
+	/// Records added afterwards may continue the last key.
+	pub(crate) fn flush_segments(&mut self) -> std::io::Result<()> {
+		if !self.current_key_data.is_empty() {
+			self.flush_current_key();
+		}
+		if !self.current_segment_data.is_empty() {
+			self.store_current_segment()?;
+		}
+		self.compressed_bytes();
+		Ok(())
+	}
+
+	/// Call `f` with the `Write` that the segments are written to
+	///
+	/// Segments that are still being compressed may be written to it
+	/// afterwards, see [`flush_segments`](Self::flush_segments).
+	pub(crate) fn with_inner<R>(&self, f: impl FnOnce(&mut W) -> R) -> R {
+		f(&mut self.writer_state.as_ref().unwrap().lock().writer)
+	}
+
 	pub(crate) fn finish(mut self) -> std::io::Result<W> {
 		self.fin()?;
 		// destructure the entire writer_state to get
@@ -334,6 +691,16 @@ impl<W: Write + Send> Writer<W> {
 		for th in self.thread_handles.drain(..) {
 			th.join().expect("thread can't be joined")?;
 		}
+
+		// without segments, the preamble hasn't been written yet
+		if let Some(writer_state) = self.writer_state.as_ref() {
+			let mut wl = writer_state.lock();
+			if !wl.preamble.is_empty() {
+				let preamble = std::mem::take(&mut wl.preamble);
+				wl.writer.write_all(&preamble)?;
+				wl.written += preamble.len() as u64;
+			}
+		}
 		Ok(())
 	}
 }
@@ -348,6 +715,7 @@ fn worker_thread<W: Write + Send>(
 	recv: channel::Receiver<WorkerMessage>,
 	writer_state: &Mutex<WriterState<W>>,
 	writer_notifier: &Condvar,
+	level: i32,
 	disable_compression: bool,
 ) -> std::io::Result<()> {
 	for message in recv {
@@ -355,20 +723,36 @@ fn worker_thread<W: Write + Send>(
 			counter,
 			header,
 			payload,
+			precompressed,
+			compression,
 		} = message;
 
-		let compressed = if disable_compression {
+		let compressed = if precompressed.is_some() || disable_compression {
 			payload
 		} else {
-			let mut encoder = lz4::EncoderBuilder::new().level(9).build(vec![]).unwrap();
-			encoder.write_all(&payload)?;
-			let (c, e) = encoder.finish();
-			e?;
-			c
+			match compression {
+				Compression::Lz4 => {
+					let mut encoder = lz4::EncoderBuilder::new()
+						.level(level as u32)
+						.build(vec![])
+						.unwrap();
+					encoder.write_all(&payload)?;
+					let (c, e) = encoder.finish();
+					e?;
+					c
+				}
+				Compression::Zstd => zstd::stream::encode_all(&payload[..], level)?,
+			}
 		};
 
 		let mut segmented: smallvec::SmallVec<[_; 4]> = smallvec::smallvec![];
-		{
+		let checksum;
+		let compressed_len = if let Some(len) = precompressed {
+			checksum = crate::segment::payload_checksum(&compressed);
+			segmented.push(&compressed[..]);
+			len
+		} else {
+			checksum = crate::crc32::crc32(&compressed);
 			let mut start = 0;
 			while let Some(pos) = crate::segment::find_segment_invocation(&compressed[start..]) {
 				segmented.push(&compressed[start..pos + start]);
@@ -376,7 +760,8 @@ fn worker_thread<W: Write + Send>(
 				start = start + pos + crate::segment::SEGMENT_INVOCATION.len();
 			}
 			segmented.push(&compressed[start..]);
-		}
+			compressed.len()
+		};
 
 		let mut wl = writer_state.lock();
 		while counter != wl.counter {
@@ -397,19 +782,27 @@ fn worker_thread<W: Write + Send>(
 			wl.stored_size_last_key = 0;
 		}
 
+		if !wl.preamble.is_empty() {
+			let preamble = std::mem::take(&mut wl.preamble);
+			wl.writer.write_all(&preamble)?;
+			wl.written += preamble.len() as u64;
+		}
+
 		let wrote_size;
 		{
 			let ps = wl.prev_size;
 			let mut bc = WriteCounter::new(&mut wl.writer);
 
 			bc.write_all(crate::segment::SEGMENT_INVOCATION)?;
-			bc.write_u16::<BigEndian>(0x0100)?;
+			bc.write_u16::<BigEndian>(crate::SEGMENT_VERSION)?;
+			bc.write_u8(compression.codec())?;
+			bc.write_u32::<BigEndian>(checksum)?;
 
 			let ee = |e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e);
 
 			wv(&mut bc, header.first_key.len().try_into().map_err(ee)?)?;
 			wv(&mut bc, header.last_key.len().try_into().map_err(ee)?)?;
-			wv(&mut bc, compressed.len().try_into().map_err(ee)?)?;
+			wv(&mut bc, compressed_len.try_into().map_err(ee)?)?;
 			wv(&mut bc, ps)?;
 			wv(&mut bc, this_key_prev)?;
 
@@ -430,6 +823,7 @@ fn worker_thread<W: Write + Send>(
 		wl.last_key = header.last_key;
 		wl.counter = counter + 1;
 		wl.prev_size = wrote_size;
+		wl.written += u64::from(wrote_size);
 		writer_notifier.notify_all();
 	}
 	Ok(())
@@ -465,7 +859,7 @@ fn near_boundary() {
 	// when a segment is about to overflow, no portion of the overflowing key should appear in it
 	// (all of it should go in the successive segment)
 	let q = "qqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqq";
-	let mut w = Writer::new_internal(vec![], true);
+	let mut w = Writer::new_internal(vec![], Compression::Lz4, 0, true);
 	w.current_key_data = vec![0x42u8; SEGMENT_SIZE_GOAL - 40];
 	w.first_segment_key = "a".to_string();
 	w.last_segment_key = "a".to_string();
@@ -474,3 +868,21 @@ fn near_boundary() {
 	let v = w.finish().unwrap();
 	assert_eq!(memchr::memmem::find_iter(&v, q).count(), 2);
 }
+
+#[test]
+fn written_counts() {
+	let mut w = Writer::new(vec![]);
+	for key in ["a", "b", "c"] {
+		for n in 0..100_000u64 {
+			w.add_record(key, n, crate::record(n)).unwrap();
+		}
+	}
+	assert_eq!(w.record_count(), 300_000);
+	assert_eq!(w.uncompressed_bytes(), 3 * (12 + 1 + 1) + 300_000 * 16);
+	w.flush_current_key();
+	w.store_current_segment().unwrap();
+	let compressed = w.compressed_bytes();
+	assert!(compressed < w.uncompressed_bytes());
+	let v = w.finish().unwrap();
+	assert_eq!(compressed, v.len() as u64);
+}